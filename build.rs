@@ -1,9 +1,65 @@
 fn main() {
     let mut config = prost_build::Config::new();
-    config.bytes(&["."]);
+    config.bytes(["."]);
     config.type_attribute(".", "#[derive(PartialOrd)]");
-    config
+    // `Value` and `CommandRequest` have hand-written JSON mappings in `pb::json`
+    for message in MESSAGES {
+        config.type_attribute(
+            message,
+            "#[derive(serde::Serialize, serde::Deserialize)]\n#[serde(default)]",
+        );
+    }
+    // Request ids, streams of responses and subscriptions only matter to the TCP protocol
+    config.field_attribute(".abi.CommandResponse.request_id", "#[serde(skip)]");
+    config.field_attribute(".abi.CommandResponse.more", "#[serde(skip)]");
+    config.field_attribute(".abi.CommandResponse.publications", "#[serde(skip)]");
+    config.field_attribute(".abi.CommandResponse.changes", "#[serde(skip)]");
+    config.type_attribute(
+        ".abi.CommandRequest.request_data",
+        "#[derive(serde::Serialize, serde::Deserialize)]\n#[serde(rename_all = \"snake_case\")]",
+    );
+    tonic_build::configure()
         .out_dir("src/pb")
-        .compile_protos(&["abi.proto"], &["."])
+        .compile_with_config(config, &["abi.proto"], &["."])
         .unwrap();
 }
+
+/// Every message but `Value` and `CommandRequest`
+const MESSAGES: &[&str] = &[
+    ".abi.CommandResponse",
+    ".abi.Hget",
+    ".abi.Hgetall",
+    ".abi.Hmget",
+    ".abi.Kvpair",
+    ".abi.Hset",
+    ".abi.Hmset",
+    ".abi.Hdel",
+    ".abi.Hmdel",
+    ".abi.Hexists",
+    ".abi.Hmexists",
+    ".abi.Hrange",
+    ".abi.Hprefix",
+    ".abi.Hsetex",
+    ".abi.Hexpire",
+    ".abi.Httl",
+    ".abi.Hpersist",
+    ".abi.CreateTable",
+    ".abi.DropTable",
+    ".abi.ListTables",
+    ".abi.RenameTable",
+    ".abi.TruncateTable",
+    ".abi.Transaction",
+    ".abi.Watch",
+    ".abi.Hsetnx",
+    ".abi.Hcas",
+    ".abi.Hdelifeq",
+    ".abi.Hincrby",
+    ".abi.Hincrbyfloat",
+    ".abi.Hdecrby",
+    ".abi.Hscan",
+    ".abi.Subscribe",
+    ".abi.Psubscribe",
+    ".abi.Unsubscribe",
+    ".abi.Publish",
+    ".abi.Hwatch",
+];
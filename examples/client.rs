@@ -1,8 +1,5 @@
 use anyhow::Result;
-use async_prost::AsyncProstStream;
-use futures::prelude::*;
-use kv::{CommandRequest, CommandResponse};
-use tokio::net::TcpStream;
+use kv::KvClient;
 use tracing::info;
 
 #[tokio::main]
@@ -11,20 +8,14 @@ async fn main() -> Result<()> {
 
     let addr = "127.0.0.1:9527";
     // 连接服务器
-    let stream = TcpStream::connect(addr).await?;
-
-    // 使用 AsyncProstStream 来处理 TCP Frame
-    let mut client =
-        AsyncProstStream::<_, CommandResponse, CommandRequest, _>::from(stream).for_async();
-
-    // 生成一个 HSET 命令
-    let cmd = CommandRequest::new_hset("table1", "hello", "world".into());
+    let client = KvClient::connect(addr).await?;
 
     // 发送 HSET 命令
-    client.send(cmd).await?;
-    if let Some(Ok(data)) = client.next().await {
-        info!("Got response {:?}", data);
-    }
+    let old = client.hset("table1", "hello", "world").await?;
+    info!("Previous value {:?}", old);
+
+    let value = client.hget("table1", "hello").await?;
+    info!("Got value {:?}", value);
 
     Ok(())
 }
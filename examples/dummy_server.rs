@@ -1,9 +1,9 @@
 use anyhow::Result;
-use async_prost::AsyncProstStream;
 use futures::prelude::*;
-use kv::{CommandRequest, CommandResponse};
+use kv::{CommandRequest, CommandResponse, FrameCodec};
 use tokio::net::TcpListener;
-use tracing::info;
+use tokio_util::codec::Framed;
+use tracing::{info, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -15,15 +15,22 @@ async fn main() -> Result<()> {
         let (stream, addr) = listener.accept().await?;
         info!("Client {:?} connected", addr);
         tokio::spawn(async move {
-            let mut stream =
-                AsyncProstStream::<_, CommandRequest, CommandResponse, _>::from(stream).for_async();
+            let mut stream = Framed::new(
+                stream,
+                FrameCodec::<CommandRequest, CommandResponse>::default(),
+            );
             while let Some(Ok(msg)) = stream.next().await {
                 info!("Got a new command: {:?}", msg);
                 // 创建一个 404 response 返回给客户端
-                let mut resp = CommandResponse::default();
-                resp.status = 404;
-                resp.message = "Not found".to_owned();
-                stream.send(resp).await.unwrap();
+                let resp = CommandResponse {
+                    status: 404,
+                    message: "Not found".to_owned(),
+                    ..Default::default()
+                };
+                if let Err(e) = stream.send(resp).await {
+                    warn!("Failed to send the response: {}", e);
+                    break;
+                }
             }
             info!("Client {:?} disconnected", addr);
         });
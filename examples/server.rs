@@ -1,28 +1,22 @@
 use anyhow::Result;
-use async_prost::AsyncProstStream;
-use futures::prelude::*;
-use kv::{CommandRequest, CommandResponse, MemTable, Service, ServiceInner};
+use kv::{MemTable, ProstServerStream, Service, ServiceInner};
 use tokio::net::TcpListener;
-use tracing::info;
+use tracing::{info, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
-    let service : Service = ServiceInner::new(MemTable::new()).into();
+    let service: Service = ServiceInner::new(MemTable::new()).into();
     let addr = "127.0.0.1:9527";
     let listener = TcpListener::bind(addr).await?;
     info!("Start listening on {}", addr);
     loop {
         let (stream, addr) = listener.accept().await?;
         info!("Client {:?} connected", addr);
-        let svc = service.clone();
+        let stream = ProstServerStream::new(stream, service.clone());
         tokio::spawn(async move {
-            let mut stream =
-                AsyncProstStream::<_, CommandRequest, CommandResponse, _>::from(stream).for_async();
-            while let Some(Ok(cmd)) = stream.next().await {
-                info!("Got a new command: {:?}", cmd);
-                let res = svc.execute(cmd);
-                stream.send(res).await.unwrap();
+            if let Err(e) = stream.process().await {
+                warn!("Failed to process the stream: {}", e);
             }
             info!("Client {:?} disconnected", addr);
         });
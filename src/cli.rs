@@ -0,0 +1,911 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use clap::{Parser, ValueEnum};
+use kv::{
+    value, ClientOptions, CommandRequest, CommandResponse, KvClient, KvError, Kvpair,
+    TlsClientConnector, Value, Watch,
+};
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::DefaultHistory, validate::Validator, Context, Editor, Helper,
+};
+use std::{
+    env, fs,
+    io::{self, BufRead, IsTerminal},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+/// Command names offered by tab completion
+const COMMANDS: &[&str] = &[
+    "hget",
+    "hgetall",
+    "hmget",
+    "hset",
+    "hmset",
+    "hdel",
+    "hmdel",
+    "hexists",
+    "hmexists",
+    "hrange",
+    "hprefix",
+    "hsetex",
+    "hexpire",
+    "httl",
+    "hpersist",
+    "create_table",
+    "drop_table",
+    "list_tables",
+    "rename_table",
+    "truncate_table",
+    "hsetnx",
+    "hcas",
+    "hdelifeq",
+    "hincrby",
+    "hincrbyfloat",
+    "hdecrby",
+    "hscan",
+    "publish",
+    "watch",
+    "multi",
+    "exec",
+    "discard",
+    "help",
+    "quit",
+];
+
+const HELP: &str = r#"Commands:
+  hget t k                      hgetall t
+  hmget t k...                  hset t k v
+  hmset t k v [k v]...          hdel t k
+  hmdel t k...                  hexists t k
+  hmexists t k...               hrange t start end [limit] [rev]
+  hprefix t prefix [limit] [rev]
+  hsetex t k v ttl_ms           hexpire t k ttl_ms
+  httl t k                      hpersist t k
+  create_table t                drop_table t
+  list_tables                   rename_table t new_name
+  truncate_table t              hsetnx t k v
+  hcas t k expected|nil v       hdelifeq t k expected
+  hincrby t k n                 hincrbyfloat t k n
+  hdecrby t k n                 hscan t cursor|"" [count]
+  publish channel v
+Transactions:
+  watch t k value|nil, then multi, the queued commands, and exec or discard
+Values:
+  42 (integer), 4.2 (float), true/false (bool), 0xcafe or b64:yv4= (binary),
+  anything else is a string, quote it to keep it one: "42", 'a b'"#;
+
+/// Command line client of the kv server
+///
+/// Without a command it starts a REPL when stdin is a terminal, and otherwise
+/// runs the commands read from stdin, one per line.
+#[derive(Debug, Parser)]
+#[command(name = "kv-cli", version)]
+struct Args {
+    /// Address of the server
+    #[arg(short, long, default_value = "127.0.0.1:9527")]
+    addr: String,
+    /// How to print the responses
+    #[arg(short, long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+    /// Run the commands of a file, one per line
+    #[arg(long, conflicts_with = "command")]
+    file: Option<PathBuf>,
+    /// Connect over TLS, trusting the server certificates signed by this CA
+    #[arg(long)]
+    ca: Option<PathBuf>,
+    /// Name the server certificate must be valid for
+    #[arg(long, requires = "ca", default_value = "localhost")]
+    domain: String,
+    /// Client certificate, for servers requiring one
+    #[arg(long, requires_all = ["ca", "key"])]
+    cert: Option<PathBuf>,
+    /// Private key of the client certificate
+    #[arg(long, requires = "cert")]
+    key: Option<PathBuf>,
+    /// Talk to a server with `multiplex` enabled
+    #[arg(long)]
+    multiplex: bool,
+    /// Run a single command and exit, e.g. `kv-cli hget t1 k1`
+    command: Vec<String>,
+}
+
+impl Args {
+    fn client_options(&self) -> Result<ClientOptions> {
+        let mut options = ClientOptions {
+            multiplex: self.multiplex,
+            ..Default::default()
+        };
+        if let Some(ca) = &self.ca {
+            let ca = fs::read_to_string(ca)?;
+            let identity = match (&self.cert, &self.key) {
+                (Some(cert), Some(key)) => {
+                    Some((fs::read_to_string(cert)?, fs::read_to_string(key)?))
+                }
+                _ => None,
+            };
+            let identity = identity
+                .as_ref()
+                .map(|(cert, key)| (cert.as_str(), key.as_str()));
+            options.tls = Some(TlsClientConnector::new(&self.domain, identity, &ca)?);
+        }
+        Ok(options)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Human readable text, with pairs laid out in a table
+    Table,
+    /// One JSON object per response
+    Json,
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    let args = Args::parse();
+    let client = KvClient::connect_with(args.addr.clone(), args.client_options()?).await?;
+    let mut session = Session::new(client);
+
+    let ok = if !args.command.is_empty() {
+        let tokens = args
+            .command
+            .iter()
+            .map(|arg| Token::from_arg(arg))
+            .collect();
+        session.run_tokens(tokens, args.format).await
+    } else if let Some(path) = &args.file {
+        session
+            .run_script(fs::read_to_string(path)?.lines(), args.format)
+            .await
+    } else if !io::stdin().is_terminal() {
+        let lines = io::stdin().lock().lines().collect::<Result<Vec<_>, _>>()?;
+        session
+            .run_script(lines.iter().map(String::as_str), args.format)
+            .await
+    } else {
+        repl(&mut session, &args).await?;
+        true
+    };
+    Ok(if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+async fn repl(session: &mut Session, args: &Args) -> Result<()> {
+    let mut editor = Editor::<CommandHelper, DefaultHistory>::new()?;
+    editor.set_helper(Some(CommandHelper));
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".kv_cli_history"));
+    if let Some(path) = &history {
+        // The file doesn't exist on the first run
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        let prompt = match session.queued {
+            Some(_) => format!("{}(TX)> ", args.addr),
+            None => format!("{}> ", args.addr),
+        };
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+        if matches!(line.trim(), "quit" | "exit") {
+            break;
+        }
+        session.run_line(&line, args.format).await;
+    }
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("Failed to save the history to {}: {}", path.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// Completes the command name, the first word of the line
+struct CommandHelper;
+
+impl Completer for CommandHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let head = &line[..pos];
+        let start = head.len() - head.trim_start().len();
+        let word = &head[start..];
+        if word.contains(char::is_whitespace) {
+            return Ok((pos, Vec::new()));
+        }
+        let word = word.to_lowercase();
+        let candidates = COMMANDS
+            .iter()
+            .filter(|name| name.starts_with(&word))
+            .map(|name| name.to_string())
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for CommandHelper {
+    type Hint = String;
+}
+
+impl Highlighter for CommandHelper {}
+
+impl Validator for CommandHelper {}
+
+impl Helper for CommandHelper {}
+
+/// What a line of input produced
+#[derive(Debug, PartialEq)]
+enum Output {
+    /// The response of the server
+    Response(CommandResponse),
+    /// A message of the client itself
+    Message(&'static str),
+}
+
+/// Connection to the server, plus the state of a transaction being built
+struct Session {
+    client: KvClient,
+    watches: Vec<Watch>,
+    /// Commands queued since `multi`, None outside of a transaction
+    queued: Option<Vec<CommandRequest>>,
+}
+
+impl Session {
+    fn new(client: KvClient) -> Self {
+        Self {
+            client,
+            watches: Vec::new(),
+            queued: None,
+        }
+    }
+
+    /// Run the lines of a script, skipping blank lines and `#` comments.
+    /// Return false if any of them failed
+    async fn run_script<'a>(
+        &mut self,
+        lines: impl IntoIterator<Item = &'a str>,
+        format: Format,
+    ) -> bool {
+        let mut ok = true;
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            ok &= self.run_line(line, format).await;
+        }
+        ok
+    }
+
+    /// Run a line and print its output, return false if it failed
+    async fn run_line(&mut self, line: &str, format: Format) -> bool {
+        match tokenize(line) {
+            Ok(tokens) => self.run_tokens(tokens, format).await,
+            Err(e) => print_output(Err(e), format),
+        }
+    }
+
+    async fn run_tokens(&mut self, tokens: Vec<Token>, format: Format) -> bool {
+        let output = self.execute(tokens).await;
+        print_output(output, format)
+    }
+
+    async fn execute(&mut self, tokens: Vec<Token>) -> Result<Output, KvError> {
+        let name = match tokens.first() {
+            Some(token) => token.text.to_lowercase(),
+            None => return Err(KvError::InvalidCommand("empty command".into())),
+        };
+        let mut args = ArgParser::new(&name, &tokens[1..]);
+        match name.as_str() {
+            "help" => Ok(Output::Message(HELP)),
+            "watch" => {
+                if self.queued.is_some() {
+                    return Err(KvError::InvalidCommand(
+                        "watch inside multi is not allowed".into(),
+                    ));
+                }
+                let watch =
+                    Watch::new(args.string("table")?, args.string("key")?, args.expected()?);
+                args.finish()?;
+                self.watches.push(watch);
+                Ok(Output::Message("OK"))
+            }
+            "multi" => {
+                args.finish()?;
+                if self.queued.is_some() {
+                    return Err(KvError::InvalidCommand("multi can not be nested".into()));
+                }
+                self.queued = Some(Vec::new());
+                Ok(Output::Message("OK"))
+            }
+            "exec" => {
+                args.finish()?;
+                let commands = self
+                    .queued
+                    .take()
+                    .ok_or_else(|| KvError::InvalidCommand("exec without multi".into()))?;
+                let watches = std::mem::take(&mut self.watches);
+                let cmd = CommandRequest::new_transaction(watches, commands);
+                Ok(Output::Response(self.client.execute(cmd).await?))
+            }
+            "discard" => {
+                args.finish()?;
+                self.queued = None;
+                self.watches.clear();
+                Ok(Output::Message("OK"))
+            }
+            _ => {
+                let cmd = parse_command(&name, args)?;
+                match &mut self.queued {
+                    Some(queued) => {
+                        queued.push(cmd);
+                        Ok(Output::Message("QUEUED"))
+                    }
+                    None => Ok(Output::Response(self.client.execute(cmd).await?)),
+                }
+            }
+        }
+    }
+}
+
+/// Build the request of a command from its arguments
+fn parse_command(name: &str, mut args: ArgParser) -> Result<CommandRequest, KvError> {
+    let cmd = match name {
+        "hget" => CommandRequest::new_hget(args.string("table")?, args.string("key")?),
+        "hgetall" => CommandRequest::new_hgetall(args.string("table")?),
+        "hmget" => CommandRequest::new_hmget(args.string("table")?, args.keys()?),
+        "hset" => {
+            CommandRequest::new_hset(args.string("table")?, args.string("key")?, args.value()?)
+        }
+        "hmset" => CommandRequest::new_hmset(args.string("table")?, args.pairs()?),
+        "hdel" => CommandRequest::new_hdel(args.string("table")?, args.string("key")?),
+        "hmdel" => CommandRequest::new_hmdel(args.string("table")?, args.keys()?),
+        "hexists" => CommandRequest::new_hexists(args.string("table")?, args.string("key")?),
+        "hmexists" => CommandRequest::new_hmexists(args.string("table")?, args.keys()?),
+        "hrange" => {
+            let (table, start, end) = (
+                args.string("table")?,
+                args.string("start")?,
+                args.string("end")?,
+            );
+            let (limit, reverse) = args.scan_options()?;
+            CommandRequest::new_hrange(table, start, end, limit, reverse)
+        }
+        "hprefix" => {
+            let (table, prefix) = (args.string("table")?, args.string("prefix")?);
+            let (limit, reverse) = args.scan_options()?;
+            CommandRequest::new_hprefix(table, prefix, limit, reverse)
+        }
+        "hsetex" => CommandRequest::new_hsetex(
+            args.string("table")?,
+            args.string("key")?,
+            args.value()?,
+            args.number("ttl_ms")?,
+        ),
+        "hexpire" => CommandRequest::new_hexpire(
+            args.string("table")?,
+            args.string("key")?,
+            args.number("ttl_ms")?,
+        ),
+        "httl" => CommandRequest::new_httl(args.string("table")?, args.string("key")?),
+        "hpersist" => CommandRequest::new_hpersist(args.string("table")?, args.string("key")?),
+        "create_table" => CommandRequest::new_create_table(args.string("table")?),
+        "drop_table" => CommandRequest::new_drop_table(args.string("table")?),
+        "list_tables" => CommandRequest::new_list_tables(),
+        "rename_table" => {
+            CommandRequest::new_rename_table(args.string("table")?, args.string("new_name")?)
+        }
+        "truncate_table" => CommandRequest::new_truncate_table(args.string("table")?),
+        "hsetnx" => {
+            CommandRequest::new_hsetnx(args.string("table")?, args.string("key")?, args.value()?)
+        }
+        "hcas" => CommandRequest::new_hcas(
+            args.string("table")?,
+            args.string("key")?,
+            args.expected()?,
+            args.value()?,
+        ),
+        "hdelifeq" => {
+            CommandRequest::new_hdelifeq(args.string("table")?, args.string("key")?, args.value()?)
+        }
+        "hincrby" => CommandRequest::new_hincrby(
+            args.string("table")?,
+            args.string("key")?,
+            args.number("delta")?,
+        ),
+        "hincrbyfloat" => CommandRequest::new_hincrbyfloat(
+            args.string("table")?,
+            args.string("key")?,
+            args.number("delta")?,
+        ),
+        "hdecrby" => CommandRequest::new_hdecrby(
+            args.string("table")?,
+            args.string("key")?,
+            args.number("delta")?,
+        ),
+        "hscan" => {
+            let (table, cursor) = (args.string("table")?, args.string("cursor")?);
+            let count = match args.tokens.len() {
+                0 => 0,
+                _ => args.number("count")?,
+            };
+            CommandRequest::new_hscan(table, cursor, count)
+        }
+        "publish" => CommandRequest::new_publish(args.string("channel")?, args.value()?),
+        _ => {
+            return Err(KvError::InvalidCommand(format!(
+                "unknown command `{}`, try `help`",
+                name
+            )))
+        }
+    };
+    args.finish()?;
+    Ok(cmd)
+}
+
+/// A word of the input, quoted words are always strings
+#[derive(Clone, Debug, PartialEq)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+impl Token {
+    /// A command line argument, already split by the shell, which may still be quoted
+    fn from_arg(arg: &str) -> Self {
+        match tokenize(arg) {
+            Ok(mut tokens) if tokens.len() == 1 => tokens.remove(0),
+            _ => Self {
+                text: arg.into(),
+                quoted: true,
+            },
+        }
+    }
+
+    /// Parse the literal into the matching kind of value
+    fn to_value(&self) -> Result<Value, KvError> {
+        let s = self.text.as_str();
+        if self.quoted {
+            return Ok(s.into());
+        }
+        let invalid = |kind: &str, e: &dyn std::fmt::Display| {
+            KvError::InvalidCommand(format!("invalid {} literal `{}`: {}", kind, s, e))
+        };
+        if let Some(hex) = s.strip_prefix("0x") {
+            let bytes = hex::decode(hex).map_err(|e| invalid("hex", &e))?;
+            return Ok(binary(bytes));
+        }
+        if let Some(b64) = s.strip_prefix("b64:") {
+            let bytes = BASE64.decode(b64).map_err(|e| invalid("base64", &e))?;
+            return Ok(binary(bytes));
+        }
+        match s {
+            "true" => return Ok(true.into()),
+            "false" => return Ok(false.into()),
+            _ => {}
+        }
+        if let Ok(i) = s.parse::<i64>() {
+            return Ok(i.into());
+        }
+        // Only plain decimal numbers, `inf` and `nan` stay strings
+        let numeric = s.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c));
+        match s.parse::<f64>() {
+            Ok(f) if numeric && f.is_finite() => Ok(f.into()),
+            _ => Ok(s.into()),
+        }
+    }
+}
+
+fn binary(bytes: Vec<u8>) -> Value {
+    Value {
+        value: Some(value::Value::Binary(bytes.into())),
+    }
+}
+
+/// Split a line into words, honouring single and double quotes like a shell
+fn tokenize(line: &str) -> Result<Vec<Token>, KvError> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(tokens);
+        }
+
+        let mut token = Token {
+            text: String::new(),
+            quoted: false,
+        };
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+            match c {
+                '\'' => {
+                    token.quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some('\'') => break,
+                            Some(c) => token.text.push(c),
+                            None => return Err(unterminated()),
+                        }
+                    }
+                }
+                '"' => {
+                    token.quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => match chars.next() {
+                                Some('n') => token.text.push('\n'),
+                                Some('t') => token.text.push('\t'),
+                                Some('r') => token.text.push('\r'),
+                                Some(c) => token.text.push(c),
+                                None => return Err(unterminated()),
+                            },
+                            Some(c) => token.text.push(c),
+                            None => return Err(unterminated()),
+                        }
+                    }
+                }
+                c => token.text.push(c),
+            }
+        }
+        tokens.push(token);
+    }
+}
+
+fn unterminated() -> KvError {
+    KvError::InvalidCommand("unterminated quote".into())
+}
+
+/// Consumes the arguments of a command one by one
+struct ArgParser<'a> {
+    name: &'a str,
+    tokens: std::slice::Iter<'a, Token>,
+}
+
+impl<'a> ArgParser<'a> {
+    fn new(name: &'a str, tokens: &'a [Token]) -> Self {
+        Self {
+            name,
+            tokens: tokens.iter(),
+        }
+    }
+
+    fn next(&mut self, what: &str) -> Result<&'a Token, KvError> {
+        self.tokens.next().ok_or_else(|| {
+            KvError::InvalidCommand(format!("{}: missing argument `{}`", self.name, what))
+        })
+    }
+
+    fn string(&mut self, what: &str) -> Result<String, KvError> {
+        Ok(self.next(what)?.text.clone())
+    }
+
+    fn value(&mut self) -> Result<Value, KvError> {
+        self.next("value")?.to_value()
+    }
+
+    /// An expected value, where a bare `nil` means the key must not exist
+    fn expected(&mut self) -> Result<Option<Value>, KvError> {
+        let token = self.next("expected")?;
+        if !token.quoted && token.text == "nil" {
+            return Ok(None);
+        }
+        token.to_value().map(Some)
+    }
+
+    fn number<T: std::str::FromStr>(&mut self, what: &str) -> Result<T, KvError> {
+        let token = self.next(what)?;
+        token.text.parse().map_err(|_| {
+            KvError::InvalidCommand(format!(
+                "{}: `{}` is not a valid {}",
+                self.name, token.text, what
+            ))
+        })
+    }
+
+    /// The remaining arguments as keys, at least one
+    fn keys(&mut self) -> Result<Vec<String>, KvError> {
+        let keys = vec![self.string("key")?];
+        Ok(keys
+            .into_iter()
+            .chain(self.tokens.by_ref().map(|t| t.text.clone()))
+            .collect())
+    }
+
+    /// The remaining arguments as key value pairs, at least one
+    fn pairs(&mut self) -> Result<Vec<Kvpair>, KvError> {
+        let mut pairs = vec![Kvpair::new(self.string("key")?, self.value()?)];
+        while let Some(key) = self.tokens.next() {
+            pairs.push(Kvpair::new(key.text.clone(), self.value()?));
+        }
+        Ok(pairs)
+    }
+
+    /// Optional `[limit] [rev]` of the scan commands
+    fn scan_options(&mut self) -> Result<(u32, bool), KvError> {
+        let (mut limit, mut reverse) = (0, false);
+        for token in self.tokens.by_ref() {
+            match token.text.parse() {
+                Ok(n) if !token.quoted => limit = n,
+                _ if token.text.eq_ignore_ascii_case("rev") => reverse = true,
+                _ => {
+                    return Err(KvError::InvalidCommand(format!(
+                        "{}: expected a limit or `rev`, got `{}`",
+                        self.name, token.text
+                    )))
+                }
+            }
+        }
+        Ok((limit, reverse))
+    }
+
+    fn finish(mut self) -> Result<(), KvError> {
+        match self.tokens.next() {
+            Some(token) => Err(KvError::InvalidCommand(format!(
+                "{}: unexpected argument `{}`",
+                self.name, token.text
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Print the output, return false if it's an error
+fn print_output(output: Result<Output, KvError>, format: Format) -> bool {
+    let ok = match &output {
+        Ok(Output::Response(res)) => (200..300).contains(&res.status),
+        Ok(Output::Message(_)) => true,
+        Err(_) => false,
+    };
+    match format {
+        Format::Table => println!("{}", render_text(&output)),
+        Format::Json => println!("{}", render_json(&output)),
+    }
+    ok
+}
+
+fn render_text(output: &Result<Output, KvError>) -> String {
+    match output {
+        Ok(Output::Response(res)) => render_response(res),
+        Ok(Output::Message(msg)) => msg.to_string(),
+        Err(e) => format!("(error) {}", e),
+    }
+}
+
+fn render_response(res: &CommandResponse) -> String {
+    if !(200..300).contains(&res.status) {
+        return format!("(error) {} {}", res.status, res.message);
+    }
+    if !res.results.is_empty() {
+        return numbered(res.results.iter().map(render_response));
+    }
+    if !res.pairs.is_empty() {
+        // HSCAN tells where the next page starts
+        return match res.values.as_slice() {
+            [cursor] => format!(
+                "{}\n(cursor) {}",
+                render_pairs(&res.pairs),
+                render_value(cursor)
+            ),
+            _ => render_pairs(&res.pairs),
+        };
+    }
+    match res.values.as_slice() {
+        [] => "OK".into(),
+        [v] => render_value(v),
+        values => numbered(values.iter().map(render_value)),
+    }
+}
+
+/// `1) a` lines, with continuation lines indented under the first one
+fn numbered(items: impl Iterator<Item = String>) -> String {
+    items
+        .enumerate()
+        .map(|(i, item)| {
+            let prefix = format!("{}) ", i + 1);
+            let indent = "\n".to_string() + &" ".repeat(prefix.len());
+            prefix + &item.replace('\n', &indent)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn render_pairs(pairs: &[Kvpair]) -> String {
+    let rows: Vec<_> = pairs
+        .iter()
+        .map(|pair| {
+            let value = pair.value.as_ref().map(render_value);
+            (pair.key.as_str(), value.unwrap_or_else(|| "(nil)".into()))
+        })
+        .collect();
+    let key_width = rows
+        .iter()
+        .map(|(k, _)| k.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(3);
+    let value_width = rows
+        .iter()
+        .map(|(_, v)| v.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(5);
+
+    let mut lines = vec![
+        format!("{:<kw$} | {}", "key", "value", kw = key_width),
+        format!("{}-+-{}", "-".repeat(key_width), "-".repeat(value_width)),
+    ];
+    for (key, value) in rows {
+        lines.push(format!("{:<kw$} | {}", key, value, kw = key_width));
+    }
+    lines.join("\n")
+}
+
+fn render_value(v: &Value) -> String {
+    match &v.value {
+        Some(value::Value::String(s)) => format!("{:?}", s),
+        Some(value::Value::Binary(b)) => format!("0x{}", hex::encode(b)),
+        Some(value::Value::Integer(i)) => i.to_string(),
+        Some(value::Value::Float(f)) => format!("{:?}", f),
+        Some(value::Value::Bool(b)) => b.to_string(),
+        None => "(nil)".into(),
+    }
+}
+
+fn render_json(output: &Result<Output, KvError>) -> serde_json::Value {
+    match output {
+        Ok(Output::Response(res)) => serde_json::to_value(res).expect("a response is valid JSON"),
+        Ok(Output::Message(msg)) => serde_json::json!({ "message": msg }),
+        Err(e) => serde_json::json!({ "error": e.to_string() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kv::{serve, GeneralConfig, MemTable, Service, ServiceInner};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn tokenize_should_honour_quotes() {
+        let tokens = tokenize(r#"hset t1 "a b" 'c "d"' e"\"f"  "#).unwrap();
+        let texts: Vec<_> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, vec!["hset", "t1", "a b", "c \"d\"", "e\"f"]);
+        assert!(!tokens[1].quoted && tokens[2].quoted && tokens[4].quoted);
+
+        assert_eq!(tokenize("hset t1 \"k").unwrap_err(), unterminated());
+        assert!(tokenize("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn literals_should_be_typed() {
+        let value = |s: &str| tokenize(s).unwrap()[0].to_value().unwrap();
+        assert_eq!(value("42"), 42.into());
+        assert_eq!(value("-4.5"), (-4.5).into());
+        assert_eq!(value("1e3"), 1000.0.into());
+        assert_eq!(value("true"), true.into());
+        assert_eq!(value("0xcafe"), binary(vec![0xca, 0xfe]));
+        assert_eq!(value("b64:yv4="), binary(vec![0xca, 0xfe]));
+        assert_eq!(value("\"42\""), "42".into());
+        assert_eq!(value("nan"), "nan".into());
+        assert_eq!(value("hello"), "hello".into());
+
+        let err = tokenize("0xzz").unwrap()[0].to_value().unwrap_err();
+        assert!(err.to_string().contains("invalid hex literal"));
+    }
+
+    #[test]
+    fn commands_should_be_parsed() {
+        let parse = |line: &str| {
+            let tokens = tokenize(line).unwrap();
+            let name = tokens[0].text.to_lowercase();
+            parse_command(&name, ArgParser::new(&name, &tokens[1..]))
+        };
+        assert_eq!(
+            parse("hset t1 k1 1.5").unwrap(),
+            CommandRequest::new_hset("t1", "k1", 1.5.into())
+        );
+        assert_eq!(
+            parse("hmset t1 a 1 b 'x'").unwrap(),
+            CommandRequest::new_hmset(
+                "t1",
+                vec![Kvpair::new("a", 1.into()), Kvpair::new("b", "x".into())]
+            )
+        );
+        assert_eq!(
+            parse("hmget t1 a b c").unwrap(),
+            CommandRequest::new_hmget("t1", vec!["a".into(), "b".into(), "c".into()])
+        );
+        assert_eq!(
+            parse("hrange t1 a \"\" 10 rev").unwrap(),
+            CommandRequest::new_hrange("t1", "a", "", 10, true)
+        );
+        assert_eq!(
+            parse("hscan t1 \"\" 100").unwrap(),
+            CommandRequest::new_hscan("t1", "", 100)
+        );
+        assert_eq!(
+            parse("publish news 'hello'").unwrap(),
+            CommandRequest::new_publish("news", "hello".into())
+        );
+        assert_eq!(
+            parse("hcas t1 k1 nil 1").unwrap(),
+            CommandRequest::new_hcas("t1", "k1", None, 1.into())
+        );
+
+        let err = |line: &str| parse(line).unwrap_err().to_string();
+        assert!(err("hget t1").contains("missing argument `key`"));
+        assert!(err("hget t1 k1 k2").contains("unexpected argument `k2`"));
+        assert!(err("hmset t1 a 1 b").contains("missing argument `value`"));
+        assert!(err("hincrby t1 k1 x").contains("not a valid delta"));
+        assert!(err("hfoo t1").contains("unknown command"));
+    }
+
+    #[test]
+    fn responses_should_be_rendered() {
+        let res = CommandResponse::from(vec![Value::from("v"), Value::default(), binary(vec![1])]);
+        assert_eq!(render_response(&res), "1) \"v\"\n2) (nil)\n3) 0x01");
+
+        let res = CommandResponse::from(vec![
+            Kvpair::new("k1", 1.into()),
+            Kvpair::new("key2", true.into()),
+        ]);
+        assert_eq!(
+            render_response(&res),
+            "key  | value\n-----+------\nk1   | 1\nkey2 | true"
+        );
+
+        let res = CommandResponse::from(KvError::NotFound("t1".into(), "k1".into()));
+        assert_eq!(
+            render_response(&res),
+            "(error) 404 Not found for table: t1, key: k1"
+        );
+
+        let output = Ok(Output::Message("OK"));
+        assert_eq!(render_json(&output).to_string(), r#"{"message":"OK"}"#);
+    }
+
+    #[tokio::test]
+    async fn session_should_run_transactions() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(async move {
+            let config = GeneralConfig::default();
+            serve(listener, service, &config, futures::future::pending()).await
+        });
+        let client = KvClient::connect(addr.to_string()).await.unwrap();
+        let mut session = Session::new(client);
+        assert_eq!(run(&mut session, "hset t1 n 1").await, "(nil)");
+        assert_eq!(run(&mut session, "watch t1 n 1").await, "OK");
+        assert_eq!(run(&mut session, "multi").await, "OK");
+        assert_eq!(run(&mut session, "hincrby t1 n 2").await, "QUEUED");
+        assert_eq!(run(&mut session, "hget t1 n").await, "QUEUED");
+        assert_eq!(run(&mut session, "exec").await, "1) 3\n2) 3");
+        assert!(run(&mut session, "exec")
+            .await
+            .contains("exec without multi"));
+    }
+
+    async fn run(session: &mut Session, line: &str) -> String {
+        render_text(&session.execute(tokenize(line).unwrap()).await)
+    }
+}
@@ -0,0 +1,435 @@
+use crate::{
+    BrokerOptions, Compression, EvictionPolicy, FrameOptions, KvError, MemTableOptions,
+    SlowSubscriberPolicy, WalOptions, MAX_FRAME_LEN,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+/// Configuration of the `kvs` server, usually read from a TOML file
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Network settings
+    #[serde(default)]
+    pub general: GeneralConfig,
+    /// Which storage backs the server
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Logging settings
+    #[serde(default)]
+    pub log: LogConfig,
+    /// Publish/subscribe settings
+    #[serde(default)]
+    pub pubsub: PubsubConfig,
+    /// TLS of the main listener, plaintext without it
+    pub tls: Option<TlsConfig>,
+}
+
+/// Network settings of the server
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeneralConfig {
+    /// Address to listen on, e.g. `127.0.0.1:9527`
+    pub addr: String,
+    /// Maximum number of concurrent connections, further clients wait to be accepted
+    pub max_connections: usize,
+    /// Close a connection after this many seconds without a request, 0 disables it
+    pub idle_timeout_secs: u64,
+    /// Give up sending a response after this many seconds, 0 disables it
+    pub write_timeout_secs: u64,
+    /// Compression of large responses, one of `none`, `gzip`, `lz4` and `zstd`
+    pub compression: Compression,
+    /// Largest frame accepted or sent in bytes, before and after decompression
+    pub max_frame_len: usize,
+    /// Expect clients to open a yamux session and send requests over its streams
+    pub multiplex: bool,
+    /// Address of the optional listener speaking the Redis protocol, e.g. `127.0.0.1:6379`
+    pub resp_addr: Option<String>,
+    /// Address of the optional HTTP/JSON gateway, e.g. `127.0.0.1:8080`
+    pub http_addr: Option<String>,
+    /// Address of the optional gRPC listener, e.g. `127.0.0.1:50051`
+    pub grpc_addr: Option<String>,
+}
+
+impl Default for GeneralConfig {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:9527".into(),
+            max_connections: 1024,
+            idle_timeout_secs: 300,
+            write_timeout_secs: 10,
+            compression: Compression::None,
+            max_frame_len: MAX_FRAME_LEN,
+            multiplex: false,
+            resp_addr: None,
+            http_addr: None,
+            grpc_addr: None,
+        }
+    }
+}
+
+/// Storage backends selectable in the config
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    /// In-memory `MemTable`
+    #[default]
+    Memory,
+    /// In-memory ordered `BTreeTable`
+    Btree,
+    /// Durable `WalTable`
+    Wal,
+    /// Durable log-structured `Bitcask`
+    Bitcask,
+}
+
+impl FromStr for StorageKind {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(Self::Memory),
+            "btree" => Ok(Self::Btree),
+            "wal" => Ok(Self::Wal),
+            "bitcask" => Ok(Self::Bitcask),
+            _ => Err(KvError::InvalidConfig(format!(
+                "unknown storage `{}`, expected one of memory, btree, wal and bitcask",
+                s
+            ))),
+        }
+    }
+}
+
+/// Storage settings of the server
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Which backend to use
+    #[serde(rename = "type")]
+    pub kind: StorageKind,
+    /// Data directory, required by the durable backends
+    pub path: Option<PathBuf>,
+    /// Reject writes to tables that were not created explicitly
+    pub strict: bool,
+    /// Approximate memory bound of the keys and values in bytes, 0 means unlimited.
+    /// Only the memory storage supports it
+    pub max_memory: usize,
+    /// What to do at `max_memory`, one of `noeviction`, `allkeys-lru`, `allkeys-lfu`
+    /// and `volatile-ttl`
+    pub eviction: EvictionPolicy,
+}
+
+/// TLS settings of the server, with PEM files
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// Certificate chain of the server
+    pub cert: PathBuf,
+    /// Private key of the server
+    pub key: PathBuf,
+    /// CA signing the client certificates, which are required if it's set
+    pub client_ca: Option<PathBuf>,
+}
+
+/// Publish/subscribe settings of the server
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PubsubConfig {
+    /// Messages queued for a subscriber before it counts as slow
+    pub queue_len: usize,
+    /// What happens to slow subscribers, `drop` their messages or `disconnect` them
+    pub slow_subscribers: SlowSubscriberPolicy,
+}
+
+impl Default for PubsubConfig {
+    fn default() -> Self {
+        let options = BrokerOptions::default();
+        Self {
+            queue_len: options.queue_len,
+            slow_subscribers: options.slow_subscribers,
+        }
+    }
+}
+
+/// Logging settings of the server
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// One of `trace`, `debug`, `info`, `warn` and `error`
+    pub level: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".into(),
+        }
+    }
+}
+
+impl GeneralConfig {
+    /// Idle timeout of a connection, None if disabled
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        Some(self.idle_timeout_secs)
+            .filter(|s| *s > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// Write timeout of a response, None if disabled
+    pub fn write_timeout(&self) -> Option<Duration> {
+        Some(self.write_timeout_secs)
+            .filter(|s| *s > 0)
+            .map(Duration::from_secs)
+    }
+
+    /// Framing of the TCP protocol
+    pub fn frame_options(&self) -> FrameOptions {
+        FrameOptions {
+            compression: self.compression,
+            max_frame_len: self.max_frame_len,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Read and validate a config file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, KvError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|e| {
+            KvError::InvalidConfig(format!("cannot read {}: {}", path.display(), e))
+        })?;
+        let config: Self = toml::from_str(&content)
+            .map_err(|e| KvError::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the settings that TOML parsing alone can't catch
+    pub fn validate(&self) -> Result<(), KvError> {
+        let invalid = |msg: String| Err(KvError::InvalidConfig(msg));
+        if self.general.addr.parse::<SocketAddr>().is_err() {
+            return invalid(format!(
+                "general.addr `{}` is not a valid socket address",
+                self.general.addr
+            ));
+        }
+        let extra = [
+            ("resp_addr", &self.general.resp_addr),
+            ("http_addr", &self.general.http_addr),
+            ("grpc_addr", &self.general.grpc_addr),
+        ];
+        for (name, addr) in extra {
+            if let Some(addr) = addr {
+                if addr.parse::<SocketAddr>().is_err() {
+                    return invalid(format!(
+                        "general.{} `{}` is not a valid socket address",
+                        name, addr
+                    ));
+                }
+                // Only the main address is served over TLS
+                if self.tls.is_some() {
+                    return invalid(format!(
+                        "general.{} would be served in plaintext, it can't be used with [tls]",
+                        name
+                    ));
+                }
+            }
+        }
+        if self.general.max_connections == 0 {
+            return invalid("general.max_connections must be positive".into());
+        }
+        if self.general.max_frame_len == 0 || self.general.max_frame_len > u32::MAX as usize {
+            return invalid(format!(
+                "general.max_frame_len must be between 1 and {}",
+                u32::MAX
+            ));
+        }
+        match (self.storage.kind, &self.storage.path) {
+            (StorageKind::Wal | StorageKind::Bitcask, None) => {
+                return invalid(format!(
+                    "storage.path is required by the {:?} storage",
+                    self.storage.kind
+                ))
+            }
+            (StorageKind::Memory | StorageKind::Btree, Some(_)) => {
+                return invalid(format!(
+                    "storage.path is not used by the {:?} storage",
+                    self.storage.kind
+                ))
+            }
+            _ => {}
+        }
+        if self.storage.strict
+            && !matches!(self.storage.kind, StorageKind::Memory | StorageKind::Wal)
+        {
+            return invalid(format!(
+                "storage.strict is not supported by the {:?} storage",
+                self.storage.kind
+            ));
+        }
+        if self.storage.max_memory > 0 && self.storage.kind != StorageKind::Memory {
+            return invalid(format!(
+                "storage.max_memory is not supported by the {:?} storage",
+                self.storage.kind
+            ));
+        }
+        if self.storage.max_memory == 0 && self.storage.eviction != EvictionPolicy::NoEviction {
+            return invalid("storage.eviction has no effect without storage.max_memory".into());
+        }
+        if self.pubsub.queue_len == 0 {
+            return invalid("pubsub.queue_len must be positive".into());
+        }
+        if !["trace", "debug", "info", "warn", "error"].contains(&self.log.level.as_str()) {
+            return invalid(format!("log.level `{}` is not a log level", self.log.level));
+        }
+        Ok(())
+    }
+
+    /// Options of a `MemTable` storage
+    pub fn memtable_options(&self) -> MemTableOptions {
+        MemTableOptions {
+            max_memory: self.storage.max_memory,
+            policy: self.storage.eviction,
+            strict: self.storage.strict,
+        }
+    }
+
+    /// Options of the broker of the service
+    pub fn broker_options(&self) -> BrokerOptions {
+        BrokerOptions {
+            queue_len: self.pubsub.queue_len,
+            slow_subscribers: self.pubsub.slow_subscribers,
+        }
+    }
+
+    /// Options of a `WalTable` storage
+    pub fn wal_options(&self) -> WalOptions {
+        WalOptions {
+            strict: self.storage.strict,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_should_be_loaded() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            [general]
+            addr = "0.0.0.0:9527"
+            max_connections = 16
+
+            [storage]
+            type = "wal"
+            path = "/tmp/kv"
+
+            [log]
+            level = "debug"
+
+            [pubsub]
+            slow_subscribers = "disconnect"
+
+            [tls]
+            cert = "fixtures/server.cert"
+            key = "fixtures/server.key"
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.general.max_connections, 16);
+        assert_eq!(config.general.idle_timeout_secs, 300);
+        assert_eq!(config.storage.kind, StorageKind::Wal);
+        assert_eq!(config.storage.path, Some("/tmp/kv".into()));
+        assert_eq!(config.log.level, "debug");
+        assert_eq!(config.pubsub.queue_len, 1024);
+        assert_eq!(
+            config.broker_options().slow_subscribers,
+            SlowSubscriberPolicy::Disconnect
+        );
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.key, PathBuf::from("fixtures/server.key"));
+        assert_eq!(tls.client_ca, None);
+    }
+
+    #[test]
+    fn memory_bound_should_be_configured() {
+        let config: ServerConfig = toml::from_str(
+            r#"
+            [storage]
+            type = "memory"
+            max_memory = 1048576
+            eviction = "allkeys-lfu"
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        let options = config.memtable_options();
+        assert_eq!(options.max_memory, 1048576);
+        assert_eq!(options.policy, EvictionPolicy::AllKeysLfu);
+        assert_eq!("volatile-ttl".parse(), Ok(EvictionPolicy::VolatileTtl));
+    }
+
+    #[test]
+    fn fixture_config_should_be_valid() {
+        let config = ServerConfig::load("fixtures/server.conf").unwrap();
+        assert_eq!(config.storage.kind, StorageKind::Wal);
+        assert_eq!(config.general.compression, Compression::Lz4);
+    }
+
+    #[test]
+    fn empty_config_should_use_defaults() {
+        let config: ServerConfig = toml::from_str("").unwrap();
+        assert_eq!(config, ServerConfig::default());
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn invalid_config_should_be_rejected() {
+        let check = |content: &str, msg: &str| {
+            let err = toml::from_str::<ServerConfig>(content)
+                .map_err(|e| KvError::InvalidConfig(e.to_string()))
+                .and_then(|c| c.validate())
+                .unwrap_err();
+            assert!(err.to_string().contains(msg), "{} in {}", msg, err);
+        };
+        check(
+            "[general]\naddr = \"localhost\"",
+            "not a valid socket address",
+        );
+        check("[storage]\ntype = \"bitcask\"", "storage.path is required");
+        check(
+            "[storage]\ntype = \"btree\"\nstrict = true",
+            "storage.strict",
+        );
+        check("[storage]\ntype = \"sled\"", "unknown variant");
+        check("[log]\nlevel = \"loud\"", "not a log level");
+        check("[pubsub]\nqueue_len = 0", "pubsub.queue_len");
+        check("[pubsub]\nslow_subscribers = \"block\"", "unknown variant");
+        check("[general]\nport = 9527", "unknown field");
+        check("[general]\nresp_addr = \"6379\"", "general.resp_addr");
+        check("[tls]\ncert = \"server.cert\"", "missing field `key`");
+        check("[general]\nmax_frame_len = 0", "general.max_frame_len");
+        check("[general]\ncompression = \"snappy\"", "unknown variant");
+        check("[general]\ngrpc_addr = \"50051\"", "general.grpc_addr");
+        check(
+            "[storage]\ntype = \"btree\"\nmax_memory = 1024",
+            "storage.max_memory",
+        );
+        check("[storage]\neviction = \"allkeys-lru\"", "storage.eviction");
+        check("[storage]\neviction = \"random\"", "unknown variant");
+        check(
+            "[general]\nhttp_addr = \"127.0.0.1:8080\"\n[tls]\ncert = \"c\"\nkey = \"k\"",
+            "can't be used with [tls]",
+        );
+    }
+}
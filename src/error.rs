@@ -8,10 +8,26 @@ pub enum KvError {
     /// The table or the key is not found
     NotFound(String, String),
 
+    #[error("Table not found: {0}")]
+    /// The table doesn't exist
+    TableNotFound(String),
+
+    #[error("Table already exists: {0}")]
+    /// The table already exists
+    TableExists(String),
+
+    #[error("Condition failed for table: {0}, key: {1}")]
+    /// A conditional write found the key in an unexpected state
+    Conflict(String, String),
+
+    #[error("Watched key changed, table: {0}, key: {1}")]
+    /// A key watched by a transaction no longer holds the expected value
+    WatchFailed(String, String),
+
     #[error("Cannot parse command: `{0}`")]
     /// Cannot parse the command
     InvalidCommand(String),
-    #[error("Cannot convert value {:0} to {1}")]
+    #[error("Cannot convert value {0:?} to {1}")]
     /// The type conversion between values failed
     ConvertError(Value, &'static str),
     #[error("Cannot process command {0} with table: {1}, key: {2}, Error: {3}")]
@@ -25,8 +41,62 @@ pub enum KvError {
     /// Error in decoding protobuf
     DecodeError(#[from] prost::DecodeError),
 
-    // TODO: 添加 JSON 处理的 Error
+    #[error("Frame of {0} bytes is larger than the limit of {1} bytes")]
+    /// A frame exceeds the maximum frame size
+    FrameTooLarge(usize, usize),
+    #[error("Invalid frame: {0}")]
+    /// A frame has unknown flags or can't be decompressed
+    FrameError(String),
+
+    #[error("TLS error: {0}")]
+    /// The certificates or keys can't be loaded
+    TlsError(String),
+
+    #[error("Not enough memory to store table: {0}, key: {1}")]
+    /// The storage reached its memory limit and can't evict anything
+    OutOfMemory(String, String),
+
+    #[error("Command {0} is not supported by the storage")]
+    /// The storage backend doesn't implement the command
+    Unsupported(&'static str),
+
+    #[error("I/O error: {0}")]
+    /// Error in reading or writing files
+    IoError(String),
+
+    #[error("Invalid config: {0}")]
+    /// The server configuration is invalid
+    InvalidConfig(String),
+
+    #[error("Server returned status {0}: {1}")]
+    /// The server failed the request, with its status code and message
+    ServerError(u32, String),
+
+    #[error("Timed out {0}")]
+    /// The client gave up waiting for the server
+    Timeout(&'static str),
+
+    #[error("Subscriber fell behind the published messages")]
+    /// A subscriber's queue filled up and it was disconnected
+    SlowSubscriber,
+
+    #[error("Failed to handle JSON: {0}")]
+    /// Error in encoding or decoding JSON
+    JsonError(String),
+
     #[error("Internal error: {0}")]
     /// Any other errors
     Internal(String),
 }
+
+impl From<serde_json::Error> for KvError {
+    fn from(e: serde_json::Error) -> Self {
+        KvError::JsonError(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for KvError {
+    fn from(e: std::io::Error) -> Self {
+        KvError::IoError(e.to_string())
+    }
+}
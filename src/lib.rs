@@ -1,12 +1,16 @@
 #![warn(missing_docs)]
 //! A simple key-value pair server.
 
+mod config;
 mod error;
+mod network;
 mod pb;
 mod service;
 mod storage;
 
+pub use config::*;
 pub use error::KvError;
+pub use network::*;
 pub use pb::abi::*;
 pub use service::*;
 pub use storage::*;
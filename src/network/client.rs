@@ -0,0 +1,1026 @@
+use crate::{
+    CommandRequest, CommandResponse, FrameOptions, KvError, Kvpair, Message, PipelinedClientStream,
+    ProstClientStream, TlsClientConnector, Value, Watch, YamuxCtrl,
+};
+use futures::SinkExt;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    sync::{Mutex as AsyncMutex, Semaphore},
+    time,
+};
+use tracing::{debug, warn};
+
+/// Settings of a `KvClient`
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    /// Maximum number of connections, further requests wait for a free one
+    pub pool_size: usize,
+    /// Give up a single connection attempt after this long
+    pub connect_timeout: Duration,
+    /// Give up a request, including sending and waiting for the response, after this long
+    pub request_timeout: Duration,
+    /// How many times to retry a failed connection attempt before giving up
+    pub connect_retries: usize,
+    /// Delay before the first retry, doubled after each failed attempt
+    pub initial_backoff: Duration,
+    /// Upper limit of the delay between two attempts
+    pub max_backoff: Duration,
+    /// Discard pooled connections unused for this long, the server may have closed them
+    pub max_idle: Duration,
+    /// Compression of the requests and the largest frame accepted from the server
+    pub frame: FrameOptions,
+    /// Talk TLS to the server, plaintext without it
+    pub tls: Option<TlsClientConnector>,
+    /// Open the pooled connections as streams of a single yamux session, for
+    /// servers with `multiplex` enabled. `pool_size` then bounds the streams.
+    pub multiplex: bool,
+    /// Send the requests over a single connection without waiting for the
+    /// previous responses, matching them by request id. `pool_size` then bounds
+    /// the requests in flight.
+    pub pipeline: bool,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            pool_size: 4,
+            connect_timeout: Duration::from_secs(3),
+            request_timeout: Duration::from_secs(5),
+            connect_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            max_idle: Duration::from_secs(60),
+            frame: FrameOptions::default(),
+            tls: None,
+            multiplex: false,
+            pipeline: false,
+        }
+    }
+}
+
+/// Client of a kv server, with a pool of connections shared by its clones
+///
+/// Connections are opened on demand, with retries and exponential backoff. A
+/// connection that fails or times out is discarded and replaced on the next
+/// request. Requests themselves are never retried, since the server may already
+/// have executed them.
+#[derive(Clone)]
+pub struct KvClient {
+    inner: Arc<ClientInner>,
+}
+
+struct ClientInner {
+    addr: String,
+    options: ClientOptions,
+    idle: Mutex<Vec<(Connection, Instant)>>,
+    permits: Semaphore,
+    session: AsyncMutex<Option<YamuxCtrl>>,
+    pipelined: AsyncMutex<Option<PipelinedClientStream>>,
+}
+
+/// A plaintext or TLS stream to the server
+trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for S {}
+
+type Connection = ProstClientStream<Box<dyn AsyncStream>>;
+
+impl KvClient {
+    /// Create a client of the server at `addr`, without connecting yet
+    pub fn new(addr: impl Into<String>, options: ClientOptions) -> Self {
+        let permits = Semaphore::new(options.pool_size.max(1));
+        Self {
+            inner: Arc::new(ClientInner {
+                addr: addr.into(),
+                options,
+                idle: Mutex::new(Vec::new()),
+                permits,
+                session: AsyncMutex::new(None),
+                pipelined: AsyncMutex::new(None),
+            }),
+        }
+    }
+
+    /// Create a client with the default options, and make sure the server is reachable
+    pub async fn connect(addr: impl Into<String>) -> Result<Self, KvError> {
+        Self::connect_with(addr, ClientOptions::default()).await
+    }
+
+    /// Create a client, and make sure the server is reachable
+    pub async fn connect_with(
+        addr: impl Into<String>,
+        options: ClientOptions,
+    ) -> Result<Self, KvError> {
+        let client = Self::new(addr, options);
+        if client.inner.options.pipeline {
+            client.inner.pipelined().await?;
+        } else {
+            let conn = client.inner.connect().await?;
+            client.inner.release(conn);
+        }
+        Ok(client)
+    }
+
+    /// Send a request, and return its response as is, whatever the status
+    pub async fn execute(&self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        let _permit = self
+            .inner
+            .permits
+            .acquire()
+            .await
+            .expect("the semaphore is never closed");
+        if self.inner.options.pipeline {
+            let conn = self.inner.pipelined().await?;
+            return match time::timeout(self.inner.options.request_timeout, conn.execute(cmd)).await
+            {
+                Ok(res) => res,
+                Err(_) => Err(KvError::Timeout("waiting for the response")),
+            };
+        }
+        let mut conn = match self.inner.acquire() {
+            Some(conn) => conn,
+            None => self.inner.connect().await?,
+        };
+        match time::timeout(self.inner.options.request_timeout, conn.execute(cmd)).await {
+            Ok(Ok(res)) => {
+                self.inner.release(conn);
+                Ok(res)
+            }
+            Ok(Err(e)) => {
+                warn!(
+                    "Discarding a broken connection to {}: {}",
+                    self.inner.addr, e
+                );
+                Err(e)
+            }
+            Err(_) => Err(KvError::Timeout("waiting for the response")),
+        }
+    }
+
+    /// Send a request, turning a non-2xx response into `KvError::ServerError`
+    pub async fn request(&self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        checked(self.execute(cmd).await?)
+    }
+
+    /// Get a key, None if it doesn't exist
+    pub async fn hget(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<Option<Value>, KvError> {
+        match self.request(CommandRequest::new_hget(table, key)).await {
+            Ok(res) => Ok(first(res).and_then(present)),
+            Err(KvError::ServerError(404, _)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get all pairs of a table
+    pub async fn hgetall(&self, table: impl Into<String>) -> Result<Vec<Kvpair>, KvError> {
+        Ok(self
+            .request(CommandRequest::new_hgetall(table))
+            .await?
+            .pairs)
+    }
+
+    /// Get several keys, None for the missing ones
+    pub async fn hmget(
+        &self,
+        table: impl Into<String>,
+        keys: Vec<String>,
+    ) -> Result<Vec<Option<Value>>, KvError> {
+        let res = self.request(CommandRequest::new_hmget(table, keys)).await?;
+        Ok(res.values.into_iter().map(present).collect())
+    }
+
+    /// Store a pair, and return the previous value
+    pub async fn hset(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        let res = self
+            .request(CommandRequest::new_hset(table, key, value.into()))
+            .await?;
+        Ok(first(res).and_then(present))
+    }
+
+    /// Store several pairs, and return their previous values
+    pub async fn hmset(
+        &self,
+        table: impl Into<String>,
+        pairs: Vec<Kvpair>,
+    ) -> Result<Vec<Option<Value>>, KvError> {
+        let res = self
+            .request(CommandRequest::new_hmset(table, pairs))
+            .await?;
+        Ok(res.values.into_iter().map(present).collect())
+    }
+
+    /// Delete a key, and return its previous value
+    pub async fn hdel(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<Option<Value>, KvError> {
+        let res = self.request(CommandRequest::new_hdel(table, key)).await?;
+        Ok(first(res).and_then(present))
+    }
+
+    /// Delete several keys, and return their previous values
+    pub async fn hmdel(
+        &self,
+        table: impl Into<String>,
+        keys: Vec<String>,
+    ) -> Result<Vec<Option<Value>>, KvError> {
+        let res = self.request(CommandRequest::new_hmdel(table, keys)).await?;
+        Ok(res.values.into_iter().map(present).collect())
+    }
+
+    /// Check if a key exists
+    pub async fn hexists(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<bool, KvError> {
+        let res = self
+            .request(CommandRequest::new_hexists(table, key))
+            .await?;
+        single(res)
+    }
+
+    /// Check if several keys exist
+    pub async fn hmexists(
+        &self,
+        table: impl Into<String>,
+        keys: Vec<String>,
+    ) -> Result<Vec<bool>, KvError> {
+        let res = self
+            .request(CommandRequest::new_hmexists(table, keys))
+            .await?;
+        res.values.into_iter().map(bool::try_from).collect()
+    }
+
+    /// Get the pairs whose keys fall in `[start, end)`, an empty bound means unbounded
+    pub async fn hrange(
+        &self,
+        table: impl Into<String>,
+        start: impl Into<String>,
+        end: impl Into<String>,
+        limit: u32,
+        reverse: bool,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let cmd = CommandRequest::new_hrange(table, start, end, limit, reverse);
+        Ok(self.request(cmd).await?.pairs)
+    }
+
+    /// Get the pairs whose keys start with `prefix`
+    pub async fn hprefix(
+        &self,
+        table: impl Into<String>,
+        prefix: impl Into<String>,
+        limit: u32,
+        reverse: bool,
+    ) -> Result<Vec<Kvpair>, KvError> {
+        let cmd = CommandRequest::new_hprefix(table, prefix, limit, reverse);
+        Ok(self.request(cmd).await?.pairs)
+    }
+
+    /// Get a page of at most `count` pairs after `cursor`, in key order, and
+    /// the cursor of the next page, None once the table is done. The first page
+    /// is after the empty cursor.
+    pub async fn hscan(
+        &self,
+        table: impl Into<String>,
+        cursor: impl Into<String>,
+        count: u32,
+    ) -> Result<(Vec<Kvpair>, Option<String>), KvError> {
+        let res = self
+            .request(CommandRequest::new_hscan(table, cursor, count))
+            .await?;
+        let cursor = String::try_from(res.values.into_iter().next().unwrap_or_default())?;
+        Ok((res.pairs, Some(cursor).filter(|c| !c.is_empty())))
+    }
+
+    /// Store a pair that expires after `ttl`, and return the previous value
+    pub async fn hsetex(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        let cmd = CommandRequest::new_hsetex(table, key, value.into(), ttl.as_millis() as u64);
+        Ok(first(self.request(cmd).await?).and_then(present))
+    }
+
+    /// Set the ttl of a key, false if the key doesn't exist
+    pub async fn hexpire(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        ttl: Duration,
+    ) -> Result<bool, KvError> {
+        let cmd = CommandRequest::new_hexpire(table, key, ttl.as_millis() as u64);
+        single(self.request(cmd).await?)
+    }
+
+    /// Get the remaining ttl of a key, None if it has no ttl
+    pub async fn httl(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<Option<Duration>, KvError> {
+        let ms: i64 = single(self.request(CommandRequest::new_httl(table, key)).await?)?;
+        Ok(u64::try_from(ms).ok().map(Duration::from_millis))
+    }
+
+    /// Remove the ttl of a key, false if it had none
+    pub async fn hpersist(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+    ) -> Result<bool, KvError> {
+        single(
+            self.request(CommandRequest::new_hpersist(table, key))
+                .await?,
+        )
+    }
+
+    /// Create a table, false if it already exists
+    pub async fn create_table(&self, table: impl Into<String>) -> Result<bool, KvError> {
+        single(
+            self.request(CommandRequest::new_create_table(table))
+                .await?,
+        )
+    }
+
+    /// Drop a table, false if it doesn't exist
+    pub async fn drop_table(&self, table: impl Into<String>) -> Result<bool, KvError> {
+        single(self.request(CommandRequest::new_drop_table(table)).await?)
+    }
+
+    /// List all tables
+    pub async fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let res = self.request(CommandRequest::new_list_tables()).await?;
+        res.values.into_iter().map(String::try_from).collect()
+    }
+
+    /// Rename a table
+    pub async fn rename_table(
+        &self,
+        table: impl Into<String>,
+        new_name: impl Into<String>,
+    ) -> Result<(), KvError> {
+        let cmd = CommandRequest::new_rename_table(table, new_name);
+        self.request(cmd).await.map(|_| ())
+    }
+
+    /// Remove all keys of a table, and return how many were removed
+    pub async fn truncate_table(&self, table: impl Into<String>) -> Result<u64, KvError> {
+        let n: i64 = single(
+            self.request(CommandRequest::new_truncate_table(table))
+                .await?,
+        )?;
+        Ok(n as u64)
+    }
+
+    /// Store a pair only if the key doesn't exist, false otherwise
+    pub async fn hsetnx(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<bool, KvError> {
+        let cmd = CommandRequest::new_hsetnx(table, key, value.into());
+        conditional(self.request(cmd).await)
+    }
+
+    /// Store a pair only if the key holds `expected`, or doesn't exist if it's None
+    pub async fn hcas(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        expected: Option<Value>,
+        value: impl Into<Value>,
+    ) -> Result<bool, KvError> {
+        let cmd = CommandRequest::new_hcas(table, key, expected, value.into());
+        conditional(self.request(cmd).await)
+    }
+
+    /// Delete a key only if it holds `expected`
+    pub async fn hdelifeq(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        expected: impl Into<Value>,
+    ) -> Result<bool, KvError> {
+        let cmd = CommandRequest::new_hdelifeq(table, key, expected.into());
+        conditional(self.request(cmd).await)
+    }
+
+    /// Add to the integer value of a key, and return the new value
+    pub async fn hincrby(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        delta: i64,
+    ) -> Result<i64, KvError> {
+        single(
+            self.request(CommandRequest::new_hincrby(table, key, delta))
+                .await?,
+        )
+    }
+
+    /// Add to the float value of a key, and return the new value
+    pub async fn hincrbyfloat(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        delta: f64,
+    ) -> Result<f64, KvError> {
+        let cmd = CommandRequest::new_hincrbyfloat(table, key, delta);
+        single(self.request(cmd).await?)
+    }
+
+    /// Subtract from the integer value of a key, and return the new value
+    pub async fn hdecrby(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        delta: i64,
+    ) -> Result<i64, KvError> {
+        single(
+            self.request(CommandRequest::new_hdecrby(table, key, delta))
+                .await?,
+        )
+    }
+
+    /// Publish a message to a channel, and return how many subscribers got it
+    pub async fn publish(
+        &self,
+        channel: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<usize, KvError> {
+        let cmd = CommandRequest::new_publish(channel, value.into());
+        Ok(single::<i64>(self.request(cmd).await?)? as usize)
+    }
+
+    /// Subscribe to `channels` on a connection of its own, outside the pool
+    pub async fn subscribe(&self, channels: Vec<String>) -> Result<Subscription, KvError> {
+        let mut subscription = self.subscription().await?;
+        subscription.subscribe(channels).await?;
+        Ok(subscription)
+    }
+
+    /// Subscribe to the channels matching glob `patterns` on a connection of
+    /// its own, outside the pool
+    pub async fn psubscribe(&self, patterns: Vec<String>) -> Result<Subscription, KvError> {
+        let mut subscription = self.subscription().await?;
+        subscription.psubscribe(patterns).await?;
+        Ok(subscription)
+    }
+
+    /// Watch the changes of a table, of `key` in it, or of the keys starting
+    /// with `key` if `prefix` is set, on a connection of its own, outside the pool
+    pub async fn watch(
+        &self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        prefix: bool,
+    ) -> Result<Subscription, KvError> {
+        let mut subscription = self.subscription().await?;
+        subscription.watch(table, key, prefix).await?;
+        Ok(subscription)
+    }
+
+    async fn subscription(&self) -> Result<Subscription, KvError> {
+        Ok(Subscription {
+            conn: self.inner.connect().await?,
+            request_timeout: self.inner.options.request_timeout,
+            received: VecDeque::new(),
+        })
+    }
+
+    /// Run `commands` atomically if all `watches` hold, and return their responses
+    pub async fn transaction(
+        &self,
+        watches: Vec<Watch>,
+        commands: Vec<CommandRequest>,
+    ) -> Result<Vec<CommandResponse>, KvError> {
+        let cmd = CommandRequest::new_transaction(watches, commands);
+        Ok(self.request(cmd).await?.results)
+    }
+}
+
+/// A connection subscribed to channels or watching keys, receiving the
+/// messages published to them and the changes of the keys
+///
+/// The messages are queued by the server until `next` reads them. A subscriber
+/// too slow for the server's queue misses messages or gets
+/// `KvError::ServerError` from `next`, as configured on the server.
+pub struct Subscription {
+    conn: Connection,
+    request_timeout: Duration,
+    // Pushed before the response to a request
+    received: VecDeque<Message>,
+}
+
+impl Subscription {
+    /// Subscribe to more channels, and return the number of subscriptions
+    pub async fn subscribe(&mut self, channels: Vec<String>) -> Result<usize, KvError> {
+        self.request(CommandRequest::new_subscribe(channels)).await
+    }
+
+    /// Subscribe to the channels matching glob `patterns`, and return the
+    /// number of subscriptions
+    pub async fn psubscribe(&mut self, patterns: Vec<String>) -> Result<usize, KvError> {
+        self.request(CommandRequest::new_psubscribe(patterns)).await
+    }
+
+    /// Watch the changes of a table, of `key` in it, or of the keys starting
+    /// with `key` if `prefix` is set, and return the number of subscriptions
+    pub async fn watch(
+        &mut self,
+        table: impl Into<String>,
+        key: impl Into<String>,
+        prefix: bool,
+    ) -> Result<usize, KvError> {
+        self.request(CommandRequest::new_hwatch(table, key, prefix))
+            .await
+    }
+
+    /// Unsubscribe from channels or patterns, from all of them and every watch
+    /// if `topics` is empty, and return the number of subscriptions left
+    pub async fn unsubscribe(&mut self, topics: Vec<String>) -> Result<usize, KvError> {
+        self.request(CommandRequest::new_unsubscribe(topics)).await
+    }
+
+    /// Wait for the next message
+    pub async fn next(&mut self) -> Result<Message, KvError> {
+        loop {
+            if let Some(message) = self.received.pop_front() {
+                return Ok(message);
+            }
+            let res = self.conn.next_response().await?;
+            if res.is_push() {
+                self.received.extend(res.into_messages());
+            } else {
+                checked(res)?;
+            }
+        }
+    }
+
+    async fn request(&mut self, cmd: CommandRequest) -> Result<usize, KvError> {
+        let res = time::timeout(self.request_timeout, async {
+            self.conn.inner.send(cmd).await?;
+            loop {
+                let res = self.conn.next_response().await?;
+                if !res.is_push() {
+                    return Ok::<_, KvError>(res);
+                }
+                self.received.extend(res.into_messages());
+            }
+        })
+        .await
+        .map_err(|_| KvError::Timeout("waiting for the response"))??;
+        Ok(single::<i64>(checked(res)?)? as usize)
+    }
+}
+
+impl ClientInner {
+    /// Take a pooled connection, dropping the ones idle for too long
+    fn acquire(&self) -> Option<Connection> {
+        let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+        while let Some((conn, since)) = idle.pop() {
+            if since.elapsed() < self.options.max_idle {
+                return Some(conn);
+            }
+        }
+        None
+    }
+
+    fn release(&self, conn: Connection) {
+        let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+        idle.push((conn, Instant::now()));
+    }
+
+    /// Open a new connection
+    async fn connect(&self) -> Result<Connection, KvError> {
+        let stream = self.open_with_retries().await?;
+        Ok(ProstClientStream::with_frame_options(
+            stream,
+            self.options.frame,
+        ))
+    }
+
+    /// The pipelined connection, reopened if it failed
+    async fn pipelined(&self) -> Result<PipelinedClientStream, KvError> {
+        let mut pipelined = self.pipelined.lock().await;
+        match pipelined.as_ref() {
+            Some(conn) if !conn.is_closed() => Ok(conn.clone()),
+            _ => {
+                let stream = self.open_with_retries().await?;
+                let conn = PipelinedClientStream::new(stream, self.options.frame);
+                *pipelined = Some(conn.clone());
+                Ok(conn)
+            }
+        }
+    }
+
+    /// Open a new stream, retrying with exponential backoff
+    async fn open_with_retries(&self) -> Result<Box<dyn AsyncStream>, KvError> {
+        let mut backoff = self.options.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            let err = match time::timeout(self.options.connect_timeout, self.open()).await {
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(e)) => e,
+                Err(_) => KvError::Timeout("connecting to the server"),
+            };
+            if attempt >= self.options.connect_retries {
+                return Err(err);
+            }
+            attempt += 1;
+            debug!(
+                "Failed to connect to {}: {}, retrying in {:?}",
+                self.addr, err, backoff
+            );
+            time::sleep(backoff).await;
+            backoff = (backoff * 2).min(self.options.max_backoff);
+        }
+    }
+
+    /// Connect once, or open a stream of the session if multiplexing
+    async fn open(&self) -> Result<Box<dyn AsyncStream>, KvError> {
+        if !self.options.multiplex {
+            return self.open_transport().await;
+        }
+        let mut session = self.session.lock().await;
+        if let Some(ctrl) = session.as_mut() {
+            match ctrl.open_stream().await {
+                Ok(stream) => return Ok(Box::new(stream)),
+                Err(e) => debug!("Reopening the session to {}: {}", self.addr, e),
+            }
+        }
+        let mut ctrl = YamuxCtrl::new_client(self.open_transport().await?);
+        let stream = ctrl.open_stream().await?;
+        *session = Some(ctrl);
+        Ok(Box::new(stream))
+    }
+
+    /// Connect the underlying stream, with the TLS handshake if enabled
+    async fn open_transport(&self) -> Result<Box<dyn AsyncStream>, KvError> {
+        let stream = TcpStream::connect(&self.addr).await?;
+        stream.set_nodelay(true)?;
+        match &self.options.tls {
+            Some(tls) => Ok(Box::new(tls.connect(stream).await?)),
+            None => Ok(Box::new(stream)),
+        }
+    }
+}
+
+/// Turn a non-2xx response into `KvError::ServerError`
+fn checked(res: CommandResponse) -> Result<CommandResponse, KvError> {
+    if (200..300).contains(&res.status) {
+        Ok(res)
+    } else {
+        Err(KvError::ServerError(res.status, res.message))
+    }
+}
+
+/// The first value of a response
+fn first(res: CommandResponse) -> Option<Value> {
+    res.values.into_iter().next()
+}
+
+/// The single value of a response, converted to `T`
+fn single<T>(res: CommandResponse) -> Result<T, KvError>
+where
+    T: TryFrom<Value, Error = KvError>,
+{
+    first(res).unwrap_or_default().try_into()
+}
+
+/// Empty values stand for missing keys
+fn present(v: Value) -> Option<Value> {
+    v.value.is_some().then_some(v)
+}
+
+/// A failed condition comes back as 409, which is an expected outcome here
+fn conditional(res: Result<CommandResponse, KvError>) -> Result<bool, KvError> {
+    match res {
+        Ok(_) => Ok(true),
+        Err(KvError::ServerError(409, _)) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{serve, GeneralConfig, KeyChange, MemTable, Publication, Service, ServiceInner};
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn client_should_run_typed_commands() {
+        let addr = start_server(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let client = KvClient::connect(addr.to_string()).await.unwrap();
+
+        assert!(client.create_table("t1").await.unwrap());
+        assert_eq!(client.hset("t1", "k1", "v1").await.unwrap(), None);
+        assert_eq!(
+            client.hset("t1", "k1", "v2").await.unwrap(),
+            Some("v1".into())
+        );
+        assert_eq!(client.hget("t1", "k1").await.unwrap(), Some("v2".into()));
+        assert_eq!(client.hget("t1", "k2").await.unwrap(), None);
+        assert!(client.hexists("t1", "k1").await.unwrap());
+        assert_eq!(
+            client
+                .hmget("t1", vec!["k1".into(), "k2".into()])
+                .await
+                .unwrap(),
+            vec![Some("v2".into()), None]
+        );
+
+        assert_eq!(client.hincrby("t1", "n", 5).await.unwrap(), 5);
+        assert_eq!(client.hdecrby("t1", "n", 2).await.unwrap(), 3);
+        assert!(client.hsetnx("t1", "nx", 1).await.unwrap());
+        assert!(!client.hsetnx("t1", "nx", 2).await.unwrap());
+        assert!(!client.hdelifeq("t1", "nx", 2).await.unwrap());
+        assert!(client.hcas("t1", "nx", Some(1.into()), 2).await.unwrap());
+
+        assert_eq!(client.httl("t1", "k1").await.unwrap(), None);
+        assert!(client
+            .hexpire("t1", "k1", Duration::from_secs(60))
+            .await
+            .unwrap());
+        assert!(client.httl("t1", "k1").await.unwrap().is_some());
+
+        let results = client
+            .transaction(
+                vec![Watch::new("t1", "n", Some(3.into()))],
+                vec![CommandRequest::new_hincrby("t1", "n", 1)],
+            )
+            .await
+            .unwrap();
+        assert_eq!(results[0].values, vec![4.into()]);
+
+        let (page, cursor) = client.hscan("t1", "", 2).await.unwrap();
+        assert_eq!(page.len(), 2);
+        let (page, cursor) = client.hscan("t1", cursor.unwrap(), 2).await.unwrap();
+        assert_eq!((page.len(), cursor), (1, None));
+
+        assert_eq!(client.list_tables().await.unwrap(), vec!["t1".to_string()]);
+        assert_eq!(client.truncate_table("t1").await.unwrap(), 3);
+        assert!(client.hgetall("t1").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn errors_should_be_decoded() {
+        let addr = start_server(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let client = KvClient::connect(addr.to_string()).await.unwrap();
+
+        client.hset("t1", "k1", "v1").await.unwrap();
+        let err = client.hincrby("t1", "k1", 1).await.unwrap_err();
+        assert!(matches!(err, KvError::ServerError(400, _)), "{:?}", err);
+        let err = client.rename_table("t2", "t3").await.unwrap_err();
+        assert!(matches!(err, KvError::ServerError(404, _)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn client_should_retry_connecting() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let client = KvClient::new(addr.to_string(), ClientOptions::default());
+        tokio::spawn(async move {
+            time::sleep(Duration::from_millis(150)).await;
+            start_server(TcpListener::bind(addr).await.unwrap());
+        });
+        client.hset("t1", "k1", "v1").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn connecting_should_give_up_after_retries() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let options = ClientOptions {
+            connect_retries: 2,
+            initial_backoff: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let client = KvClient::new(addr.to_string(), options);
+        let err = client.hget("t1", "k1").await.unwrap_err();
+        assert!(matches!(err, KvError::IoError(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn requests_should_time_out() {
+        // A server that accepts connections but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                streams.push(stream);
+            }
+        });
+
+        let options = ClientOptions {
+            request_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let client = KvClient::new(addr.to_string(), options);
+        let err = client.hget("t1", "k1").await.unwrap_err();
+        assert_eq!(err, KvError::Timeout("waiting for the response"));
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_should_share_the_pool() {
+        let addr = start_server(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let options = ClientOptions {
+            pool_size: 2,
+            ..Default::default()
+        };
+        let client = KvClient::new(addr.to_string(), options);
+
+        let tasks: Vec<_> = (0..20)
+            .map(|_| {
+                let client = client.clone();
+                tokio::spawn(async move { client.hincrby("t1", "n", 1).await.unwrap() })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(client.hget("t1", "n").await.unwrap(), Some(20.into()));
+        assert!(client.inner.idle.lock().unwrap().len() <= 2);
+    }
+
+    #[tokio::test]
+    async fn multiplexed_requests_should_share_one_connection() {
+        // Any connection beyond the first would wait for a permit forever
+        let config = GeneralConfig {
+            max_connections: 1,
+            multiplex: true,
+            ..Default::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = start_server_with(listener, config);
+        let options = ClientOptions {
+            pool_size: 8,
+            request_timeout: Duration::from_secs(1),
+            multiplex: true,
+            ..Default::default()
+        };
+        let client = KvClient::new(addr.to_string(), options);
+
+        let tasks: Vec<_> = (0..20)
+            .map(|_| {
+                let client = client.clone();
+                tokio::spawn(async move { client.hincrby("t1", "n", 1).await.unwrap() })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(client.hget("t1", "n").await.unwrap(), Some(20.into()));
+        assert!(client.inner.idle.lock().unwrap().len() > 1);
+    }
+
+    #[tokio::test]
+    async fn pipelined_requests_should_share_one_connection() {
+        let config = GeneralConfig {
+            max_connections: 1,
+            ..Default::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = start_server_with(listener, config);
+        let options = ClientOptions {
+            pool_size: 8,
+            request_timeout: Duration::from_secs(1),
+            pipeline: true,
+            ..Default::default()
+        };
+        let client = KvClient::connect_with(addr.to_string(), options)
+            .await
+            .unwrap();
+
+        let tasks: Vec<_> = (0..20)
+            .map(|i| {
+                let client = client.clone();
+                tokio::spawn(async move { client.hset("t1", format!("k{}", i), i).await.unwrap() })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let keys = (0..20).map(|i| format!("k{}", i)).collect();
+        let values = client.hmget("t1", keys).await.unwrap();
+        assert_eq!(values, (0..20).map(|i| Some(i.into())).collect::<Vec<_>>());
+        assert!(client.inner.idle.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn subscriptions_should_receive_published_messages() {
+        let addr = start_server(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let client = KvClient::connect(addr.to_string()).await.unwrap();
+
+        let mut direct = client.subscribe(vec!["news".into()]).await.unwrap();
+        let mut matching = client.psubscribe(vec!["n*".into()]).await.unwrap();
+        assert_eq!(direct.psubscribe(vec!["sports.*".into()]).await.unwrap(), 2);
+
+        assert_eq!(client.publish("news", "hello").await.unwrap(), 2);
+        assert_eq!(client.publish("sports.ski", 1).await.unwrap(), 1);
+        assert_eq!(client.publish("weather", 2).await.unwrap(), 0);
+
+        let publication = next_publication(&mut direct).await;
+        assert_eq!(
+            (publication.channel.as_str(), publication.pattern.as_str()),
+            ("news", "")
+        );
+        assert_eq!(publication.value, Some("hello".into()));
+        let publication = next_publication(&mut direct).await;
+        assert_eq!(publication.pattern, "sports.*");
+        assert_eq!(next_publication(&mut matching).await.pattern, "n*");
+
+        assert_eq!(matching.unsubscribe(Vec::new()).await.unwrap(), 0);
+        assert_eq!(client.publish("news", 3).await.unwrap(), 1);
+        assert_eq!(next_publication(&mut direct).await.value, Some(3.into()));
+    }
+
+    #[tokio::test]
+    async fn watches_should_stream_key_changes() {
+        let addr = start_server(TcpListener::bind("127.0.0.1:0").await.unwrap());
+        let client = KvClient::connect(addr.to_string()).await.unwrap();
+        let mut watch = client.watch("config", "db.", true).await.unwrap();
+
+        client.hset("config", "db.host", "a").await.unwrap();
+        client.hset("config", "cache.host", "b").await.unwrap();
+        client.hset("config", "db.host", "c").await.unwrap();
+        client
+            .hsetex("config", "db.lock", 1, Duration::from_millis(50))
+            .await
+            .unwrap();
+
+        let change = next_change(&mut watch).await;
+        assert_eq!(change.key, "db.host");
+        assert_eq!(
+            (change.old_value, change.new_value),
+            (None, Some("a".into()))
+        );
+        let change = next_change(&mut watch).await;
+        assert_eq!(change.old_value, Some("a".into()));
+        assert_eq!(change.new_value, Some("c".into()));
+        assert_eq!(next_change(&mut watch).await.key, "db.lock");
+        // Nobody touches the key, the server notices its ttl running out
+        let change = time::timeout(Duration::from_secs(1), next_change(&mut watch))
+            .await
+            .unwrap();
+        assert_eq!(change.key, "db.lock");
+        assert!(change.expired);
+        assert_eq!(change.old_value, Some(1.into()));
+    }
+
+    async fn next_publication(subscription: &mut Subscription) -> Publication {
+        match subscription.next().await.unwrap() {
+            Message::Publication(publication) => publication,
+            other => panic!("expected a publication, got {:?}", other),
+        }
+    }
+
+    async fn next_change(subscription: &mut Subscription) -> KeyChange {
+        match subscription.next().await.unwrap() {
+            Message::Change(change) => change,
+            other => panic!("expected a change, got {:?}", other),
+        }
+    }
+
+    fn start_server(listener: TcpListener) -> SocketAddr {
+        start_server_with(listener, GeneralConfig::default())
+    }
+
+    fn start_server_with(listener: TcpListener, config: GeneralConfig) -> SocketAddr {
+        let addr = listener.local_addr().unwrap();
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(async move {
+            serve(listener, service, &config, futures::future::pending())
+                .await
+                .unwrap()
+        });
+        addr
+    }
+}
@@ -0,0 +1,402 @@
+//! Framing of the TCP protocol. Every message is preceded by a 5 byte header:
+//!
+//! ```text
+//! +-------+----------------------+---------------------+
+//! | flags | payload length (u32) | payload             |
+//! +-------+----------------------+---------------------+
+//!  1 byte   4 bytes, big endian    protobuf, maybe compressed
+//! ```
+//!
+//! The two low bits of `flags` tell how the payload is compressed, the others
+//! are reserved and must be zero. The length is checked against the maximum
+//! frame size before anything is allocated, and so is the decompressed size.
+
+use crate::{CommandRequest, CommandResponse, KvError};
+use bytes::{Buf, BufMut, BytesMut};
+use flate2::{read::GzDecoder, write::GzEncoder};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Read, Write},
+    marker::PhantomData,
+    str::FromStr,
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Length of the frame header
+pub const FRAME_HEADER_LEN: usize = 5;
+/// Default maximum size of a frame payload, compressed or not
+pub const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+/// Messages smaller than this are never compressed, they'd fit in a single
+/// Ethernet packet anyway
+pub const COMPRESSION_THRESHOLD: usize = 1436;
+
+const COMPRESSION_MASK: u8 = 0b11;
+
+/// How frame payloads are compressed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Send payloads as they are
+    #[default]
+    None,
+    /// gzip, the best ratio but the slowest
+    Gzip,
+    /// lz4, the fastest
+    Lz4,
+    /// zstd, a good ratio at a decent speed
+    Zstd,
+}
+
+impl Compression {
+    fn flag(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Gzip => 1,
+            Self::Lz4 => 2,
+            Self::Zstd => 3,
+        }
+    }
+
+    fn from_flags(flags: u8) -> Result<Self, KvError> {
+        if flags & !COMPRESSION_MASK != 0 {
+            return Err(KvError::FrameError(format!(
+                "unknown flags {:#010b}",
+                flags
+            )));
+        }
+        Ok(match flags {
+            0 => Self::None,
+            1 => Self::Gzip,
+            2 => Self::Lz4,
+            _ => Self::Zstd,
+        })
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>, KvError> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Self::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            Self::Zstd => Ok(zstd::encode_all(data, 0)?),
+        }
+    }
+
+    /// Decompress `data`, failing as soon as the output grows beyond `max_len`
+    fn decompress(self, data: &[u8], max_len: usize) -> Result<Vec<u8>, KvError> {
+        let too_large = |len| KvError::FrameTooLarge(len, max_len);
+        let corrupted = |e: std::io::Error| KvError::FrameError(format!("{:?}: {}", self, e));
+        let mut buf = Vec::new();
+        match self {
+            Self::None => buf.extend_from_slice(data),
+            Self::Gzip => {
+                let decoder = GzDecoder::new(data);
+                decoder
+                    .take(max_len as u64 + 1)
+                    .read_to_end(&mut buf)
+                    .map_err(corrupted)?;
+            }
+            Self::Lz4 => {
+                // The decompressed size is prepended, check it before lz4_flex allocates
+                let size = data
+                    .get(..4)
+                    .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+                    .ok_or_else(|| KvError::FrameError("Lz4: missing size".into()))?;
+                if size > max_len {
+                    return Err(too_large(size));
+                }
+                buf = lz4_flex::decompress_size_prepended(data)
+                    .map_err(|e| KvError::FrameError(format!("Lz4: {}", e)))?;
+            }
+            Self::Zstd => {
+                let decoder = zstd::Decoder::new(data).map_err(corrupted)?;
+                decoder
+                    .take(max_len as u64 + 1)
+                    .read_to_end(&mut buf)
+                    .map_err(corrupted)?;
+            }
+        }
+        if buf.len() > max_len {
+            return Err(too_large(buf.len()));
+        }
+        Ok(buf)
+    }
+}
+
+impl FromStr for Compression {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "gzip" => Ok(Self::Gzip),
+            "lz4" => Ok(Self::Lz4),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(KvError::InvalidConfig(format!(
+                "unknown compression `{}`, expected one of none, gzip, lz4 and zstd",
+                s
+            ))),
+        }
+    }
+}
+
+/// How frames are written and which frames are accepted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameOptions {
+    /// Compression of the frames sent, received frames may use any
+    pub compression: Compression,
+    /// Largest payload sent or accepted, before and after decompression
+    pub max_frame_len: usize,
+}
+
+impl Default for FrameOptions {
+    fn default() -> Self {
+        Self {
+            compression: Compression::None,
+            max_frame_len: MAX_FRAME_LEN,
+        }
+    }
+}
+
+/// Encode and decode protobuf messages as frames
+pub trait FrameCoder
+where
+    Self: Message + Default + Sized,
+{
+    /// Append the frame of `self` to `buf`. Nothing is written if the message
+    /// is larger than `options.max_frame_len`.
+    fn encode_frame(&self, buf: &mut BytesMut, options: &FrameOptions) -> Result<(), KvError> {
+        let max_len = options.max_frame_len.min(u32::MAX as usize);
+        let len = self.encoded_len();
+        if len > max_len {
+            return Err(KvError::FrameTooLarge(len, max_len));
+        }
+
+        if options.compression != Compression::None && len > COMPRESSION_THRESHOLD {
+            let mut encoded = Vec::with_capacity(len);
+            self.encode(&mut encoded)?;
+            let compressed = options.compression.compress(&encoded)?;
+            if compressed.len() < len {
+                buf.reserve(FRAME_HEADER_LEN + compressed.len());
+                buf.put_u8(options.compression.flag());
+                buf.put_u32(compressed.len() as u32);
+                buf.put_slice(&compressed);
+                return Ok(());
+            }
+        }
+
+        buf.reserve(FRAME_HEADER_LEN + len);
+        buf.put_u8(Compression::None.flag());
+        buf.put_u32(len as u32);
+        self.encode(buf)?;
+        Ok(())
+    }
+
+    /// Take a whole frame from the front of `buf`, None if it isn't complete yet
+    fn decode_frame(buf: &mut BytesMut, options: &FrameOptions) -> Result<Option<Self>, KvError> {
+        if buf.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+        let compression = Compression::from_flags(buf[0])?;
+        let len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+        if len > options.max_frame_len {
+            return Err(KvError::FrameTooLarge(len, options.max_frame_len));
+        }
+        if buf.len() < FRAME_HEADER_LEN + len {
+            buf.reserve(FRAME_HEADER_LEN + len - buf.len());
+            return Ok(None);
+        }
+
+        buf.advance(FRAME_HEADER_LEN);
+        let payload = buf.split_to(len);
+        let msg = match compression {
+            Compression::None => Self::decode(payload)?,
+            _ => Self::decode(&compression.decompress(&payload, options.max_frame_len)?[..])?,
+        };
+        Ok(Some(msg))
+    }
+}
+
+impl FrameCoder for CommandRequest {}
+impl FrameCoder for CommandResponse {}
+
+/// `tokio_util` codec decoding `In` frames and encoding `Out` frames
+#[derive(Debug)]
+pub struct FrameCodec<In, Out> {
+    options: FrameOptions,
+    _messages: PhantomData<fn(Out) -> In>,
+}
+
+impl<In, Out> FrameCodec<In, Out> {
+    /// A codec with the given options
+    pub fn new(options: FrameOptions) -> Self {
+        Self {
+            options,
+            _messages: PhantomData,
+        }
+    }
+
+    /// The options the codec was created with
+    pub fn options(&self) -> &FrameOptions {
+        &self.options
+    }
+}
+
+impl<In, Out> Default for FrameCodec<In, Out> {
+    fn default() -> Self {
+        Self::new(FrameOptions::default())
+    }
+}
+
+impl<In: FrameCoder, Out> Decoder for FrameCodec<In, Out> {
+    type Item = In;
+    type Error = KvError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<In>, KvError> {
+        In::decode_frame(src, &self.options)
+    }
+}
+
+impl<In, Out: FrameCoder> Encoder<Out> for FrameCodec<In, Out> {
+    type Error = KvError;
+
+    fn encode(&mut self, item: Out, dst: &mut BytesMut) -> Result<(), KvError> {
+        item.encode_frame(dst, &self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Kvpair, Value};
+
+    fn big_response() -> CommandResponse {
+        let pairs: Vec<_> = (0..1000)
+            .map(|i| {
+                Kvpair::new(
+                    format!("key{}", i),
+                    Value::from("a fairly compressible value"),
+                )
+            })
+            .collect();
+        pairs.into()
+    }
+
+    #[test]
+    fn frames_should_round_trip_with_every_compression() {
+        let res = big_response();
+        for compression in [
+            Compression::None,
+            Compression::Gzip,
+            Compression::Lz4,
+            Compression::Zstd,
+        ] {
+            let options = FrameOptions {
+                compression,
+                ..Default::default()
+            };
+            let mut buf = BytesMut::new();
+            res.encode_frame(&mut buf, &options).unwrap();
+            assert_eq!(buf[0], compression.flag());
+            if compression != Compression::None {
+                assert!(buf.len() < res.encoded_len());
+            }
+            let decoded = CommandResponse::decode_frame(&mut buf, &options).unwrap();
+            assert_eq!(decoded, Some(res.clone()));
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn small_messages_should_not_be_compressed() {
+        let options = FrameOptions {
+            compression: Compression::Zstd,
+            ..Default::default()
+        };
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let mut buf = BytesMut::new();
+        cmd.encode_frame(&mut buf, &options).unwrap();
+        assert_eq!(buf[0], Compression::None.flag());
+        assert_eq!(buf.len(), FRAME_HEADER_LEN + cmd.encoded_len());
+    }
+
+    #[test]
+    fn partial_frames_should_wait_for_more_data() {
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        let mut full = BytesMut::new();
+        cmd.encode_frame(&mut full, &FrameOptions::default())
+            .unwrap();
+
+        let mut buf = BytesMut::new();
+        for byte in &full[..full.len() - 1] {
+            buf.put_u8(*byte);
+            let decoded = CommandRequest::decode_frame(&mut buf, &FrameOptions::default());
+            assert_eq!(decoded, Ok(None));
+        }
+        buf.put_u8(full[full.len() - 1]);
+        let decoded = CommandRequest::decode_frame(&mut buf, &FrameOptions::default());
+        assert_eq!(decoded, Ok(Some(cmd)));
+    }
+
+    #[test]
+    fn oversized_frames_should_be_rejected_before_allocating() {
+        let mut buf = BytesMut::new();
+        buf.put_u8(0);
+        buf.put_u32(u32::MAX);
+        let err = CommandRequest::decode_frame(&mut buf, &FrameOptions::default()).unwrap_err();
+        assert_eq!(
+            err,
+            KvError::FrameTooLarge(u32::MAX as usize, MAX_FRAME_LEN)
+        );
+        assert!(buf.capacity() < 1024);
+
+        let options = FrameOptions {
+            max_frame_len: 1024,
+            ..Default::default()
+        };
+        let err = big_response().encode_frame(&mut buf, &options).unwrap_err();
+        assert!(matches!(err, KvError::FrameTooLarge(_, 1024)));
+    }
+
+    #[test]
+    fn decompressed_size_should_be_limited() {
+        let res = big_response();
+        let len = res.encoded_len();
+        for compression in [Compression::Gzip, Compression::Lz4, Compression::Zstd] {
+            let sender = FrameOptions {
+                compression,
+                ..Default::default()
+            };
+            let mut buf = BytesMut::new();
+            res.encode_frame(&mut buf, &sender).unwrap();
+
+            // The compressed frame fits, but not what it expands to
+            let receiver = FrameOptions {
+                max_frame_len: len - 1,
+                ..Default::default()
+            };
+            let err = CommandResponse::decode_frame(&mut buf, &receiver).unwrap_err();
+            assert!(matches!(err, KvError::FrameTooLarge(_, max) if max == len - 1));
+        }
+    }
+
+    #[test]
+    fn unknown_flags_should_be_rejected() {
+        let mut buf = BytesMut::new();
+        buf.put_u8(0b100);
+        buf.put_u32(0);
+        let err = CommandRequest::decode_frame(&mut buf, &FrameOptions::default()).unwrap_err();
+        assert!(matches!(err, KvError::FrameError(_)));
+
+        let mut buf = BytesMut::new();
+        buf.put_u8(Compression::Zstd.flag());
+        buf.put_u32(3);
+        buf.put_slice(b"bad");
+        let err = CommandRequest::decode_frame(&mut buf, &FrameOptions::default()).unwrap_err();
+        assert!(matches!(err, KvError::FrameError(_)));
+    }
+}
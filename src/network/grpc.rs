@@ -0,0 +1,255 @@
+use super::{accept_loop, spawn_execute, spawn_streaming};
+use crate::{
+    command_request::RequestData,
+    kv_service_server::{KvService, KvServiceServer},
+    CommandRequest, CommandResponse, CreateTable, DropTable, GeneralConfig, Hcas, Hdecrby, Hdel,
+    Hdelifeq, Hexists, Hexpire, Hget, Hgetall, Hincrby, Hincrbyfloat, Hmdel, Hmexists, Hmget,
+    Hmset, Hpersist, Hprefix, Hrange, Hscan, Hset, Hsetex, Hsetnx, Httl, KvError, Kvpair,
+    ListTables, Publish, RenameTable, Service, Storage, Transaction, TruncateTable,
+};
+use futures::{future, stream, Future, Stream, StreamExt};
+use hyper::server::conn::Http;
+use std::pin::Pin;
+use tokio::net::TcpListener;
+use tonic::{Code, Request, Response, Status};
+use tracing::info;
+
+/// gRPC front-end of a `Service`: every RPC runs its command through
+/// `Service::execute`, so the hooks and the storage are the same as over TCP.
+///
+/// Like the other front-ends, a failed command is a successful RPC whose
+/// `CommandResponse` carries the status; only `HgetallStream` fails the RPC itself.
+pub struct GrpcService<Store> {
+    service: Service<Store>,
+}
+
+impl<Store: Storage> GrpcService<Store> {
+    /// Serve `service` over gRPC
+    pub fn new(service: Service<Store>) -> Self {
+        Self { service }
+    }
+}
+
+impl<Store> GrpcService<Store>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    async fn run(&self, data: RequestData) -> CommandResponse {
+        let cmd = CommandRequest {
+            request_data: Some(data),
+            ..Default::default()
+        };
+        spawn_execute(&self.service, cmd).await
+    }
+}
+
+/// The RPCs that map one to one to a `RequestData` variant
+macro_rules! kv_service {
+    ($($method:ident($msg:ident) => $variant:ident),* $(,)?) => {
+        #[tonic::async_trait]
+        impl<Store> KvService for GrpcService<Store>
+        where
+            Store: Storage + Send + Sync + 'static,
+        {
+            async fn execute(
+                &self,
+                req: Request<CommandRequest>,
+            ) -> Result<Response<CommandResponse>, Status> {
+                Ok(Response::new(spawn_execute(&self.service, req.into_inner()).await))
+            }
+
+            $(
+                async fn $method(
+                    &self,
+                    req: Request<$msg>,
+                ) -> Result<Response<CommandResponse>, Status> {
+                    Ok(Response::new(self.run(RequestData::$variant(req.into_inner())).await))
+                }
+            )*
+
+            type HgetallStreamStream =
+                Pin<Box<dyn Stream<Item = Result<Kvpair, Status>> + Send + 'static>>;
+
+            async fn hgetall_stream(
+                &self,
+                req: Request<Hgetall>,
+            ) -> Result<Response<Self::HgetallStreamStream>, Status> {
+                let cmd = CommandRequest {
+                    request_data: Some(RequestData::Hgetall(req.into_inner())),
+                    ..Default::default()
+                };
+                // gRPC has its own limit on the size of messages
+                let mut responses = spawn_streaming(self.service.clone(), cmd, usize::MAX);
+                let first = match responses.next().await {
+                    Some(res) if !failed(&res) => res,
+                    Some(res) => return Err(status_of(&res)),
+                    None => return Err(Status::internal("the command failed")),
+                };
+                let pairs = stream::once(future::ready(first))
+                    .chain(responses)
+                    .flat_map(|res| {
+                        let items: Vec<_> = if failed(&res) {
+                            vec![Err(status_of(&res))]
+                        } else {
+                            res.pairs.into_iter().map(Ok).collect()
+                        };
+                        stream::iter(items)
+                    });
+                Ok(Response::new(Box::pin(pairs)))
+            }
+        }
+    };
+}
+
+kv_service! {
+    hget(Hget) => Hget,
+    hgetall(Hgetall) => Hgetall,
+    hmget(Hmget) => Hmget,
+    hset(Hset) => Hset,
+    hmset(Hmset) => Hmset,
+    hdel(Hdel) => Hdel,
+    hmdel(Hmdel) => Hmdel,
+    hexists(Hexists) => Hexists,
+    hmexists(Hmexists) => Hmexists,
+    hrange(Hrange) => Hrange,
+    hprefix(Hprefix) => Hprefix,
+    hsetex(Hsetex) => Hsetex,
+    hexpire(Hexpire) => Hexpire,
+    httl(Httl) => Httl,
+    hpersist(Hpersist) => Hpersist,
+    create_table(CreateTable) => CreateTable,
+    drop_table(DropTable) => DropTable,
+    list_tables(ListTables) => ListTables,
+    rename_table(RenameTable) => RenameTable,
+    truncate_table(TruncateTable) => TruncateTable,
+    transaction(Transaction) => Transaction,
+    hsetnx(Hsetnx) => Hsetnx,
+    hcas(Hcas) => Hcas,
+    hdelifeq(Hdelifeq) => Hdelifeq,
+    hincrby(Hincrby) => Hincrby,
+    hincrbyfloat(Hincrbyfloat) => Hincrbyfloat,
+    hdecrby(Hdecrby) => Hdecrby,
+    hscan(Hscan) => Hscan,
+    publish(Publish) => Publish,
+}
+
+fn failed(res: &CommandResponse) -> bool {
+    !(200..300).contains(&res.status)
+}
+
+/// The gRPC status closest to the HTTP status of a failed response
+fn status_of(res: &CommandResponse) -> Status {
+    let code = match res.status {
+        400 => Code::InvalidArgument,
+        404 => Code::NotFound,
+        409 => Code::Aborted,
+        501 => Code::Unimplemented,
+        507 => Code::ResourceExhausted,
+        _ => Code::Internal,
+    };
+    Status::new(code, res.message.clone())
+}
+
+/// Accept gRPC clients on `listener` until `shutdown` resolves, with the same
+/// limits as `serve`
+pub async fn serve_grpc<Store>(
+    listener: TcpListener,
+    service: Service<Store>,
+    config: &GeneralConfig,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), KvError>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    // hyper writes the responses without telling when they are flushed
+    if service.has_after_send() {
+        return Err(KvError::InvalidConfig(
+            "after-send hooks can't be used with the gRPC gateway".into(),
+        ));
+    }
+    info!("Accepting gRPC clients on {:?}", listener.local_addr()?);
+    let server = KvServiceServer::new(GrpcService::new(service));
+    let mut http = Http::new();
+    http.http2_only(true);
+    accept_loop(listener, config.max_connections, shutdown, |stream| {
+        let conn = http.serve_connection(stream, server.clone());
+        async move { conn.await.map_err(|e| KvError::IoError(e.to_string())) }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{kv_service_client::KvServiceClient, MemTable, ServiceInner, Value};
+    use futures::StreamExt;
+    use tonic::transport::Channel;
+
+    #[tokio::test]
+    async fn unary_rpcs_should_execute_commands() {
+        let mut client = start().await;
+        let hset = Hset {
+            table: "t1".into(),
+            pair: Some(Kvpair::new("k1", "v1".into())),
+        };
+        let res = client.hset(hset).await.unwrap().into_inner();
+        assert_eq!(res.status, 200);
+
+        let hget = Hget {
+            table: "t1".into(),
+            key: "k1".into(),
+        };
+        let res = client.hget(hget).await.unwrap().into_inner();
+        assert_eq!(res.values, vec![Value::from("v1")]);
+
+        let cmd = CommandRequest::new_hget("t1", "k2");
+        let res = client.execute(cmd).await.unwrap().into_inner();
+        assert_eq!(res.status, 404);
+    }
+
+    #[tokio::test]
+    async fn hgetall_stream_should_stream_pairs() {
+        let mut client = start().await;
+        let pairs = vec![Kvpair::new("a", 1.into()), Kvpair::new("b", 2.into())];
+        let cmd = CommandRequest::new_hmset("t1", pairs.clone());
+        client.execute(cmd).await.unwrap();
+
+        let hgetall = Hgetall { table: "t1".into() };
+        let stream = client.hgetall_stream(hgetall).await.unwrap().into_inner();
+        let mut streamed: Vec<_> = stream.map(|pair| pair.unwrap()).collect().await;
+        streamed.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(streamed, pairs);
+    }
+
+    #[test]
+    fn failed_responses_should_map_to_status_codes() {
+        let res = CommandResponse::from(KvError::NotFound("t1".into(), "k1".into()));
+        assert_eq!(status_of(&res).code(), Code::NotFound);
+        let res = CommandResponse::from(KvError::InvalidCommand("bad".into()));
+        assert_eq!(status_of(&res).code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn after_send_hooks_should_be_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let service: Service = ServiceInner::new(MemTable::new())
+            .fn_after_send(|_| {})
+            .into();
+        let config = GeneralConfig::default();
+        let res = serve_grpc(listener, service, &config, futures::future::pending()).await;
+        assert!(matches!(res, Err(KvError::InvalidConfig(_))));
+    }
+
+    async fn start() -> KvServiceClient<Channel> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(async move {
+            let config = GeneralConfig::default();
+            serve_grpc(listener, service, &config, futures::future::pending()).await
+        });
+        KvServiceClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap()
+    }
+}
@@ -0,0 +1,284 @@
+use super::{accept_loop, spawn_execute};
+use crate::{CommandRequest, CommandResponse, GeneralConfig, KvError, Service, Storage, Value};
+use futures::Future;
+use hyper::{
+    body::HttpBody, header, server::conn::Http, service::service_fn, Body, Method, Request,
+    Response, StatusCode,
+};
+use percent_encoding::percent_decode_str;
+use std::{collections::HashMap, convert::Infallible};
+use tokio::net::TcpListener;
+use tracing::{debug, info};
+
+/// Largest request body accepted
+const MAX_BODY_LEN: usize = 64 * 1024 * 1024;
+
+/// Accept HTTP clients on `listener` until `shutdown` resolves, with the same
+/// limits as `serve`. The routes are:
+///
+/// - `POST /v1/command` with a JSON `CommandRequest` as the body
+/// - `GET /v1/tables`, and `PUT` or `DELETE /v1/tables/{table}`
+/// - `GET /v1/tables/{table}/keys`, filtered by the `prefix`, or the `start` and
+///   `end` query parameters, with optional `limit` and `reverse`
+/// - `GET`, `PUT` or `DELETE /v1/tables/{table}/keys/{key}`, the body of `PUT` is a
+///   JSON value and an optional `ttl_ms` query parameter makes it expire
+///
+/// Every response is a JSON `CommandResponse`, with its status as the HTTP status.
+pub async fn serve_http<Store>(
+    listener: TcpListener,
+    service: Service<Store>,
+    config: &GeneralConfig,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), KvError>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    // hyper writes the responses without telling when they are flushed
+    if service.has_after_send() {
+        return Err(KvError::InvalidConfig(
+            "after-send hooks can't be used with the HTTP gateway".into(),
+        ));
+    }
+    info!("Accepting HTTP clients on {:?}", listener.local_addr()?);
+    let mut http = Http::new();
+    http.http1_only(true);
+    if let Some(idle) = config.idle_timeout() {
+        http.http1_header_read_timeout(idle);
+    }
+    accept_loop(listener, config.max_connections, shutdown, |stream| {
+        let service = service.clone();
+        let conn = http.serve_connection(
+            stream,
+            service_fn(move |req| {
+                let service = service.clone();
+                async move { Ok::<_, Infallible>(handle(&service, req).await) }
+            }),
+        );
+        async move { conn.await.map_err(|e| KvError::IoError(e.to_string())) }
+    })
+    .await
+}
+
+async fn handle<Store>(service: &Service<Store>, req: Request<Body>) -> Response<Body>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    debug!("Got a new HTTP request: {} {}", req.method(), req.uri());
+    let res = route(service, req)
+        .await
+        .unwrap_or_else(CommandResponse::from);
+    let status =
+        StatusCode::from_u16(res.status as u16).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let body = serde_json::to_vec(&res).expect("a response can always be encoded");
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.into())
+        .expect("the response is valid")
+}
+
+async fn route<Store>(
+    service: &Service<Store>,
+    req: Request<Body>,
+) -> Result<CommandResponse, KvError>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    let segments = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| percent_decode_str(s).decode_utf8().map(|s| s.into_owned()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| bad_request("the path is not valid UTF-8"))?;
+    let query: HashMap<String, String> = req
+        .uri()
+        .query()
+        .map(|q| form_urlencoded::parse(q.as_bytes()).into_owned().collect())
+        .unwrap_or_default();
+    let segments: Vec<_> = segments.iter().map(String::as_str).collect();
+    let method = req.method().clone();
+
+    let cmd = match (&method, segments.as_slice()) {
+        (&Method::POST, ["v1", "command"]) => {
+            let body = read_body(req).await?;
+            serde_json::from_slice::<CommandRequest>(&body)?
+        }
+        (&Method::GET, ["v1", "tables"]) => CommandRequest::new_list_tables(),
+        (&Method::PUT, ["v1", "tables", table]) => CommandRequest::new_create_table(*table),
+        (&Method::DELETE, ["v1", "tables", table]) => CommandRequest::new_drop_table(*table),
+        (&Method::GET, ["v1", "tables", table, "keys"]) => scan(table, &query)?,
+        (&Method::GET, ["v1", "tables", table, "keys", key]) => {
+            CommandRequest::new_hget(*table, *key)
+        }
+        (&Method::PUT, ["v1", "tables", table, "keys", key]) => {
+            let body = read_body(req).await?;
+            let value: Value = serde_json::from_slice(&body)?;
+            match query.get("ttl_ms") {
+                Some(ttl) => {
+                    CommandRequest::new_hsetex(*table, *key, value, number(ttl, "ttl_ms")?)
+                }
+                None => CommandRequest::new_hset(*table, *key, value),
+            }
+        }
+        (&Method::DELETE, ["v1", "tables", table, "keys", key]) => {
+            CommandRequest::new_hdel(*table, *key)
+        }
+        _ => {
+            return Ok(CommandResponse {
+                status: StatusCode::NOT_FOUND.as_u16() as _,
+                message: format!("No route for {} {}", method, segments.join("/")),
+                ..Default::default()
+            })
+        }
+    };
+    Ok(spawn_execute(service, cmd).await)
+}
+
+/// HPREFIX if there's a `prefix`, HRANGE if there's a bound or a limit, HGETALL otherwise
+fn scan(table: &str, query: &HashMap<String, String>) -> Result<CommandRequest, KvError> {
+    let limit = match query.get("limit") {
+        Some(limit) => number(limit, "limit")?,
+        None => 0,
+    };
+    let reverse = match query.get("reverse").map(String::as_str) {
+        None | Some("false") => false,
+        Some("true") | Some("") => true,
+        Some(_) => return Err(bad_request("reverse must be true or false")),
+    };
+    let get = |name: &str| query.get(name).cloned().unwrap_or_default();
+    if let Some(prefix) = query.get("prefix") {
+        Ok(CommandRequest::new_hprefix(
+            table,
+            prefix.as_str(),
+            limit,
+            reverse,
+        ))
+    } else if query.contains_key("start") || query.contains_key("end") || limit > 0 || reverse {
+        Ok(CommandRequest::new_hrange(
+            table,
+            get("start"),
+            get("end"),
+            limit,
+            reverse,
+        ))
+    } else {
+        Ok(CommandRequest::new_hgetall(table))
+    }
+}
+
+async fn read_body(req: Request<Body>) -> Result<Vec<u8>, KvError> {
+    let mut body = req.into_body();
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| KvError::IoError(e.to_string()))?;
+        if buf.len() + chunk.len() > MAX_BODY_LEN {
+            return Err(KvError::FrameTooLarge(
+                buf.len() + chunk.len(),
+                MAX_BODY_LEN,
+            ));
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf)
+}
+
+fn number<T: std::str::FromStr>(s: &str, name: &str) -> Result<T, KvError> {
+    s.parse()
+        .map_err(|_| bad_request(&format!("{} must be a non-negative integer", name)))
+}
+
+fn bad_request(msg: &str) -> KvError {
+    KvError::InvalidCommand(msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, ServiceInner};
+
+    async fn call(
+        service: &Service,
+        method: Method,
+        uri: &str,
+        body: &str,
+    ) -> (StatusCode, serde_json::Value) {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::from(body.to_owned()))
+            .unwrap();
+        let res = handle(service, req).await;
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn after_send_hooks_should_be_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let service: Service = ServiceInner::new(MemTable::new())
+            .fn_after_send(|_| {})
+            .into();
+        let config = GeneralConfig::default();
+        let res = serve_http(listener, service, &config, futures::future::pending()).await;
+        assert!(matches!(res, Err(KvError::InvalidConfig(_))));
+    }
+
+    #[tokio::test]
+    async fn command_route_should_execute_json_requests() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let body =
+            r#"{"hset": {"table": "t1", "pair": {"key": "k1", "value": {"binary": "yv4="}}}}"#;
+        let (status, _) = call(&service, Method::POST, "/v1/command", body).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let body = r#"{"hget": {"table": "t1", "key": "k1"}}"#;
+        let (status, res) = call(&service, Method::POST, "/v1/command", body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(res["values"], serde_json::json!([{ "binary": "yv4=" }]));
+
+        let (status, res) = call(&service, Method::POST, "/v1/command", "{").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(res["message"].as_str().unwrap().contains("JSON"));
+    }
+
+    #[tokio::test]
+    async fn rest_routes_should_map_to_commands() {
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        let (status, _) = call(&service, Method::PUT, "/v1/tables/t1/keys/a%2Fb", "42").await;
+        assert_eq!(status, StatusCode::OK);
+        call(
+            &service,
+            Method::PUT,
+            "/v1/tables/t1/keys/c?ttl_ms=60000",
+            "1.5",
+        )
+        .await;
+
+        let (status, res) = call(&service, Method::GET, "/v1/tables/t1/keys/a%2Fb", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(res["values"], serde_json::json!([42]));
+
+        let (_, res) = call(&service, Method::GET, "/v1/tables/t1/keys?prefix=a", "").await;
+        assert_eq!(
+            res["pairs"],
+            serde_json::json!([{ "key": "a/b", "value": 42 }])
+        );
+        let (_, res) = call(&service, Method::GET, "/v1/tables", "").await;
+        assert_eq!(res["values"], serde_json::json!(["t1"]));
+
+        let (status, _) = call(&service, Method::DELETE, "/v1/tables/t1/keys/a%2Fb", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call(&service, Method::GET, "/v1/tables/t1/keys/a%2Fb", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = call(&service, Method::PUT, "/v1/tables/t1", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call(&service, Method::GET, "/v2/whatever", "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(&service, Method::GET, "/v1/tables/t1/keys?limit=x", "").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,720 @@
+mod client;
+mod frame;
+mod grpc;
+mod http;
+mod multiplex;
+mod pipeline;
+mod resp;
+mod tls;
+
+pub use self::http::*;
+pub use client::*;
+pub use frame::*;
+pub use grpc::*;
+pub use multiplex::*;
+pub use pipeline::*;
+pub use resp::*;
+pub use tls::*;
+
+use crate::{
+    command_request::RequestData, is_subscription, AfterSend, CommandRequest, CommandResponse,
+    ConnectionInfo, GeneralConfig, KvError, Service, Storage, Subscriber,
+};
+use futures::{
+    stream::{self, BoxStream, SelectAll},
+    Future, SinkExt, Stream, StreamExt,
+};
+use prost::Message;
+use std::{io, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Semaphore},
+    task, time,
+};
+use tokio_util::codec::Framed;
+use tracing::{debug, info, warn};
+
+/// Most requests with an id running at once on a connection, the next ones
+/// are read once one of them completes
+const MAX_IN_FLIGHT: usize = 128;
+
+/// Most messages pushed to a subscriber in a single frame
+const PUSH_BATCH: usize = 64;
+
+/// How long the listeners wait after failing to accept a connection for lack of resources
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// 处理服务器端的某个 accept 下来的 socket 的读写
+pub struct ProstServerStream<S, Store> {
+    inner: Framed<S, FrameCodec<CommandRequest, CommandResponse>>,
+    service: Service<Store>,
+    conn: ConnectionInfo,
+    idle_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+/// The request a response answers, kept only for the after-send hooks
+type Answered = Option<Arc<CommandRequest>>;
+
+impl<S, Store> ProstServerStream<S, Store>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    Store: Storage + Send + Sync + 'static,
+{
+    /// Wrap an accepted stream, with no timeouts and the default frame options
+    pub fn new(stream: S, service: Service<Store>) -> Self {
+        Self {
+            inner: Framed::new(stream, FrameCodec::default()),
+            service,
+            conn: ConnectionInfo::default(),
+            idle_timeout: None,
+            write_timeout: None,
+        }
+    }
+
+    /// Describe the connection to the after-send hooks
+    pub fn with_connection_info(mut self, conn: ConnectionInfo) -> Self {
+        self.conn = conn;
+        self
+    }
+
+    /// Close the connection after `idle` without a request, and fail a response
+    /// that takes longer than `write` to send
+    pub fn with_timeouts(mut self, idle: Option<Duration>, write: Option<Duration>) -> Self {
+        self.idle_timeout = idle;
+        self.write_timeout = write;
+        self
+    }
+
+    /// Compress the responses and limit the frame size as told by `options`
+    pub fn with_frame_options(mut self, options: FrameOptions) -> Self {
+        *self.inner.codec_mut() = FrameCodec::new(options);
+        self
+    }
+
+    /// Serve requests until the client disconnects or stays idle for too long
+    ///
+    /// Requests with a `request_id` run concurrently and are answered as soon as
+    /// they complete, tagged with their id. A request without one waits for
+    /// those in flight, so clients sending one request at a time see no change.
+    ///
+    /// HGETALL is answered with a stream of responses, see `Service::execute_streaming`.
+    ///
+    /// Once the connection subscribes, the messages published to its channels
+    /// and the changes of the keys it watches are pushed to it between the
+    /// responses. A subscriber disconnected for being slow gets
+    /// `KvError::SlowSubscriber` before the connection closes.
+    ///
+    /// The after-send hooks of the service are called once every frame is
+    /// flushed, or failed to be.
+    pub async fn process(mut self) -> Result<(), KvError> {
+        let mut in_flight = SelectAll::new();
+        let mut subscriber: Option<Subscriber> = None;
+        loop {
+            // Only a connection with nothing running or subscribed is idle
+            let idle = self
+                .idle_timeout
+                .filter(|_| in_flight.is_empty() && subscriber.is_none());
+            tokio::select! {
+                Some((request, res)) = in_flight.next() => self.reply(request, res).await?,
+                message = async { subscriber.as_mut()?.recv().await }, if subscriber.is_some() => {
+                    match message {
+                        Some(message) => {
+                            // Whatever else is queued goes in the same frame
+                            let mut batch = vec![message];
+                            while batch.len() < PUSH_BATCH {
+                                match subscriber.as_mut().and_then(Subscriber::try_recv) {
+                                    Some(message) => batch.push(message),
+                                    None => break,
+                                }
+                            }
+                            self.reply(None, batch.into()).await?
+                        }
+                        None => {
+                            warn!("Disconnecting a slow subscriber");
+                            return self.reply(None, KvError::SlowSubscriber.into()).await;
+                        }
+                    }
+                }
+                // Any watching connection may notice the watched keys expiring, once
+                _ = self.service.expiry_due(), if subscriber.as_ref().is_some_and(Subscriber::is_watching) => {
+                    self.service.notify_expired()
+                }
+                next = self.inner.next(), if in_flight.len() < MAX_IN_FLIGHT => {
+                    let cmd = match next {
+                        Some(cmd) => cmd?,
+                        None => break,
+                    };
+                    debug!("Got a new command: {:?}", cmd);
+                    let request = self.service.has_after_send().then(|| Arc::new(cmd.clone()));
+                    if is_subscription(&cmd) {
+                        let request_id = cmd.request_id;
+                        let mut sub = subscriber.take().unwrap_or_else(|| self.service.subscriber());
+                        let mut res = self.service.execute_subscription(cmd, &mut sub);
+                        res.request_id = request_id;
+                        // Without subscriptions left, the connection is a plain one again
+                        subscriber = Some(sub).filter(|sub| sub.subscriptions() > 0);
+                        self.reply(request, res).await?;
+                        continue;
+                    }
+                    let max_len = self.inner.codec().options().max_frame_len;
+                    if cmd.request_id != 0 {
+                        let responses = spawn_streaming(self.service.clone(), cmd, max_len);
+                        in_flight.push(responses.map(move |res| (request.clone(), res)).boxed());
+                        continue;
+                    }
+                    while let Some((request, res)) = in_flight.next().await {
+                        self.reply(request, res).await?;
+                    }
+                    if matches!(cmd.request_data, Some(RequestData::Hgetall(_))) {
+                        let mut responses = spawn_streaming(self.service.clone(), cmd, max_len);
+                        while let Some(res) = responses.next().await {
+                            self.reply(request.clone(), res).await?;
+                        }
+                    } else {
+                        let res = spawn_execute(&self.service, cmd).await;
+                        self.reply(request, res).await?;
+                    }
+                }
+                _ = time::sleep(idle.unwrap_or_default()), if idle.is_some() => {
+                    debug!("Closing idle connection");
+                    return Ok(());
+                }
+            }
+        }
+
+        // The client is done sending, answer what's still running
+        while let Some((request, res)) = in_flight.next().await {
+            self.reply(request, res).await?;
+        }
+        Ok(())
+    }
+
+    async fn reply(&mut self, request: Answered, res: CommandResponse) -> Result<(), KvError> {
+        let request_id = res.request_id;
+        match self.send(request.as_deref(), res).await {
+            // Nothing was written, the client gets an error instead
+            Err(e @ KvError::FrameTooLarge(_, _)) => {
+                warn!("Failed to send the response: {}", e);
+                let mut res = CommandResponse::from(e);
+                res.request_id = request_id;
+                self.send(request.as_deref(), res).await
+            }
+            res => res,
+        }
+    }
+
+    async fn send(
+        &mut self,
+        request: Option<&CommandRequest>,
+        res: CommandResponse,
+    ) -> Result<(), KvError> {
+        // The frame takes the response, the hooks get a copy
+        let response = self.service.has_after_send().then(|| res.clone());
+        let outcome = match self.write_timeout {
+            Some(write) => time::timeout(write, self.inner.send(res))
+                .await
+                .map_err(|_| KvError::IoError("timed out sending the response".into()))
+                .and_then(|sent| sent),
+            None => self.inner.send(res).await,
+        };
+        if let Some(response) = &response {
+            self.service.after_send(&AfterSend {
+                conn: &self.conn,
+                request,
+                response,
+                outcome: outcome.as_ref().copied(),
+            });
+        }
+        outcome
+    }
+}
+
+/// Execute `cmd` on the blocking pool, since storage is synchronous
+///
+/// A command that panics gets an error.
+pub(crate) async fn spawn_execute<Store>(
+    service: &Service<Store>,
+    cmd: CommandRequest,
+) -> CommandResponse
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    let service = service.clone();
+    match task::spawn_blocking(move || service.execute(cmd)).await {
+        Ok(res) => res,
+        Err(_) => KvError::Internal("the command failed".into()).into(),
+    }
+}
+
+/// Responses to buffer for a stream before its producer waits for them to be sent
+const STREAM_BUFFER: usize = 4;
+
+/// Execute `cmd` with `Service::execute_streaming` on the blocking pool, since
+/// storage is synchronous, and stream its responses tagged with its request id
+///
+/// The responses are sized to fit in `max_len`, one still larger than that
+/// ends the stream with an error, and so does a command that panics.
+pub(crate) fn spawn_streaming<Store>(
+    service: Service<Store>,
+    cmd: CommandRequest,
+    max_len: usize,
+) -> BoxStream<'static, CommandResponse>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    let request_id = cmd.request_id;
+    let tagged = move |mut res: CommandResponse| {
+        res.request_id = request_id;
+        res
+    };
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+    task::spawn_blocking(move || {
+        for res in service.execute_streaming(cmd, max_len) {
+            let len = res.encoded_len();
+            let res = if len > max_len {
+                KvError::FrameTooLarge(len, max_len).into()
+            } else {
+                res
+            };
+            let last = !res.more;
+            // Stop walking the table once the connection is gone
+            if tx.blocking_send(tagged(res)).is_err() || last {
+                break;
+            }
+        }
+    });
+
+    // A stream cut short gets an error as its last response
+    stream::unfold(Some(rx), move |rx| async move {
+        let mut rx = rx?;
+        match rx.recv().await {
+            Some(res) if res.more => Some((res, Some(rx))),
+            Some(res) => Some((res, None)),
+            None => {
+                let err = KvError::Internal("the command failed".into());
+                Some((tagged(err.into()), None))
+            }
+        }
+    })
+    .boxed()
+}
+
+/// 处理客户端 socket 的读写，一次发送一个请求并等待它的响应
+pub struct ProstClientStream<S> {
+    inner: Framed<S, FrameCodec<CommandResponse, CommandRequest>>,
+}
+
+impl<S> ProstClientStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    /// Wrap a connected stream, with the default frame options
+    pub fn new(stream: S) -> Self {
+        Self::with_frame_options(stream, FrameOptions::default())
+    }
+
+    /// Wrap a connected stream, compressing the requests and limiting the frame
+    /// size as told by `options`
+    pub fn with_frame_options(stream: S, options: FrameOptions) -> Self {
+        Self {
+            inner: Framed::new(stream, FrameCodec::new(options)),
+        }
+    }
+
+    /// Send a request and wait for its response. The pairs of a stream of
+    /// responses are gathered into its first response.
+    pub async fn execute(&mut self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        self.inner.send(cmd).await?;
+        let mut res = self.next_response().await?;
+        while res.more {
+            let next = self.next_response().await?;
+            res.merge_chunk(next);
+        }
+        Ok(res)
+    }
+
+    /// Send a request and return its responses as they come, for HGETALL to
+    /// walk large tables without holding all their pairs
+    pub async fn execute_streaming(
+        &mut self,
+        cmd: CommandRequest,
+    ) -> Result<impl Stream<Item = Result<CommandResponse, KvError>> + '_, KvError> {
+        self.inner.send(cmd).await?;
+        Ok(stream::unfold(Some(self), |this| async move {
+            let this = this?;
+            match this.next_response().await {
+                Ok(res) if res.more => Some((Ok(res), Some(this))),
+                res => Some((res, None)),
+            }
+        }))
+    }
+
+    async fn next_response(&mut self) -> Result<CommandResponse, KvError> {
+        match self.inner.next().await {
+            Some(res) => res,
+            None => Err(KvError::IoError("the server closed the connection".into())),
+        }
+    }
+}
+
+/// Accept connections on `listener` until `shutdown` resolves
+///
+/// At most `config.max_connections` clients are served at once, the others wait in
+/// the listen backlog until a connection closes.
+pub async fn serve<Store>(
+    listener: TcpListener,
+    service: Service<Store>,
+    config: &GeneralConfig,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), KvError>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    let options = ConnectionOptions::from(config);
+    accept_loop(listener, config.max_connections, shutdown, |stream| {
+        let conn = ConnectionInfo {
+            peer_addr: stream.peer_addr().ok(),
+            ..Default::default()
+        };
+        process_connection(stream, service.clone(), conn, options)
+    })
+    .await
+}
+
+/// How accepted connections of the TCP protocol are served
+#[derive(Clone, Copy)]
+struct ConnectionOptions {
+    idle: Option<Duration>,
+    write: Option<Duration>,
+    frame: FrameOptions,
+    multiplex: bool,
+}
+
+impl From<&GeneralConfig> for ConnectionOptions {
+    fn from(config: &GeneralConfig) -> Self {
+        Self {
+            idle: config.idle_timeout(),
+            write: config.write_timeout(),
+            frame: config.frame_options(),
+            multiplex: config.multiplex,
+        }
+    }
+}
+
+/// Serve requests on a connection, or on every stream of its yamux session if
+/// multiplexing is enabled
+async fn process_connection<S, Store>(
+    stream: S,
+    service: Service<Store>,
+    conn: ConnectionInfo,
+    options: ConnectionOptions,
+) -> Result<(), KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    Store: Storage + Send + Sync + 'static,
+{
+    let ConnectionOptions {
+        idle,
+        write,
+        frame,
+        multiplex,
+    } = options;
+    if !multiplex {
+        return ProstServerStream::new(stream, service)
+            .with_connection_info(conn)
+            .with_timeouts(idle, write)
+            .with_frame_options(frame)
+            .process()
+            .await;
+    }
+    let conn = ConnectionInfo {
+        multiplexed: true,
+        ..conn
+    };
+    serve_yamux(stream, idle, |stream| {
+        ProstServerStream::new(stream, service.clone())
+            .with_connection_info(conn.clone())
+            .with_timeouts(idle, write)
+            .with_frame_options(frame)
+            .process()
+    })
+    .await
+}
+
+/// Hand every accepted connection to `handle`, with at most `max_connections` of
+/// them running at once. Failing to accept a connection never stops the loop.
+async fn accept_loop<F, Fut>(
+    listener: TcpListener,
+    max_connections: usize,
+    shutdown: impl Future<Output = ()>,
+    handle: F,
+) -> Result<(), KvError>
+where
+    F: Fn(TcpStream) -> Fut,
+    Fut: Future<Output = Result<(), KvError>> + Send + 'static,
+{
+    let permits = Arc::new(Semaphore::new(max_connections));
+    tokio::pin!(shutdown);
+    loop {
+        let permit = tokio::select! {
+            permit = permits.clone().acquire_owned() => {
+                permit.expect("the semaphore is never closed")
+            }
+            _ = &mut shutdown => break,
+        };
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = &mut shutdown => break,
+        };
+        let (stream, addr) = match accepted {
+            Ok(accepted) => accepted,
+            // The client went away before it was accepted
+            Err(e) if is_connection_error(&e) => {
+                debug!("Failed to accept a connection: {}", e);
+                continue;
+            }
+            // Out of file descriptors or memory, give the open connections time to close
+            Err(e) => {
+                warn!("Failed to accept a connection: {}", e);
+                tokio::select! {
+                    _ = time::sleep(ACCEPT_BACKOFF) => continue,
+                    _ = &mut shutdown => break,
+                }
+            }
+        };
+        info!("Client {:?} connected", addr);
+        if let Err(e) = stream.set_nodelay(true) {
+            warn!("Failed to set TCP_NODELAY for {:?}: {}", addr, e);
+        }
+
+        let process = handle(stream);
+        tokio::spawn(async move {
+            if let Err(e) = process.await {
+                warn!("Failed to process the stream of {:?}: {}", addr, e);
+            }
+            info!("Client {:?} disconnected", addr);
+            drop(permit);
+        });
+    }
+    info!("Stopped accepting connections");
+    Ok(())
+}
+
+/// Errors of a single connection, as opposed to the ones of the listener
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::Interrupted
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Kvpair, MemTable, ServiceInner, Value};
+    use std::net::SocketAddr;
+
+    type ClientStream = Framed<TcpStream, FrameCodec<CommandResponse, CommandRequest>>;
+
+    #[tokio::test]
+    async fn server_should_serve_requests() {
+        let addr = start_server(GeneralConfig::default()).await;
+        let mut client = connect(addr).await;
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        client.send(cmd).await.unwrap();
+        let res = client.next().await.unwrap().unwrap();
+        assert_eq!(res.status, 200);
+
+        client
+            .send(CommandRequest::new_hget("t1", "k1"))
+            .await
+            .unwrap();
+        let res = client.next().await.unwrap().unwrap();
+        assert_eq!(res.values, vec![Value::from("v1")]);
+    }
+
+    #[tokio::test]
+    async fn idle_connections_should_be_closed() {
+        let config = GeneralConfig {
+            idle_timeout_secs: 1,
+            ..Default::default()
+        };
+        let addr = start_server(config).await;
+        let mut client = connect(addr).await;
+        assert!(client.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn oversized_responses_should_become_errors() {
+        let config = GeneralConfig {
+            max_frame_len: 1024,
+            ..Default::default()
+        };
+        let addr = start_server(config).await;
+        let mut client = connect(addr).await;
+        let value = Value::from("value".repeat(20));
+        for i in 0..100 {
+            let cmd = CommandRequest::new_hset("t1", format!("key{}", i), value.clone());
+            client.send(cmd).await.unwrap();
+            client.next().await.unwrap().unwrap();
+        }
+
+        let keys = (0..20).map(|i| format!("key{}", i)).collect();
+        client
+            .send(CommandRequest::new_hmget("t1", keys))
+            .await
+            .unwrap();
+        let res = client.next().await.unwrap().unwrap();
+        assert_eq!(res.status, 413);
+
+        // HGETALL is streamed in chunks small enough for the frame limit
+        client
+            .send(CommandRequest::new_hgetall("t1"))
+            .await
+            .unwrap();
+        let mut count = 0;
+        loop {
+            let res = client.next().await.unwrap().unwrap();
+            assert_eq!(res.status, 200);
+            assert!(res.pairs.len() < 100);
+            count += res.pairs.len();
+            if !res.more {
+                break;
+            }
+        }
+        assert_eq!(count, 100);
+
+        // The connection is still usable
+        let cmd = CommandRequest::new_hget("t1", "key1");
+        client.send(cmd).await.unwrap();
+        let res = client.next().await.unwrap().unwrap();
+        assert_eq!(res.values, vec![value]);
+    }
+
+    #[tokio::test]
+    async fn hgetall_should_be_streamed() {
+        let addr = start_server(GeneralConfig::default()).await;
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut client = ProstClientStream::new(stream);
+        let value = Value::from("v".repeat(1000));
+        let pairs: Vec<_> = (0..200)
+            .map(|i| Kvpair::new(format!("k{}", i), value.clone()))
+            .collect();
+        client
+            .execute(CommandRequest::new_hmset("t1", pairs))
+            .await
+            .unwrap();
+
+        let responses: Vec<_> = client
+            .execute_streaming(CommandRequest::new_hgetall("t1"))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(responses.len() > 1);
+        let count: usize = responses
+            .iter()
+            .map(|res| res.as_ref().unwrap().pairs.len())
+            .sum();
+        assert_eq!(count, 200);
+
+        // Without streaming, the pairs are gathered and the connection is still in sync
+        let res = client
+            .execute(CommandRequest::new_hgetall("t1"))
+            .await
+            .unwrap();
+        assert_eq!((res.pairs.len(), res.more), (200, false));
+        let res = client
+            .execute(CommandRequest::new_hget("t1", "k1"))
+            .await
+            .unwrap();
+        assert_eq!(res.values, vec![value]);
+    }
+
+    #[tokio::test]
+    async fn extra_connections_should_wait_for_a_permit() {
+        let config = GeneralConfig {
+            max_connections: 1,
+            ..Default::default()
+        };
+        let addr = start_server(config).await;
+        let mut first = connect(addr).await;
+        let mut second = connect(addr).await;
+
+        second
+            .send(CommandRequest::new_list_tables())
+            .await
+            .unwrap();
+        let waiting = time::timeout(Duration::from_millis(200), second.next()).await;
+        assert!(waiting.is_err());
+
+        // Closing the first connection lets the second one in
+        first.close().await.unwrap();
+        drop(first);
+        let res = second.next().await.unwrap().unwrap();
+        assert_eq!(res.status, 200);
+    }
+
+    #[tokio::test]
+    async fn after_send_hooks_should_see_flushed_responses() {
+        let sent = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let hook_sent = sent.clone();
+        let service: Service = ServiceInner::new(MemTable::new())
+            .fn_after_send(move |sent: &AfterSend| {
+                hook_sent.lock().unwrap().push((
+                    sent.conn.clone(),
+                    sent.request.cloned(),
+                    sent.response.status,
+                    sent.outcome.is_ok(),
+                ));
+            })
+            .into();
+        let addr = start_service(GeneralConfig::default(), service).await;
+        let mut client = connect(addr).await;
+        let local_addr = client.get_ref().local_addr().unwrap();
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        client.send(cmd.clone()).await.unwrap();
+        client.next().await.unwrap().unwrap();
+        // The first response was flushed before the second request was read
+        client
+            .send(CommandRequest::new_hget("t1", "k1"))
+            .await
+            .unwrap();
+        client.next().await.unwrap().unwrap();
+
+        let sent = sent.lock().unwrap();
+        let (conn, request, status, delivered) = &sent[0];
+        assert_eq!(conn.peer_addr, Some(local_addr));
+        assert!(!conn.tls && !conn.multiplexed);
+        assert_eq!(request.as_ref(), Some(&cmd));
+        assert_eq!(*status, 200);
+        assert!(delivered);
+    }
+
+    async fn start_server(config: GeneralConfig) -> SocketAddr {
+        start_service(config, ServiceInner::new(MemTable::new()).into()).await
+    }
+
+    async fn start_service(config: GeneralConfig, service: Service) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            serve(listener, service, &config, futures::future::pending())
+                .await
+                .unwrap()
+        });
+        addr
+    }
+
+    async fn connect(addr: SocketAddr) -> ClientStream {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        Framed::new(stream, FrameCodec::default())
+    }
+}
@@ -0,0 +1,187 @@
+use crate::KvError;
+use futures::{Future, StreamExt};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time,
+};
+use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
+use tracing::{debug, warn};
+use yamux::{Config, Connection, ConnectionError, Control, Mode};
+
+/// A stream multiplexed over a yamux session
+pub type YamuxStream = Compat<yamux::Stream>;
+
+/// Client side of a yamux session, opening any number of concurrent streams
+/// over a single connection
+///
+/// Each stream speaks the same framed protocol as a plain connection, one
+/// request at a time, so a client gets concurrency by opening more streams
+/// rather than more connections.
+#[derive(Clone)]
+pub struct YamuxCtrl {
+    ctrl: Control,
+}
+
+impl YamuxCtrl {
+    /// Start a session over a connected stream. The session is driven by a
+    /// background task until the connection closes.
+    pub fn new_client<S>(stream: S) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let conn = Connection::new(stream.compat(), Config::default(), Mode::Client);
+        let ctrl = conn.control();
+        tokio::spawn(async move {
+            let streams = yamux::into_stream(conn);
+            tokio::pin!(streams);
+            // Servers never open streams, so there's nothing to do but drive the session
+            while let Some(stream) = streams.next().await {
+                if let Err(e) = stream {
+                    debug!("The yamux session failed: {}", e);
+                    break;
+                }
+            }
+        });
+        Self { ctrl }
+    }
+
+    /// Open a new stream, failing if the session is closed
+    pub async fn open_stream(&mut self) -> Result<YamuxStream, KvError> {
+        let stream = self.ctrl.open_stream().await.map_err(session_error)?;
+        Ok(stream.compat())
+    }
+}
+
+/// Accept the streams a client opens over `stream` and hand each of them to `handle`
+///
+/// The session is closed once no stream has been open for `idle`.
+pub(crate) async fn serve_yamux<S, F, Fut>(
+    stream: S,
+    idle: Option<Duration>,
+    handle: F,
+) -> Result<(), KvError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    F: Fn(YamuxStream) -> Fut,
+    Fut: Future<Output = Result<(), KvError>> + Send + 'static,
+{
+    let conn = Connection::new(stream.compat(), Config::default(), Mode::Server);
+    let streams = yamux::into_stream(conn);
+    tokio::pin!(streams);
+    // Every running stream holds a clone
+    let active = Arc::new(());
+    loop {
+        let next = match idle {
+            Some(idle) => match time::timeout(idle, streams.next()).await {
+                Ok(next) => next,
+                Err(_) if Arc::strong_count(&active) > 1 => continue,
+                Err(_) => {
+                    debug!("Closing idle yamux session");
+                    return Ok(());
+                }
+            },
+            None => streams.next().await,
+        };
+        let stream = match next {
+            Some(stream) => stream.map_err(session_error)?,
+            None => return Ok(()),
+        };
+
+        let process = handle(stream.compat());
+        let running = active.clone();
+        tokio::spawn(async move {
+            if let Err(e) = process.await {
+                warn!("Failed to process a yamux stream: {}", e);
+            }
+            drop(running);
+        });
+    }
+}
+
+fn session_error(e: ConnectionError) -> KvError {
+    KvError::IoError(format!("yamux session: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CommandRequest, MemTable, ProstClientStream, ProstServerStream, Service, ServiceInner,
+        Value,
+    };
+    use futures::future;
+    use std::net::SocketAddr;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn streams_should_run_concurrently_over_one_connection() {
+        let addr = start_server(None).await;
+        let stream = TcpStream::connect(addr).await.unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut ctrl = YamuxCtrl::new_client(stream);
+
+        // A stream that is waiting doesn't hold the others back
+        let _waiting = ctrl.open_stream().await.unwrap();
+        let mut clients = Vec::new();
+        for _ in 0..10 {
+            clients.push(ProstClientStream::new(ctrl.open_stream().await.unwrap()));
+        }
+        let results = future::join_all(clients.iter_mut().enumerate().map(|(i, client)| {
+            let cmd = CommandRequest::new_hset("t1", format!("k{}", i), (i as i64).into());
+            client.execute(cmd)
+        }))
+        .await;
+        assert!(results.into_iter().all(|res| res.unwrap().status == 200));
+
+        let res = clients[0]
+            .execute(CommandRequest::new_hget("t1", "k9"))
+            .await
+            .unwrap();
+        assert_eq!(res.values, vec![Value::from(9)]);
+    }
+
+    #[tokio::test]
+    async fn idle_sessions_should_be_closed() {
+        let addr = start_server(Some(Duration::from_millis(200))).await;
+        let stream = TcpStream::connect(addr).await.unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut ctrl = YamuxCtrl::new_client(stream);
+
+        // Streams in use keep the session alive
+        let mut client = ProstClientStream::new(ctrl.open_stream().await.unwrap());
+        for _ in 0..3 {
+            let res = client.execute(CommandRequest::new_list_tables()).await;
+            assert_eq!(res.unwrap().status, 200);
+            time::sleep(Duration::from_millis(100)).await;
+        }
+
+        // Once its streams are closed for being idle, the session goes too
+        drop(client);
+        time::sleep(Duration::from_millis(700)).await;
+        let res = match ctrl.open_stream().await {
+            Ok(stream) => {
+                let mut client = ProstClientStream::new(stream);
+                client.execute(CommandRequest::new_list_tables()).await
+            }
+            Err(e) => Err(e),
+        };
+        assert!(res.is_err());
+    }
+
+    async fn start_server(idle: Option<Duration>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve_yamux(stream, idle, |stream| {
+                ProstServerStream::new(stream, service.clone())
+                    .with_timeouts(idle, None)
+                    .process()
+            })
+            .await
+        });
+        addr
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{CommandRequest, CommandResponse, FrameCodec, FrameOptions, KvError};
+use futures::{SinkExt, StreamExt};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{mpsc, oneshot},
+};
+use tokio_util::codec::Framed;
+use tracing::{debug, warn};
+
+/// Requests queued for sending before `execute` waits
+const QUEUE_LEN: usize = 128;
+
+type Reply = oneshot::Sender<Result<CommandResponse, KvError>>;
+
+/// Client side of a connection pipelining requests: any number of them can be
+/// in flight at once, and the responses, which may come back in any order, are
+/// matched to their requests by `request_id`
+///
+/// Clones share the connection, which is driven by a background task until the
+/// last clone is dropped or the connection fails.
+#[derive(Clone)]
+pub struct PipelinedClientStream {
+    requests: mpsc::Sender<(CommandRequest, Reply)>,
+}
+
+impl PipelinedClientStream {
+    /// Start pipelining over a connected stream
+    pub fn new<S>(stream: S, options: FrameOptions) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (requests, queued) = mpsc::channel(QUEUE_LEN);
+        let framed = Framed::new(stream, FrameCodec::new(options));
+        tokio::spawn(run(framed, queued));
+        Self { requests }
+    }
+
+    /// Send a request and wait for its response. The `request_id` of `cmd` is
+    /// replaced by one unique to the connection.
+    pub async fn execute(&self, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        let (reply, response) = oneshot::channel();
+        self.requests
+            .send((cmd, reply))
+            .await
+            .map_err(|_| closed())?;
+        response.await.map_err(|_| closed())?
+    }
+
+    /// Whether the connection failed or was closed by the server
+    pub fn is_closed(&self) -> bool {
+        self.requests.is_closed()
+    }
+}
+
+/// Send the queued requests and dispatch the responses as they come, until every
+/// handle is dropped or the connection fails
+async fn run<S>(
+    framed: Framed<S, FrameCodec<CommandResponse, CommandRequest>>,
+    mut queued: mpsc::Receiver<(CommandRequest, Reply)>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    let (mut sink, mut stream) = framed.split();
+    // The response gathered so far for a stream of responses
+    let pending: Mutex<HashMap<u64, (Reply, Option<CommandResponse>)>> = Mutex::new(HashMap::new());
+    let pending_requests = || pending.lock().unwrap_or_else(PoisonError::into_inner);
+
+    // Sending and receiving run side by side, so a large request being written
+    // never keeps the responses from being read
+    let send = async {
+        let mut next_id = 0u64;
+        while let Some((mut cmd, reply)) = queued.recv().await {
+            next_id = next_id.checked_add(1).unwrap_or(1);
+            cmd.request_id = next_id;
+            // Registered first, the response may be read before `send` returns
+            pending_requests().insert(next_id, (reply, None));
+            sink.send(cmd).await?;
+        }
+        Ok(())
+    };
+    let receive = async {
+        while let Some(res) = stream.next().await {
+            let res = res?;
+            let mut pending = pending_requests();
+            let (reply, gathered) = match pending.remove(&res.request_id) {
+                Some(entry) => entry,
+                None => {
+                    warn!("Got a response to unknown request {}", res.request_id);
+                    continue;
+                }
+            };
+            let res = match gathered {
+                Some(mut gathered) => {
+                    gathered.merge_chunk(res);
+                    gathered
+                }
+                None => res,
+            };
+            if res.more {
+                pending.insert(res.request_id, (reply, Some(res)));
+            } else {
+                // The caller may have given up waiting
+                reply.send(Ok(res)).ok();
+            }
+        }
+        Err(KvError::IoError("the server closed the connection".into()))
+    };
+
+    let result: Result<(), KvError> = tokio::select! {
+        res = send => res,
+        res = receive => res,
+    };
+    if let Err(e) = result {
+        debug!("The pipelined connection failed: {}", e);
+        // Closing the queue first makes new requests fail right away
+        queued.close();
+        for (_, (reply, _)) in pending_requests().drain() {
+            reply.send(Err(KvError::IoError(e.to_string()))).ok();
+        }
+    }
+}
+
+fn closed() -> KvError {
+    KvError::IoError("the connection is closed".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        command_request::RequestData, serve, GeneralConfig, Kvpair, MemTable, Service,
+        ServiceInner, Value,
+    };
+    use futures::future;
+    use std::{net::SocketAddr, thread, time::Duration};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn responses_should_come_back_as_they_complete() {
+        let addr = start_server().await;
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut framed = Framed::new(stream, FrameCodec::<CommandResponse, _>::default());
+
+        let mut slow = CommandRequest::new_hget("t1", "slow");
+        slow.request_id = 1;
+        let mut fast = CommandRequest::new_hget("t1", "fast");
+        fast.request_id = 2;
+        framed.send(slow).await.unwrap();
+        framed.send(fast).await.unwrap();
+
+        let first = framed.next().await.unwrap().unwrap();
+        let second = framed.next().await.unwrap().unwrap();
+        assert_eq!((first.request_id, second.request_id), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn requests_without_ids_should_be_answered_in_order() {
+        let addr = start_server().await;
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut framed = Framed::new(stream, FrameCodec::<CommandResponse, _>::default());
+
+        let mut slow = CommandRequest::new_hget("t1", "slow");
+        slow.request_id = 1;
+        framed.send(slow).await.unwrap();
+        framed
+            .send(CommandRequest::new_hget("t1", "fast"))
+            .await
+            .unwrap();
+
+        let first = framed.next().await.unwrap().unwrap();
+        let second = framed.next().await.unwrap().unwrap();
+        assert_eq!((first.request_id, second.request_id), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn pipelined_client_should_match_responses() {
+        let addr = start_server().await;
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let client = PipelinedClientStream::new(stream, FrameOptions::default());
+
+        let sets = (0..100).map(|i| {
+            let cmd = CommandRequest::new_hset("t1", format!("k{}", i), Value::from(i as i64));
+            client.execute(cmd)
+        });
+        for res in future::join_all(sets).await {
+            assert_eq!(res.unwrap().status, 200);
+        }
+        let gets =
+            (0..100).map(|i| client.execute(CommandRequest::new_hget("t1", format!("k{}", i))));
+        for (i, res) in future::join_all(gets).await.into_iter().enumerate() {
+            assert_eq!(res.unwrap().values, vec![Value::from(i as i64)]);
+        }
+    }
+
+    #[tokio::test]
+    async fn streamed_responses_should_be_gathered() {
+        let addr = start_server().await;
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let client = PipelinedClientStream::new(stream, FrameOptions::default());
+        let value = Value::from("v".repeat(1000));
+        let pairs: Vec<_> = (0..200)
+            .map(|i| Kvpair::new(format!("k{}", i), value.clone()))
+            .collect();
+        client
+            .execute(CommandRequest::new_hmset("t1", pairs))
+            .await
+            .unwrap();
+
+        let (all, one) = future::join(
+            client.execute(CommandRequest::new_hgetall("t1")),
+            client.execute(CommandRequest::new_hget("t1", "k1")),
+        )
+        .await;
+        assert_eq!(all.unwrap().pairs.len(), 200);
+        assert_eq!(one.unwrap().values, vec![value]);
+    }
+
+    #[tokio::test]
+    async fn pending_requests_should_fail_when_the_connection_closes() {
+        // A server that reads a request and hangs up
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut framed = Framed::new(
+                stream,
+                FrameCodec::<CommandRequest, CommandResponse>::default(),
+            );
+            framed.next().await;
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let client = PipelinedClientStream::new(stream, FrameOptions::default());
+        let err = client
+            .execute(CommandRequest::new_list_tables())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, KvError::IoError(_)), "{:?}", err);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(client.is_closed());
+    }
+
+    /// Reading the key `slow` takes a while
+    fn slow_down(cmd: &CommandRequest) {
+        if let Some(RequestData::Hget(hget)) = &cmd.request_data {
+            if hget.key == "slow" {
+                thread::sleep(Duration::from_millis(200));
+            }
+        }
+    }
+
+    async fn start_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service: Service = ServiceInner::new(MemTable::new())
+            .fn_received(slow_down)
+            .into();
+        tokio::spawn(async move {
+            serve(
+                listener,
+                service,
+                &GeneralConfig::default(),
+                future::pending(),
+            )
+            .await
+        });
+        addr
+    }
+}
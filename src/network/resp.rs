@@ -0,0 +1,723 @@
+use super::{accept_loop, spawn_execute};
+use crate::{
+    value, AfterSend, CommandRequest, CommandResponse, ConnectionInfo, GeneralConfig, KvError,
+    Kvpair, Service, Storage, Value,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::Future;
+use http::StatusCode;
+use std::{mem, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    time,
+};
+use tracing::{debug, info};
+
+/// Largest bulk string accepted in a request
+const MAX_BULK_LEN: usize = 64 * 1024 * 1024;
+/// Largest inline request, or length line of an array or a bulk string
+const MAX_INLINE_LEN: usize = 64 * 1024;
+/// Largest number of arguments accepted in a request
+const MAX_ARGS: usize = 1024 * 1024;
+
+/// A RESP reply, encoded as RESP2 or RESP3 depending on the connection
+#[derive(Clone, Debug, PartialEq)]
+enum RespFrame {
+    /// `+OK`
+    Simple(String),
+    /// `-ERR message`, the message starts with the error code
+    Error(String),
+    /// `:1`
+    Integer(i64),
+    /// `$3 foo`
+    Bulk(Bytes),
+    /// `$-1` in RESP2, `_` in RESP3
+    Null,
+    /// `*2 ...`
+    Array(Vec<RespFrame>),
+    /// `%1 ...` in RESP3, a flat array of keys and values in RESP2
+    Map(Vec<(RespFrame, RespFrame)>),
+}
+
+impl RespFrame {
+    /// Encode the frame into `buf`, using RESP3 if `resp3` is set
+    fn encode(&self, buf: &mut BytesMut, resp3: bool) {
+        match self {
+            Self::Simple(s) => put_line(buf, b'+', s),
+            Self::Error(e) => put_line(buf, b'-', e),
+            Self::Integer(i) => put_line(buf, b':', &i.to_string()),
+            Self::Bulk(b) => {
+                put_line(buf, b'$', &b.len().to_string());
+                buf.put_slice(b);
+                buf.put_slice(b"\r\n");
+            }
+            Self::Null if resp3 => buf.put_slice(b"_\r\n"),
+            Self::Null => buf.put_slice(b"$-1\r\n"),
+            Self::Array(frames) => {
+                put_line(buf, b'*', &frames.len().to_string());
+                frames.iter().for_each(|f| f.encode(buf, resp3));
+            }
+            Self::Map(pairs) => {
+                match resp3 {
+                    true => put_line(buf, b'%', &pairs.len().to_string()),
+                    false => put_line(buf, b'*', &(pairs.len() * 2).to_string()),
+                }
+                for (k, v) in pairs {
+                    k.encode(buf, resp3);
+                    v.encode(buf, resp3);
+                }
+            }
+        }
+    }
+
+    fn ok() -> Self {
+        Self::Simple("OK".into())
+    }
+
+    fn error(msg: impl std::fmt::Display) -> Self {
+        Self::Error(format!("ERR {}", msg))
+    }
+
+    fn bulk(s: impl Into<String>) -> Self {
+        Self::Bulk(Bytes::from(s.into()))
+    }
+}
+
+fn put_line(buf: &mut BytesMut, prefix: u8, line: &str) {
+    buf.put_u8(prefix);
+    buf.put_slice(line.as_bytes());
+    buf.put_slice(b"\r\n");
+}
+
+/// Parses requests out of the read buffer, as arrays of bulk strings or inline
+/// commands. Bulk strings are split off the buffer as soon as they are complete,
+/// so the bytes of a request that arrives in many reads are not copied again.
+#[derive(Debug, Default)]
+struct RespParser {
+    /// The bulk strings of the array being received, and how many are still missing
+    pending: Option<(Vec<Bytes>, usize)>,
+    /// How much of the buffer was searched for a line ending without finding one
+    scanned: usize,
+}
+
+impl RespParser {
+    /// Parse the next request from the front of `buf`, return None if it's not
+    /// complete yet
+    fn parse(&mut self, buf: &mut BytesMut) -> Result<Option<Vec<Bytes>>, KvError> {
+        if self.pending.is_none() {
+            match buf.first() {
+                None => return Ok(None),
+                Some(b'*') => {
+                    let (n, next) =
+                        match find_line(&mut self.scanned, buf, 1, "too big mbulk count string")? {
+                            Some((line, next)) => (parse_number::<i64>(line)?, next),
+                            None => return Ok(None),
+                        };
+                    if n > MAX_ARGS as i64 {
+                        return Err(protocol_error("invalid multibulk length"));
+                    }
+                    buf.advance(next);
+                    let n = n.max(0) as usize;
+                    // The count comes from the client, only allocate for what arrives
+                    self.pending = Some((Vec::with_capacity(n.min(64)), n));
+                }
+                Some(_) => {
+                    let (args, next) =
+                        match find_line(&mut self.scanned, buf, 0, "too big inline request")? {
+                            Some((line, next)) => {
+                                let args = line
+                                    .split(|b| b.is_ascii_whitespace())
+                                    .filter(|arg| !arg.is_empty())
+                                    .map(Bytes::copy_from_slice)
+                                    .collect();
+                                (args, next)
+                            }
+                            None => return Ok(None),
+                        };
+                    buf.advance(next);
+                    return Ok(Some(args));
+                }
+            }
+        }
+
+        let (args, missing) = self.pending.as_mut().expect("an array is being parsed");
+        while *missing > 0 {
+            match buf.first() {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(&b) => {
+                    return Err(protocol_error(format!("expected '$', got '{}'", b as char)))
+                }
+            }
+            let (len, start) =
+                match find_line(&mut self.scanned, buf, 1, "too big bulk count string")? {
+                    Some((line, start)) => (parse_number::<usize>(line)?, start),
+                    None => return Ok(None),
+                };
+            if len > MAX_BULK_LEN {
+                return Err(protocol_error("invalid bulk length"));
+            }
+            let end = start + len;
+            if buf.len() < end + 2 {
+                return Ok(None);
+            }
+            if &buf[end..end + 2] != b"\r\n" {
+                return Err(protocol_error("bulk string is not terminated by CRLF"));
+            }
+            buf.advance(start);
+            args.push(buf.split_to(len).freeze());
+            buf.advance(2);
+            *missing -= 1;
+        }
+        Ok(self.pending.take().map(|(args, _)| args))
+    }
+}
+
+/// The line starting at `start` without its line ending, and the position after
+/// it. A line longer than `MAX_INLINE_LEN` fails with `too_big`, and `scanned`
+/// remembers how far a line without its end was searched
+fn find_line<'a>(
+    scanned: &mut usize,
+    buf: &'a [u8],
+    start: usize,
+    too_big: &str,
+) -> Result<Option<(&'a [u8], usize)>, KvError> {
+    let from = (*scanned).max(start);
+    match buf
+        .get(from..)
+        .and_then(|rest| rest.iter().position(|b| *b == b'\n'))
+    {
+        Some(i) => {
+            *scanned = 0;
+            let end = from + i;
+            let line = &buf[start..end];
+            Ok(Some((line.strip_suffix(b"\r").unwrap_or(line), end + 1)))
+        }
+        None if buf.len() > start + MAX_INLINE_LEN => Err(protocol_error(too_big)),
+        None => {
+            *scanned = buf.len();
+            Ok(None)
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(line: &[u8]) -> Result<T, KvError> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| protocol_error("invalid length"))
+}
+
+fn protocol_error(msg: impl std::fmt::Display) -> KvError {
+    KvError::InvalidCommand(format!("Protocol error: {}", msg))
+}
+
+/// Serve Redis clients on an accepted socket, translating the hash commands into
+/// `CommandRequest`s
+pub struct RespServerStream<S, Store> {
+    stream: S,
+    service: Service<Store>,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+    parser: RespParser,
+    resp3: bool,
+    idle_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    conn: ConnectionInfo,
+    // Requests answered in `write_buf`, kept for the after-send hooks until it's flushed
+    unsent: Vec<(CommandRequest, CommandResponse)>,
+}
+
+impl<S, Store> RespServerStream<S, Store>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    Store: Storage + Send + Sync + 'static,
+{
+    /// Wrap an accepted stream, with no timeouts. It speaks RESP2 until `HELLO 3`
+    pub fn new(stream: S, service: Service<Store>) -> Self {
+        Self {
+            stream,
+            service,
+            read_buf: BytesMut::with_capacity(4096),
+            write_buf: BytesMut::with_capacity(4096),
+            parser: RespParser::default(),
+            resp3: false,
+            idle_timeout: None,
+            write_timeout: None,
+            conn: ConnectionInfo::default(),
+            unsent: Vec::new(),
+        }
+    }
+
+    /// Describe the connection to the after-send hooks
+    pub fn with_connection_info(mut self, conn: ConnectionInfo) -> Self {
+        self.conn = conn;
+        self
+    }
+
+    /// Close the connection after `idle` without a request, and fail a reply that
+    /// takes longer than `write` to send
+    pub fn with_timeouts(mut self, idle: Option<Duration>, write: Option<Duration>) -> Self {
+        self.idle_timeout = idle;
+        self.write_timeout = write;
+        self
+    }
+
+    /// Serve requests until the client disconnects, quits or stays idle for too long
+    pub async fn process(mut self) -> Result<(), KvError> {
+        loop {
+            let args = match self.parser.parse(&mut self.read_buf) {
+                Ok(Some(args)) => args,
+                Ok(None) => {
+                    // Answer the pipelined requests in one write before waiting for more
+                    self.flush().await?;
+                    if !self.fill().await? {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    // The stream can't be resynchronized after a protocol error
+                    RespFrame::error(e).encode(&mut self.write_buf, self.resp3);
+                    return self.flush().await;
+                }
+            };
+            if args.is_empty() {
+                continue;
+            }
+
+            debug!("Got a new RESP command: {:?}", args);
+            let quit = args[0].eq_ignore_ascii_case(b"quit");
+            let reply = self.execute(&args).await;
+            reply.encode(&mut self.write_buf, self.resp3);
+            if quit {
+                return self.flush().await;
+            }
+        }
+    }
+
+    /// Read more bytes, return false if the client closed the connection
+    async fn fill(&mut self) -> Result<bool, KvError> {
+        let read = self.stream.read_buf(&mut self.read_buf);
+        let n = match self.idle_timeout {
+            Some(idle) => match time::timeout(idle, read).await {
+                Ok(n) => n?,
+                Err(_) => {
+                    debug!("Closing idle RESP connection");
+                    return Ok(false);
+                }
+            },
+            None => read.await?,
+        };
+        Ok(n > 0)
+    }
+
+    async fn flush(&mut self) -> Result<(), KvError> {
+        if self.write_buf.is_empty() {
+            return Ok(());
+        }
+        let buf = self.write_buf.split();
+        let write = self.stream.write_all(&buf);
+        let outcome = match self.write_timeout {
+            Some(timeout) => time::timeout(timeout, write)
+                .await
+                .map_err(|_| KvError::IoError("timed out sending the reply".into()))
+                .and_then(|written| written.map_err(KvError::from)),
+            None => write.await.map_err(KvError::from),
+        };
+        for (request, response) in mem::take(&mut self.unsent) {
+            self.service.after_send(&AfterSend {
+                conn: &self.conn,
+                request: Some(&request),
+                response: &response,
+                outcome: outcome.as_ref().copied(),
+            });
+        }
+        outcome
+    }
+
+    async fn execute(&mut self, args: &[Bytes]) -> RespFrame {
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+        match self.dispatch(&name, &args[1..]).await {
+            Ok(frame) | Err(frame) => frame,
+        }
+    }
+
+    async fn dispatch(&mut self, name: &str, args: &[Bytes]) -> Result<RespFrame, RespFrame> {
+        let arity = |ok: bool| match ok {
+            true => Ok(()),
+            false => Err(RespFrame::error(format!(
+                "wrong number of arguments for '{}' command",
+                name
+            ))),
+        };
+        match name {
+            "hget" => {
+                arity(args.len() == 2)?;
+                let cmd = CommandRequest::new_hget(text(&args[0])?, text(&args[1])?);
+                // A missing field is a null reply, not an error
+                let mut res = self.call(cmd).await;
+                if is_success(&res) {
+                    Ok(value_frame(res.values.pop().unwrap_or_default()))
+                } else if res.status == StatusCode::NOT_FOUND.as_u16() as u32 {
+                    Ok(RespFrame::Null)
+                } else {
+                    Err(error_frame(res))
+                }
+            }
+            "hmget" => {
+                arity(args.len() >= 2)?;
+                let cmd = CommandRequest::new_hmget(text(&args[0])?, texts(&args[1..])?);
+                let res = self.request(cmd).await?;
+                Ok(RespFrame::Array(
+                    res.values.into_iter().map(value_frame).collect(),
+                ))
+            }
+            "hset" | "hmset" => {
+                arity(args.len() >= 3 && args.len() % 2 == 1)?;
+                let pairs = args[1..]
+                    .chunks(2)
+                    .map(|pair| Ok(Kvpair::new(text(&pair[0])?, bytes_value(&pair[1]))))
+                    .collect::<Result<Vec<_>, RespFrame>>()?;
+                let cmd = CommandRequest::new_hmset(text(&args[0])?, pairs);
+                let res = self.request(cmd).await?;
+                match name {
+                    "hmset" => Ok(RespFrame::ok()),
+                    // The number of fields that didn't exist before
+                    _ => Ok(count(&res, |v| v.value.is_none())),
+                }
+            }
+            "hgetall" => {
+                arity(args.len() == 1)?;
+                let res = self
+                    .request(CommandRequest::new_hgetall(text(&args[0])?))
+                    .await?;
+                let pairs = res.pairs.into_iter().map(|pair| {
+                    let value = value_frame(pair.value.unwrap_or_default());
+                    (RespFrame::bulk(pair.key), value)
+                });
+                Ok(RespFrame::Map(pairs.collect()))
+            }
+            "hdel" => {
+                arity(args.len() >= 2)?;
+                let cmd = CommandRequest::new_hmdel(text(&args[0])?, texts(&args[1..])?);
+                let res = self.request(cmd).await?;
+                Ok(count(&res, |v| v.value.is_some()))
+            }
+            "hexists" => {
+                arity(args.len() == 2)?;
+                let cmd = CommandRequest::new_hexists(text(&args[0])?, text(&args[1])?);
+                let res = self.request(cmd).await?;
+                Ok(count(&res, |v| v == &Value::from(true)))
+            }
+            "ping" => match args {
+                [] => Ok(RespFrame::Simple("PONG".into())),
+                [msg] => Ok(RespFrame::Bulk(msg.clone())),
+                _ => arity(false).map(|_| RespFrame::Null),
+            },
+            "echo" => {
+                arity(args.len() == 1)?;
+                Ok(RespFrame::Bulk(args[0].clone()))
+            }
+            "hello" => self.hello(args),
+            "quit" => Ok(RespFrame::ok()),
+            // Client libraries call these on connect, the answers don't matter to them
+            "command" => Ok(RespFrame::Array(Vec::new())),
+            "client" | "select" => Ok(RespFrame::ok()),
+            _ => Err(RespFrame::error(format!("unknown command '{}'", name))),
+        }
+    }
+
+    /// `HELLO [protover ...]`, switch the protocol and describe the server
+    fn hello(&mut self, args: &[Bytes]) -> Result<RespFrame, RespFrame> {
+        if let Some(version) = args.first() {
+            match version.as_ref() {
+                b"2" => self.resp3 = false,
+                b"3" => self.resp3 = true,
+                _ => {
+                    return Err(RespFrame::Error(
+                        "NOPROTO unsupported protocol version".into(),
+                    ))
+                }
+            }
+        }
+        let proto = if self.resp3 { 3 } else { 2 };
+        Ok(RespFrame::Map(vec![
+            (RespFrame::bulk("server"), RespFrame::bulk("kv")),
+            (
+                RespFrame::bulk("version"),
+                RespFrame::bulk(env!("CARGO_PKG_VERSION")),
+            ),
+            (RespFrame::bulk("proto"), RespFrame::Integer(proto)),
+            (RespFrame::bulk("mode"), RespFrame::bulk("standalone")),
+            (RespFrame::bulk("role"), RespFrame::bulk("master")),
+            (RespFrame::bulk("modules"), RespFrame::Array(Vec::new())),
+        ]))
+    }
+
+    /// Execute a request, keeping a copy for the after-send hooks if there are any
+    async fn call(&mut self, cmd: CommandRequest) -> CommandResponse {
+        let request = self.service.has_after_send().then(|| cmd.clone());
+        let res = spawn_execute(&self.service, cmd).await;
+        if let Some(request) = request {
+            self.unsent.push((request, res.clone()));
+        }
+        res
+    }
+
+    /// Execute a request, turning a failed one into an error reply
+    async fn request(&mut self, cmd: CommandRequest) -> Result<CommandResponse, RespFrame> {
+        let res = self.call(cmd).await;
+        match is_success(&res) {
+            true => Ok(res),
+            false => Err(error_frame(res)),
+        }
+    }
+}
+
+/// Keys must be UTF-8, unlike values
+fn text(arg: &Bytes) -> Result<String, RespFrame> {
+    String::from_utf8(arg.to_vec())
+        .map_err(|_| RespFrame::error("keys and field names must be valid UTF-8"))
+}
+
+fn texts(args: &[Bytes]) -> Result<Vec<String>, RespFrame> {
+    args.iter().map(text).collect()
+}
+
+/// Redis values are bytes: keep them as strings when they are valid UTF-8
+fn bytes_value(arg: &Bytes) -> Value {
+    match std::str::from_utf8(arg) {
+        Ok(s) => s.into(),
+        Err(_) => Value {
+            value: Some(value::Value::Binary(arg.clone())),
+        },
+    }
+}
+
+/// Every value is a bulk string to a Redis client, numbers are formatted as text
+fn value_frame(v: Value) -> RespFrame {
+    match v.value {
+        Some(value::Value::String(s)) => RespFrame::bulk(s),
+        Some(value::Value::Binary(b)) => RespFrame::Bulk(b),
+        Some(value::Value::Integer(i)) => RespFrame::bulk(i.to_string()),
+        Some(value::Value::Float(f)) => RespFrame::bulk(f.to_string()),
+        Some(value::Value::Bool(b)) => RespFrame::bulk(if b { "1" } else { "0" }),
+        None => RespFrame::Null,
+    }
+}
+
+fn count(res: &CommandResponse, f: impl Fn(&Value) -> bool) -> RespFrame {
+    RespFrame::Integer(res.values.iter().filter(|v| f(v)).count() as i64)
+}
+
+fn is_success(res: &CommandResponse) -> bool {
+    StatusCode::from_u16(res.status as u16).is_ok_and(|status| status.is_success())
+}
+
+fn error_frame(res: CommandResponse) -> RespFrame {
+    RespFrame::error(res.message)
+}
+
+/// Accept Redis clients on `listener` until `shutdown` resolves, with the same
+/// limits as `serve`
+pub async fn serve_resp<Store>(
+    listener: TcpListener,
+    service: Service<Store>,
+    config: &GeneralConfig,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), KvError>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    info!("Accepting Redis clients on {:?}", listener.local_addr()?);
+    let (idle, write) = (config.idle_timeout(), config.write_timeout());
+    accept_loop(listener, config.max_connections, shutdown, |stream| {
+        let conn = ConnectionInfo {
+            peer_addr: stream.peer_addr().ok(),
+            ..Default::default()
+        };
+        RespServerStream::new(stream, service.clone())
+            .with_connection_info(conn)
+            .with_timeouts(idle, write)
+            .process()
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemTable, ServiceInner};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::duplex;
+
+    #[test]
+    fn requests_should_be_parsed() {
+        let mut parser = RespParser::default();
+        let mut buf = BytesMut::from(
+            &b"*3\r\n$4\r\nHGET\r\n$2\r\nt1\r\n$2\r\nk1\r\nPING\r\n*1\r\n$4\r\nPI"[..],
+        );
+        let args = parser.parse(&mut buf).unwrap().unwrap();
+        assert_eq!(args, vec!["HGET", "t1", "k1"]);
+        let args = parser.parse(&mut buf).unwrap().unwrap();
+        assert_eq!(args, vec!["PING"]);
+        assert_eq!(parser.parse(&mut buf).unwrap(), None);
+
+        buf.extend_from_slice(b"NG\r\n");
+        let args = parser.parse(&mut buf).unwrap().unwrap();
+        assert_eq!(args, vec!["PING"]);
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&b"*1\r\n:1\r\n"[..]);
+        assert!(RespParser::default().parse(&mut buf).is_err());
+    }
+
+    #[test]
+    fn partial_requests_should_resume_where_they_stopped() {
+        let mut parser = RespParser::default();
+        let mut buf = BytesMut::new();
+        for chunk in ["*2\r\n$4\r", "\nHGE", "T\r\n$5\r\nhel", "lo\r", "\n"] {
+            assert_eq!(parser.parse(&mut buf).unwrap(), None);
+            buf.extend_from_slice(chunk.as_bytes());
+        }
+        let args = parser.parse(&mut buf).unwrap().unwrap();
+        assert_eq!(args, vec!["HGET", "hello"]);
+        assert!(buf.is_empty());
+
+        // Complete bulk strings are taken out of the buffer right away
+        let mut buf = BytesMut::from(&b"*2\r\n$3\r\nfoo\r\n$3\r\nba"[..]);
+        assert_eq!(parser.parse(&mut buf).unwrap(), None);
+        assert_eq!(&buf[..], b"$3\r\nba");
+    }
+
+    #[test]
+    fn long_inline_requests_should_be_rejected() {
+        let mut buf = BytesMut::from(vec![b'a'; MAX_INLINE_LEN + 1].as_slice());
+        assert!(RespParser::default().parse(&mut buf).is_err());
+
+        let mut buf = BytesMut::from(vec![b'a'; MAX_INLINE_LEN].as_slice());
+        assert_eq!(RespParser::default().parse(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn frames_should_be_encoded() {
+        let frame = RespFrame::Map(vec![(RespFrame::bulk("k"), RespFrame::Null)]);
+        let mut buf = BytesMut::new();
+        frame.encode(&mut buf, false);
+        assert_eq!(&buf[..], b"*2\r\n$1\r\nk\r\n$-1\r\n");
+
+        let mut buf = BytesMut::new();
+        frame.encode(&mut buf, true);
+        assert_eq!(&buf[..], b"%1\r\n$1\r\nk\r\n_\r\n");
+    }
+
+    #[tokio::test]
+    async fn hash_commands_should_be_translated() {
+        let mut client = start();
+        assert_eq!(call(&mut client, "HSET t1 k1 v1 k2 v2\r\n").await, ":2\r\n");
+        assert_eq!(call(&mut client, "HSET t1 k1 v3\r\n").await, ":0\r\n");
+        assert_eq!(call(&mut client, "HGET t1 k1\r\n").await, "$2\r\nv3\r\n");
+        assert_eq!(call(&mut client, "HGET t1 k9\r\n").await, "$-1\r\n");
+        assert_eq!(
+            call(&mut client, "HMGET t1 k2 k9\r\n").await,
+            "*2\r\n$2\r\nv2\r\n$-1\r\n"
+        );
+        assert_eq!(call(&mut client, "HEXISTS t1 k2\r\n").await, ":1\r\n");
+        assert_eq!(call(&mut client, "HDEL t1 k2 k9\r\n").await, ":1\r\n");
+        assert_eq!(
+            call(&mut client, "HGETALL t1\r\n").await,
+            "*2\r\n$2\r\nk1\r\n$2\r\nv3\r\n"
+        );
+        assert_eq!(
+            call(&mut client, "HGET t1\r\n").await,
+            "-ERR wrong number of arguments for 'hget' command\r\n"
+        );
+        assert_eq!(
+            call(&mut client, "FLUSHALL\r\n").await,
+            "-ERR unknown command 'flushall'\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn hello_should_switch_to_resp3() {
+        let mut client = start();
+        let reply = call(&mut client, "*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n").await;
+        assert!(reply.starts_with("%6\r\n"), "{}", reply);
+        assert!(reply.contains("$5\r\nproto\r\n:3\r\n"), "{}", reply);
+
+        call(&mut client, "HSET t1 k1 v1\r\n").await;
+        assert_eq!(
+            call(&mut client, "HGETALL t1\r\n").await,
+            "%1\r\n$2\r\nk1\r\n$2\r\nv1\r\n"
+        );
+        assert_eq!(call(&mut client, "HGET t1 k9\r\n").await, "_\r\n");
+        assert_eq!(
+            call(&mut client, "HELLO 4\r\n").await,
+            "-NOPROTO unsupported protocol version\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn pipelined_requests_should_be_answered_in_order() {
+        let mut client = start();
+        let reply = call(&mut client, "PING\r\nHSET t1 k1 1\r\nHGET t1 k1\r\n").await;
+        assert_eq!(reply, "+PONG\r\n:1\r\n$1\r\n1\r\n");
+    }
+
+    #[tokio::test]
+    async fn after_send_hooks_should_see_flushed_replies() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let hook_sent = sent.clone();
+        let service: Service = ServiceInner::new(MemTable::new())
+            .fn_after_send(move |sent: &AfterSend| {
+                hook_sent.lock().unwrap().push((
+                    sent.request.cloned(),
+                    sent.response.status,
+                    sent.outcome.is_ok(),
+                ));
+            })
+            .into();
+        let (mut client, server) = duplex(4096);
+        tokio::spawn(RespServerStream::new(server, service).process());
+
+        call(&mut client, "PING\r\nHSET t1 k1 1\r\nHGET t1 k2\r\n").await;
+        let sent = sent.lock().unwrap();
+        assert_eq!(
+            *sent,
+            vec![
+                (
+                    Some(CommandRequest::new_hmset(
+                        "t1",
+                        vec![Kvpair::new("k1", "1".into())]
+                    )),
+                    200,
+                    true
+                ),
+                (Some(CommandRequest::new_hget("t1", "k2")), 404, true),
+            ]
+        );
+    }
+
+    fn start() -> tokio::io::DuplexStream {
+        let (client, server) = duplex(4096);
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(RespServerStream::new(server, service).process());
+        client
+    }
+
+    /// Send `req` and read everything that arrives until the client goes quiet
+    async fn call(client: &mut tokio::io::DuplexStream, req: &str) -> String {
+        client.write_all(req.as_bytes()).await.unwrap();
+        let mut reply = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match time::timeout(Duration::from_millis(50), client.read(&mut buf)).await {
+                Ok(Ok(n)) if n > 0 => reply.extend_from_slice(&buf[..n]),
+                _ if !reply.is_empty() => return String::from_utf8(reply).unwrap(),
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use super::{accept_loop, process_connection, ConnectionOptions};
+use crate::{ConnectionInfo, GeneralConfig, KvError, Service, Storage, TlsConfig};
+use futures::Future;
+use std::{fmt, fs, io::Cursor, path::Path, sync::Arc};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    time,
+};
+use tokio_rustls::{
+    client,
+    rustls::{
+        server::AllowAnyAuthenticatedClient, Certificate, ClientConfig, PrivateKey, RootCertStore,
+        ServerConfig, ServerName,
+    },
+    server, TlsAcceptor, TlsConnector,
+};
+use tracing::info;
+
+/// Server side of TLS, optionally requiring a client certificate signed by a CA
+#[derive(Clone)]
+pub struct TlsServerAcceptor {
+    inner: TlsAcceptor,
+}
+
+impl TlsServerAcceptor {
+    /// Build an acceptor from PEM contents. With `client_ca`, clients must present
+    /// a certificate signed by it.
+    pub fn new(cert: &str, key: &str, client_ca: Option<&str>) -> Result<Self, KvError> {
+        let certs = load_certs(cert)?;
+        let key = load_key(key)?;
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match client_ca {
+            Some(ca) => {
+                let roots = load_roots(ca)?;
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(certs, key)
+            .map_err(|e| KvError::TlsError(e.to_string()))?;
+        Ok(Self {
+            inner: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+
+    /// Build an acceptor from the PEM files named in `config`
+    pub fn load(config: &TlsConfig) -> Result<Self, KvError> {
+        let cert = read_pem(&config.cert)?;
+        let key = read_pem(&config.key)?;
+        let client_ca = config.client_ca.as_deref().map(read_pem).transpose()?;
+        Self::new(&cert, &key, client_ca.as_deref())
+    }
+
+    /// Run the server side of the handshake on an accepted stream
+    pub async fn accept<S>(&self, stream: S) -> Result<server::TlsStream<S>, KvError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        Ok(self.inner.accept(stream).await?)
+    }
+}
+
+/// Client side of TLS, optionally presenting a client certificate
+#[derive(Clone)]
+pub struct TlsClientConnector {
+    inner: TlsConnector,
+    domain: ServerName,
+}
+
+impl TlsClientConnector {
+    /// Build a connector from PEM contents. The server certificate must be valid
+    /// for `domain` and signed by `server_ca`; `identity` is the certificate and
+    /// the key presented to servers that authenticate their clients.
+    pub fn new(
+        domain: &str,
+        identity: Option<(&str, &str)>,
+        server_ca: &str,
+    ) -> Result<Self, KvError> {
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(load_roots(server_ca)?);
+        let config = match identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+                .map_err(|e| KvError::TlsError(e.to_string()))?,
+            None => builder.with_no_client_auth(),
+        };
+        let domain = ServerName::try_from(domain)
+            .map_err(|_| KvError::TlsError(format!("invalid server name `{}`", domain)))?;
+        Ok(Self {
+            inner: TlsConnector::from(Arc::new(config)),
+            domain,
+        })
+    }
+
+    /// Run the client side of the handshake on a connected stream
+    pub async fn connect<S>(&self, stream: S) -> Result<client::TlsStream<S>, KvError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        Ok(self.inner.connect(self.domain.clone(), stream).await?)
+    }
+}
+
+impl fmt::Debug for TlsClientConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsClientConnector")
+            .field("domain", &self.domain)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Like `serve`, with every connection going through the TLS handshake first
+///
+/// A handshake that takes longer than the idle timeout is given up.
+pub async fn serve_tls<Store>(
+    listener: TcpListener,
+    service: Service<Store>,
+    config: &GeneralConfig,
+    acceptor: TlsServerAcceptor,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), KvError>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    info!("Accepting TLS clients on {:?}", listener.local_addr()?);
+    let options = ConnectionOptions::from(config);
+    let idle = options.idle;
+    accept_loop(listener, config.max_connections, shutdown, |stream| {
+        let (service, acceptor) = (service.clone(), acceptor.clone());
+        let conn = ConnectionInfo {
+            peer_addr: stream.peer_addr().ok(),
+            tls: true,
+            multiplexed: false,
+        };
+        async move {
+            let stream = match idle {
+                Some(idle) => time::timeout(idle, acceptor.accept(stream))
+                    .await
+                    .map_err(|_| KvError::Timeout("waiting for the TLS handshake"))??,
+                None => acceptor.accept(stream).await?,
+            };
+            process_connection(stream, service, conn, options).await
+        }
+    })
+    .await
+}
+
+fn read_pem(path: &Path) -> Result<String, KvError> {
+    fs::read_to_string(path)
+        .map_err(|e| KvError::TlsError(format!("cannot read {}: {}", path.display(), e)))
+}
+
+fn load_certs(pem: &str) -> Result<Vec<Certificate>, KvError> {
+    let certs = rustls_pemfile::certs(&mut Cursor::new(pem))
+        .map_err(|_| KvError::TlsError("invalid certificate".into()))?;
+    if certs.is_empty() {
+        return Err(KvError::TlsError("no certificate found".into()));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(pem: &str) -> Result<PrivateKey, KvError> {
+    use rustls_pemfile::Item;
+
+    let mut reader = Cursor::new(pem);
+    loop {
+        match rustls_pemfile::read_one(&mut reader) {
+            Ok(Some(Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key))) => {
+                return Ok(PrivateKey(key))
+            }
+            Ok(Some(_)) => continue,
+            Ok(None) => return Err(KvError::TlsError("no private key found".into())),
+            Err(_) => return Err(KvError::TlsError("invalid private key".into())),
+        }
+    }
+}
+
+fn load_roots(pem: &str) -> Result<RootCertStore, KvError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(pem)? {
+        roots
+            .add(&cert)
+            .map_err(|e| KvError::TlsError(format!("invalid CA certificate: {}", e)))?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientOptions, CommandRequest, KvClient, MemTable, ServiceInner};
+    use rcgen::{BasicConstraints, Certificate as CertGen, CertificateParams, IsCa};
+    use std::net::SocketAddr;
+
+    /// A CA with a server certificate for `localhost` and a client certificate,
+    /// all in PEM
+    struct Pki {
+        ca: String,
+        server: (String, String),
+        client: (String, String),
+    }
+
+    impl Pki {
+        fn generate() -> Self {
+            let mut params = CertificateParams::new(vec![]);
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = CertGen::from_params(params).unwrap();
+            let issue = |name: &str| {
+                let cert = CertGen::from_params(CertificateParams::new(vec![name.into()])).unwrap();
+                (
+                    cert.serialize_pem_with_signer(&ca).unwrap(),
+                    cert.serialize_private_key_pem(),
+                )
+            };
+            Self {
+                server: issue("localhost"),
+                client: issue("client"),
+                ca: ca.serialize_pem().unwrap(),
+            }
+        }
+
+        fn connector(&self, identity: bool) -> TlsClientConnector {
+            let identity = identity.then_some((self.client.0.as_str(), self.client.1.as_str()));
+            TlsClientConnector::new("localhost", identity, &self.ca).unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn clients_should_talk_over_tls() {
+        let pki = Pki::generate();
+        let acceptor = TlsServerAcceptor::new(&pki.server.0, &pki.server.1, None).unwrap();
+        let addr = start_server(acceptor).await;
+
+        let client = connect(addr, pki.connector(false));
+        client.hset("t1", "k1", "v1").await.unwrap();
+        assert_eq!(client.hget("t1", "k1").await.unwrap(), Some("v1".into()));
+    }
+
+    #[tokio::test]
+    async fn client_certificates_should_be_required_with_a_client_ca() {
+        let pki = Pki::generate();
+        let acceptor = TlsServerAcceptor::new(&pki.server.0, &pki.server.1, Some(&pki.ca)).unwrap();
+        let addr = start_server(acceptor).await;
+
+        let client = connect(addr, pki.connector(true));
+        let res = client.execute(CommandRequest::new_hget("t1", "k1")).await;
+        assert_eq!(res.unwrap().status, 404);
+
+        // TLS 1.3 servers check the client certificate after the client is done
+        // with the handshake, so the failure shows up on the first request
+        let client = connect(addr, pki.connector(false));
+        assert!(client.hget("t1", "k1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn servers_signed_by_another_ca_should_be_rejected() {
+        let (pki, other) = (Pki::generate(), Pki::generate());
+        let acceptor = TlsServerAcceptor::new(&other.server.0, &other.server.1, None).unwrap();
+        let addr = start_server(acceptor).await;
+
+        let client = connect(addr, pki.connector(false));
+        let err = client.hget("t1", "k1").await.unwrap_err();
+        assert!(matches!(err, KvError::IoError(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn invalid_pem_should_be_rejected() {
+        let pki = Pki::generate();
+        let err = TlsServerAcceptor::new("", &pki.server.1, None).err();
+        assert_eq!(err, Some(KvError::TlsError("no certificate found".into())));
+        let err = TlsServerAcceptor::new(&pki.server.0, &pki.server.0, None).err();
+        assert_eq!(err, Some(KvError::TlsError("no private key found".into())));
+    }
+
+    async fn start_server(acceptor: TlsServerAcceptor) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service: Service = ServiceInner::new(MemTable::new()).into();
+        tokio::spawn(async move {
+            let config = GeneralConfig::default();
+            serve_tls(
+                listener,
+                service,
+                &config,
+                acceptor,
+                futures::future::pending(),
+            )
+            .await
+        });
+        addr
+    }
+
+    fn connect(addr: SocketAddr, tls: TlsClientConnector) -> KvClient {
+        let options = ClientOptions {
+            connect_retries: 0,
+            tls: Some(tls),
+            ..Default::default()
+        };
+        KvClient::new(addr.to_string(), options)
+    }
+}
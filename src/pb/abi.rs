@@ -1,178 +1,2240 @@
 /// 来自客户端的命令请求
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct CommandRequest {
-    #[prost(oneof="command_request::RequestData", tags="1, 2, 3, 4, 5, 6, 7, 8, 9")]
+    /// Copied to the response. Requests with an id are executed concurrently and
+    /// answered as they complete, the ones without wait for their turn.
+    /// Numbered apart from the commands above, which keep growing.
+    #[prost(uint64, tag = "100")]
+    pub request_id: u64,
+    #[prost(
+        oneof = "command_request::RequestData",
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33"
+    )]
     pub request_data: ::core::option::Option<command_request::RequestData>,
 }
 /// Nested message and enum types in `CommandRequest`.
 pub mod command_request {
-    #[derive(PartialOrd)]
+    #[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
     #[derive(Clone, PartialEq, ::prost::Oneof)]
     pub enum RequestData {
         /// Get a key from a table, return with a value
-        #[prost(message, tag="1")]
+        #[prost(message, tag = "1")]
         Hget(super::Hget),
         /// Get all key-value pairs from the table
-        #[prost(message, tag="2")]
+        #[prost(message, tag = "2")]
         Hgetall(super::Hgetall),
         /// Get multiple keys, and return their values
-        #[prost(message, tag="3")]
+        #[prost(message, tag = "3")]
         Hmget(super::Hmget),
         /// Store a key-value pair in a table.
-        /// If the table does not exist, then it will be created.
-        #[prost(message, tag="4")]
+        /// If the table does not exist, then it will be created,
+        /// unless the storage runs in strict mode.
+        #[prost(message, tag = "4")]
         Hset(super::Hset),
         /// Store multiple key-value pairs in a table.
         /// If the table does not exist, then it will be created.
-        #[prost(message, tag="5")]
+        #[prost(message, tag = "5")]
         Hmset(super::Hmset),
         /// Delete a key from a table,
         /// and return the pervious key.
-        #[prost(message, tag="6")]
+        #[prost(message, tag = "6")]
         Hdel(super::Hdel),
         /// Delete multiple keys from a table,
         /// and return the pervious keys.
-        #[prost(message, tag="7")]
+        #[prost(message, tag = "7")]
         Hmdel(super::Hmdel),
         /// Check if the key exists in the table.
-        #[prost(message, tag="8")]
+        #[prost(message, tag = "8")]
         Hexists(super::Hexists),
         /// Check if multiple keys exist in the table.
-        #[prost(message, tag="9")]
+        #[prost(message, tag = "9")]
         Hmexists(super::Hmexists),
+        /// Get the pairs whose keys fall in a range, in lexicographic order
+        #[prost(message, tag = "10")]
+        Hrange(super::Hrange),
+        /// Get the pairs whose keys start with a prefix, in lexicographic order
+        #[prost(message, tag = "11")]
+        Hprefix(super::Hprefix),
+        /// Store a key-value pair that expires after a ttl
+        #[prost(message, tag = "12")]
+        Hsetex(super::Hsetex),
+        /// Set the ttl of an existing key
+        #[prost(message, tag = "13")]
+        Hexpire(super::Hexpire),
+        /// Get the remaining ttl of a key
+        #[prost(message, tag = "14")]
+        Httl(super::Httl),
+        /// Remove the ttl of a key
+        #[prost(message, tag = "15")]
+        Hpersist(super::Hpersist),
+        /// Create an empty table
+        #[prost(message, tag = "16")]
+        CreateTable(super::CreateTable),
+        /// Drop a table with all of its keys
+        #[prost(message, tag = "17")]
+        DropTable(super::DropTable),
+        /// List the names of all tables
+        #[prost(message, tag = "18")]
+        ListTables(super::ListTables),
+        /// Rename a table, the new name must not exist yet
+        #[prost(message, tag = "19")]
+        RenameTable(super::RenameTable),
+        /// Remove all keys from a table
+        #[prost(message, tag = "20")]
+        TruncateTable(super::TruncateTable),
+        /// Execute several commands atomically
+        #[prost(message, tag = "21")]
+        Transaction(super::Transaction),
+        /// Store a key-value pair only if the key does not exist
+        #[prost(message, tag = "22")]
+        Hsetnx(super::Hsetnx),
+        /// Store a key-value pair only if the key holds the expected value
+        #[prost(message, tag = "23")]
+        Hcas(super::Hcas),
+        /// Delete a key only if it holds the expected value
+        #[prost(message, tag = "24")]
+        Hdelifeq(super::Hdelifeq),
+        /// Add to the integer value of a key, and return the new value
+        #[prost(message, tag = "25")]
+        Hincrby(super::Hincrby),
+        /// Add to the float value of a key, and return the new value
+        #[prost(message, tag = "26")]
+        Hincrbyfloat(super::Hincrbyfloat),
+        /// Subtract from the integer value of a key, and return the new value
+        #[prost(message, tag = "27")]
+        Hdecrby(super::Hdecrby),
+        /// Get a page of the pairs of a table, and the cursor of the next page
+        #[prost(message, tag = "28")]
+        Hscan(super::Hscan),
+        /// Subscribe the connection to channels, the messages published to them
+        /// are then pushed to it. Only over TCP.
+        #[prost(message, tag = "29")]
+        Subscribe(super::Subscribe),
+        /// Subscribe the connection to the channels matching glob patterns
+        #[prost(message, tag = "30")]
+        Psubscribe(super::Psubscribe),
+        /// Unsubscribe the connection from channels or patterns
+        #[prost(message, tag = "31")]
+        Unsubscribe(super::Unsubscribe),
+        /// Publish a message to a channel, and return how many subscribers got it
+        #[prost(message, tag = "32")]
+        Publish(super::Publish),
+        /// Stream the changes of the keys of a table, of a key or of the keys
+        /// under a prefix, with their old and new values. Only over TCP.
+        #[prost(message, tag = "33")]
+        Hwatch(super::Hwatch),
     }
 }
 /// 服务器的响应
-#[derive(PartialOrd)]
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct CommandResponse {
     /// 状态码：复用 HTTP 2xx/4xx/5xx 状态码
-    #[prost(uint32, tag="1")]
+    #[prost(uint32, tag = "1")]
     pub status: u32,
     /// 如果不是 2xx， message 里包含详细的信息
-    #[prost(string, tag="2")]
+    #[prost(string, tag = "2")]
     pub message: ::prost::alloc::string::String,
     /// 成功返回的 values
-    #[prost(message, repeated, tag="3")]
+    #[prost(message, repeated, tag = "3")]
     pub values: ::prost::alloc::vec::Vec<Value>,
     /// 成功返回的 kv pairs
-    #[prost(message, repeated, tag="4")]
+    #[prost(message, repeated, tag = "4")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+    /// 事务中每个命令的结果
+    #[prost(message, repeated, tag = "5")]
+    pub results: ::prost::alloc::vec::Vec<CommandResponse>,
+    /// The id of the request this responds to
+    #[prost(uint64, tag = "6")]
+    #[serde(skip)]
+    pub request_id: u64,
+    /// Set on every response of a stream but the last one, which marks its end.
+    /// Over TCP, HGETALL is answered with such a stream.
+    #[prost(bool, tag = "7")]
+    #[serde(skip)]
+    pub more: bool,
+    /// Messages pushed to a subscribed connection, rather than a response to a
+    /// request. Its request_id is always 0.
+    #[prost(message, repeated, tag = "8")]
+    #[serde(skip)]
+    pub publications: ::prost::alloc::vec::Vec<Publication>,
+    /// Changes of watched keys pushed to the connection, like publications
+    #[prost(message, repeated, tag = "9")]
+    #[serde(skip)]
+    pub changes: ::prost::alloc::vec::Vec<KeyChange>,
 }
 /// 从 table 中获取一个 key，返回 value
-#[derive(PartialOrd)]
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hget {
     /// The table to cope with
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
     /// The key to cope with
-    #[prost(string, tag="2")]
+    #[prost(string, tag = "2")]
     pub key: ::prost::alloc::string::String,
 }
 /// 从 table 中获取所有的 Kvpair
-#[derive(PartialOrd)]
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hgetall {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
 }
 /// 从 table 中获取一组 key，返回它们的 value
-#[derive(PartialOrd)]
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmget {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, repeated, tag="2")]
+    #[prost(string, repeated, tag = "2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 /// 返回的值
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Value {
-    #[prost(oneof="value::Value", tags="1, 2, 3, 4, 5")]
+    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5")]
     pub value: ::core::option::Option<value::Value>,
 }
 /// Nested message and enum types in `Value`.
 pub mod value {
-    #[derive(PartialOrd)]
-    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    #[derive(PartialOrd, Clone, PartialEq, ::prost::Oneof)]
     pub enum Value {
-        #[prost(string, tag="1")]
+        #[prost(string, tag = "1")]
         String(::prost::alloc::string::String),
-        #[prost(bytes, tag="2")]
+        #[prost(bytes, tag = "2")]
         Binary(::prost::bytes::Bytes),
-        #[prost(int64, tag="3")]
+        #[prost(int64, tag = "3")]
         Integer(i64),
-        #[prost(double, tag="4")]
+        #[prost(double, tag = "4")]
         Float(f64),
-        #[prost(bool, tag="5")]
+        #[prost(bool, tag = "5")]
         Bool(bool),
     }
 }
 /// 返回的 Kvpair
-#[derive(PartialOrd)]
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Kvpair {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub key: ::prost::alloc::string::String,
-    #[prost(message, optional, tag="2")]
+    #[prost(message, optional, tag = "2")]
     pub value: ::core::option::Option<Value>,
 }
 /// 往 table 里存一个 kvpair，
 /// 如果 table 不存在就创建这个 table
-#[derive(PartialOrd)]
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hset {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(message, optional, tag="2")]
+    #[prost(message, optional, tag = "2")]
     pub pair: ::core::option::Option<Kvpair>,
 }
 /// 往 table 中存一组 kvpair，
 /// 如果 table 不存在就创建这个 table
-#[derive(PartialOrd)]
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmset {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(message, repeated, tag="2")]
+    #[prost(message, repeated, tag = "2")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
 }
 /// 从 table 中删除一个 key，返回它之前的值
-#[derive(PartialOrd)]
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hdel {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, tag="2")]
+    #[prost(string, tag = "2")]
     pub key: ::prost::alloc::string::String,
 }
 /// 从 table 中删除一组 key，返回它们之前的值
-#[derive(PartialOrd)]
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmdel {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, repeated, tag="2")]
+    #[prost(string, repeated, tag = "2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 /// 查看 key 是否存在
-#[derive(PartialOrd)]
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hexists {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, tag="2")]
+    #[prost(string, tag = "2")]
     pub key: ::prost::alloc::string::String,
 }
 /// 查看一组 key 是否存在
-#[derive(PartialOrd)]
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Hmexists {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, repeated, tag="2")]
+    #[prost(string, repeated, tag = "2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
+/// 按字典序获取 [start, end) 范围内的 kvpair
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hrange {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    /// Inclusive lower bound, unbounded if empty
+    #[prost(string, tag = "2")]
+    pub start: ::prost::alloc::string::String,
+    /// Exclusive upper bound, unbounded if empty
+    #[prost(string, tag = "3")]
+    pub end: ::prost::alloc::string::String,
+    /// Maximum number of pairs to return, 0 means no limit
+    #[prost(uint32, tag = "4")]
+    pub limit: u32,
+    /// Iterate from the largest key downwards
+    #[prost(bool, tag = "5")]
+    pub reverse: bool,
+}
+/// 按字典序获取 key 以 prefix 开头的 kvpair
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hprefix {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub prefix: ::prost::alloc::string::String,
+    /// Maximum number of pairs to return, 0 means no limit
+    #[prost(uint32, tag = "3")]
+    pub limit: u32,
+    /// Iterate from the largest key downwards
+    #[prost(bool, tag = "4")]
+    pub reverse: bool,
+}
+/// 往 table 里存一个 kvpair，并在 ttl_ms 毫秒之后过期
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hsetex {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub pair: ::core::option::Option<Kvpair>,
+    #[prost(uint64, tag = "3")]
+    pub ttl_ms: u64,
+}
+/// 为一个已存在的 key 设置 ttl_ms 毫秒的过期时间，返回 key 是否存在
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hexpire {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub ttl_ms: u64,
+}
+/// 查看 key 剩余的毫秒数，没有过期时间时返回 -1
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Httl {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 移除 key 的过期时间，返回之前是否有过期时间
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hpersist {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 创建一个空的 table，返回之前是否不存在
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CreateTable {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 删除一个 table 及其所有数据，返回之前是否存在
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DropTable {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 按字典序列出所有 table
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListTables {}
+/// 重命名 table，new_name 不能已经存在
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct RenameTable {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub new_name: ::prost::alloc::string::String,
+}
+/// 清空 table 里的所有数据，返回删除的 key 数量
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TruncateTable {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+}
+/// 原子地执行一组命令：watches 全部满足才会执行，任何一个命令失败都会回滚之前的修改
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Transaction {
+    /// Abort the transaction unless every watched key still holds the expected value
+    #[prost(message, repeated, tag = "1")]
+    pub watches: ::prost::alloc::vec::Vec<Watch>,
+    /// Only key level commands are allowed, no table commands or nested transactions
+    #[prost(message, repeated, tag = "2")]
+    pub commands: ::prost::alloc::vec::Vec<CommandRequest>,
+}
+/// 乐观锁：期望 key 当前的值
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Watch {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    /// The expected value, an empty value means the key must not exist
+    #[prost(message, optional, tag = "3")]
+    pub value: ::core::option::Option<Value>,
+}
+/// 只有 key 不存在时才存入 kvpair
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hsetnx {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub pair: ::core::option::Option<Kvpair>,
+}
+/// 只有 key 当前的 value 等于 expected 时才存入 kvpair
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hcas {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub pair: ::core::option::Option<Kvpair>,
+    /// An empty expected value means the key must not exist
+    #[prost(message, optional, tag = "3")]
+    pub expected: ::core::option::Option<Value>,
+}
+/// 只有 key 当前的 value 等于 expected 时才删除 key
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hdelifeq {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "3")]
+    pub expected: ::core::option::Option<Value>,
+}
+/// 把 key 的整数 value 加上 delta，key 不存在时从 0 开始
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hincrby {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub delta: i64,
+}
+/// 把 key 的浮点数 value 加上 delta，key 不存在时从 0 开始
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hincrbyfloat {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(double, tag = "3")]
+    pub delta: f64,
+}
+/// 把 key 的整数 value 减去 delta，key 不存在时从 0 开始
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hdecrby {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "3")]
+    pub delta: i64,
+}
+/// 从 cursor 之后按字典序获取至多 count 个 kvpair，
+/// values 中返回下一页的 cursor，为空时表示已经遍历完
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hscan {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    /// The last key of the previous page, empty for the first page
+    #[prost(string, tag = "2")]
+    pub cursor: ::prost::alloc::string::String,
+    /// Maximum number of pairs in the page, 0 means the default of 10, and
+    /// the server lowers it to at most 1000
+    #[prost(uint32, tag = "3")]
+    pub count: u32,
+}
+/// 订阅一组 channel，返回连接当前订阅的 channel 和 pattern 总数
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Subscribe {
+    #[prost(string, repeated, tag = "1")]
+    pub channels: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 订阅匹配一组 glob pattern 的 channel：* 匹配任意字符串，? 匹配单个字符，
+/// \[abc\] 和 \[a-z\] 匹配其中的字符，\[^abc\] 匹配其余字符，\ 转义下一个字符
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Psubscribe {
+    #[prost(string, repeated, tag = "1")]
+    pub patterns: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 取消订阅一组 channel 或 pattern，为空时取消全部订阅，返回剩余的订阅数
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Unsubscribe {
+    #[prost(string, repeated, tag = "1")]
+    pub topics: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 向 channel 发布一条消息，返回收到消息的订阅数
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Publish {
+    #[prost(string, tag = "1")]
+    pub channel: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub value: ::core::option::Option<Value>,
+}
+/// 推送给订阅者的消息
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Publication {
+    #[prost(string, tag = "1")]
+    pub channel: ::prost::alloc::string::String,
+    /// The pattern the channel matched, empty for a subscription to the channel itself
+    #[prost(string, tag = "2")]
+    pub pattern: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "3")]
+    pub value: ::core::option::Option<Value>,
+}
+/// 监听 table 中 key 的变化：key 为空时监听整个 table，prefix 为 true 时监听以 key 开头的所有 key。
+/// 返回连接当前的订阅总数，UNSUBSCRIBE 不带参数时一并取消
+#[derive(PartialOrd, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Hwatch {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(bool, tag = "3")]
+    pub prefix: bool,
+}
+/// key 的一次变化
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct KeyChange {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    /// Empty if the key didn't exist
+    #[prost(message, optional, tag = "3")]
+    pub old_value: ::core::option::Option<Value>,
+    /// Empty if the key was deleted or expired
+    #[prost(message, optional, tag = "4")]
+    pub new_value: ::core::option::Option<Value>,
+    /// Deleted because its ttl ran out
+    #[prost(bool, tag = "5")]
+    pub expired: bool,
+}
+#[doc = r" Generated client implementations."]
+pub mod kv_service_client {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    #[doc = " gRPC 服务：每个命令一个 RPC，返回和 TCP 协议相同的 CommandResponse"]
+    #[derive(Debug, Clone)]
+    pub struct KvServiceClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl KvServiceClient<tonic::transport::Channel> {
+        #[doc = r" Attempt to create a new client by connecting to a given endpoint."]
+        pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+        where
+            D: std::convert::TryInto<tonic::transport::Endpoint>,
+            D::Error: Into<StdError>,
+        {
+            let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
+            Ok(Self::new(conn))
+        }
+    }
+    impl<T> KvServiceClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::ResponseBody: Body + Send + 'static,
+        T::Error: Into<StdError>,
+        <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> KvServiceClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<http::Request<tonic::body::BoxBody>>>::Error:
+                Into<StdError> + Send + Sync,
+        {
+            KvServiceClient::new(InterceptedService::new(inner, interceptor))
+        }
+        #[doc = r" Compress requests with `gzip`."]
+        #[doc = r""]
+        #[doc = r" This requires the server to support it otherwise it might respond with an"]
+        #[doc = r" error."]
+        pub fn send_gzip(mut self) -> Self {
+            self.inner = self.inner.send_gzip();
+            self
+        }
+        #[doc = r" Enable decompressing responses with `gzip`."]
+        pub fn accept_gzip(mut self) -> Self {
+            self.inner = self.inner.accept_gzip();
+            self
+        }
+        #[doc = " Execute any command, including transactions"]
+        pub async fn execute(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CommandRequest>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Execute");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hget(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hget>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hget");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hgetall(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hgetall>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hgetall");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hmget(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hmget>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hmget");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hset(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hset>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hset");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hmset(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hmset>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hmset");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hdel(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hdel>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hdel");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hmdel(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hmdel>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hmdel");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hexists(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hexists>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hexists");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hmexists(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hmexists>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hmexists");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hrange(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hrange>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hrange");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hprefix(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hprefix>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hprefix");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hsetex(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hsetex>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hsetex");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hexpire(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hexpire>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hexpire");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn httl(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Httl>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Httl");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hpersist(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hpersist>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hpersist");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn create_table(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CreateTable>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/CreateTable");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn drop_table(
+            &mut self,
+            request: impl tonic::IntoRequest<super::DropTable>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/DropTable");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn list_tables(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListTables>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/ListTables");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn rename_table(
+            &mut self,
+            request: impl tonic::IntoRequest<super::RenameTable>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/RenameTable");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn truncate_table(
+            &mut self,
+            request: impl tonic::IntoRequest<super::TruncateTable>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/TruncateTable");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn transaction(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Transaction>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Transaction");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hsetnx(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hsetnx>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hsetnx");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hcas(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hcas>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hcas");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hdelifeq(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hdelifeq>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hdelifeq");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hincrby(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hincrby>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hincrby");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hincrbyfloat(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hincrbyfloat>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hincrbyfloat");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hdecrby(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hdecrby>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hdecrby");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn hscan(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hscan>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Hscan");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        pub async fn publish(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Publish>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/Publish");
+            self.inner.unary(request.into_request(), path, codec).await
+        }
+        #[doc = " Stream the pairs of a table one by one, a failure ends the stream with its status"]
+        pub async fn hgetall_stream(
+            &mut self,
+            request: impl tonic::IntoRequest<super::Hgetall>,
+        ) -> Result<tonic::Response<tonic::codec::Streaming<super::Kvpair>>, tonic::Status>
+        {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static("/abi.KvService/HgetallStream");
+            self.inner
+                .server_streaming(request.into_request(), path, codec)
+                .await
+        }
+    }
+}
+#[doc = r" Generated server implementations."]
+pub mod kv_service_server {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::*;
+    #[doc = "Generated trait containing gRPC methods that should be implemented for use with KvServiceServer."]
+    #[async_trait]
+    pub trait KvService: Send + Sync + 'static {
+        #[doc = " Execute any command, including transactions"]
+        async fn execute(
+            &self,
+            request: tonic::Request<super::CommandRequest>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hget(
+            &self,
+            request: tonic::Request<super::Hget>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hgetall(
+            &self,
+            request: tonic::Request<super::Hgetall>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hmget(
+            &self,
+            request: tonic::Request<super::Hmget>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hset(
+            &self,
+            request: tonic::Request<super::Hset>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hmset(
+            &self,
+            request: tonic::Request<super::Hmset>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hdel(
+            &self,
+            request: tonic::Request<super::Hdel>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hmdel(
+            &self,
+            request: tonic::Request<super::Hmdel>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hexists(
+            &self,
+            request: tonic::Request<super::Hexists>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hmexists(
+            &self,
+            request: tonic::Request<super::Hmexists>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hrange(
+            &self,
+            request: tonic::Request<super::Hrange>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hprefix(
+            &self,
+            request: tonic::Request<super::Hprefix>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hsetex(
+            &self,
+            request: tonic::Request<super::Hsetex>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hexpire(
+            &self,
+            request: tonic::Request<super::Hexpire>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn httl(
+            &self,
+            request: tonic::Request<super::Httl>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hpersist(
+            &self,
+            request: tonic::Request<super::Hpersist>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn create_table(
+            &self,
+            request: tonic::Request<super::CreateTable>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn drop_table(
+            &self,
+            request: tonic::Request<super::DropTable>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn list_tables(
+            &self,
+            request: tonic::Request<super::ListTables>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn rename_table(
+            &self,
+            request: tonic::Request<super::RenameTable>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn truncate_table(
+            &self,
+            request: tonic::Request<super::TruncateTable>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn transaction(
+            &self,
+            request: tonic::Request<super::Transaction>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hsetnx(
+            &self,
+            request: tonic::Request<super::Hsetnx>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hcas(
+            &self,
+            request: tonic::Request<super::Hcas>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hdelifeq(
+            &self,
+            request: tonic::Request<super::Hdelifeq>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hincrby(
+            &self,
+            request: tonic::Request<super::Hincrby>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hincrbyfloat(
+            &self,
+            request: tonic::Request<super::Hincrbyfloat>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hdecrby(
+            &self,
+            request: tonic::Request<super::Hdecrby>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn hscan(
+            &self,
+            request: tonic::Request<super::Hscan>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        async fn publish(
+            &self,
+            request: tonic::Request<super::Publish>,
+        ) -> Result<tonic::Response<super::CommandResponse>, tonic::Status>;
+        #[doc = "Server streaming response type for the HgetallStream method."]
+        type HgetallStreamStream: futures_core::Stream<Item = Result<super::Kvpair, tonic::Status>>
+            + Send
+            + 'static;
+        #[doc = " Stream the pairs of a table one by one, a failure ends the stream with its status"]
+        async fn hgetall_stream(
+            &self,
+            request: tonic::Request<super::Hgetall>,
+        ) -> Result<tonic::Response<Self::HgetallStreamStream>, tonic::Status>;
+    }
+    #[doc = " gRPC 服务：每个命令一个 RPC，返回和 TCP 协议相同的 CommandResponse"]
+    #[derive(Debug)]
+    pub struct KvServiceServer<T: KvService> {
+        inner: _Inner<T>,
+        accept_compression_encodings: (),
+        send_compression_encodings: (),
+    }
+    struct _Inner<T>(Arc<T>);
+    impl<T: KvService> KvServiceServer<T> {
+        pub fn new(inner: T) -> Self {
+            let inner = Arc::new(inner);
+            let inner = _Inner(inner);
+            Self {
+                inner,
+                accept_compression_encodings: Default::default(),
+                send_compression_encodings: Default::default(),
+            }
+        }
+        pub fn with_interceptor<F>(inner: T, interceptor: F) -> InterceptedService<Self, F>
+        where
+            F: tonic::service::Interceptor,
+        {
+            InterceptedService::new(Self::new(inner), interceptor)
+        }
+    }
+    impl<T, B> tonic::codegen::Service<http::Request<B>> for KvServiceServer<T>
+    where
+        T: KvService,
+        B: Body + Send + 'static,
+        B::Error: Into<StdError> + Send + 'static,
+    {
+        type Response = http::Response<tonic::body::BoxBody>;
+        type Error = Never;
+        type Future = BoxFuture<Self::Response, Self::Error>;
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+        fn call(&mut self, req: http::Request<B>) -> Self::Future {
+            let inner = self.inner.clone();
+            match req.uri().path() {
+                "/abi.KvService/Execute" => {
+                    #[allow(non_camel_case_types)]
+                    struct ExecuteSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::CommandRequest> for ExecuteSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CommandRequest>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).execute(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ExecuteSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hget" => {
+                    #[allow(non_camel_case_types)]
+                    struct HgetSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hget> for HgetSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Hget>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hget(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HgetSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hgetall" => {
+                    #[allow(non_camel_case_types)]
+                    struct HgetallSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hgetall> for HgetallSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Hgetall>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hgetall(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HgetallSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hmget" => {
+                    #[allow(non_camel_case_types)]
+                    struct HmgetSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hmget> for HmgetSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Hmget>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hmget(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HmgetSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hset" => {
+                    #[allow(non_camel_case_types)]
+                    struct HsetSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hset> for HsetSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Hset>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hset(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HsetSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hmset" => {
+                    #[allow(non_camel_case_types)]
+                    struct HmsetSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hmset> for HmsetSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Hmset>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hmset(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HmsetSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hdel" => {
+                    #[allow(non_camel_case_types)]
+                    struct HdelSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hdel> for HdelSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Hdel>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hdel(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HdelSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hmdel" => {
+                    #[allow(non_camel_case_types)]
+                    struct HmdelSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hmdel> for HmdelSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Hmdel>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hmdel(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HmdelSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hexists" => {
+                    #[allow(non_camel_case_types)]
+                    struct HexistsSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hexists> for HexistsSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Hexists>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hexists(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HexistsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hmexists" => {
+                    #[allow(non_camel_case_types)]
+                    struct HmexistsSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hmexists> for HmexistsSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Hmexists>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hmexists(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HmexistsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hrange" => {
+                    #[allow(non_camel_case_types)]
+                    struct HrangeSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hrange> for HrangeSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Hrange>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hrange(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HrangeSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hprefix" => {
+                    #[allow(non_camel_case_types)]
+                    struct HprefixSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hprefix> for HprefixSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Hprefix>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hprefix(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HprefixSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hsetex" => {
+                    #[allow(non_camel_case_types)]
+                    struct HsetexSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hsetex> for HsetexSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Hsetex>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hsetex(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HsetexSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hexpire" => {
+                    #[allow(non_camel_case_types)]
+                    struct HexpireSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hexpire> for HexpireSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Hexpire>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hexpire(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HexpireSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Httl" => {
+                    #[allow(non_camel_case_types)]
+                    struct HttlSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Httl> for HttlSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Httl>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).httl(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HttlSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hpersist" => {
+                    #[allow(non_camel_case_types)]
+                    struct HpersistSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hpersist> for HpersistSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Hpersist>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hpersist(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HpersistSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/CreateTable" => {
+                    #[allow(non_camel_case_types)]
+                    struct CreateTableSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::CreateTable> for CreateTableSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CreateTable>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).create_table(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = CreateTableSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/DropTable" => {
+                    #[allow(non_camel_case_types)]
+                    struct DropTableSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::DropTable> for DropTableSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::DropTable>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).drop_table(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = DropTableSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/ListTables" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListTablesSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::ListTables> for ListTablesSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListTables>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).list_tables(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = ListTablesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/RenameTable" => {
+                    #[allow(non_camel_case_types)]
+                    struct RenameTableSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::RenameTable> for RenameTableSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::RenameTable>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).rename_table(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = RenameTableSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/TruncateTable" => {
+                    #[allow(non_camel_case_types)]
+                    struct TruncateTableSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::TruncateTable> for TruncateTableSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::TruncateTable>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).truncate_table(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = TruncateTableSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Transaction" => {
+                    #[allow(non_camel_case_types)]
+                    struct TransactionSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Transaction> for TransactionSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Transaction>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).transaction(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = TransactionSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hsetnx" => {
+                    #[allow(non_camel_case_types)]
+                    struct HsetnxSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hsetnx> for HsetnxSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Hsetnx>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hsetnx(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HsetnxSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hcas" => {
+                    #[allow(non_camel_case_types)]
+                    struct HcasSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hcas> for HcasSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Hcas>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hcas(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HcasSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hdelifeq" => {
+                    #[allow(non_camel_case_types)]
+                    struct HdelifeqSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hdelifeq> for HdelifeqSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Hdelifeq>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hdelifeq(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HdelifeqSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hincrby" => {
+                    #[allow(non_camel_case_types)]
+                    struct HincrbySvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hincrby> for HincrbySvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Hincrby>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hincrby(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HincrbySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hincrbyfloat" => {
+                    #[allow(non_camel_case_types)]
+                    struct HincrbyfloatSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hincrbyfloat> for HincrbyfloatSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Hincrbyfloat>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hincrbyfloat(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HincrbyfloatSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hdecrby" => {
+                    #[allow(non_camel_case_types)]
+                    struct HdecrbySvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hdecrby> for HdecrbySvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Hdecrby>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hdecrby(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HdecrbySvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Hscan" => {
+                    #[allow(non_camel_case_types)]
+                    struct HscanSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Hscan> for HscanSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(&mut self, request: tonic::Request<super::Hscan>) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hscan(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HscanSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/Publish" => {
+                    #[allow(non_camel_case_types)]
+                    struct PublishSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::UnaryService<super::Publish> for PublishSvc<T> {
+                        type Response = super::CommandResponse;
+                        type Future = BoxFuture<tonic::Response<Self::Response>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Publish>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).publish(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = PublishSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/abi.KvService/HgetallStream" => {
+                    #[allow(non_camel_case_types)]
+                    struct HgetallStreamSvc<T: KvService>(pub Arc<T>);
+                    impl<T: KvService> tonic::server::ServerStreamingService<super::Hgetall> for HgetallStreamSvc<T> {
+                        type Response = super::Kvpair;
+                        type ResponseStream = T::HgetallStreamStream;
+                        type Future =
+                            BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::Hgetall>,
+                        ) -> Self::Future {
+                            let inner = self.0.clone();
+                            let fut = async move { (*inner).hgetall_stream(request).await };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = HgetallStreamSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec).apply_compression_config(
+                            accept_compression_encodings,
+                            send_compression_encodings,
+                        );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                _ => Box::pin(async move {
+                    Ok(http::Response::builder()
+                        .status(200)
+                        .header("grpc-status", "12")
+                        .header("content-type", "application/grpc")
+                        .body(empty_body())
+                        .unwrap())
+                }),
+            }
+        }
+    }
+    impl<T: KvService> Clone for KvServiceServer<T> {
+        fn clone(&self) -> Self {
+            let inner = self.inner.clone();
+            Self {
+                inner,
+                accept_compression_encodings: self.accept_compression_encodings,
+                send_compression_encodings: self.send_compression_encodings,
+            }
+        }
+    }
+    impl<T: KvService> Clone for _Inner<T> {
+        fn clone(&self) -> Self {
+            Self(self.0.clone())
+        }
+    }
+    impl<T: std::fmt::Debug> std::fmt::Debug for _Inner<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{:?}", self.0)
+        }
+    }
+    impl<T: KvService> tonic::transport::NamedService for KvServiceServer<T> {
+        const NAME: &'static str = "abi.KvService";
+    }
+}
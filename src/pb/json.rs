@@ -0,0 +1,167 @@
+//! JSON mapping of `Value`: strings, numbers and booleans map to their JSON
+//! counterparts, an empty value to `null`, and binary to `{"binary": "<base64>"}`.
+//! Integers and floats are told apart by the decimal point, `1` vs `1.0`.
+//!
+//! A `CommandRequest` is an object with the command as its only key, e.g.
+//! `{"hget": {"table": "t1", "key": "k1"}}`.
+
+use super::abi::{command_request::RequestData, value, CommandRequest, Value};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{
+    de::{self, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::fmt;
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.value {
+            Some(value::Value::String(s)) => serializer.serialize_str(s),
+            Some(value::Value::Binary(b)) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("binary", &BASE64.encode(b))?;
+                map.end()
+            }
+            Some(value::Value::Integer(i)) => serializer.serialize_i64(*i),
+            Some(value::Value::Float(f)) => serializer.serialize_f64(*f),
+            Some(value::Value::Bool(b)) => serializer.serialize_bool(*b),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a string, number, boolean, null or {\"binary\": \"<base64>\"}")
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Value, E> {
+        Ok(s.into())
+    }
+
+    fn visit_string<E: de::Error>(self, s: String) -> Result<Value, E> {
+        Ok(s.into())
+    }
+
+    fn visit_i64<E: de::Error>(self, i: i64) -> Result<Value, E> {
+        Ok(i.into())
+    }
+
+    fn visit_u64<E: de::Error>(self, u: u64) -> Result<Value, E> {
+        i64::try_from(u)
+            .map(Value::from)
+            .map_err(|_| E::custom(format!("integer {} is out of range", u)))
+    }
+
+    fn visit_f64<E: de::Error>(self, f: f64) -> Result<Value, E> {
+        Ok(f.into())
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<Value, E> {
+        Ok(b.into())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::default())
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::default())
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let encoded = match map.next_entry::<String, String>()? {
+            Some((key, encoded)) if key == "binary" => encoded,
+            _ => return Err(de::Error::custom("expected {\"binary\": \"<base64>\"}")),
+        };
+        if map.next_key::<String>()?.is_some() {
+            return Err(de::Error::custom("expected {\"binary\": \"<base64>\"}"));
+        }
+        let bytes = BASE64
+            .decode(encoded)
+            .map_err(|e| de::Error::custom(format!("invalid base64: {}", e)))?;
+        Ok(Value {
+            value: Some(value::Value::Binary(bytes.into())),
+        })
+    }
+}
+
+impl Serialize for CommandRequest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.request_data {
+            Some(data) => data.serialize(serializer),
+            None => serializer.serialize_map(Some(0))?.end(),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CommandRequest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self {
+            request_data: Some(RequestData::deserialize(deserializer)?),
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CommandRequest, CommandResponse, Kvpair, Value};
+
+    #[test]
+    fn values_should_round_trip() {
+        let binary = Value {
+            value: Some(super::value::Value::Binary(vec![0xca, 0xfe].into())),
+        };
+        let values = vec![
+            Value::from("v"),
+            Value::from(1),
+            Value::from(1.0),
+            Value::from(true),
+            Value::default(),
+            binary,
+        ];
+        let json = serde_json::to_string(&values).unwrap();
+        assert_eq!(json, r#"["v",1,1.0,true,null,{"binary":"yv4="}]"#);
+        let parsed: Vec<Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, values);
+
+        assert!(serde_json::from_str::<Value>(r#"{"binary":"!"}"#).is_err());
+        assert!(serde_json::from_str::<Value>(r#"[1]"#).is_err());
+    }
+
+    #[test]
+    fn commands_should_map_to_json() {
+        let cmd: CommandRequest =
+            serde_json::from_str(r#"{"hset":{"table":"t1","pair":{"key":"k1","value":42}}}"#)
+                .unwrap();
+        assert_eq!(cmd, CommandRequest::new_hset("t1", "k1", 42.into()));
+
+        let cmd: CommandRequest = serde_json::from_str(r#"{"hgetall":{"table":"t1"}}"#).unwrap();
+        assert_eq!(
+            serde_json::to_string(&cmd).unwrap(),
+            r#"{"hgetall":{"table":"t1"}}"#
+        );
+
+        let res = CommandResponse::from(vec![Kvpair::new("k1", "v1".into())]);
+        assert_eq!(
+            serde_json::to_string(&res).unwrap(),
+            r#"{"status":200,"message":"","values":[],"pairs":[{"key":"k1","value":"v1"}],"results":[]}"#
+        );
+    }
+}
@@ -1,13 +1,11 @@
+#[allow(missing_docs)]
 pub mod abi;
-
-use std::convert::TryFrom;
+mod json;
 
 use abi::{command_request::RequestData, *};
-use bytes::Bytes;
 use http::StatusCode;
-use prost::Message;
 
-use crate::KvError;
+use crate::{KvError, Message, ScanRange};
 
 impl CommandRequest {
     /// Create HSET
@@ -17,6 +15,7 @@ impl CommandRequest {
                 table: table.into(),
                 pair: Some(Kvpair::new(key, value)),
             })),
+            ..Default::default()
         }
     }
 
@@ -25,8 +24,9 @@ impl CommandRequest {
         Self {
             request_data: Some(RequestData::Hmset(Hmset {
                 table: table.into(),
-                pairs: pairs,
+                pairs,
             })),
+            ..Default::default()
         }
     }
 
@@ -37,6 +37,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -45,8 +46,9 @@ impl CommandRequest {
         Self {
             request_data: Some(RequestData::Hmget(Hmget {
                 table: table.into(),
-                keys: keys,
+                keys,
             })),
+            ..Default::default()
         }
     }
 
@@ -56,6 +58,7 @@ impl CommandRequest {
             request_data: Some(RequestData::Hgetall(Hgetall {
                 table: table.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -66,6 +69,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -74,8 +78,9 @@ impl CommandRequest {
         Self {
             request_data: Some(RequestData::Hmdel(Hmdel {
                 table: table.into(),
-                keys: keys,
+                keys,
             })),
+            ..Default::default()
         }
     }
 
@@ -86,6 +91,7 @@ impl CommandRequest {
                 table: table.into(),
                 key: key.into(),
             })),
+            ..Default::default()
         }
     }
 
@@ -94,8 +100,354 @@ impl CommandRequest {
         Self {
             request_data: Some(RequestData::Hmexists(Hmexists {
                 table: table.into(),
-                keys: keys,
+                keys,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HRANGE, an empty bound means unbounded
+    pub fn new_hrange(
+        table: impl Into<String>,
+        start: impl Into<String>,
+        end: impl Into<String>,
+        limit: u32,
+        reverse: bool,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hrange(Hrange {
+                table: table.into(),
+                start: start.into(),
+                end: end.into(),
+                limit,
+                reverse,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HPREFIX
+    pub fn new_hprefix(
+        table: impl Into<String>,
+        prefix: impl Into<String>,
+        limit: u32,
+        reverse: bool,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hprefix(Hprefix {
+                table: table.into(),
+                prefix: prefix.into(),
+                limit,
+                reverse,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HSETEX
+    pub fn new_hsetex(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        value: Value,
+        ttl_ms: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hsetex(Hsetex {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+                ttl_ms,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HEXPIRE
+    pub fn new_hexpire(table: impl Into<String>, key: impl Into<String>, ttl_ms: u64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hexpire(Hexpire {
+                table: table.into(),
+                key: key.into(),
+                ttl_ms,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HTTL
+    pub fn new_httl(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Httl(Httl {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HPERSIST
+    pub fn new_hpersist(table: impl Into<String>, key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hpersist(Hpersist {
+                table: table.into(),
+                key: key.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create CREATE TABLE
+    pub fn new_create_table(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::CreateTable(CreateTable {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create DROP TABLE
+    pub fn new_drop_table(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::DropTable(DropTable {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create LIST TABLES
+    pub fn new_list_tables() -> Self {
+        Self {
+            request_data: Some(RequestData::ListTables(ListTables {})),
+            ..Default::default()
+        }
+    }
+
+    /// Create RENAME TABLE
+    pub fn new_rename_table(table: impl Into<String>, new_name: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::RenameTable(RenameTable {
+                table: table.into(),
+                new_name: new_name.into(),
             })),
+            ..Default::default()
+        }
+    }
+
+    /// Create TRUNCATE TABLE
+    pub fn new_truncate_table(table: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::TruncateTable(TruncateTable {
+                table: table.into(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HSETNX
+    pub fn new_hsetnx(table: impl Into<String>, key: impl Into<String>, value: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Hsetnx(Hsetnx {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HCAS, an `expected` of None means the key must not exist
+    pub fn new_hcas(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        expected: Option<Value>,
+        value: Value,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::Hcas(Hcas {
+                table: table.into(),
+                pair: Some(Kvpair::new(key, value)),
+                expected,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HDELIFEQ
+    pub fn new_hdelifeq(table: impl Into<String>, key: impl Into<String>, expected: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Hdelifeq(Hdelifeq {
+                table: table.into(),
+                key: key.into(),
+                expected: Some(expected),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HINCRBY
+    pub fn new_hincrby(table: impl Into<String>, key: impl Into<String>, delta: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hincrby(Hincrby {
+                table: table.into(),
+                key: key.into(),
+                delta,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HINCRBYFLOAT
+    pub fn new_hincrbyfloat(table: impl Into<String>, key: impl Into<String>, delta: f64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hincrbyfloat(Hincrbyfloat {
+                table: table.into(),
+                key: key.into(),
+                delta,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HDECRBY
+    pub fn new_hdecrby(table: impl Into<String>, key: impl Into<String>, delta: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Hdecrby(Hdecrby {
+                table: table.into(),
+                key: key.into(),
+                delta,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HSCAN, starting after `cursor`, the first page if it's empty
+    pub fn new_hscan(table: impl Into<String>, cursor: impl Into<String>, count: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Hscan(Hscan {
+                table: table.into(),
+                cursor: cursor.into(),
+                count,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create SUBSCRIBE
+    pub fn new_subscribe(channels: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Subscribe(Subscribe { channels })),
+            ..Default::default()
+        }
+    }
+
+    /// Create PSUBSCRIBE
+    pub fn new_psubscribe(patterns: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Psubscribe(Psubscribe { patterns })),
+            ..Default::default()
+        }
+    }
+
+    /// Create UNSUBSCRIBE, from every channel and pattern if `topics` is empty
+    pub fn new_unsubscribe(topics: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Unsubscribe(Unsubscribe { topics })),
+            ..Default::default()
+        }
+    }
+
+    /// Create PUBLISH
+    pub fn new_publish(channel: impl Into<String>, value: Value) -> Self {
+        Self {
+            request_data: Some(RequestData::Publish(Publish {
+                channel: channel.into(),
+                value: Some(value),
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create HWATCH, of the whole table if `key` is empty, of the keys starting
+    /// with `key` if `prefix` is set
+    pub fn new_hwatch(table: impl Into<String>, key: impl Into<String>, prefix: bool) -> Self {
+        Self {
+            request_data: Some(RequestData::Hwatch(Hwatch {
+                table: table.into(),
+                key: key.into(),
+                prefix,
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Create a transaction of `commands`, only executed if all `watches` hold
+    pub fn new_transaction(watches: Vec<Watch>, commands: Vec<CommandRequest>) -> Self {
+        Self {
+            request_data: Some(RequestData::Transaction(Transaction { watches, commands })),
+            ..Default::default()
+        }
+    }
+}
+
+impl CommandResponse {
+    /// Whether it carries messages pushed to a subscriber, rather than answers a request
+    pub fn is_push(&self) -> bool {
+        !self.publications.is_empty() || !self.changes.is_empty()
+    }
+
+    /// The messages pushed to a subscriber, publications first
+    pub fn into_messages(self) -> impl Iterator<Item = Message> {
+        let publications = self.publications.into_iter().map(Message::Publication);
+        publications.chain(self.changes.into_iter().map(Message::Change))
+    }
+
+    /// Append the next response of a stream to this one, failing as soon as
+    /// one of them does
+    pub fn merge_chunk(&mut self, next: CommandResponse) {
+        self.more = next.more;
+        if self.status != next.status {
+            self.status = next.status;
+            self.message = next.message;
+            self.pairs.clear();
+        }
+        self.pairs.extend(next.pairs);
+    }
+}
+
+impl Watch {
+    /// Watch a key, expecting it to hold `value`, or to not exist if `value` is None
+    pub fn new(table: impl Into<String>, key: impl Into<String>, value: Option<Value>) -> Self {
+        Self {
+            table: table.into(),
+            key: key.into(),
+            value,
+        }
+    }
+}
+
+/// Hrange -> ScanRange
+impl From<Hrange> for ScanRange {
+    fn from(cmd: Hrange) -> Self {
+        let bound = |s: String| if s.is_empty() { None } else { Some(s) };
+        Self {
+            start: bound(cmd.start),
+            end: bound(cmd.end),
+            prefix: None,
+            limit: cmd.limit as _,
+            reverse: cmd.reverse,
+        }
+    }
+}
+
+/// Hprefix -> ScanRange
+impl From<Hprefix> for ScanRange {
+    fn from(cmd: Hprefix) -> Self {
+        Self {
+            start: None,
+            end: None,
+            prefix: Some(cmd.prefix),
+            limit: cmd.limit as _,
+            reverse: cmd.reverse,
         }
     }
 }
@@ -114,7 +466,7 @@ impl Kvpair {
 impl From<(String, Value)> for Kvpair {
     fn from(t: (String, Value)) -> Self {
         Self {
-            key: t.0.into(),
+            key: t.0,
             value: Some(t.1),
         }
     }
@@ -147,6 +499,15 @@ impl From<i64> for Value {
     }
 }
 
+/// f64 -> Value
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Self {
+            value: Some(value::Value::Float(f)),
+        }
+    }
+}
+
 /// bool -> Value
 impl From<bool> for Value {
     fn from(b: bool) -> Self {
@@ -156,6 +517,54 @@ impl From<bool> for Value {
     }
 }
 
+/// Value -> String
+impl TryFrom<Value> for String {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::String(s)) => Ok(s),
+            _ => Err(KvError::ConvertError(v, "string")),
+        }
+    }
+}
+
+/// Value -> i64
+impl TryFrom<Value> for i64 {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Integer(i)) => Ok(i),
+            _ => Err(KvError::ConvertError(v, "integer")),
+        }
+    }
+}
+
+/// Value -> f64
+impl TryFrom<Value> for f64 {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Float(f)) => Ok(f),
+            _ => Err(KvError::ConvertError(v, "float")),
+        }
+    }
+}
+
+/// Value -> bool
+impl TryFrom<Value> for bool {
+    type Error = KvError;
+
+    fn try_from(v: Value) -> Result<Self, Self::Error> {
+        match v.value {
+            Some(value::Value::Bool(b)) => Ok(b),
+            _ => Err(KvError::ConvertError(v, "bool")),
+        }
+    }
+}
+
 /// 从 Value 转换成 CommandResponse
 impl From<Value> for CommandResponse {
     fn from(v: Value) -> Self {
@@ -188,6 +597,23 @@ impl From<Vec<Kvpair>> for CommandResponse {
     }
 }
 
+/// Messages pushed to a subscriber
+impl From<Vec<Message>> for CommandResponse {
+    fn from(v: Vec<Message>) -> Self {
+        let mut res = Self {
+            status: StatusCode::OK.as_u16() as _,
+            ..Default::default()
+        };
+        for message in v {
+            match message {
+                Message::Publication(publication) => res.publications.push(publication),
+                Message::Change(change) => res.changes.push(change),
+            }
+        }
+        res
+    }
+}
+
 /// Bool -> CommandResponse
 impl From<bool> for CommandResponse {
     fn from(v: bool) -> Self {
@@ -205,13 +631,26 @@ impl From<KvError> for CommandResponse {
         let mut result = Self {
             status: StatusCode::INTERNAL_SERVER_ERROR.as_u16() as _,
             message: e.to_string(),
-            values: vec![],
-            pairs: vec![],
+            ..Default::default()
         };
 
         match e {
-            KvError::NotFound(_, _) => result.status = StatusCode::NOT_FOUND.as_u16() as _,
-            KvError::InvalidCommand(_) => result.status = StatusCode::BAD_REQUEST.as_u16() as _,
+            KvError::NotFound(_, _) | KvError::TableNotFound(_) => {
+                result.status = StatusCode::NOT_FOUND.as_u16() as _
+            }
+            KvError::TableExists(_) | KvError::Conflict(_, _) | KvError::WatchFailed(_, _) => {
+                result.status = StatusCode::CONFLICT.as_u16() as _
+            }
+            KvError::InvalidCommand(_) | KvError::ConvertError(_, _) | KvError::JsonError(_) => {
+                result.status = StatusCode::BAD_REQUEST.as_u16() as _
+            }
+            KvError::OutOfMemory(_, _) => {
+                result.status = StatusCode::INSUFFICIENT_STORAGE.as_u16() as _
+            }
+            KvError::Unsupported(_) => result.status = StatusCode::NOT_IMPLEMENTED.as_u16() as _,
+            KvError::FrameTooLarge(_, _) => {
+                result.status = StatusCode::PAYLOAD_TOO_LARGE.as_u16() as _
+            }
             _ => {}
         }
 
@@ -0,0 +1,207 @@
+use anyhow::Result;
+use clap::Parser;
+use futures::{future, FutureExt};
+use kv::{
+    serve, serve_grpc, serve_http, serve_resp, serve_tls, BTreeTable, Bitcask, EvictionPolicy,
+    MemTable, ServerConfig, Service, ServiceInner, Storage, StorageKind, TlsServerAcceptor,
+    WalTable,
+};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tokio::{net::TcpListener, signal, sync::watch};
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+/// A simple structured key-value server
+#[derive(Debug, Parser)]
+#[command(name = "kvs", version)]
+struct Args {
+    /// Path of the TOML config file, the defaults are used without it
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Address to listen on, overrides `general.addr`
+    #[arg(long)]
+    addr: Option<String>,
+    /// Address of the Redis protocol listener, overrides `general.resp_addr`
+    #[arg(long)]
+    resp_addr: Option<String>,
+    /// Address of the HTTP/JSON gateway, overrides `general.http_addr`
+    #[arg(long)]
+    http_addr: Option<String>,
+    /// Address of the gRPC listener, overrides `general.grpc_addr`
+    #[arg(long)]
+    grpc_addr: Option<String>,
+    /// Maximum number of concurrent connections, overrides `general.max_connections`
+    #[arg(long)]
+    max_connections: Option<usize>,
+    /// Idle timeout of a connection in seconds, overrides `general.idle_timeout_secs`
+    #[arg(long)]
+    idle_timeout_secs: Option<u64>,
+    /// Write timeout of a response in seconds, overrides `general.write_timeout_secs`
+    #[arg(long)]
+    write_timeout_secs: Option<u64>,
+    /// One of memory, btree, wal and bitcask, overrides `storage.type`
+    #[arg(long)]
+    storage: Option<StorageKind>,
+    /// Data directory of the durable storages, overrides `storage.path`
+    #[arg(long)]
+    path: Option<PathBuf>,
+    /// Memory bound of the memory storage in bytes, overrides `storage.max_memory`
+    #[arg(long)]
+    max_memory: Option<usize>,
+    /// One of noeviction, allkeys-lru, allkeys-lfu and volatile-ttl, overrides
+    /// `storage.eviction`
+    #[arg(long)]
+    eviction: Option<EvictionPolicy>,
+    /// Log level, overrides `log.level`. RUST_LOG takes precedence over both
+    #[arg(long)]
+    log_level: Option<String>,
+}
+
+impl Args {
+    fn into_config(self) -> Result<ServerConfig> {
+        let mut config = match &self.config {
+            Some(path) => ServerConfig::load(path)?,
+            None => ServerConfig::default(),
+        };
+        if let Some(addr) = self.addr {
+            config.general.addr = addr;
+        }
+        if let Some(addr) = self.resp_addr {
+            config.general.resp_addr = Some(addr);
+        }
+        if let Some(addr) = self.http_addr {
+            config.general.http_addr = Some(addr);
+        }
+        if let Some(addr) = self.grpc_addr {
+            config.general.grpc_addr = Some(addr);
+        }
+        if let Some(n) = self.max_connections {
+            config.general.max_connections = n;
+        }
+        if let Some(secs) = self.idle_timeout_secs {
+            config.general.idle_timeout_secs = secs;
+        }
+        if let Some(secs) = self.write_timeout_secs {
+            config.general.write_timeout_secs = secs;
+        }
+        if let Some(kind) = self.storage {
+            config.storage.kind = kind;
+        }
+        if let Some(path) = self.path {
+            config.storage.path = Some(path);
+        }
+        if let Some(max_memory) = self.max_memory {
+            config.storage.max_memory = max_memory;
+        }
+        if let Some(eviction) = self.eviction {
+            config.storage.eviction = eviction;
+        }
+        if let Some(level) = self.log_level {
+            config.log.level = level;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = Args::parse().into_config()?;
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.log.level));
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    let path = config.storage.path.as_ref();
+    match config.storage.kind {
+        StorageKind::Memory => {
+            let store = Arc::new(MemTable::with_options(config.memtable_options()));
+            store.spawn_sweeper(Duration::from_secs(1));
+            run(&config, store).await
+        }
+        StorageKind::Btree => run(&config, BTreeTable::new()).await,
+        StorageKind::Wal => {
+            let store = Arc::new(WalTable::with_options(path.unwrap(), config.wal_options())?);
+            store.spawn_sweeper(Duration::from_secs(1));
+            run(&config, store).await
+        }
+        StorageKind::Bitcask => run(&config, Bitcask::open(path.unwrap())?).await,
+    }
+}
+
+async fn run<Store>(config: &ServerConfig, store: Store) -> Result<()>
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    let service: Service<Store> = ServiceInner::new(store)
+        .with_broker_options(config.broker_options())
+        .into();
+    let listener = TcpListener::bind(&config.general.addr).await?;
+    info!(
+        "Start listening on {} with the {:?} storage",
+        config.general.addr, config.storage.kind
+    );
+
+    let (stop, stopped) = watch::channel(());
+    tokio::spawn(async move {
+        stop_signal().await;
+        info!("Shutting down");
+        stop.send(()).ok();
+    });
+    let shutdown = |mut stopped: watch::Receiver<()>| async move {
+        stopped.changed().await.ok();
+    };
+
+    let general = &config.general;
+    let main = match &config.tls {
+        Some(tls) => {
+            let acceptor = TlsServerAcceptor::load(tls)?;
+            let stopped = shutdown(stopped.clone());
+            serve_tls(listener, service.clone(), general, acceptor, stopped).boxed()
+        }
+        None => serve(
+            listener,
+            service.clone(),
+            general,
+            shutdown(stopped.clone()),
+        )
+        .boxed(),
+    };
+    let mut servers = vec![main];
+    if let Some(addr) = &general.resp_addr {
+        let listener = TcpListener::bind(addr).await?;
+        let stopped = shutdown(stopped.clone());
+        servers.push(serve_resp(listener, service.clone(), general, stopped).boxed());
+    }
+    if let Some(addr) = &general.http_addr {
+        let listener = TcpListener::bind(addr).await?;
+        let stopped = shutdown(stopped.clone());
+        servers.push(serve_http(listener, service.clone(), general, stopped).boxed());
+    }
+    if let Some(addr) = &general.grpc_addr {
+        let listener = TcpListener::bind(addr).await?;
+        let stopped = shutdown(stopped.clone());
+        servers.push(serve_grpc(listener, service.clone(), general, stopped).boxed());
+    }
+    future::try_join_all(servers).await?;
+    Ok(())
+}
+
+/// Resolve on Ctrl-C, or on the SIGTERM service managers and containers stop with
+async fn stop_signal() {
+    #[cfg(unix)]
+    {
+        use signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                _ = signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            },
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                signal::ctrl_c().await.ok();
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await.ok();
+}
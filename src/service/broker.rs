@@ -0,0 +1,593 @@
+use crate::{storage::now_ms, Hwatch, KeyChange, Publication, Value};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        mpsc::{self, error::TrySendError},
+        Notify,
+    },
+    time,
+};
+use tracing::{debug, warn};
+
+/// What happens to a subscriber whose queue is full when a message is published
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowSubscriberPolicy {
+    /// The subscriber misses the message
+    #[default]
+    Drop,
+    /// The subscriber is disconnected once it has read the queued messages
+    Disconnect,
+}
+
+/// Settings of a `Broker`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BrokerOptions {
+    /// Messages queued for a subscriber before it counts as slow
+    pub queue_len: usize,
+    /// What to do with slow subscribers
+    pub slow_subscribers: SlowSubscriberPolicy,
+}
+
+impl Default for BrokerOptions {
+    fn default() -> Self {
+        Self {
+            queue_len: 1024,
+            slow_subscribers: SlowSubscriberPolicy::Drop,
+        }
+    }
+}
+
+/// What a subscriber receives
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    /// A message published to a channel
+    Publication(Publication),
+    /// A change of a watched key
+    Change(KeyChange),
+}
+
+/// Fans the messages published to a channel, and the changes of watched keys,
+/// out to their subscribers
+///
+/// Every subscriber has a bounded queue, so a slow one never holds the
+/// publishers back: it misses messages or gets disconnected, as told by
+/// `BrokerOptions::slow_subscribers`.
+pub struct Broker {
+    options: BrokerOptions,
+    next_id: AtomicU64,
+    topics: Mutex<Topics>,
+    // Woken when the earliest deadline of `Topics::expiring` moves
+    deadlines: Notify,
+}
+
+#[derive(Default)]
+struct Topics {
+    queues: HashMap<u64, mpsc::Sender<Message>>,
+    channels: HashMap<String, HashSet<u64>>,
+    patterns: HashMap<String, HashSet<u64>>,
+    // By table
+    watches: HashMap<String, Vec<(u64, Hwatch)>>,
+    // Watched keys with a ttl, by deadline, with their value
+    expiring: BTreeMap<(u64, String, String), Value>,
+    deadlines: HashMap<(String, String), u64>,
+}
+
+impl Broker {
+    /// Create a broker without subscribers
+    pub fn new(options: BrokerOptions) -> Self {
+        Self {
+            options,
+            next_id: AtomicU64::new(0),
+            topics: Mutex::new(Topics::default()),
+            deadlines: Notify::new(),
+        }
+    }
+
+    /// Register a subscriber, which receives nothing until it subscribes
+    pub fn subscriber(self: &Arc<Self>) -> Subscriber {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel(self.options.queue_len.max(1));
+        self.topics().queues.insert(id, tx);
+        Subscriber {
+            broker: Arc::clone(self),
+            id,
+            rx,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            watches: Vec::new(),
+        }
+    }
+
+    /// Queue `value` for the subscribers of `channel`, and return how many got it.
+    /// A subscriber matching several of its subscriptions gets it once for each.
+    pub fn publish(&self, channel: &str, value: Value) -> usize {
+        let mut topics = self.topics();
+        let direct = topics
+            .channels
+            .get(channel)
+            .into_iter()
+            .flatten()
+            .map(|id| (*id, String::new()));
+        let matched = topics
+            .patterns
+            .iter()
+            .filter(|(pattern, _)| glob_match(pattern, channel))
+            .flat_map(|(pattern, ids)| ids.iter().map(|id| (*id, pattern.clone())));
+        let messages: Vec<_> = direct
+            .chain(matched)
+            .map(|(id, pattern)| {
+                let publication = Publication {
+                    channel: channel.into(),
+                    pattern,
+                    value: Some(value.clone()),
+                };
+                (id, Message::Publication(publication))
+            })
+            .collect();
+        self.deliver(&mut topics, messages)
+    }
+
+    /// Whether anyone watches `key` of `table`, or any key of it if `key` is None
+    pub fn is_watched(&self, table: &str, key: Option<&str>) -> bool {
+        self.topics().watches.get(table).is_some_and(|watches| {
+            key.is_none_or(|key| watches.iter().any(|(_, w)| covers(w, key)))
+        })
+    }
+
+    /// Queue `changes` for the subscribers watching their keys
+    pub fn notify(&self, changes: Vec<KeyChange>) {
+        if changes.is_empty() {
+            return;
+        }
+        let mut topics = self.topics();
+        let mut messages = Vec::new();
+        for change in changes {
+            let watchers = topics.watches.get(&change.table).into_iter().flatten();
+            let mut ids: Vec<_> = watchers
+                .filter(|(_, w)| covers(w, &change.key))
+                .map(|(id, _)| *id)
+                .collect();
+            // Overlapping watches of a subscriber get a change once
+            ids.dedup();
+            messages.extend(
+                ids.into_iter()
+                    .map(|id| (id, Message::Change(change.clone()))),
+            );
+        }
+        self.deliver(&mut topics, messages);
+    }
+
+    /// Expect `key` of `table`, holding `value`, to expire at `deadline` in
+    /// milliseconds since the epoch, or never if it's None
+    pub fn expire_at(&self, table: &str, key: &str, deadline: Option<u64>, value: Value) {
+        let mut topics = self.topics();
+        let id = (table.to_owned(), key.to_owned());
+        if let Some(previous) = topics.deadlines.remove(&id) {
+            topics
+                .expiring
+                .remove(&(previous, id.0.clone(), id.1.clone()));
+        }
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        let earliest = topics.expiring.keys().next().map(|(at, _, _)| *at);
+        topics.deadlines.insert(id.clone(), deadline);
+        topics.expiring.insert((deadline, id.0, id.1), value);
+        if earliest.is_none_or(|at| deadline < at) {
+            self.deadlines.notify_waiters();
+        }
+    }
+
+    /// Wait until a key expected to expire by `expire_at` is due
+    pub async fn expiry_due(&self) {
+        loop {
+            // Created first so a deadline moved meanwhile still wakes it
+            let moved = self.deadlines.notified();
+            let earliest = self.topics().expiring.keys().next().map(|(at, _, _)| *at);
+            match earliest {
+                Some(at) => {
+                    let wait = Duration::from_millis(at.saturating_sub(now_ms()));
+                    if wait.is_zero() {
+                        return;
+                    }
+                    tokio::select! {
+                        _ = time::sleep(wait) => return,
+                        _ = moved => {}
+                    }
+                }
+                None => moved.await,
+            }
+        }
+    }
+
+    /// Take the keys due to expire, with their last value
+    pub fn take_expired(&self) -> Vec<(String, String, Value)> {
+        let mut topics = self.topics();
+        let now = now_ms();
+        let mut expired = Vec::new();
+        while let Some(entry) = topics.expiring.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            let ((_, table, key), value) = entry.remove_entry();
+            topics.deadlines.remove(&(table.clone(), key.clone()));
+            expired.push((table, key, value));
+        }
+        expired
+    }
+
+    /// Queue `messages` for their subscribers, and return how many were queued
+    fn deliver(&self, topics: &mut Topics, messages: Vec<(u64, Message)>) -> usize {
+        let mut delivered = 0;
+        let mut slow = Vec::new();
+        for (id, message) in messages {
+            // Gone if it was disconnected for being slow
+            let queue = match topics.queues.get(&id) {
+                Some(queue) => queue,
+                None => continue,
+            };
+            match queue.try_send(message) {
+                Ok(()) => delivered += 1,
+                Err(TrySendError::Full(_)) => slow.push(id),
+                Err(TrySendError::Closed(_)) => {}
+            }
+        }
+
+        for id in slow {
+            match self.options.slow_subscribers {
+                SlowSubscriberPolicy::Drop => debug!("Subscriber {} missed a message", id),
+                SlowSubscriberPolicy::Disconnect => {
+                    warn!("Disconnecting subscriber {} for falling behind", id);
+                    // Its queue closes once the queued messages are read
+                    topics.queues.remove(&id);
+                }
+            }
+        }
+        delivered
+    }
+
+    fn topics(&self) -> MutexGuard<'_, Topics> {
+        self.topics.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Subscriptions of a connection, and the queue of the messages sent to them
+///
+/// Dropping it unsubscribes from everything.
+pub struct Subscriber {
+    broker: Arc<Broker>,
+    id: u64,
+    rx: mpsc::Receiver<Message>,
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+    watches: Vec<Hwatch>,
+}
+
+impl Subscriber {
+    /// Subscribe to `channels`, and return the number of subscriptions
+    pub fn subscribe(&mut self, channels: Vec<String>) -> usize {
+        let mut topics = self.broker.topics();
+        for channel in channels {
+            topics
+                .channels
+                .entry(channel.clone())
+                .or_default()
+                .insert(self.id);
+            self.channels.insert(channel);
+        }
+        self.subscriptions()
+    }
+
+    /// Subscribe to the channels matching glob `patterns`, and return the number
+    /// of subscriptions
+    pub fn psubscribe(&mut self, patterns: Vec<String>) -> usize {
+        let mut topics = self.broker.topics();
+        for pattern in patterns {
+            topics
+                .patterns
+                .entry(pattern.clone())
+                .or_default()
+                .insert(self.id);
+            self.patterns.insert(pattern);
+        }
+        self.subscriptions()
+    }
+
+    /// Watch the changes of a table, a key or a key prefix, and return the
+    /// number of subscriptions
+    pub fn watch(&mut self, watch: Hwatch) -> usize {
+        if !self.watches.contains(&watch) {
+            let mut topics = self.broker.topics();
+            let watches = topics.watches.entry(watch.table.clone()).or_default();
+            watches.push((self.id, watch.clone()));
+            // Keep the watches of a subscriber together, so it gets a change once
+            watches.sort_by_key(|(id, _)| *id);
+            self.watches.push(watch);
+        }
+        self.subscriptions()
+    }
+
+    /// Unsubscribe from channels or patterns, from all of them and every watch
+    /// if `topics` is empty, and return the number of subscriptions left
+    pub fn unsubscribe(&mut self, topics: Vec<String>) -> usize {
+        let all = topics.is_empty();
+        let topics = if all {
+            self.channels
+                .iter()
+                .chain(&self.patterns)
+                .cloned()
+                .collect()
+        } else {
+            topics
+        };
+        let mut subscribed = self.broker.topics();
+        for topic in topics {
+            if self.channels.remove(&topic) {
+                remove(&mut subscribed.channels, &topic, self.id);
+            }
+            if self.patterns.remove(&topic) {
+                remove(&mut subscribed.patterns, &topic, self.id);
+            }
+        }
+        if all {
+            for watch in self.watches.drain(..) {
+                if let Some(watches) = subscribed.watches.get_mut(&watch.table) {
+                    watches.retain(|(id, _)| *id != self.id);
+                    if watches.is_empty() {
+                        subscribed.watches.remove(&watch.table);
+                    }
+                }
+            }
+        }
+        drop(subscribed);
+        self.subscriptions()
+    }
+
+    /// Number of channels, patterns and watches subscribed to
+    pub fn subscriptions(&self) -> usize {
+        self.channels.len() + self.patterns.len() + self.watches.len()
+    }
+
+    /// Whether it watches keys, whose expiry then needs to be waited for
+    pub fn is_watching(&self) -> bool {
+        !self.watches.is_empty()
+    }
+
+    /// Wait for the next message, None once the subscriber is disconnected for
+    /// being slow
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.rx.recv().await
+    }
+
+    /// The next queued message, if any, without waiting
+    pub fn try_recv(&mut self) -> Option<Message> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.unsubscribe(Vec::new());
+        self.broker.topics().queues.remove(&self.id);
+    }
+}
+
+fn remove(subscribed: &mut HashMap<String, HashSet<u64>>, topic: &str, id: u64) {
+    if let Some(ids) = subscribed.get_mut(topic) {
+        ids.remove(&id);
+        if ids.is_empty() {
+            subscribed.remove(topic);
+        }
+    }
+}
+
+/// Whether `watch` covers `key` of its table
+fn covers(watch: &Hwatch, key: &str) -> bool {
+    if watch.prefix {
+        key.starts_with(&watch.key)
+    } else {
+        watch.key.is_empty() || watch.key == key
+    }
+}
+
+/// Match `text` against a glob pattern: `*` matches any string, `?` any
+/// character, `[abc]` and `[a-z]` the characters of the set, `[^abc]` the
+/// others, and `\` escapes the next character
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*` if the rest fails to match
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match_set(&pattern[p..], text[t]),
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == text[t]).then_some(2),
+            Some(c) => (*c == text[t]).then_some(1),
+            None => None,
+        };
+        match (step, star) {
+            (Some(len), _) => {
+                p += len;
+                t += 1;
+            }
+            // Let the last `*` take one more character
+            (None, Some((after, taken))) => {
+                star = Some((after, taken + 1));
+                p = after;
+                t = taken + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Match `c` against the set opening `pattern`, and return the length of the set
+fn match_set(pattern: &[char], c: char) -> Option<usize> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('^' | '!'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    // A `]` right after the opening is part of the set
+    let start = i;
+    loop {
+        match pattern.get(i) {
+            // An unterminated set matches the `[` literally
+            None => return (c == '[').then_some(1),
+            Some(']') if i > start => break,
+            Some('\\') if i + 1 < pattern.len() => {
+                matched |= pattern[i + 1] == c;
+                i += 2;
+            }
+            Some(&low)
+                if pattern.get(i + 1) == Some(&'-')
+                    && i + 2 < pattern.len()
+                    && pattern[i + 2] != ']' =>
+            {
+                matched |= (low..=pattern[i + 2]).contains(&c);
+                i += 3;
+            }
+            Some(&other) => {
+                matched |= other == c;
+                i += 1;
+            }
+        }
+    }
+    (matched != negated).then_some(i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs_should_match() {
+        let cases = [
+            ("news.*", "news.sports", true),
+            ("news.*", "news.", true),
+            ("news.*", "weather", false),
+            ("*.log", "app.error.log", true),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-c]llo", "hbllo", true),
+            ("h[a-c]llo", "hdllo", false),
+            ("a\\*b", "a*b", true),
+            ("a\\*b", "axb", false),
+            ("a[", "a[", true),
+            ("*a*b", "xxaxxbxb", true),
+            ("*a*b", "xxaxxbx", false),
+            ("配置.*", "配置.db", true),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(glob_match(pattern, text), expected, "{} {}", pattern, text);
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_should_reach_matching_subscribers() {
+        let broker = Arc::new(Broker::new(BrokerOptions::default()));
+        let mut direct = broker.subscriber();
+        let mut matching = broker.subscriber();
+        let _idle = broker.subscriber();
+        assert_eq!(direct.subscribe(vec!["config.db".into()]), 1);
+        assert_eq!(matching.psubscribe(vec!["config.*".into()]), 1);
+
+        assert_eq!(broker.publish("config.db", "reload".into()), 2);
+        let publication = next_publication(&mut direct).await;
+        assert_eq!(publication.channel, "config.db");
+        assert_eq!(publication.pattern, "");
+        assert_eq!(publication.value, Some("reload".into()));
+        let publication = next_publication(&mut matching).await;
+        assert_eq!(publication.pattern, "config.*");
+
+        assert_eq!(broker.publish("config.cache", 1.into()), 1);
+        assert_eq!(matching.unsubscribe(Vec::new()), 0);
+        assert_eq!(broker.publish("config.cache", 2.into()), 0);
+        drop(direct);
+        assert_eq!(broker.publish("config.db", 3.into()), 0);
+        assert!(broker.topics().channels.is_empty());
+    }
+
+    #[tokio::test]
+    async fn slow_subscribers_should_miss_messages() {
+        let options = BrokerOptions {
+            queue_len: 2,
+            slow_subscribers: SlowSubscriberPolicy::Drop,
+        };
+        let broker = Arc::new(Broker::new(options));
+        let mut subscriber = broker.subscriber();
+        subscriber.subscribe(vec!["ch".into()]);
+
+        let delivered: Vec<_> = (0..3).map(|i| broker.publish("ch", i.into())).collect();
+        assert_eq!(delivered, vec![1, 1, 0]);
+        assert_eq!(
+            next_publication(&mut subscriber).await.value,
+            Some(0.into())
+        );
+        assert_eq!(
+            next_publication(&mut subscriber).await.value,
+            Some(1.into())
+        );
+        assert_eq!(broker.publish("ch", 3.into()), 1);
+        assert_eq!(
+            next_publication(&mut subscriber).await.value,
+            Some(3.into())
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_subscribers_should_be_disconnected() {
+        let options = BrokerOptions {
+            queue_len: 2,
+            slow_subscribers: SlowSubscriberPolicy::Disconnect,
+        };
+        let broker = Arc::new(Broker::new(options));
+        let mut subscriber = broker.subscriber();
+        subscriber.subscribe(vec!["ch".into()]);
+
+        for i in 0..3 {
+            broker.publish("ch", i.into());
+        }
+        assert_eq!(broker.publish("ch", 3.into()), 0);
+        // The queued messages are still delivered
+        assert_eq!(
+            next_publication(&mut subscriber).await.value,
+            Some(0.into())
+        );
+        assert_eq!(
+            next_publication(&mut subscriber).await.value,
+            Some(1.into())
+        );
+        assert!(subscriber.recv().await.is_none());
+    }
+
+    async fn next_publication(subscriber: &mut Subscriber) -> Publication {
+        match subscriber.recv().await {
+            Some(Message::Publication(publication)) => publication,
+            other => panic!("expected a publication, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use super::command_service::written_keys;
+use crate::{
+    command_request::RequestData, storage::now_ms, Broker, CommandResponse, Hwatch, KeyChange,
+    KvError, ScanRange, Storage, Value,
+};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet},
+    hash::{Hash, Hasher},
+    sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+/// Number of locks the watched tables and keys are spread over
+const STRIPES: usize = 64;
+
+/// The keys and tables a command may change, narrowed down to the watched ones
+/// with `watched`. Their values are captured before it runs and compared with
+/// the ones left afterwards.
+///
+/// The command must hold `WatchLocks::lock_tables` from `watched` on, so no
+/// watch is added in between, and `WatchLocks::lock_keys` between `capture`
+/// and `finish`, so the changes are exactly the ones it made. A command that
+/// runs exclusively needs neither.
+pub(crate) struct ChangeTracker {
+    keys: Vec<(String, String)>,
+    tables: Vec<String>,
+    // What a table command does to the keys of its tables, None for key writes
+    table_op: Option<TableOp>,
+    before: BTreeMap<(String, String), Value>,
+}
+
+/// A command changing every key of a table at once
+enum TableOp {
+    /// DROP and TRUNCATE leave the table empty
+    Clear,
+    /// RENAME moves the keys of a table to the new name
+    Rename(String),
+}
+
+/// Locks serializing the commands that change the same watched keys, while
+/// the other commands run alongside. Table commands hold the lock of their
+/// tables exclusively and key writes share it, so HWATCH holding it adds its
+/// watch between the writes. Writes of watched keys also hold their locks.
+pub(crate) struct WatchLocks {
+    tables: Vec<RwLock<()>>,
+    keys: Vec<Mutex<()>>,
+}
+
+/// The locks a tracked command holds while it runs
+pub(crate) struct WatchGuard<'a> {
+    _shared: Vec<RwLockReadGuard<'a, ()>>,
+    _exclusive: Vec<RwLockWriteGuard<'a, ()>>,
+    _keys: Vec<MutexGuard<'a, ()>>,
+}
+
+impl Default for WatchLocks {
+    fn default() -> Self {
+        Self {
+            tables: (0..STRIPES).map(|_| RwLock::new(())).collect(),
+            keys: (0..STRIPES).map(|_| Mutex::new(())).collect(),
+        }
+    }
+}
+
+impl WatchLocks {
+    /// Lock the tables `tracker` changes, before it is narrowed down to the
+    /// watched ones. The stripes are taken in order, here and in `lock_keys`,
+    /// so two commands never wait for each other.
+    pub(crate) fn lock_tables(&self, tracker: &ChangeTracker) -> WatchGuard<'_> {
+        if tracker.table_op.is_some() {
+            return self.exclusive(stripes(tracker.tables.iter()));
+        }
+        let shared = stripes(tracker.keys.iter().map(|(table, _)| table))
+            .into_iter()
+            .map(|i| {
+                self.tables[i]
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+            })
+            .collect();
+        WatchGuard {
+            _shared: shared,
+            _exclusive: Vec::new(),
+            _keys: Vec::new(),
+        }
+    }
+
+    /// Lock the watched keys `tracker` reads, once it holds `lock_tables`
+    pub(crate) fn lock_keys(&self, tracker: &ChangeTracker) -> WatchGuard<'_> {
+        let keys = stripes(tracker.keys.iter())
+            .into_iter()
+            .map(|i| self.keys[i].lock().unwrap_or_else(PoisonError::into_inner))
+            .collect();
+        WatchGuard {
+            _shared: Vec::new(),
+            _exclusive: Vec::new(),
+            _keys: keys,
+        }
+    }
+
+    /// Lock `table` exclusively, so no command changes it meanwhile
+    pub(crate) fn lock_table(&self, table: &str) -> WatchGuard<'_> {
+        self.exclusive(stripes([table]))
+    }
+
+    fn exclusive(&self, stripes: Vec<usize>) -> WatchGuard<'_> {
+        let exclusive = stripes
+            .into_iter()
+            .map(|i| {
+                self.tables[i]
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner)
+            })
+            .collect();
+        WatchGuard {
+            _shared: Vec::new(),
+            _exclusive: exclusive,
+            _keys: Vec::new(),
+        }
+    }
+}
+
+/// The sorted, distinct stripes of `items`
+fn stripes<T: Hash>(items: impl IntoIterator<Item = T>) -> Vec<usize> {
+    let stripes: BTreeSet<_> = items
+        .into_iter()
+        .map(|item| {
+            let mut hasher = DefaultHasher::new();
+            item.hash(&mut hasher);
+            hasher.finish() as usize % STRIPES
+        })
+        .collect();
+    stripes.into_iter().collect()
+}
+
+impl ChangeTracker {
+    /// Keys and tables `data` may change
+    pub(crate) fn new(data: &RequestData) -> Self {
+        let (keys, tables, table_op) = match data {
+            RequestData::DropTable(v) => (Vec::new(), vec![v.table.clone()], Some(TableOp::Clear)),
+            RequestData::TruncateTable(v) => {
+                (Vec::new(), vec![v.table.clone()], Some(TableOp::Clear))
+            }
+            RequestData::RenameTable(v) => (
+                Vec::new(),
+                vec![v.table.clone(), v.new_name.clone()],
+                Some(TableOp::Rename(v.new_name.clone())),
+            ),
+            RequestData::Transaction(txn) => {
+                let commands = txn.commands.iter().filter_map(|c| c.request_data.as_ref());
+                let keys = commands.flat_map(|data| written_keys(data).unwrap_or_default());
+                (keys.collect(), Vec::new(), None)
+            }
+            data => (written_keys(data).unwrap_or_default(), Vec::new(), None),
+        };
+        Self {
+            keys,
+            tables,
+            table_op,
+            before: BTreeMap::new(),
+        }
+    }
+
+    /// Only the keys and tables that are watched, None if there are none
+    pub(crate) fn watched(mut self, broker: &Broker) -> Option<Self> {
+        self.keys
+            .retain(|(table, key)| broker.is_watched(table, Some(key)));
+        self.tables.retain(|table| broker.is_watched(table, None));
+        if self.keys.is_empty() && self.tables.is_empty() {
+            return None;
+        }
+        Some(self)
+    }
+
+    /// Read the values before the command runs
+    pub(crate) fn capture(mut self, store: &impl Storage) -> Self {
+        let mut before = BTreeMap::new();
+        self.read_keys(store, &mut before);
+        for table in &self.tables {
+            read_table(store, table, &mut before);
+        }
+        self.before = before;
+        self
+    }
+
+    /// The changes `res` made since `capture`, and let `broker` know when the
+    /// keys left with a ttl expire
+    pub(crate) fn finish(
+        mut self,
+        res: &CommandResponse,
+        store: &impl Storage,
+        broker: &Broker,
+    ) -> Vec<KeyChange> {
+        let mut after = self.after(res, store);
+        let ids: BTreeSet<_> = self.before.keys().chain(after.keys()).cloned().collect();
+        let mut changes = Vec::new();
+        for (table, key) in ids {
+            let id = (table, key);
+            let (old, new) = (self.before.remove(&id), after.remove(&id));
+            let (table, key) = id;
+            let deadline = match &new {
+                Some(_) => match store.ttl(&table, &key) {
+                    Ok(Some(ttl)) => Some(now_ms() + ttl.as_millis() as u64),
+                    _ => None,
+                },
+                None => None,
+            };
+            broker.expire_at(&table, &key, deadline, new.clone().unwrap_or_default());
+            if old != new {
+                changes.push(KeyChange {
+                    table,
+                    key,
+                    old_value: old,
+                    new_value: new,
+                    expired: false,
+                });
+            }
+        }
+        changes
+    }
+
+    /// The values left by the command. Table commands make their result known
+    /// without reading the tables again, save the new name of a renamed one.
+    fn after(
+        &self,
+        res: &CommandResponse,
+        store: &impl Storage,
+    ) -> BTreeMap<(String, String), Value> {
+        let mut after = BTreeMap::new();
+        self.read_keys(store, &mut after);
+        match &self.table_op {
+            // A failed table command changed nothing
+            Some(_) if res.status != 200 => after.extend(
+                self.before
+                    .iter()
+                    .map(|(id, value)| (id.clone(), value.clone())),
+            ),
+            Some(TableOp::Clear) => {}
+            // The table didn't exist before, so its keys are all new
+            Some(TableOp::Rename(new_name)) if self.tables.contains(new_name) => {
+                read_table(store, new_name, &mut after)
+            }
+            Some(TableOp::Rename(_)) | None => {}
+        }
+        after
+    }
+
+    fn read_keys(&self, store: &impl Storage, values: &mut BTreeMap<(String, String), Value>) {
+        for (table, key) in &self.keys {
+            if let Ok(Some(value)) = store.get(table, key) {
+                values.insert((table.clone(), key.clone()), value);
+            }
+        }
+    }
+}
+
+fn read_table(store: &impl Storage, table: &str, values: &mut BTreeMap<(String, String), Value>) {
+    // A missing table has no keys
+    for pair in store.get_all(table).unwrap_or_default() {
+        values.insert((table.into(), pair.key), pair.value.unwrap_or_default());
+    }
+}
+
+/// Let `broker` know when the keys covered by `watch` that already have a ttl
+/// expire, as writes made from now on are tracked
+pub(crate) fn register_expiry(watch: &Hwatch, store: &impl Storage, broker: &Broker) {
+    let pairs = if !watch.prefix && !watch.key.is_empty() {
+        match store.get(&watch.table, &watch.key) {
+            Ok(Some(value)) => vec![(watch.key.clone(), value)],
+            _ => Vec::new(),
+        }
+    } else {
+        let range = ScanRange {
+            prefix: Some(watch.key.clone()).filter(|prefix| !prefix.is_empty()),
+            ..Default::default()
+        };
+        store
+            .scan(&watch.table, &range)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|pair| (pair.key, pair.value.unwrap_or_default()))
+            .collect()
+    };
+    for (key, value) in pairs {
+        match store.ttl(&watch.table, &key) {
+            Ok(Some(ttl)) => {
+                let deadline = now_ms() + ttl.as_millis() as u64;
+                broker.expire_at(&watch.table, &key, Some(deadline), value);
+            }
+            // Keys of a storage without ttls never expire
+            Err(KvError::Unsupported(_)) => break,
+            _ => {}
+        }
+    }
+}
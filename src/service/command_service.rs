@@ -1,4 +1,7 @@
-use crate::*;
+use crate::{command_request::RequestData, *};
+use http::StatusCode;
+use std::time::Duration;
+use tracing::warn;
 
 impl CommandService for Hget {
     fn execute(self, store: &impl Storage) -> CommandResponse {
@@ -48,16 +51,21 @@ impl CommandService for Hset {
 impl CommandService for Hmset {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         let table = self.table;
-        self.pairs
-            .into_iter()
-            .map(
-                |pair| match store.set(&table, pair.key, pair.value.unwrap_or_default()) {
-                    Ok(Some(v)) => v,
-                    _ => Value::default(),
-                },
-            )
-            .collect::<Vec<_>>()
-            .into()
+        // A crash never leaves only some of the pairs written
+        let values = store.batch(|| {
+            self.pairs
+                .into_iter()
+                .map(|pair| {
+                    store
+                        .set(&table, pair.key, pair.value.unwrap_or_default())
+                        .map(|v| v.unwrap_or_default())
+                })
+                .collect::<Result<Vec<_>, _>>()
+        });
+        match values.and_then(|values| values) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
     }
 }
 
@@ -76,7 +84,7 @@ impl CommandService for Hmdel {
         self.keys
             .iter()
             .map(|key| match store.del(&self.table, key.as_str()) {
-                Ok(Some(v)) => v.into(),
+                Ok(Some(v)) => v,
                 _ => Value::default(),
             })
             .collect::<Vec<_>>()
@@ -107,10 +115,399 @@ impl CommandService for Hmexists {
     }
 }
 
+impl CommandService for Hrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let table = self.table.clone();
+        match store.scan(&table, &self.into()) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hprefix {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let table = self.table.clone();
+        match store.scan(&table, &self.into()) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// Page size of HSCAN when the count is 0
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// Largest page of HSCAN, a larger count gets a page of this size so that a
+/// client can't have the whole table read at once
+const MAX_SCAN_COUNT: usize = 1000;
+
+impl CommandService for Hscan {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let count = match self.count {
+            0 => DEFAULT_SCAN_COUNT,
+            n => (n as usize).min(MAX_SCAN_COUNT),
+        };
+        // One pair beyond the page tells whether there's a next page, and the
+        // page after the cursor is found through the ordered index of the storage
+        let range = ScanRange {
+            start: (!self.cursor.is_empty()).then(|| after(&self.cursor)),
+            limit: count + 1,
+            ..Default::default()
+        };
+        let mut pairs = match store.scan(&self.table, &range) {
+            Ok(pairs) => pairs,
+            Err(e) => return e.into(),
+        };
+        let cursor = if pairs.len() > count {
+            pairs.truncate(count);
+            pairs[count - 1].key.clone()
+        } else {
+            String::new()
+        };
+        let mut res = CommandResponse::from(pairs);
+        res.values = vec![cursor.into()];
+        res
+    }
+}
+
+impl CommandService for Hsetex {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        if self.ttl_ms == 0 {
+            return KvError::InvalidCommand("HSETEX requires a positive ttl".into()).into();
+        }
+        let ttl = Duration::from_millis(self.ttl_ms);
+        match self.pair {
+            Some(v) => match store.set_ex(&self.table, v.key, v.value.unwrap_or_default(), ttl) {
+                Ok(Some(v)) => v.into(),
+                Ok(None) => Value::default().into(),
+                Err(e) => e.into(),
+            },
+            None => Value::default().into(),
+        }
+    }
+}
+
+impl CommandService for Hexpire {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        if self.ttl_ms == 0 {
+            return KvError::InvalidCommand("HEXPIRE requires a positive ttl".into()).into();
+        }
+        match store.expire(&self.table, &self.key, Duration::from_millis(self.ttl_ms)) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Httl {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.ttl(&self.table, &self.key) {
+            Ok(Some(ttl)) => Value::from(ttl.as_millis() as i64).into(),
+            Ok(None) => Value::from(-1).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hpersist {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.persist(&self.table, &self.key) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hsetnx {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let pair = match self.pair {
+            Some(pair) => pair,
+            None => return KvError::InvalidCommand("HSETNX requires a pair".into()).into(),
+        };
+        let value = pair.value.unwrap_or_default();
+        match store.set_nx(&self.table, pair.key.clone(), value) {
+            Ok(true) => true.into(),
+            Ok(false) => KvError::Conflict(self.table, pair.key).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hcas {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let pair = match self.pair {
+            Some(pair) => pair,
+            None => return KvError::InvalidCommand("HCAS requires a pair".into()).into(),
+        };
+        let value = pair.value.unwrap_or_default();
+        match store.compare_and_set(&self.table, pair.key.clone(), self.expected.as_ref(), value) {
+            Ok(true) => true.into(),
+            Ok(false) => KvError::Conflict(self.table, pair.key).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hdelifeq {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let expected = match self.expected {
+            Some(v) => v,
+            None => {
+                return KvError::InvalidCommand("HDELIFEQ requires an expected value".into()).into()
+            }
+        };
+        match store.del_if_eq(&self.table, &self.key, &expected) {
+            Ok(true) => true.into(),
+            Ok(false) => KvError::Conflict(self.table, self.key).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hincrby {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.incr_by(&self.table, &self.key, self.delta) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hincrbyfloat {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        if !self.delta.is_finite() {
+            return KvError::InvalidCommand("HINCRBYFLOAT requires a finite delta".into()).into();
+        }
+        match store.incr_by_float(&self.table, &self.key, self.delta) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Hdecrby {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let delta = match self.delta.checked_neg() {
+            Some(delta) => delta,
+            None => return KvError::InvalidCommand("HDECRBY delta is out of range".into()).into(),
+        };
+        match store.incr_by(&self.table, &self.key, delta) {
+            Ok(v) => Value::from(v).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for CreateTable {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.create_table(&self.table) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for DropTable {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.drop_table(&self.table) {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for ListTables {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.list_tables() {
+            Ok(v) => v.into_iter().map(Value::from).collect::<Vec<_>>().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for RenameTable {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.rename_table(&self.table, &self.new_name) {
+            Ok(()) => Value::default().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for TruncateTable {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.truncate_table(&self.table) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Transaction {
+    /// Commands run one by one; if any of them fails, the changes of the previous
+    /// ones are undone. Isolation from concurrent requests is up to the caller,
+    /// `Service` runs transactions exclusively.
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let mut writes = Vec::with_capacity(self.commands.len());
+        for cmd in &self.commands {
+            match cmd.request_data.as_ref().map(written_keys) {
+                Some(Ok(keys)) => writes.push(keys),
+                Some(Err(e)) => return e.into(),
+                None => return KvError::InvalidCommand("Request has no data".into()).into(),
+            }
+        }
+
+        for watch in self.watches {
+            match store.get(&watch.table, &watch.key) {
+                Ok(v) if v == watch.value => {}
+                Ok(_) => return KvError::WatchFailed(watch.table, watch.key).into(),
+                Err(e) => return e.into(),
+            }
+        }
+
+        // A crash never leaves only some of the commands applied
+        match store.batch(|| apply(self.commands, writes, store)) {
+            Ok(res) => res,
+            Err(e) => e.into(),
+        }
+    }
+}
+
+/// Run the commands of a transaction, undoing them all if one fails
+fn apply(
+    commands: Vec<CommandRequest>,
+    writes: Vec<Vec<(String, String)>>,
+    store: &impl Storage,
+) -> CommandResponse {
+    let mut undo = Vec::new();
+    let mut results = Vec::with_capacity(commands.len());
+    for (i, (cmd, keys)) in commands.into_iter().zip(writes).enumerate() {
+        for (table, key) in keys {
+            match Undo::save(store, table, key) {
+                Ok(u) => undo.push(u),
+                Err(e) => {
+                    rollback(store, undo);
+                    return e.into();
+                }
+            }
+        }
+
+        let res = dispatch(cmd, store);
+        if !(200..300).contains(&res.status) {
+            rollback(store, undo);
+            let message = format!("Transaction aborted at command {}: {}", i, res.message);
+            results.push(res.clone());
+            return CommandResponse {
+                status: res.status,
+                message,
+                results,
+                ..Default::default()
+            };
+        }
+        results.push(res);
+    }
+
+    CommandResponse {
+        status: StatusCode::OK.as_u16() as _,
+        results,
+        ..Default::default()
+    }
+}
+
+/// The state of a key before a transaction modified it
+struct Undo {
+    table: String,
+    key: String,
+    value: Option<Value>,
+    ttl: Option<Duration>,
+}
+
+impl Undo {
+    fn save(store: &impl Storage, table: String, key: String) -> Result<Self, KvError> {
+        let value = store.get(&table, &key)?;
+        // Storages without ttl support can't have changed one either
+        let ttl = match value {
+            Some(_) => store.ttl(&table, &key).unwrap_or_default(),
+            None => None,
+        };
+        Ok(Self {
+            table,
+            key,
+            value,
+            ttl,
+        })
+    }
+
+    fn restore(self, store: &impl Storage) -> Result<(), KvError> {
+        match (self.value, self.ttl) {
+            (Some(v), Some(ttl)) => store.set_ex(&self.table, self.key, v, ttl).map(|_| ()),
+            (Some(v), None) => store.set(&self.table, self.key, v).map(|_| ()),
+            (None, _) => store.del(&self.table, &self.key).map(|_| ()),
+        }
+    }
+}
+
+/// Undo the changes in reverse order, so the oldest state of every key wins
+fn rollback(store: &impl Storage, undo: Vec<Undo>) {
+    for u in undo.into_iter().rev() {
+        let (table, key) = (u.table.clone(), u.key.clone());
+        if let Err(e) = u.restore(store) {
+            warn!("Failed to roll back {}/{}: {:?}", table, key, e);
+        }
+    }
+}
+
+/// Keys a command may modify, or an error if it can't run inside a transaction
+pub(super) fn written_keys(data: &RequestData) -> Result<Vec<(String, String)>, KvError> {
+    let (table, keys): (&str, Vec<&String>) = match data {
+        RequestData::Hget(_)
+        | RequestData::Hgetall(_)
+        | RequestData::Hmget(_)
+        | RequestData::Hexists(_)
+        | RequestData::Hmexists(_)
+        | RequestData::Hrange(_)
+        | RequestData::Hprefix(_)
+        | RequestData::Hscan(_)
+        | RequestData::Httl(_) => return Ok(Vec::new()),
+        RequestData::Hset(v) => (&v.table, v.pair.iter().map(|p| &p.key).collect()),
+        RequestData::Hmset(v) => (&v.table, v.pairs.iter().map(|p| &p.key).collect()),
+        RequestData::Hsetex(v) => (&v.table, v.pair.iter().map(|p| &p.key).collect()),
+        RequestData::Hsetnx(v) => (&v.table, v.pair.iter().map(|p| &p.key).collect()),
+        RequestData::Hcas(v) => (&v.table, v.pair.iter().map(|p| &p.key).collect()),
+        RequestData::Hdelifeq(v) => (&v.table, vec![&v.key]),
+        RequestData::Hincrby(v) => (&v.table, vec![&v.key]),
+        RequestData::Hincrbyfloat(v) => (&v.table, vec![&v.key]),
+        RequestData::Hdecrby(v) => (&v.table, vec![&v.key]),
+        RequestData::Hdel(v) => (&v.table, vec![&v.key]),
+        RequestData::Hmdel(v) => (&v.table, v.keys.iter().collect()),
+        RequestData::Hexpire(v) => (&v.table, vec![&v.key]),
+        RequestData::Hpersist(v) => (&v.table, vec![&v.key]),
+        RequestData::CreateTable(_)
+        | RequestData::DropTable(_)
+        | RequestData::ListTables(_)
+        | RequestData::RenameTable(_)
+        | RequestData::TruncateTable(_)
+        | RequestData::Transaction(_)
+        | RequestData::Subscribe(_)
+        | RequestData::Psubscribe(_)
+        | RequestData::Unsubscribe(_)
+        | RequestData::Publish(_)
+        | RequestData::Hwatch(_) => {
+            return Err(KvError::InvalidCommand(
+                "Only key commands are allowed in a transaction".into(),
+            ))
+        }
+    };
+    Ok(keys
+        .into_iter()
+        .map(|key| (table.to_owned(), key.clone()))
+        .collect())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::command_request::RequestData;
 
     #[test]
     fn hset_should_work() {
@@ -236,19 +633,341 @@ mod tests {
         assert_res_ok(res, &[true.into(), false.into()], &[]);
     }
 
-    fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
-        match cmd.request_data.unwrap() {
-            RequestData::Hget(v) => v.execute(store),
-            RequestData::Hmget(v) => v.execute(store),
-            RequestData::Hgetall(v) => v.execute(store),
-            RequestData::Hset(v) => v.execute(store),
-            RequestData::Hmset(v) => v.execute(store),
-            RequestData::Hdel(v) => v.execute(store),
-            RequestData::Hmdel(v) => v.execute(store),
-            RequestData::Hexists(v) => v.execute(store),
-            RequestData::Hmexists(v) => v.execute(store),
-            _ => unimplemented!(),
+    #[test]
+    fn hrange_should_work() {
+        let store = BTreeTable::new();
+        let pairs = ["a", "b", "c", "d"]
+            .map(|k| Kvpair::new(k, k.into()))
+            .to_vec();
+        dispatch(CommandRequest::new_hmset("t1", pairs.clone()), &store);
+
+        let cmd = CommandRequest::new_hrange("t1", "b", "", 0, false);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.pairs, &pairs[1..]);
+
+        let cmd = CommandRequest::new_hrange("t1", "", "d", 2, true);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.pairs, vec![pairs[2].clone(), pairs[1].clone()]);
+    }
+
+    #[test]
+    fn hscan_should_page_through_a_table() {
+        let store = MemTable::new();
+        let pairs: Vec<_> = (0..25)
+            .map(|i| Kvpair::new(format!("k{:02}", i), i.into()))
+            .collect();
+        dispatch(CommandRequest::new_hmset("t1", pairs.clone()), &store);
+
+        let (mut scanned, mut cursor) = (Vec::new(), String::new());
+        loop {
+            let res = dispatch(CommandRequest::new_hscan("t1", &cursor, 10), &store);
+            assert!(res.pairs.len() <= 10);
+            scanned.extend(res.pairs);
+            cursor = res.values[0].clone().try_into().unwrap();
+            if cursor.is_empty() {
+                break;
+            }
         }
+        assert_eq!(scanned, pairs);
+
+        // The default page size, and no next page when the last one is full
+        let res = dispatch(CommandRequest::new_hscan("t1", "k14", 0), &store);
+        assert_eq!(res.pairs, &pairs[15..]);
+        assert_eq!(res.values, vec![Value::from("")]);
+
+        // A larger count than the server allows gets the largest page
+        let pairs: Vec<_> = (0..=MAX_SCAN_COUNT)
+            .map(|i| Kvpair::new(format!("k{:04}", i), (i as i64).into()))
+            .collect();
+        dispatch(CommandRequest::new_hmset("t2", pairs.clone()), &store);
+        let res = dispatch(CommandRequest::new_hscan("t2", "", u32::MAX), &store);
+        assert_eq!(res.pairs, &pairs[..MAX_SCAN_COUNT]);
+        let cursor = &pairs[MAX_SCAN_COUNT - 1].key;
+        assert_eq!(res.values, vec![Value::from(cursor.as_str())]);
+    }
+
+    #[test]
+    fn hprefix_should_work() {
+        let store = MemTable::new();
+        let pairs = ["user:1", "user:2", "admin"]
+            .map(|k| Kvpair::new(k, k.into()))
+            .to_vec();
+        dispatch(CommandRequest::new_hmset("t1", pairs.clone()), &store);
+
+        let cmd = CommandRequest::new_hprefix("t1", "user:", 0, true);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.pairs, vec![pairs[1].clone(), pairs[0].clone()]);
+    }
+
+    #[test]
+    fn hsetex_and_httl_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_hsetex("session", "s1", "token".into(), 60_000);
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let res = dispatch(CommandRequest::new_httl("session", "s1"), &store);
+        assert_eq!(res.status, 200);
+        let ttl = match res.values[0].value {
+            Some(value::Value::Integer(i)) => i,
+            _ => panic!("ttl should be an integer"),
+        };
+        assert!(ttl > 59_000 && ttl <= 60_000);
+
+        let res = dispatch(CommandRequest::new_hpersist("session", "s1"), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_httl("session", "s1"), &store);
+        assert_res_ok(res, &[(-1).into()], &[]);
+    }
+
+    #[test]
+    fn hexpire_should_work() {
+        let store = MemTable::new();
+        dispatch(
+            CommandRequest::new_hset("session", "s1", "token".into()),
+            &store,
+        );
+        let res = dispatch(CommandRequest::new_hexpire("session", "s1", 1), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_hexpire("session", "s2", 1), &store);
+        assert_res_ok(res, &[false.into()], &[]);
+
+        std::thread::sleep(Duration::from_millis(5));
+        let res = dispatch(CommandRequest::new_hget("session", "s1"), &store);
+        assert_res_error(res, 404, "Not found");
+        let res = dispatch(CommandRequest::new_httl("session", "s1"), &store);
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn zero_ttl_should_be_rejected() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_hsetex("session", "s1", "token".into(), 0);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "positive ttl");
+    }
+
+    #[test]
+    fn table_commands_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_create_table("t1"), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        dispatch(
+            CommandRequest::new_hset("t2", "hello", "world".into()),
+            &store,
+        );
+        let res = dispatch(CommandRequest::new_list_tables(), &store);
+        assert_res_ok(res, &["t1".into(), "t2".into()], &[]);
+
+        let res = dispatch(CommandRequest::new_rename_table("t2", "t1"), &store);
+        assert_res_error(res, 409, "Table already exists: t1");
+        let res = dispatch(CommandRequest::new_rename_table("t2", "t3"), &store);
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let res = dispatch(CommandRequest::new_truncate_table("t3"), &store);
+        assert_res_ok(res, &[1.into()], &[]);
+        let res = dispatch(CommandRequest::new_truncate_table("t2"), &store);
+        assert_res_error(res, 404, "Table not found: t2");
+
+        let res = dispatch(CommandRequest::new_drop_table("t1"), &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_list_tables(), &store);
+        assert_res_ok(res, &["t3".into()], &[]);
+    }
+
+    #[test]
+    fn hget_should_not_create_table() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hget("t1", "hello"), &store);
+        assert_res_error(res, 404, "Not found");
+        let res = dispatch(CommandRequest::new_list_tables(), &store);
+        assert_res_ok(res, &[], &[]);
+    }
+
+    #[test]
+    fn hsetnx_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_hsetnx("locks", "leader", "node1".into());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let cmd = CommandRequest::new_hsetnx("locks", "leader", "node2".into());
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 409, "Condition failed for table: locks, key: leader");
+    }
+
+    #[test]
+    fn hcas_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_hcas("locks", "leader", None, "node1".into());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let cmd = CommandRequest::new_hcas("locks", "leader", Some("node2".into()), "node3".into());
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 409, "Condition failed");
+        let cmd = CommandRequest::new_hcas("locks", "leader", Some("node1".into()), "node2".into());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_hget("locks", "leader"), &store);
+        assert_res_ok(res, &["node2".into()], &[]);
+    }
+
+    #[test]
+    fn hdelifeq_should_work() {
+        let store = MemTable::new();
+        dispatch(
+            CommandRequest::new_hset("locks", "leader", "node1".into()),
+            &store,
+        );
+        let cmd = CommandRequest::new_hdelifeq("locks", "leader", "node2".into());
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 409, "Condition failed");
+        let cmd = CommandRequest::new_hdelifeq("locks", "leader", "node1".into());
+        let res = dispatch(cmd, &store);
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = dispatch(CommandRequest::new_hexists("locks", "leader"), &store);
+        assert_res_ok(res, &[false.into()], &[]);
+    }
+
+    #[test]
+    fn hincrby_and_hdecrby_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_hincrby("stats", "views", 10), &store);
+        assert_res_ok(res, &[10.into()], &[]);
+        let res = dispatch(CommandRequest::new_hdecrby("stats", "views", 3), &store);
+        assert_res_ok(res, &[7.into()], &[]);
+        let res = dispatch(
+            CommandRequest::new_hdecrby("stats", "views", i64::MIN),
+            &store,
+        );
+        assert_res_error(res, 400, "HDECRBY delta is out of range");
+    }
+
+    #[test]
+    fn hincrbyfloat_should_work() {
+        let store = MemTable::new();
+        let res = dispatch(
+            CommandRequest::new_hincrbyfloat("stats", "avg", 1.5),
+            &store,
+        );
+        assert_res_ok(res, &[1.5.into()], &[]);
+        let res = dispatch(
+            CommandRequest::new_hincrbyfloat("stats", "avg", f64::NAN),
+            &store,
+        );
+        assert_res_error(res, 400, "HINCRBYFLOAT requires a finite delta");
+    }
+
+    #[test]
+    fn hincrby_on_non_integer_should_return_400() {
+        let store = MemTable::new();
+        dispatch(
+            CommandRequest::new_hset("stats", "views", "many".into()),
+            &store,
+        );
+        let res = dispatch(CommandRequest::new_hincrby("stats", "views", 1), &store);
+        assert_res_error(res, 400, "Cannot convert value");
+    }
+
+    #[test]
+    fn transaction_should_apply_all_commands() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("stock", "a", 10.into()), &store);
+        let cmd = CommandRequest::new_transaction(
+            vec![
+                Watch::new("stock", "a", Some(10.into())),
+                Watch::new("stock", "b", None),
+            ],
+            vec![
+                CommandRequest::new_hset("stock", "a", 7.into()),
+                CommandRequest::new_hset("stock", "b", 3.into()),
+                CommandRequest::new_hmget("stock", vec!["a".into(), "b".into()]),
+            ],
+        );
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.status, 200);
+        let values: Vec<_> = res.results.into_iter().map(|r| r.values).collect();
+        assert_eq!(
+            values,
+            vec![
+                vec![10.into()],
+                vec![Value::default()],
+                vec![7.into(), 3.into()]
+            ]
+        );
+    }
+
+    #[test]
+    fn transaction_should_abort_when_watched_key_changed() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("stock", "a", 10.into()), &store);
+        let cmd = CommandRequest::new_transaction(
+            vec![Watch::new("stock", "a", Some(9.into()))],
+            vec![CommandRequest::new_hset("stock", "a", 7.into())],
+        );
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 409, "Watched key changed");
+        let res = dispatch(CommandRequest::new_hget("stock", "a"), &store);
+        assert_res_ok(res, &[10.into()], &[]);
+    }
+
+    #[test]
+    fn transaction_should_roll_back_on_failure() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("stock", "a", 10.into()), &store);
+        store
+            .set_ex("stock", "c".into(), 1.into(), Duration::from_secs(60))
+            .unwrap();
+        let cmd = CommandRequest::new_transaction(
+            vec![],
+            vec![
+                CommandRequest::new_hset("stock", "a", 7.into()),
+                CommandRequest::new_hset("stock", "b", 3.into()),
+                CommandRequest::new_hpersist("stock", "c"),
+                CommandRequest::new_hsetex("stock", "b", 4.into(), 0),
+            ],
+        );
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.status, 400);
+        assert!(res.message.contains("Transaction aborted at command 3"));
+        assert_eq!(res.results.len(), 4);
+
+        let res = dispatch(
+            CommandRequest::new_hmget("stock", vec!["a".into(), "b".into()]),
+            &store,
+        );
+        assert_res_ok(res, &[10.into(), Value::default()], &[]);
+        assert!(store.ttl("stock", "c").unwrap().is_some());
+    }
+
+    #[test]
+    fn transaction_should_reject_table_commands() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_transaction(
+            vec![],
+            vec![
+                CommandRequest::new_hset("t1", "k1", 1.into()),
+                CommandRequest::new_drop_table("t1"),
+            ],
+        );
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 400, "Only key commands are allowed");
+        assert_eq!(store.list_tables(), Ok(vec![]));
+    }
+
+    #[test]
+    fn hset_out_of_memory_should_return_507() {
+        let store = MemTable::with_options(MemTableOptions {
+            max_memory: 1,
+            policy: EvictionPolicy::NoEviction,
+            ..Default::default()
+        });
+        let cmd = CommandRequest::new_hset("t1", "hello", "world".into());
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 507, "Not enough memory");
+
+        let cmd = CommandRequest::new_hmset("t1", vec![Kvpair::new("hello", "world".into())]);
+        let res = dispatch(cmd, &store);
+        assert_res_error(res, 507, "Not enough memory");
     }
 
     // 测试成功返回的结果
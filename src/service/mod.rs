@@ -1,39 +1,128 @@
 use crate::{
-    command_request::RequestData, CommandRequest, CommandResponse, KvError, MemTable, Storage,
+    command_request::RequestData, CommandRequest, CommandResponse, KeyChange, KvError, Kvpair,
+    MemTable, Storage, Value,
 };
-use std::sync::Arc;
-use tracing::debug;
+use prost::{length_delimiter_len, Message as _};
+use std::{
+    any::Any,
+    iter::{self, Peekable},
+    net::SocketAddr,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, PoisonError, RwLock},
+};
+use tracing::{debug, warn};
 
+mod broker;
+mod changes;
 mod command_service;
 
+pub use broker::*;
+
+use changes::{register_expiry, ChangeTracker, WatchLocks};
+
+/// A hook called with an immutable event
+pub type Listener<Arg> = Box<dyn Fn(&Arg) + Send + Sync>;
+
+/// A hook called with a mutable event
+pub type ListenerMut<Arg> = Box<dyn Fn(&mut Arg) + Send + Sync>;
+
+/// A hook that may rewrite a received request, or answer it by returning a
+/// response, in which case the request is not executed
+pub type Interceptor = Box<dyn Fn(&mut CommandRequest) -> Option<CommandResponse> + Send + Sync>;
+
+/// A hook called once a response is written to the client, or failed to be
+pub type AfterSendHook = Box<dyn Fn(&AfterSend<'_>) + Send + Sync>;
+
+/// The connection a response is sent on
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Address of the client, None if the stream isn't a TCP connection
+    pub peer_addr: Option<SocketAddr>,
+    /// Whether the connection is encrypted with TLS
+    pub tls: bool,
+    /// Whether the response goes over a yamux stream of the connection
+    pub multiplexed: bool,
+}
+
+/// A response the transport tried to send, as told to the after-send hooks
+#[derive(Debug)]
+pub struct AfterSend<'a> {
+    /// The connection it was sent on
+    pub conn: &'a ConnectionInfo,
+    /// The request it answers, None for the messages pushed to a subscriber
+    pub request: Option<&'a CommandRequest>,
+    /// The response as sent, after the before-send hooks
+    pub response: &'a CommandResponse,
+    /// Ok once the frame is flushed, or why the client didn't get it
+    pub outcome: Result<(), &'a KvError>,
+}
+
 /// Notify immutable events
 pub trait Notify<Arg> {
+    /// Call every registered hook with `arg`
     fn notify(&self, arg: &Arg);
 }
 
-impl<Arg> Notify<Arg> for Vec<fn(&Arg)> {
+impl<Arg> Notify<Arg> for Vec<Listener<Arg>> {
     #[inline]
     fn notify(&self, arg: &Arg) {
         for f in self {
-            f(arg)
+            contain(|| f(arg));
         }
     }
 }
 
 /// Notify mutable events
 pub trait NotifyMut<Arg> {
+    /// Call every registered hook with a mutable `arg`
     fn notify(&self, arg: &mut Arg);
 }
 
-impl<Arg> NotifyMut<Arg> for Vec<fn(&mut Arg)> {
+impl<Arg> NotifyMut<Arg> for Vec<ListenerMut<Arg>> {
     #[inline]
     fn notify(&self, arg: &mut Arg) {
         for f in self {
-            f(arg)
+            contain(|| f(arg));
         }
     }
 }
 
+/// Hooks of every event in one object, for those sharing state such as metrics
+///
+/// Register it with `ServiceInner::with_hook`, every method does nothing by default.
+pub trait Hook: Send + Sync {
+    /// Called when a request is received
+    fn on_received(&self, _cmd: &CommandRequest) {}
+    /// Rewrite a received request, or answer it in place of the storage
+    fn intercept(&self, _cmd: &mut CommandRequest) -> Option<CommandResponse> {
+        None
+    }
+    /// Called after a request is executed
+    fn on_executed(&self, _res: &CommandResponse) {}
+    /// Modify the response before it is sent
+    fn on_before_send(&self, _res: &mut CommandResponse) {}
+    /// Called once the response is written to the client, or failed to be
+    fn on_after_send(&self, _sent: &AfterSend) {}
+}
+
+/// Run a hook, so that a panic is logged instead of unwinding into the service
+fn contain<R>(hook: impl FnOnce() -> R) -> Option<R> {
+    match panic::catch_unwind(AssertUnwindSafe(hook)) {
+        Ok(r) => Some(r),
+        Err(e) => {
+            warn!("A hook panicked: {}", panic_message(&*e));
+            None
+        }
+    }
+}
+
+fn panic_message(e: &(dyn Any + Send)) -> &str {
+    match e.downcast_ref::<&str>() {
+        Some(msg) => msg,
+        None => e.downcast_ref::<String>().map_or("unknown", String::as_str),
+    }
+}
+
 /// 对 Command 的处理的抽象
 pub trait CommandService {
     /// 处理 Command，返回 Response
@@ -54,10 +143,228 @@ impl<Store> Clone for Service<Store> {
 }
 
 impl<Store: Storage> Service<Store> {
-    pub fn execute(&self, cmd: CommandRequest) -> CommandResponse {
-        debug!("Git request: {:?}", cmd);
-        self.inner.on_received.notify(&cmd);
-        let mut res = dispatch(cmd, &self.inner.store);
+    /// Run the hooks around dispatching `cmd` to the storage
+    pub fn execute(&self, mut cmd: CommandRequest) -> CommandResponse {
+        match self.receive(&mut cmd) {
+            Some(res) => res,
+            None => self.run(cmd),
+        }
+    }
+
+    /// Execute a received request
+    fn run(&self, cmd: CommandRequest) -> CommandResponse {
+        let (store, broker) = (&self.inner.store, &self.inner.broker);
+        let tracker = cmd.request_data.as_ref().map(ChangeTracker::new);
+        let res = match &cmd.request_data {
+            // Messages go through the broker, never the storage
+            Some(RequestData::Publish(publish)) => {
+                let value = publish.value.clone().unwrap_or_default();
+                let delivered = broker.publish(&publish.channel, value);
+                Value::from(delivered as i64).into()
+            }
+            Some(RequestData::Transaction(_)) => {
+                let _guard = self
+                    .inner
+                    .txn_lock
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner);
+                let tracker = tracker
+                    .and_then(|tracker| tracker.watched(broker))
+                    .map(|tracker| tracker.capture(store));
+                let res = dispatch(cmd, store);
+                if let Some(tracker) = tracker {
+                    // Still exclusive, so the watchers get the changes in order
+                    broker.notify(tracker.finish(&res, store, broker));
+                }
+                res
+            }
+            _ => {
+                let _guard = self
+                    .inner
+                    .txn_lock
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner);
+                let watch_locks = &self.inner.watch_locks;
+                // Whether the command is tracked holds until it's done, as no
+                // watch is added to its tables meanwhile
+                let _tables = tracker
+                    .as_ref()
+                    .map(|tracker| watch_locks.lock_tables(tracker));
+                match tracker.and_then(|tracker| tracker.watched(broker)) {
+                    // Watched keys are read around the command, so no other
+                    // command changing them may run meanwhile
+                    Some(tracker) => {
+                        let _watched = watch_locks.lock_keys(&tracker);
+                        let tracker = tracker.capture(store);
+                        let res = dispatch(cmd, store);
+                        broker.notify(tracker.finish(&res, store, broker));
+                        res
+                    }
+                    None => dispatch(cmd, store),
+                }
+            }
+        };
+        self.respond(res)
+    }
+
+    /// Wait until a watched key is due to expire, for `notify_expired` to tell
+    /// its watchers
+    pub async fn expiry_due(&self) {
+        self.inner.broker.expiry_due().await
+    }
+
+    /// Let the watchers know about the watched keys whose ttl ran out
+    pub fn notify_expired(&self) {
+        let expired = self.inner.broker.take_expired();
+        if expired.is_empty() {
+            return;
+        }
+        let _guard = self
+            .inner
+            .txn_lock
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        // A key set again since then is no longer expired
+        let changes = expired
+            .into_iter()
+            .filter(|(table, key, _)| matches!(self.inner.store.get(table, key), Ok(None)))
+            .map(|(table, key, value)| KeyChange {
+                table,
+                key,
+                old_value: Some(value),
+                new_value: None,
+                expired: true,
+            })
+            .collect();
+        self.inner.broker.notify(changes);
+    }
+
+    /// Register a subscriber of the broker, for a connection to subscribe with
+    pub fn subscriber(&self) -> Subscriber {
+        self.inner.broker.subscriber()
+    }
+
+    /// Run SUBSCRIBE, PSUBSCRIBE, UNSUBSCRIBE and HWATCH on the `subscriber` of the
+    /// connection, answering with the number of subscriptions it holds.
+    /// Other commands go to `execute`.
+    pub fn execute_subscription(
+        &self,
+        mut cmd: CommandRequest,
+        subscriber: &mut Subscriber,
+    ) -> CommandResponse {
+        if !is_subscription(&cmd) {
+            return self.execute(cmd);
+        }
+        if let Some(res) = self.receive(&mut cmd) {
+            return res;
+        }
+        let subscriptions = match cmd.request_data {
+            Some(RequestData::Subscribe(v)) => subscriber.subscribe(v.channels),
+            Some(RequestData::Psubscribe(v)) => subscriber.psubscribe(v.patterns),
+            Some(RequestData::Unsubscribe(v)) => subscriber.unsubscribe(v.topics),
+            Some(RequestData::Hwatch(v)) => {
+                let (store, broker) = (&self.inner.store, &self.inner.broker);
+                let _guard = self
+                    .inner
+                    .txn_lock
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner);
+                // Writes to the table wait for the watch and are tracked, the
+                // ones it waited for are done
+                let _watched = self.inner.watch_locks.lock_table(&v.table);
+                let subscriptions = subscriber.watch(v.clone());
+                register_expiry(&v, store, broker);
+                subscriptions
+            }
+            // Rewritten by a hook
+            _ => return self.run(cmd),
+        };
+        self.respond(Value::from(subscriptions as i64).into())
+    }
+
+    /// Like `execute`, with HGETALL answered by a stream of responses that walks
+    /// the table with `Storage::get_iter`, a page at a time. Every page is read
+    /// between transactions, but the pages may differ in what they have seen.
+    /// Every response of the stream but the last one has `more` set, and a
+    /// table that fails to be read ends it with an error.
+    ///
+    /// The responses are sized to take at most half of `max_len`, the frame
+    /// limit of the connection, leaving room for the rest of the response.
+    ///
+    /// Other commands are answered with a single response.
+    pub fn execute_streaming(
+        &self,
+        mut cmd: CommandRequest,
+        max_len: usize,
+    ) -> Box<dyn Iterator<Item = CommandResponse> + '_> {
+        if !matches!(cmd.request_data, Some(RequestData::Hgetall(_))) {
+            return Box::new(iter::once(self.execute(cmd)));
+        }
+        if let Some(res) = self.receive(&mut cmd) {
+            return Box::new(iter::once(res));
+        }
+        let table = match &cmd.request_data {
+            Some(RequestData::Hgetall(hgetall)) => hgetall.table.clone(),
+            // Rewritten by a hook
+            _ => return Box::new(iter::once(self.run(cmd))),
+        };
+        let read = || {
+            self.inner
+                .txn_lock
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+        };
+        let pairs = {
+            let _guard = read();
+            self.inner.store.get_iter(&table)
+        };
+        match pairs {
+            Ok(mut pairs) => {
+                // The next page is loaded by whichever call reaches its end
+                let pairs = iter::from_fn(move || {
+                    let _guard = read();
+                    pairs.next()
+                });
+                let chunk_len = STREAM_CHUNK_LEN.min(max_len / 2);
+                Box::new(Chunks::new(pairs, chunk_len).map(|res| self.respond(res)))
+            }
+            Err(e) => Box::new(iter::once(self.respond(e.into()))),
+        }
+    }
+
+    /// Whether any after-send hook is registered, the transport only keeps a
+    /// copy of the requests and responses for them then
+    pub fn has_after_send(&self) -> bool {
+        !self.inner.on_after_send.is_empty()
+    }
+
+    /// Run the after-send hooks, once the transport knows whether the response
+    /// reached the client
+    pub fn after_send(&self, sent: &AfterSend) {
+        for f in &self.inner.on_after_send {
+            contain(|| f(sent));
+        }
+    }
+
+    /// Run the hooks on a received request, and return the response if one of
+    /// them answered it. A request whose interceptor panics is rejected.
+    fn receive(&self, cmd: &mut CommandRequest) -> Option<CommandResponse> {
+        debug!("Got request: {:?}", cmd);
+        self.inner.on_received.notify(cmd);
+        for intercept in &self.inner.on_intercept {
+            let res = match contain(|| intercept(cmd)) {
+                Some(None) => continue,
+                Some(Some(res)) => res,
+                None => KvError::Internal("a hook failed on the request".into()).into(),
+            };
+            debug!("Intercepted request: {:?}", cmd);
+            return Some(self.respond(res));
+        }
+        None
+    }
+
+    /// Run the hooks on a response about to be sent
+    fn respond(&self, mut res: CommandResponse) -> CommandResponse {
         debug!("Executed response: {:?}", res);
         self.inner.on_executed.notify(&res);
         self.inner.on_before_send.notify(&mut res);
@@ -69,46 +376,161 @@ impl<Store: Storage> Service<Store> {
     }
 }
 
+/// A response of a stream holds at most this many bytes of pairs
+const STREAM_CHUNK_LEN: usize = 64 * 1024;
+
+/// Responses of a stream, each holding the next pairs up to `chunk_len` bytes,
+/// or a single pair larger than that. A pair that fails to load ends the
+/// stream with an error.
+struct Chunks<I: Iterator> {
+    pairs: Peekable<I>,
+    chunk_len: usize,
+    done: bool,
+}
+
+impl<I: Iterator<Item = Result<Kvpair, KvError>>> Chunks<I> {
+    fn new(pairs: I, chunk_len: usize) -> Self {
+        Self {
+            pairs: pairs.peekable(),
+            chunk_len,
+            done: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<Kvpair, KvError>>> Iterator for Chunks<I> {
+    type Item = CommandResponse;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let (mut chunk, mut len) = (Vec::new(), 0);
+        loop {
+            let pair_len = match self.pairs.peek() {
+                // As encoded in the pairs of the response
+                Some(Ok(pair)) => 1 + length_delimiter_len(pair.encoded_len()) + pair.encoded_len(),
+                // The pairs read so far go first
+                Some(Err(_)) if chunk.is_empty() => {
+                    self.done = true;
+                    return self.pairs.next().and_then(Result::err).map(Into::into);
+                }
+                Some(Err(_)) | None => break,
+            };
+            if !chunk.is_empty() && len + pair_len > self.chunk_len {
+                break;
+            }
+            chunk.extend(self.pairs.next().and_then(Result::ok));
+            len += pair_len;
+        }
+        // An empty table still gets the response marking the end
+        let mut res = CommandResponse::from(chunk);
+        res.more = self.pairs.peek().is_some();
+        self.done = !res.more;
+        Some(res)
+    }
+}
+
+/// Whether `cmd` changes the subscriptions of a connection
+pub fn is_subscription(cmd: &CommandRequest) -> bool {
+    matches!(
+        cmd.request_data,
+        Some(
+            RequestData::Subscribe(_)
+                | RequestData::Psubscribe(_)
+                | RequestData::Unsubscribe(_)
+                | RequestData::Hwatch(_)
+        )
+    )
+}
+
 /// Service 内部数据结构
 pub struct ServiceInner<Store> {
     store: Store,
-    on_received: Vec<fn(&CommandRequest)>,
-    on_executed: Vec<fn(&CommandResponse)>,
-    on_before_send: Vec<fn(&mut CommandResponse)>,
-    on_after_send: Vec<fn()>,
+    broker: Arc<Broker>,
+    // Transactions hold it exclusively, every other command shares it
+    txn_lock: RwLock<()>,
+    // Serializes the commands changing the same watched keys
+    watch_locks: WatchLocks,
+    on_received: Vec<Listener<CommandRequest>>,
+    on_intercept: Vec<Interceptor>,
+    on_executed: Vec<Listener<CommandResponse>>,
+    on_before_send: Vec<ListenerMut<CommandResponse>>,
+    on_after_send: Vec<AfterSendHook>,
 }
 
 impl<Store: Storage> ServiceInner<Store> {
     /// Create a service containing the storage and hooks
     pub fn new(store: Store) -> Self {
-        Self { 
+        Self {
             store,
+            broker: Arc::new(Broker::new(BrokerOptions::default())),
+            txn_lock: RwLock::new(()),
+            watch_locks: WatchLocks::default(),
             on_received: Vec::new(),
+            on_intercept: Vec::new(),
             on_executed: Vec::new(),
             on_before_send: Vec::new(),
             on_after_send: Vec::new(),
         }
     }
 
-    pub fn fn_received(mut self, f: fn(&CommandRequest)) -> Self {
-        self.on_received.push(f);
+    /// Replace the broker with one using `options`
+    pub fn with_broker_options(mut self, options: BrokerOptions) -> Self {
+        self.broker = Arc::new(Broker::new(options));
+        self
+    }
+
+    /// Register a hook called when a request is received
+    pub fn fn_received(mut self, f: impl Fn(&CommandRequest) + Send + Sync + 'static) -> Self {
+        self.on_received.push(Box::new(f));
+        self
+    }
+
+    /// Register a hook that may rewrite a received request, or answer it by
+    /// returning a response, which then goes through the response hooks
+    /// without the request being executed
+    pub fn fn_intercept(
+        mut self,
+        f: impl Fn(&mut CommandRequest) -> Option<CommandResponse> + Send + Sync + 'static,
+    ) -> Self {
+        self.on_intercept.push(Box::new(f));
         self
     }
 
-    pub fn fn_executed(mut self, f: fn(&CommandResponse)) -> Self {
-        self.on_executed.push(f);
+    /// Register a hook called after a request is executed
+    pub fn fn_executed(mut self, f: impl Fn(&CommandResponse) + Send + Sync + 'static) -> Self {
+        self.on_executed.push(Box::new(f));
         self
     }
 
-    pub fn fn_before_send(mut self, f: fn(&mut CommandResponse)) -> Self {
-        self.on_before_send.push(f);
+    /// Register a hook that may modify the response before it is sent
+    pub fn fn_before_send(
+        mut self,
+        f: impl Fn(&mut CommandResponse) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_before_send.push(Box::new(f));
         self
     }
 
-    pub fn fn_after_send(mut self, f: fn()) -> Self {
-        self.on_after_send.push(f);
+    /// Register a hook called once a response is written to the client, or
+    /// failed to be. The TCP and RESP protocols call it, the HTTP and gRPC
+    /// gateways can't tell when a response is flushed and refuse to start.
+    pub fn fn_after_send(mut self, f: impl Fn(&AfterSend) + Send + Sync + 'static) -> Self {
+        self.on_after_send.push(Box::new(f));
         self
     }
+
+    /// Register every method of `hook`
+    pub fn with_hook(self, hook: Arc<dyn Hook>) -> Self {
+        let (received, intercept, executed) = (hook.clone(), hook.clone(), hook.clone());
+        let (before_send, after_send) = (hook.clone(), hook);
+        self.fn_received(move |cmd| received.on_received(cmd))
+            .fn_intercept(move |cmd| intercept.intercept(cmd))
+            .fn_executed(move |res| executed.on_executed(res))
+            .fn_before_send(move |res| before_send.on_before_send(res))
+            .fn_after_send(move |sent| after_send.on_after_send(sent))
+    }
 }
 
 impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
@@ -120,26 +542,73 @@ impl<Store: Storage> From<ServiceInner<Store>> for Service<Store> {
     }
 }
 
-/// 从 Request 中得到 Response，目前处理 HGET/HGETALL/HSET
+/// 从 Request 中得到 Response
+///
+/// Every `RequestData` variant must be listed here explicitly: there is no
+/// wildcard arm, so adding a message to `abi.proto` without wiring up its
+/// `CommandService` implementation fails to compile.
 pub fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
-    match cmd.request_data {
-        Some(RequestData::Hget(param)) => param.execute(store),
-        Some(RequestData::Hmget(param)) => param.execute(store),
-        Some(RequestData::Hgetall(param)) => param.execute(store),
-        Some(RequestData::Hset(param)) => param.execute(store),
-        None => KvError::InvalidCommand("Request has no data".to_owned()).into(),
-        _ => KvError::Internal("Not implemented".to_owned()).into(),
+    let data = match cmd.request_data {
+        Some(data) => data,
+        None => return KvError::InvalidCommand("Request has no data".to_owned()).into(),
+    };
+
+    match data {
+        RequestData::Hget(param) => param.execute(store),
+        RequestData::Hgetall(param) => param.execute(store),
+        RequestData::Hmget(param) => param.execute(store),
+        RequestData::Hset(param) => param.execute(store),
+        RequestData::Hmset(param) => param.execute(store),
+        RequestData::Hdel(param) => param.execute(store),
+        RequestData::Hmdel(param) => param.execute(store),
+        RequestData::Hexists(param) => param.execute(store),
+        RequestData::Hmexists(param) => param.execute(store),
+        RequestData::Hrange(param) => param.execute(store),
+        RequestData::Hprefix(param) => param.execute(store),
+        RequestData::Hsetex(param) => param.execute(store),
+        RequestData::Hexpire(param) => param.execute(store),
+        RequestData::Httl(param) => param.execute(store),
+        RequestData::Hpersist(param) => param.execute(store),
+        RequestData::CreateTable(param) => param.execute(store),
+        RequestData::DropTable(param) => param.execute(store),
+        RequestData::ListTables(param) => param.execute(store),
+        RequestData::RenameTable(param) => param.execute(store),
+        RequestData::TruncateTable(param) => param.execute(store),
+        RequestData::Transaction(param) => param.execute(store),
+        RequestData::Hsetnx(param) => param.execute(store),
+        RequestData::Hcas(param) => param.execute(store),
+        RequestData::Hdelifeq(param) => param.execute(store),
+        RequestData::Hincrby(param) => param.execute(store),
+        RequestData::Hincrbyfloat(param) => param.execute(store),
+        RequestData::Hdecrby(param) => param.execute(store),
+        RequestData::Hscan(param) => param.execute(store),
+        RequestData::Subscribe(_)
+        | RequestData::Psubscribe(_)
+        | RequestData::Unsubscribe(_)
+        | RequestData::Hwatch(_) => {
+            KvError::InvalidCommand("Subscriptions need a TCP connection".into()).into()
+        }
+        RequestData::Publish(_) => {
+            KvError::InvalidCommand("PUBLISH goes through Service::execute".into()).into()
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use http::StatusCode;
-    use std::thread;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
     use tracing::info;
 
     use super::*;
-    use crate::{MemTable, Value};
+    use crate::{value, Kvpair, MemTable, Value, Watch};
 
     #[test]
     fn service_should_works() {
@@ -168,8 +637,8 @@ mod tests {
         fn d(res: &mut CommandResponse) {
             res.status = StatusCode::CREATED.as_u16() as _;
         }
-        fn e() {
-            info!("Data is sent");
+        fn e(sent: &AfterSend) {
+            info!("Data is sent: {:?}", sent.outcome);
         }
 
         let service: Service = ServiceInner::new(MemTable::default())
@@ -181,30 +650,381 @@ mod tests {
             .into();
 
         let res = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
-        assert_eq!(res.status, StatusCode::CREATED.as_u16() as _);
+        assert_eq!(res.status, StatusCode::CREATED.as_u16() as u32);
         assert_eq!(res.message, "");
         assert_eq!(res.values, vec![Value::default()]);
     }
-}
 
-#[cfg(test)]
-use crate::{Kvpair, Value};
+    #[test]
+    fn hooks_should_capture_state() {
+        #[derive(Default)]
+        struct Metrics {
+            received: AtomicUsize,
+            failed: AtomicUsize,
+        }
 
-// 测试成功返回的结果
-#[cfg(test)]
-pub fn assert_res_ok(mut res: CommandResponse, values: &[Value], pairs: &[Kvpair]) {
-    res.pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    assert_eq!(res.status, 200);
-    assert_eq!(res.message, "");
-    assert_eq!(res.values, values);
-    assert_eq!(res.pairs, pairs);
-}
+        impl Hook for Metrics {
+            fn on_received(&self, _cmd: &CommandRequest) {
+                self.received.fetch_add(1, Ordering::Relaxed);
+            }
+            fn on_executed(&self, res: &CommandResponse) {
+                if res.status >= 400 {
+                    self.failed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
 
-// 测试失败返回的结果
-#[cfg(test)]
-fn assert_res_error(res: CommandResponse, code: u32, msg: &str) {
-    assert_eq!(res.status, code);
-    assert!(res.message.contains(msg));
-    assert_eq!(res.values, &[]);
-    assert_eq!(res.pairs, &[]);
+        let metrics = Arc::new(Metrics::default());
+        let statuses = Arc::new(Mutex::new(Vec::new()));
+        let seen = statuses.clone();
+        let service: Service = ServiceInner::new(MemTable::default())
+            .with_hook(metrics.clone())
+            .fn_executed(move |res| seen.lock().unwrap().push(res.status))
+            .into();
+
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        service.execute(CommandRequest::new_hget("t1", "k2"));
+        assert_eq!(metrics.received.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.failed.load(Ordering::Relaxed), 1);
+        assert_eq!(*statuses.lock().unwrap(), vec![200, 404]);
+    }
+
+    #[test]
+    fn interceptors_should_reject_or_rewrite_requests() {
+        let service: Service = ServiceInner::new(MemTable::default())
+            .fn_intercept(|cmd| match &cmd.request_data {
+                Some(RequestData::DropTable(_)) => {
+                    let mut res = CommandResponse::from(KvError::InvalidCommand("denied".into()));
+                    res.status = StatusCode::FORBIDDEN.as_u16() as _;
+                    Some(res)
+                }
+                _ => None,
+            })
+            // Every client gets its own tenant's table
+            .fn_intercept(|cmd| {
+                if let Some(RequestData::Hset(hset)) = &mut cmd.request_data {
+                    hset.table = format!("tenant1.{}", hset.table);
+                }
+                None
+            })
+            .into();
+
+        let res = service.execute(CommandRequest::new_drop_table("t1"));
+        assert_res_error(res, 403, "denied");
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        let res = service.execute(CommandRequest::new_hget("tenant1.t1", "k1"));
+        assert_res_ok(res, &["v1".into()], &[]);
+    }
+
+    #[test]
+    fn hook_panics_should_be_contained() {
+        let service: Service = ServiceInner::new(MemTable::default())
+            .fn_received(|_| panic!("broken metrics"))
+            .fn_before_send(|res| {
+                res.message = "seen".into();
+                panic!("broken logger")
+            })
+            .into();
+        let res = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert_eq!((res.status, res.message.as_str()), (200, "seen"));
+
+        // A request can't slip through a failing interceptor
+        let service: Service = ServiceInner::new(MemTable::default())
+            .fn_intercept(|_| panic!("broken auth"))
+            .into();
+        let res = service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert_res_error(res, 500, "a hook failed");
+        let store = &service.inner.store;
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+    }
+
+    #[test]
+    fn hgetall_should_be_streamed_in_chunks() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let value = Value::from("v".repeat(1000));
+        let pairs: Vec<_> = (0..200)
+            .map(|i| Kvpair::new(format!("k{}", i), value.clone()))
+            .collect();
+        service.execute(CommandRequest::new_hmset("t1", pairs));
+
+        let responses: Vec<_> = service
+            .execute_streaming(CommandRequest::new_hgetall("t1"), usize::MAX)
+            .collect();
+        assert!(responses.len() > 1);
+        let (last, chunks) = responses.split_last().unwrap();
+        assert!(chunks.iter().all(|res| res.more && res.status == 200));
+        assert!(!last.more);
+        let count: usize = responses.iter().map(|res| res.pairs.len()).sum();
+        assert_eq!(count, 200);
+
+        // A lower frame limit makes the chunks smaller
+        let smaller: Vec<_> = service
+            .execute_streaming(CommandRequest::new_hgetall("t1"), 8 * 1024)
+            .collect();
+        assert!(smaller.len() > responses.len());
+        assert!(smaller.iter().all(|res| res.encoded_len() <= 8 * 1024));
+        let count: usize = smaller.iter().map(|res| res.pairs.len()).sum();
+        assert_eq!(count, 200);
+
+        // An empty table still ends its stream
+        let responses: Vec<_> = service
+            .execute_streaming(CommandRequest::new_hgetall("t2"), usize::MAX)
+            .collect();
+        assert_eq!(responses.len(), 1);
+        assert_res_ok(responses[0].clone(), &[], &[]);
+        assert!(!responses[0].more);
+    }
+
+    #[test]
+    fn failed_reads_should_end_the_stream_with_an_error() {
+        let pairs = vec![
+            Ok(Kvpair::new("k1", 1.into())),
+            Err(KvError::Internal("broken page".into())),
+            Ok(Kvpair::new("k2", 2.into())),
+        ];
+        let responses: Vec<_> = Chunks::new(pairs.into_iter(), usize::MAX).collect();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].pairs, vec![Kvpair::new("k1", 1.into())]);
+        assert!(responses[0].more);
+        assert_res_error(responses[1].clone(), 500, "broken page");
+        assert!(!responses[1].more);
+    }
+
+    #[test]
+    fn request_without_data_should_return_400() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let res = service.execute(CommandRequest::default());
+        assert_res_error(res, 400, "Request has no data");
+    }
+
+    #[test]
+    fn every_command_should_be_dispatched() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let pairs = vec![Kvpair::new("k1", 1.into()), Kvpair::new("k2", 2.into())];
+        let keys = vec!["k1".to_owned(), "k2".to_owned()];
+
+        let res = service.execute(CommandRequest::new_hmset("t1", pairs.clone()));
+        assert_res_ok(res, &[Value::default(), Value::default()], &[]);
+        let res = service.execute(CommandRequest::new_hmexists("t1", keys.clone()));
+        assert_res_ok(res, &[true.into(), true.into()], &[]);
+        let res = service.execute(CommandRequest::new_hexists("t1", "k1"));
+        assert_res_ok(res, &[true.into()], &[]);
+        let res = service.execute(CommandRequest::new_hdel("t1", "k1"));
+        assert_res_ok(res, &[1.into()], &[]);
+        let res = service.execute(CommandRequest::new_hmdel("t1", keys));
+        assert_res_ok(res, &[Value::default(), 2.into()], &[]);
+    }
+
+    #[test]
+    fn transactions_should_be_isolated() {
+        fn int(v: &Value) -> i64 {
+            match v.value {
+                Some(value::Value::Integer(i)) => i,
+                _ => panic!("Expected an integer, got {:?}", v),
+            }
+        }
+
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        service.execute(CommandRequest::new_hset("stock", "a", 100.into()));
+        service.execute(CommandRequest::new_hset("stock", "b", 0.into()));
+
+        // Move one item from a to b in every transaction, retrying on conflicts
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let service = service.clone();
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        loop {
+                            let cmd =
+                                CommandRequest::new_hmget("stock", vec!["a".into(), "b".into()]);
+                            let res = service.execute(cmd);
+                            let (a, b) = (int(&res.values[0]), int(&res.values[1]));
+                            let cmd = CommandRequest::new_transaction(
+                                vec![
+                                    Watch::new("stock", "a", Some(a.into())),
+                                    Watch::new("stock", "b", Some(b.into())),
+                                ],
+                                vec![
+                                    CommandRequest::new_hset("stock", "a", (a - 1).into()),
+                                    CommandRequest::new_hset("stock", "b", (b + 1).into()),
+                                ],
+                            );
+                            if service.execute(cmd).status == 200 {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let res = service.execute(CommandRequest::new_hmget(
+            "stock",
+            vec!["a".into(), "b".into()],
+        ));
+        assert_res_ok(res, &[60.into(), 40.into()], &[]);
+    }
+
+    #[test]
+    fn watched_changes_should_be_pushed() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let mut subscriber = service.subscriber();
+        let res = service.execute_subscription(
+            CommandRequest::new_hwatch("t1", "user.", true),
+            &mut subscriber,
+        );
+        assert_res_ok(res, &[1.into()], &[]);
+        let mut changes = || {
+            let mut changes = Vec::new();
+            while let Some(message) = subscriber.try_recv() {
+                match message {
+                    Message::Change(c) => {
+                        changes.push((c.key, c.old_value, c.new_value, c.expired))
+                    }
+                    other => panic!("expected a change, got {:?}", other),
+                }
+            }
+            changes
+        };
+
+        service.execute(CommandRequest::new_hset("t1", "user.a", 1.into()));
+        service.execute(CommandRequest::new_hset("t1", "other", 1.into()));
+        service.execute(CommandRequest::new_hset("t1", "user.a", 1.into()));
+        service.execute(CommandRequest::new_hincrby("t1", "user.a", 2));
+        service.execute(CommandRequest::new_hdel("t1", "user.a"));
+        let a = "user.a".to_string();
+        assert_eq!(
+            changes(),
+            vec![
+                (a.clone(), None, Some(1.into()), false),
+                (a.clone(), Some(1.into()), Some(3.into()), false),
+                (a, Some(3.into()), None, false),
+            ]
+        );
+
+        service.execute(CommandRequest::new_transaction(
+            vec![],
+            vec![
+                CommandRequest::new_hset("t1", "user.b", 1.into()),
+                CommandRequest::new_hset("t1", "user.c", 2.into()),
+            ],
+        ));
+        service.execute(CommandRequest::new_truncate_table("t1"));
+        let (b, c) = ("user.b".to_string(), "user.c".to_string());
+        assert_eq!(
+            changes(),
+            vec![
+                (b.clone(), None, Some(1.into()), false),
+                (c.clone(), None, Some(2.into()), false),
+                (b, Some(1.into()), None, false),
+                (c, Some(2.into()), None, false),
+            ]
+        );
+
+        // Nothing is due yet
+        service.execute(CommandRequest::new_hsetex("t1", "user.d", 4.into(), 60_000));
+        service.notify_expired();
+        assert_eq!(
+            changes(),
+            vec![("user.d".to_string(), None, Some(4.into()), false)]
+        );
+    }
+
+    #[test]
+    fn watched_keys_should_expire() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        let mut subscriber = service.subscriber();
+        let watch = CommandRequest::new_hwatch("t1", "user.", true);
+        service.execute_subscription(watch, &mut subscriber);
+
+        service.execute(CommandRequest::new_hsetex("t1", "user.a", 1.into(), 20));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut changes = Vec::new();
+        while !changes.iter().any(|(_, _, expired)| *expired) {
+            assert!(Instant::now() < deadline, "no expiry in {:?}", changes);
+            thread::sleep(Duration::from_millis(10));
+            service.notify_expired();
+            while let Some(Message::Change(c)) = subscriber.try_recv() {
+                changes.push((c.old_value, c.new_value, c.expired));
+            }
+        }
+        assert_eq!(
+            changes,
+            vec![(None, Some(1.into()), false), (Some(1.into()), None, true),]
+        );
+    }
+
+    #[test]
+    fn ttls_set_before_a_watch_should_expire() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        service.execute(CommandRequest::new_hsetex("t1", "a", 1.into(), 20));
+        service.execute(CommandRequest::new_hsetex("t1", "b", 2.into(), 20));
+        service.execute(CommandRequest::new_hsetex("t2", "c", 3.into(), 20));
+        let mut subscriber = service.subscriber();
+        for watch in [
+            CommandRequest::new_hwatch("t1", "", false),
+            CommandRequest::new_hwatch("t2", "c", false),
+        ] {
+            service.execute_subscription(watch, &mut subscriber);
+        }
+
+        thread::sleep(Duration::from_millis(30));
+        service.notify_expired();
+        let mut expired = Vec::new();
+        while let Some(Message::Change(c)) = subscriber.try_recv() {
+            assert!(c.expired);
+            expired.push((c.table, c.key));
+        }
+        expired.sort();
+        let expected = [("t1", "a"), ("t1", "b"), ("t2", "c")];
+        assert_eq!(
+            expired,
+            expected.map(|(t, k)| (t.to_string(), k.to_string()))
+        );
+    }
+
+    #[test]
+    fn renaming_a_watched_table_should_move_its_keys() {
+        let service: Service = ServiceInner::new(MemTable::default()).into();
+        service.execute(CommandRequest::new_hset("t1", "a", 1.into()));
+        let mut subscriber = service.subscriber();
+        for table in ["t1", "t2"] {
+            let watch = CommandRequest::new_hwatch(table, "", false);
+            service.execute_subscription(watch, &mut subscriber);
+        }
+
+        service.execute(CommandRequest::new_rename_table("t1", "t2"));
+        service.execute(CommandRequest::new_rename_table("t1", "t2"));
+        let mut changes = Vec::new();
+        while let Some(Message::Change(c)) = subscriber.try_recv() {
+            changes.push((c.table, c.old_value, c.new_value));
+        }
+        assert_eq!(
+            changes,
+            vec![
+                ("t1".to_string(), Some(1.into()), None),
+                ("t2".to_string(), None, Some(1.into())),
+            ]
+        );
+    }
+
+    // 测试成功返回的结果
+    fn assert_res_ok(mut res: CommandResponse, values: &[Value], pairs: &[Kvpair]) {
+        res.pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(res.status, 200);
+        assert_eq!(res.message, "");
+        assert_eq!(res.values, values);
+        assert_eq!(res.pairs, pairs);
+    }
+
+    // 测试失败返回的结果
+    fn assert_res_error(res: CommandResponse, code: u32, msg: &str) {
+        assert_eq!(res.status, code);
+        assert!(res.message.contains(msg));
+        assert_eq!(res.values, &[]);
+        assert_eq!(res.pairs, &[]);
+    }
 }
@@ -0,0 +1,1168 @@
+use super::{
+    add_float, add_integer,
+    record::{get_str, is_last_record, put_str, read_record, write_record, RECORD_HEADER_LEN},
+};
+use crate::{KvError, Kvpair, ScanRange, Storage, Value};
+use bytes::{Buf, BufMut, BytesMut};
+use dashmap::DashMap;
+use prost::Message;
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, MutexGuard, RwLock, Weak,
+    },
+    thread,
+    time::Duration,
+};
+use tracing::{debug, warn};
+
+const DATA_EXT: &str = "data";
+const HINT_EXT: &str = "hint";
+
+const FLAG_PUT: u8 = 1;
+const FLAG_TOMBSTONE: u8 = 2;
+const FLAG_CREATE_TABLE: u8 = 3;
+const FLAG_DROP_TABLE: u8 = 4;
+// The key field of a rename record holds the new name
+const FLAG_RENAME_TABLE: u8 = 5;
+const FLAG_TRUNCATE_TABLE: u8 = 6;
+
+const HINT_ENTRY: u8 = 1;
+const HINT_TABLE: u8 = 2;
+
+/// Options of a `Bitcask`
+#[derive(Clone, Debug)]
+pub struct BitcaskOptions {
+    /// Start a new segment once the active one reaches this size
+    pub max_file_size: u64,
+    /// Call `fsync` after every appended record
+    pub sync: bool,
+    /// How often the background merger wakes up, None disables it
+    pub merge_interval: Option<Duration>,
+    /// Stale bytes needed before the background merger compacts the segments
+    pub merge_threshold: u64,
+}
+
+impl Default for BitcaskOptions {
+    fn default() -> Self {
+        Self {
+            max_file_size: 64 * 1024 * 1024,
+            sync: true,
+            merge_interval: Some(Duration::from_secs(60)),
+            merge_threshold: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// A log-structured storage in the style of Bitcask: values are appended to
+/// segment files and an in-memory keydir points at the latest record of every key.
+#[derive(Debug)]
+pub struct Bitcask {
+    inner: Arc<Inner>,
+    // Dropping the sender stops the background merger
+    _merger: Option<mpsc::Sender<()>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    dir: PathBuf,
+    options: BitcaskOptions,
+    keydir: KeyDir,
+    writer: Mutex<ActiveSegment>,
+    // Held while resolving a location and reading it, so a merge can't delete the file in between
+    readers: Mutex<HashMap<u64, File>>,
+    // Also held by table operations, so a merge never sees tables move under it
+    merging: Mutex<()>,
+    stale_bytes: AtomicU64,
+}
+
+#[derive(Debug)]
+struct ActiveSegment {
+    id: u64,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+/// Where the latest record of every key lives, by table. Keys are kept in order for scans
+type KeyDir = DashMap<String, RwLock<BTreeMap<String, Location>>>;
+
+/// A record of a hint file
+#[derive(Debug, PartialEq)]
+enum Hint {
+    Table(String),
+    Entry(String, String, Location),
+}
+
+/// Where the latest record of a key lives
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Location {
+    file_id: u64,
+    offset: u64,
+    len: u64,
+}
+
+impl Bitcask {
+    /// Open (or create) a Bitcask in `dir` with default options
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, KvError> {
+        Self::with_options(dir, BitcaskOptions::default())
+    }
+
+    /// Open (or create) a Bitcask in `dir`, rebuilding the keydir from hint and segment files
+    pub fn with_options(dir: impl AsRef<Path>, options: BitcaskOptions) -> Result<Self, KvError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        remove_tmp_files(&dir)?;
+        let keydir = DashMap::new();
+        let mut stale_bytes = 0;
+        let ids = segment_ids(&dir)?;
+        for &id in &ids {
+            stale_bytes += if hint_path(&dir, id).exists() {
+                load_hint(&dir, id, &keydir)?
+            } else {
+                // Only the last segment was being written when the process stopped
+                load_segment(&dir, id, &keydir, ids.last() == Some(&id))?
+            };
+        }
+        debug!("Loaded {} segments from {:?}", ids.len(), dir);
+
+        let active_id = ids.last().map(|id| id + 1).unwrap_or_default();
+        let inner = Arc::new(Inner {
+            writer: Mutex::new(ActiveSegment::create(&dir, active_id)?),
+            dir,
+            keydir,
+            readers: Mutex::new(HashMap::new()),
+            merging: Mutex::new(()),
+            stale_bytes: AtomicU64::new(stale_bytes),
+            options,
+        });
+
+        let merger = inner
+            .options
+            .merge_interval
+            .map(|interval| spawn_merger(Arc::downgrade(&inner), interval));
+
+        Ok(Self {
+            inner,
+            _merger: merger,
+        })
+    }
+
+    /// Rewrite the live records of all inactive segments into a single segment with a hint file
+    pub fn merge(&self) -> Result<(), KvError> {
+        self.inner.merge()
+    }
+}
+
+impl Storage for Bitcask {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.inner.get(table, key)
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+        self.inner.set(table, key, value)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        Ok(self.inner.locate(table, key).is_some())
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.inner.del(table, key)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.inner.get_all(table)
+    }
+
+    fn scan(&self, table: &str, range: &ScanRange) -> Result<Vec<Kvpair>, KvError> {
+        self.inner.scan(table, range)
+    }
+
+    fn compare_and_set(
+        &self,
+        table: &str,
+        key: String,
+        expected: Option<&Value>,
+        value: Value,
+    ) -> Result<bool, KvError> {
+        self.inner.compare_and_set(table, key, expected, value)
+    }
+
+    fn del_if_eq(&self, table: &str, key: &str, expected: &Value) -> Result<bool, KvError> {
+        self.inner.del_if_eq(table, key, expected)
+    }
+
+    fn incr_by(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.inner
+            .update_number(table, key, |v| add_integer(v, delta))
+    }
+
+    fn incr_by_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        self.inner
+            .update_number(table, key, |v| add_float(v, delta))
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        self.inner.create_table(table)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        self.inner.drop_table(table)
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let mut tables: Vec<_> = self.inner.keydir.iter().map(|t| t.key().clone()).collect();
+        tables.sort_unstable();
+        Ok(tables)
+    }
+
+    fn rename_table(&self, table: &str, new_name: &str) -> Result<(), KvError> {
+        self.inner.rename_table(table, new_name)
+    }
+
+    fn truncate_table(&self, table: &str) -> Result<usize, KvError> {
+        self.inner.truncate_table(table)
+    }
+}
+
+impl Inner {
+    fn locate(&self, table: &str, key: &str) -> Option<Location> {
+        self.keydir
+            .get(table)
+            .and_then(|t| t.read().unwrap().get(key).copied())
+    }
+
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let mut readers = self.readers.lock().unwrap();
+        match self.locate(table, key) {
+            Some(loc) => self.read_value(&mut readers, loc, table, key).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.scan(table, &ScanRange::default())
+    }
+
+    fn scan(&self, table: &str, range: &ScanRange) -> Result<Vec<Kvpair>, KvError> {
+        let (lower, upper) = match range.bounds() {
+            Some(bounds) => bounds,
+            None => return Ok(Vec::new()),
+        };
+        let limit = if range.limit > 0 {
+            range.limit
+        } else {
+            usize::MAX
+        };
+        let mut readers = self.readers.lock().unwrap();
+        let locations: Vec<_> = match self.keydir.get(table) {
+            Some(t) => {
+                let t = t.read().unwrap();
+                let iter = t.range((lower, upper)).map(|(k, loc)| (k.clone(), *loc));
+                if range.reverse {
+                    iter.rev().take(limit).collect()
+                } else {
+                    iter.take(limit).collect()
+                }
+            }
+            None => return Ok(Vec::new()),
+        };
+
+        locations
+            .into_iter()
+            .map(|(key, loc)| {
+                let value = self.read_value(&mut readers, loc, table, &key)?;
+                Ok(Kvpair::new(key, value))
+            })
+            .collect()
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+        let mut writer = self.writer.lock().unwrap();
+        let old = self.get(table, &key)?;
+        self.put(&mut writer, table, key, &value)?;
+        Ok(old)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let mut writer = self.writer.lock().unwrap();
+        let old = match self.get(table, key)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        self.remove(&mut writer, table, key)?;
+        Ok(Some(old))
+    }
+
+    // Holding the writer lock keeps every other write out between the check and the write
+    fn compare_and_set(
+        &self,
+        table: &str,
+        key: String,
+        expected: Option<&Value>,
+        value: Value,
+    ) -> Result<bool, KvError> {
+        let mut writer = self.writer.lock().unwrap();
+        if self.get(table, &key)?.as_ref() != expected {
+            return Ok(false);
+        }
+        self.put(&mut writer, table, key, &value)?;
+        Ok(true)
+    }
+
+    fn del_if_eq(&self, table: &str, key: &str, expected: &Value) -> Result<bool, KvError> {
+        let mut writer = self.writer.lock().unwrap();
+        if self.get(table, key)?.as_ref() != Some(expected) {
+            return Ok(false);
+        }
+        self.remove(&mut writer, table, key)?;
+        Ok(true)
+    }
+
+    /// Replace a numeric value with `f(current)` under the writer lock
+    fn update_number<T: Copy + Into<Value>>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnOnce(Option<&Value>) -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        let mut writer = self.writer.lock().unwrap();
+        let result = f(self.get(table, key)?.as_ref())?;
+        self.put(&mut writer, table, key.into(), &result.into())?;
+        Ok(result)
+    }
+
+    fn put(
+        &self,
+        writer: &mut ActiveSegment,
+        table: &str,
+        key: String,
+        value: &Value,
+    ) -> Result<(), KvError> {
+        let payload = encode_entry(FLAG_PUT, table, &key, Some(value));
+        let loc = self
+            .append(writer, &payload)
+            .map_err(|e| KvError::StorageError("set", table.into(), key.clone(), e.to_string()))?;
+
+        let table = self.keydir.entry(table.into()).or_default();
+        if let Some(prev) = table.write().unwrap().insert(key, loc) {
+            self.stale_bytes.fetch_add(prev.len, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn remove(&self, writer: &mut ActiveSegment, table: &str, key: &str) -> Result<(), KvError> {
+        let payload = encode_entry(FLAG_TOMBSTONE, table, key, None);
+        let loc = self
+            .append(writer, &payload)
+            .map_err(|e| KvError::StorageError("del", table.into(), key.into(), e.to_string()))?;
+
+        if let Some(prev) = self
+            .keydir
+            .get(table)
+            .and_then(|t| t.write().unwrap().remove(key))
+        {
+            // Both the old record and the tombstone itself are garbage now
+            self.stale_bytes
+                .fetch_add(prev.len + loc.len, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        let _merging = self.merging.lock().unwrap();
+        let mut writer = self.writer.lock().unwrap();
+        if self.keydir.contains_key(table) {
+            return Ok(false);
+        }
+        self.append_table_op(&mut writer, FLAG_CREATE_TABLE, table, "")?;
+        self.keydir.insert(table.into(), Default::default());
+        Ok(true)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        let _merging = self.merging.lock().unwrap();
+        let mut writer = self.writer.lock().unwrap();
+        if !self.keydir.contains_key(table) {
+            return Ok(false);
+        }
+        let loc = self.append_table_op(&mut writer, FLAG_DROP_TABLE, table, "")?;
+        if let Some((_, t)) = self.keydir.remove(table) {
+            let stale: u64 = t.into_inner().unwrap().values().map(|l| l.len).sum();
+            self.stale_bytes
+                .fetch_add(stale + loc.len, Ordering::Relaxed);
+        }
+        Ok(true)
+    }
+
+    fn rename_table(&self, table: &str, new_name: &str) -> Result<(), KvError> {
+        let _merging = self.merging.lock().unwrap();
+        let mut writer = self.writer.lock().unwrap();
+        if self.keydir.contains_key(new_name) {
+            return Err(KvError::TableExists(new_name.into()));
+        }
+        if !self.keydir.contains_key(table) {
+            return Err(KvError::TableNotFound(table.into()));
+        }
+        let loc = self.append_table_op(&mut writer, FLAG_RENAME_TABLE, table, new_name)?;
+        if let Some((_, t)) = self.keydir.remove(table) {
+            self.keydir.insert(new_name.into(), t);
+        }
+        self.stale_bytes.fetch_add(loc.len, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn truncate_table(&self, table: &str) -> Result<usize, KvError> {
+        let _merging = self.merging.lock().unwrap();
+        let mut writer = self.writer.lock().unwrap();
+        let t = self
+            .keydir
+            .get(table)
+            .ok_or_else(|| KvError::TableNotFound(table.into()))?;
+        let loc = self.append_table_op(&mut writer, FLAG_TRUNCATE_TABLE, table, "")?;
+        let removed = mem::take(&mut *t.write().unwrap());
+        let stale: u64 = removed.values().map(|l| l.len).sum();
+        let removed = removed.len();
+        self.stale_bytes
+            .fetch_add(stale + loc.len, Ordering::Relaxed);
+        Ok(removed)
+    }
+
+    fn append_table_op(
+        &self,
+        writer: &mut ActiveSegment,
+        flag: u8,
+        table: &str,
+        arg: &str,
+    ) -> Result<Location, KvError> {
+        let payload = encode_entry(flag, table, arg, None);
+        self.append(writer, &payload)
+            .map_err(|e| KvError::StorageError("table", table.into(), arg.into(), e.to_string()))
+    }
+
+    /// Append a record to the active segment, rotating it once it is full
+    fn append(&self, writer: &mut ActiveSegment, payload: &[u8]) -> Result<Location, KvError> {
+        let offset = writer.size;
+        let len = write_record(&mut writer.file, payload)?;
+        writer.file.flush()?;
+        if self.options.sync {
+            writer.file.get_ref().sync_data()?;
+        }
+        writer.size += len;
+
+        let loc = Location {
+            file_id: writer.id,
+            offset,
+            len,
+        };
+
+        if writer.size >= self.options.max_file_size {
+            self.rotate(writer, writer.id + 1)?;
+        }
+        Ok(loc)
+    }
+
+    fn rotate(&self, writer: &mut ActiveSegment, id: u64) -> Result<(), KvError> {
+        writer.file.flush()?;
+        writer.file.get_ref().sync_all()?;
+        *writer = ActiveSegment::create(&self.dir, id)?;
+        debug!("Rotated to segment {}", id);
+        Ok(())
+    }
+
+    fn read_value(
+        &self,
+        readers: &mut MutexGuard<HashMap<u64, File>>,
+        loc: Location,
+        table: &str,
+        key: &str,
+    ) -> Result<Value, KvError> {
+        let err = |e: &str| KvError::StorageError("get", table.into(), key.into(), e.into());
+        let payload = self
+            .read_payload(readers, loc)?
+            .ok_or_else(|| err("checksum mismatch"))?;
+        match decode_entry(&payload) {
+            Some((FLAG_PUT, _, _, value)) => Value::decode(value).map_err(|e| e.into()),
+            _ => Err(err("malformed record")),
+        }
+    }
+
+    fn read_payload(
+        &self,
+        readers: &mut MutexGuard<HashMap<u64, File>>,
+        loc: Location,
+    ) -> Result<Option<Vec<u8>>, KvError> {
+        let file = match readers.entry(loc.file_id) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(File::open(data_path(&self.dir, loc.file_id))?),
+        };
+        file.seek(SeekFrom::Start(loc.offset))?;
+        Ok(read_record(&mut file.take(loc.len))?)
+    }
+
+    fn merge(&self) -> Result<(), KvError> {
+        let _merging = self.merging.lock().unwrap();
+
+        // Reserve an id for the merged segment between the old ones and the new active one
+        let merge_id = {
+            let mut writer = self.writer.lock().unwrap();
+            let merge_id = writer.id + 1;
+            self.rotate(&mut writer, merge_id + 1)?;
+            merge_id
+        };
+        let old_ids: Vec<_> = segment_ids(&self.dir)?
+            .into_iter()
+            .filter(|id| *id < merge_id)
+            .collect();
+        if old_ids.is_empty() {
+            return Ok(());
+        }
+
+        // Table operations wait for the merge, so this is the complete list of tables
+        let tables: Vec<_> = self.keydir.iter().map(|t| t.key().clone()).collect();
+        let candidates: Vec<_> = self
+            .keydir
+            .iter()
+            .flat_map(|t| {
+                let table = t.key().clone();
+                t.read()
+                    .unwrap()
+                    .iter()
+                    .filter(|(_, loc)| loc.file_id < merge_id)
+                    .map(|(key, loc)| (table.clone(), key.clone(), *loc))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        // The merged segment isn't the last one, so it must never be seen half written
+        let data_tmp = tmp_path(&data_path(&self.dir, merge_id));
+        let mut data = BufWriter::new(File::create(&data_tmp)?);
+        let mut hint = Vec::new();
+        let mut moved = Vec::with_capacity(candidates.len());
+        let mut offset = 0;
+        // Empty tables have no entries to bring them back on reload
+        for table in tables {
+            let payload = encode_entry(FLAG_CREATE_TABLE, &table, "", None);
+            offset += write_record(&mut data, &payload)?;
+            write_record(&mut hint, &encode_hint(&Hint::Table(table)))?;
+        }
+        for (table, key, old) in candidates {
+            let payload = {
+                let mut readers = self.readers.lock().unwrap();
+                self.read_payload(&mut readers, old)?
+            };
+            // Re-encode the record, the table may have been renamed since it was written
+            let value = payload
+                .as_deref()
+                .and_then(decode_entry)
+                .filter(|(flag, ..)| *flag == FLAG_PUT)
+                .and_then(|(.., value)| Value::decode(value).ok());
+            let payload = match value {
+                Some(value) => encode_entry(FLAG_PUT, &table, &key, Some(&value)),
+                None => {
+                    warn!(
+                        "Dropping corrupted record of {}/{} during merge",
+                        table, key
+                    );
+                    continue;
+                }
+            };
+
+            let len = write_record(&mut data, &payload)?;
+            let new = Location {
+                file_id: merge_id,
+                offset,
+                len,
+            };
+            offset += len;
+            let entry = Hint::Entry(table.clone(), key.clone(), new);
+            write_record(&mut hint, &encode_hint(&entry))?;
+            moved.push((table, key, old, new));
+        }
+        data.flush()?;
+        data.get_ref().sync_all()?;
+        fs::rename(&data_tmp, data_path(&self.dir, merge_id))?;
+        File::open(&self.dir)?.sync_all()?;
+
+        // The hint file only appears once it is complete, after the segment it describes
+        let hint_tmp = tmp_path(&hint_path(&self.dir, merge_id));
+        fs::write(&hint_tmp, &hint)?;
+        File::open(&hint_tmp)?.sync_all()?;
+        fs::rename(&hint_tmp, hint_path(&self.dir, merge_id))?;
+        File::open(&self.dir)?.sync_all()?;
+
+        // Whatever the old segments held beyond the live records is reclaimed
+        let live: u64 = moved.iter().map(|(_, _, old, _)| old.len).sum();
+        let mut old_len = 0;
+        for &id in &old_ids {
+            old_len += fs::metadata(data_path(&self.dir, id))?.len();
+        }
+
+        let mut readers = self.readers.lock().unwrap();
+        for (table, key, old, new) in moved {
+            if let Some(table) = self.keydir.get(&table) {
+                if let Some(loc) = table.write().unwrap().get_mut(&key) {
+                    // Keys written during the merge already point at the new active segment
+                    if *loc == old {
+                        *loc = new;
+                    }
+                }
+            }
+        }
+        for id in old_ids {
+            readers.remove(&id);
+            fs::remove_file(data_path(&self.dir, id))?;
+            if hint_path(&self.dir, id).exists() {
+                fs::remove_file(hint_path(&self.dir, id))?;
+            }
+        }
+        // Writes made during the merge counted their own stale bytes, which stay
+        let reclaimed = old_len.saturating_sub(live);
+        self.stale_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |stale| {
+                Some(stale.saturating_sub(reclaimed))
+            })
+            .ok();
+        debug!("Merged inactive segments into segment {}", merge_id);
+
+        Ok(())
+    }
+}
+
+impl ActiveSegment {
+    fn create(dir: &Path, id: u64) -> Result<Self, KvError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(data_path(dir, id))?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            id,
+            file: BufWriter::new(file),
+            size,
+        })
+    }
+}
+
+fn spawn_merger(inner: Weak<Inner>, interval: Duration) -> mpsc::Sender<()> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+            let inner = match inner.upgrade() {
+                Some(inner) => inner,
+                None => break,
+            };
+            if inner.stale_bytes.load(Ordering::Relaxed) >= inner.options.merge_threshold {
+                if let Err(e) = inner.merge() {
+                    warn!("Failed to merge segments: {:?}", e);
+                }
+            }
+        }
+    });
+    tx
+}
+
+/// Rebuild the keydir from a segment, returning the stale bytes found in it
+///
+/// A torn record at the end of the `active` segment is dropped, any other bad
+/// record fails the load rather than losing the records after it.
+fn load_segment(dir: &Path, id: u64, keydir: &KeyDir, active: bool) -> Result<u64, KvError> {
+    let path = data_path(dir, id);
+    let mut reader = BufReader::new(File::open(&path)?);
+    let mut offset = 0;
+    let mut stale = 0;
+
+    while let Some(payload) = read_record(&mut reader)? {
+        let len = (RECORD_HEADER_LEN + payload.len()) as u64;
+        let (flag, table, key, _) = match decode_entry(&payload) {
+            Some(entry) => entry,
+            None => break,
+        };
+        match flag {
+            FLAG_PUT => {
+                let loc = Location {
+                    file_id: id,
+                    offset,
+                    len,
+                };
+                if let Some(prev) = keydir
+                    .entry(table)
+                    .or_default()
+                    .get_mut()
+                    .unwrap()
+                    .insert(key, loc)
+                {
+                    stale += prev.len;
+                }
+            }
+            FLAG_TOMBSTONE => {
+                if let Some(prev) = keydir
+                    .get_mut(&table)
+                    .and_then(|mut t| t.get_mut().unwrap().remove(&key))
+                {
+                    stale += prev.len;
+                }
+                stale += len;
+            }
+            FLAG_CREATE_TABLE => {
+                keydir.entry(table).or_default();
+            }
+            FLAG_DROP_TABLE => {
+                if let Some((_, t)) = keydir.remove(&table) {
+                    stale += t.into_inner().unwrap().values().map(|l| l.len).sum::<u64>();
+                }
+                stale += len;
+            }
+            FLAG_RENAME_TABLE => {
+                if !keydir.contains_key(&key) {
+                    if let Some((_, t)) = keydir.remove(&table) {
+                        keydir.insert(key, t);
+                    }
+                }
+                stale += len;
+            }
+            _ => {
+                if let Some(mut t) = keydir.get_mut(&table) {
+                    let removed = mem::take(t.get_mut().unwrap());
+                    stale += removed.values().map(|l| l.len).sum::<u64>();
+                }
+                stale += len;
+            }
+        }
+        offset += len;
+    }
+
+    let file_len = fs::metadata(&path)?.len();
+    if file_len > offset {
+        if !active || !is_last_record(reader.get_mut(), offset, file_len)? {
+            return Err(KvError::IoError(format!(
+                "corrupted record at offset {} of {:?}",
+                offset, path
+            )));
+        }
+        // Drop a torn record left by a crash so the segment stays readable
+        warn!("Truncating torn tail of {:?} at offset {}", path, offset);
+        let file = OpenOptions::new().write(true).open(&path)?;
+        file.set_len(offset)?;
+        file.sync_all()?;
+    }
+
+    Ok(stale)
+}
+
+/// Rebuild the keydir from the hint file of a merged segment
+/// Load the keydir entries of segment `id` from its hint file, or from the segment
+/// itself if the hint file is damaged
+fn load_hint(dir: &Path, id: u64, keydir: &KeyDir) -> Result<u64, KvError> {
+    let path = hint_path(dir, id);
+    let mut reader = BufReader::new(File::open(&path)?);
+    let mut hints = Vec::new();
+    let mut len = 0;
+    while let Some(payload) = read_record(&mut reader)? {
+        match decode_hint(&payload, id) {
+            Some(hint) => hints.push(hint),
+            None => break,
+        }
+        len += (RECORD_HEADER_LEN + payload.len()) as u64;
+    }
+    if len < fs::metadata(&path)?.len() {
+        warn!(
+            "Corrupted hint file {:?}, loading segment {} instead",
+            path, id
+        );
+        return load_segment(dir, id, keydir, false);
+    }
+
+    let mut stale = 0;
+    for hint in hints {
+        match hint {
+            Hint::Table(table) => {
+                keydir.entry(table).or_default();
+            }
+            Hint::Entry(table, key, loc) => {
+                if let Some(prev) = keydir
+                    .entry(table)
+                    .or_default()
+                    .get_mut()
+                    .unwrap()
+                    .insert(key, loc)
+                {
+                    stale += prev.len;
+                }
+            }
+        }
+    }
+    Ok(stale)
+}
+
+fn encode_entry(flag: u8, table: &str, key: &str, value: Option<&Value>) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+    buf.put_u8(flag);
+    put_str(&mut buf, table);
+    put_str(&mut buf, key);
+    if let Some(v) = value {
+        v.encode(&mut buf).unwrap();
+    }
+    buf.to_vec()
+}
+
+fn decode_entry(mut payload: &[u8]) -> Option<(u8, String, String, &[u8])> {
+    if !payload.has_remaining() {
+        return None;
+    }
+    let flag = payload.get_u8();
+    let table = get_str(&mut payload)?;
+    let key = get_str(&mut payload)?;
+    match flag {
+        FLAG_PUT..=FLAG_TRUNCATE_TABLE => Some((flag, table, key, payload)),
+        _ => None,
+    }
+}
+
+fn encode_hint(hint: &Hint) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+    match hint {
+        Hint::Table(table) => {
+            buf.put_u8(HINT_TABLE);
+            put_str(&mut buf, table);
+        }
+        Hint::Entry(table, key, loc) => {
+            buf.put_u8(HINT_ENTRY);
+            put_str(&mut buf, table);
+            put_str(&mut buf, key);
+            buf.put_u64_le(loc.offset);
+            buf.put_u64_le(loc.len);
+        }
+    }
+    buf.to_vec()
+}
+
+fn decode_hint(mut payload: &[u8], file_id: u64) -> Option<Hint> {
+    if !payload.has_remaining() {
+        return None;
+    }
+    let flag = payload.get_u8();
+    let table = get_str(&mut payload)?;
+    match flag {
+        HINT_TABLE => Some(Hint::Table(table)),
+        HINT_ENTRY => {
+            let key = get_str(&mut payload)?;
+            if payload.remaining() < 16 {
+                return None;
+            }
+            let loc = Location {
+                file_id,
+                offset: payload.get_u64_le(),
+                len: payload.get_u64_le(),
+            };
+            Some(Hint::Entry(table, key, loc))
+        }
+        _ => None,
+    }
+}
+
+fn data_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{}.{}", id, DATA_EXT))
+}
+
+fn hint_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{}.{}", id, HINT_EXT))
+}
+
+/// Where a file of a merge is written before it's renamed to `path`
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+/// Remove the files of a merge that stopped before renaming them
+fn remove_tmp_files(dir: &Path) -> Result<(), KvError> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "tmp") {
+            warn!("Removing {:?} left by an unfinished merge", path);
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Ids of all segments in `dir`, in ascending order
+fn segment_ids(dir: &Path) -> Result<Vec<u64>, KvError> {
+    let mut ids: Vec<u64> = fs::read_dir(dir)?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != DATA_EXT {
+                return None;
+            }
+            path.file_stem()?.to_str()?.parse().ok()
+        })
+        .collect();
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::ITER_PAGE_LEN;
+    use tempfile::tempdir;
+
+    fn options() -> BitcaskOptions {
+        BitcaskOptions {
+            max_file_size: 128,
+            sync: false,
+            merge_interval: None,
+            merge_threshold: 0,
+        }
+    }
+
+    #[test]
+    fn data_should_survive_reopen() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::with_options(dir.path(), options()).unwrap();
+            for i in 0..20 {
+                store.set("t1", format!("k{}", i), i.into()).unwrap();
+            }
+            store.del("t1", "k3").unwrap();
+            store.set("t1", "k5".into(), "five".into()).unwrap();
+        }
+
+        let store = Bitcask::with_options(dir.path(), options()).unwrap();
+        assert!(segment_ids(dir.path()).unwrap().len() > 2);
+        assert_eq!(store.get("t1", "k3"), Ok(None));
+        assert_eq!(store.get("t1", "k5"), Ok(Some("five".into())));
+        assert_eq!(store.get("t1", "k19"), Ok(Some(19.into())));
+        assert_eq!(store.get_all("t1").unwrap().len(), 19);
+    }
+
+    #[test]
+    fn merge_should_compact_segments_and_write_hints() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::with_options(dir.path(), options()).unwrap();
+            for i in 0..50 {
+                store.set("t1", format!("k{}", i % 5), i.into()).unwrap();
+            }
+            store.del("t1", "k0").unwrap();
+            store.merge().unwrap();
+
+            // One merged segment with its hint file, plus the new active segment
+            assert_eq!(segment_ids(dir.path()).unwrap().len(), 2);
+            assert_eq!(store.get("t1", "k4"), Ok(Some(49.into())));
+            store.set("t1", "k1".into(), "new".into()).unwrap();
+        }
+
+        let ids = segment_ids(dir.path()).unwrap();
+        assert!(hint_path(dir.path(), ids[0]).exists());
+
+        let store = Bitcask::with_options(dir.path(), options()).unwrap();
+        assert_eq!(store.get("t1", "k0"), Ok(None));
+        assert_eq!(store.get("t1", "k1"), Ok(Some("new".into())));
+        assert_eq!(store.get("t1", "k4"), Ok(Some(49.into())));
+        assert_eq!(store.get_all("t1").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn unfinished_merge_should_be_discarded_on_open() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::with_options(dir.path(), options()).unwrap();
+            store.set("t1", "k1".into(), 1.into()).unwrap();
+        }
+        // A merge that stopped while writing its segment
+        let tmp = tmp_path(&data_path(dir.path(), 0));
+        fs::write(&tmp, [1, 2, 3]).unwrap();
+
+        let store = Bitcask::with_options(dir.path(), options()).unwrap();
+        assert!(!tmp.exists());
+        assert_eq!(store.get("t1", "k1"), Ok(Some(1.into())));
+    }
+
+    #[test]
+    fn corrupted_hint_should_fall_back_to_the_segment() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::with_options(dir.path(), options()).unwrap();
+            for i in 0..10 {
+                store.set("t1", format!("k{}", i), i.into()).unwrap();
+            }
+            store.merge().unwrap();
+        }
+        let ids = segment_ids(dir.path()).unwrap();
+        let hint = hint_path(dir.path(), ids[0]);
+        let mut data = fs::read(&hint).unwrap();
+        let middle = data.len() / 2;
+        data[middle] ^= 0xff;
+        fs::write(&hint, data).unwrap();
+
+        let store = Bitcask::with_options(dir.path(), options()).unwrap();
+        assert_eq!(store.get_all("t1").unwrap().len(), 10);
+        assert_eq!(store.get("t1", "k9"), Ok(Some(9.into())));
+    }
+
+    #[test]
+    fn failed_page_should_end_the_iteration_with_an_error() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::with_options(dir.path(), options()).unwrap();
+        for i in 0..ITER_PAGE_LEN * 2 {
+            store
+                .set("t1", format!("k{:04}", i), (i as i64).into())
+                .unwrap();
+        }
+        let mut pairs = store.get_iter("t1").unwrap();
+        for _ in 0..ITER_PAGE_LEN {
+            pairs.next().unwrap().unwrap();
+        }
+        // The second page can't be read back
+        for id in segment_ids(dir.path()).unwrap() {
+            let file = OpenOptions::new()
+                .write(true)
+                .open(data_path(dir.path(), id))
+                .unwrap();
+            file.set_len(0).unwrap();
+        }
+        assert!(pairs.next().unwrap().is_err());
+        assert!(pairs.next().is_none());
+    }
+
+    #[test]
+    fn merge_should_keep_the_stale_bytes_it_did_not_reclaim() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::with_options(dir.path(), options()).unwrap();
+        store.set("t1", "k1".into(), 1.into()).unwrap();
+        store.set("t1", "k1".into(), 2.into()).unwrap();
+        // As counted by a write to the active segment while the merge runs
+        store.inner.stale_bytes.fetch_add(100, Ordering::Relaxed);
+        store.merge().unwrap();
+        assert_eq!(store.inner.stale_bytes.load(Ordering::Relaxed), 100);
+    }
+
+    #[test]
+    fn tables_should_survive_merge_and_reopen() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::with_options(dir.path(), options()).unwrap();
+            store.create_table("empty").unwrap();
+            for i in 0..10 {
+                store.set("t1", format!("k{}", i), i.into()).unwrap();
+                store.set("t2", format!("k{}", i), i.into()).unwrap();
+            }
+            store.rename_table("t1", "t3").unwrap();
+            store.truncate_table("t2").unwrap();
+            store.merge().unwrap();
+            store.drop_table("t2").unwrap();
+        }
+
+        let store = Bitcask::with_options(dir.path(), options()).unwrap();
+        assert_eq!(store.list_tables(), Ok(vec!["empty".into(), "t3".into()]));
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.get("t3", "k9"), Ok(Some(9.into())));
+        assert_eq!(store.get_all("t3").unwrap().len(), 10);
+
+        // A second merge works from the hint file of the first one
+        store.merge().unwrap();
+        drop(store);
+        let store = Bitcask::with_options(dir.path(), options()).unwrap();
+        assert_eq!(store.list_tables(), Ok(vec!["empty".into(), "t3".into()]));
+        assert_eq!(store.get_all("t3").unwrap().len(), 10);
+    }
+
+    #[test]
+    fn background_merger_should_run() {
+        let dir = tempdir().unwrap();
+        let options = BitcaskOptions {
+            merge_interval: Some(Duration::from_millis(10)),
+            ..options()
+        };
+        let store = Bitcask::with_options(dir.path(), options).unwrap();
+        for i in 0..50 {
+            store.set("t1", "k1".into(), i.into()).unwrap();
+        }
+
+        for _ in 0..100 {
+            if segment_ids(dir.path()).unwrap().len() <= 2 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(segment_ids(dir.path()).unwrap().len() <= 2);
+        assert_eq!(store.get("t1", "k1"), Ok(Some(49.into())));
+    }
+
+    #[test]
+    fn corrupted_record_should_fail_crc_check() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::with_options(dir.path(), options()).unwrap();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+
+        let path = data_path(dir.path(), 0);
+        let mut data = fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 0xff;
+        fs::write(&path, data).unwrap();
+
+        assert!(matches!(
+            store.get("t1", "k1"),
+            Err(KvError::StorageError("get", _, _, _))
+        ));
+    }
+
+    #[test]
+    fn torn_record_should_be_discarded() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::with_options(dir.path(), options()).unwrap();
+            store.set("t1", "k1".into(), "v1".into()).unwrap();
+            store.set("t1", "k2".into(), "v2".into()).unwrap();
+        }
+
+        let path = data_path(dir.path(), 0);
+        let len = fs::metadata(&path).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 3).unwrap();
+
+        let store = Bitcask::with_options(dir.path(), options()).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+    }
+
+    #[test]
+    fn corruption_before_the_tail_should_fail_the_open() {
+        let dir = tempdir().unwrap();
+        {
+            let store = Bitcask::with_options(dir.path(), options()).unwrap();
+            for i in 0..20 {
+                store.set("t1", format!("k{}", i), i.into()).unwrap();
+            }
+        }
+        let ids = segment_ids(dir.path()).unwrap();
+        assert!(ids.len() > 2);
+
+        // The last byte of a sealed segment, then of the first record of the last one
+        for id in [ids[0], ids[ids.len() - 1]] {
+            let path = data_path(dir.path(), id);
+            let mut data = fs::read(&path).unwrap();
+            let end = if id == ids[0] {
+                data.len()
+            } else {
+                RECORD_HEADER_LEN + u32::from_le_bytes(data[..4].try_into().unwrap()) as usize
+            };
+            data[end - 1] ^= 0xff;
+            fs::write(&path, &data).unwrap();
+
+            let res = Bitcask::with_options(dir.path(), options());
+            assert!(matches!(res, Err(KvError::IoError(_))));
+            data[end - 1] ^= 0xff;
+            fs::write(&path, &data).unwrap();
+        }
+    }
+}
@@ -1,10 +1,229 @@
-use crate::{KvError, Kvpair, Storage, StorageIter, Value};
-use dashmap::{mapref::one::Ref, DashMap};
+use super::{add_float, add_integer, now_ms};
+use crate::{KvError, Kvpair, ScanRange, Storage, Value};
+use dashmap::{mapref::entry::Entry as MapEntry, mapref::one::Ref, DashMap};
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    ops::Bound,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use tracing::debug;
+
+/// Rough bookkeeping cost of an entry on top of its key and value
+const ENTRY_OVERHEAD: usize = 64;
+/// Largest encoded size of an integer or float value
+const MAX_NUMBER_LEN: usize = 11;
+/// Keys compared to pick each one to evict
+const EVICTION_SAMPLES: usize = 5;
 
 /// 使用 DashMap 构建的 Memtable，实现了 Storage trait
-#[derive(Clone, Debug, Default)]
+#[derive(Debug, Default)]
 pub struct MemTable {
-    tables: DashMap<String, DashMap<String, Value>>,
+    tables: DashMap<String, Table>,
+    options: MemTableOptions,
+    /// Approximate number of bytes held by all entries
+    used: AtomicUsize,
+    /// Logical clock advanced on every access, used to order entries for LRU
+    clock: AtomicU64,
+    /// Number of entries with a ttl, the only ones VolatileTtl can evict
+    volatile: AtomicUsize,
+    /// The table and key the last eviction sample ended at
+    eviction_cursor: Mutex<Option<(String, String)>>,
+    // Creating a table shares it, renaming holds it so no table appears meanwhile
+    renaming: RwLock<()>,
+}
+
+/// Options of a `MemTable`
+#[derive(Clone, Debug, Default)]
+pub struct MemTableOptions {
+    /// Approximate upper bound of the memory used by keys and values, 0 means unlimited
+    pub max_memory: usize,
+    /// What to do when a write would exceed `max_memory`
+    pub policy: EvictionPolicy,
+    /// Reject writes to tables that were not created with `create_table`
+    pub strict: bool,
+}
+
+/// How a `MemTable` makes room once it reaches its memory limit
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EvictionPolicy {
+    /// Reject the write with `KvError::OutOfMemory`
+    #[default]
+    #[serde(rename = "noeviction")]
+    NoEviction,
+    /// Evict the least recently used keys
+    #[serde(rename = "allkeys-lru")]
+    AllKeysLru,
+    /// Evict the least frequently used keys
+    #[serde(rename = "allkeys-lfu")]
+    AllKeysLfu,
+    /// Evict the keys with a ttl that expire soonest
+    #[serde(rename = "volatile-ttl")]
+    VolatileTtl,
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "noeviction" => Ok(Self::NoEviction),
+            "allkeys-lru" => Ok(Self::AllKeysLru),
+            "allkeys-lfu" => Ok(Self::AllKeysLfu),
+            "volatile-ttl" => Ok(Self::VolatileTtl),
+            _ => Err(KvError::InvalidConfig(format!(
+                "unknown eviction policy `{}`, expected one of noeviction, allkeys-lru, \
+                 allkeys-lfu and volatile-ttl",
+                s
+            ))),
+        }
+    }
+}
+
+/// A value together with its expiry and access metadata
+#[derive(Debug)]
+pub(crate) struct Entry {
+    pub(crate) value: Value,
+    /// Unix timestamp in milliseconds after which the entry is gone
+    pub(crate) expire_at: Option<u64>,
+    size: usize,
+    last_access: AtomicU64,
+    hits: AtomicU64,
+}
+
+impl Entry {
+    fn new(key: &str, value: Value, expire_at: Option<u64>, now: u64) -> Self {
+        Self {
+            size: key.len() + value.encoded_len() + ENTRY_OVERHEAD,
+            value,
+            expire_at,
+            last_access: AtomicU64::new(now),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expire_at.is_some_and(|t| t <= now)
+    }
+
+    fn touch(&self, tick: u64) {
+        self.last_access.store(tick, Ordering::Relaxed);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Lower scores are evicted first, None means the entry can't be evicted.
+    /// Expired entries go before any other.
+    fn eviction_score(&self, policy: EvictionPolicy, now: u64) -> Option<(bool, u64, u64)> {
+        if self.is_expired(now) {
+            return Some((false, 0, 0));
+        }
+        let last_access = self.last_access.load(Ordering::Relaxed);
+        let (a, b) = match policy {
+            EvictionPolicy::NoEviction => return None,
+            EvictionPolicy::AllKeysLru => (last_access, 0),
+            EvictionPolicy::AllKeysLfu => (self.hits.load(Ordering::Relaxed), last_access),
+            EvictionPolicy::VolatileTtl => (self.expire_at?, last_access),
+        };
+        Some((true, a, b))
+    }
+}
+
+impl Clone for Entry {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            expire_at: self.expire_at,
+            size: self.size,
+            last_access: AtomicU64::new(self.last_access.load(Ordering::Relaxed)),
+            hits: AtomicU64::new(self.hits.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl From<(String, Entry)> for Kvpair {
+    fn from((key, entry): (String, Entry)) -> Self {
+        Kvpair::new(key, entry.value)
+    }
+}
+
+/// The entries of a table, with its keys kept in order for scans
+#[derive(Debug, Default)]
+struct Table {
+    entries: DashMap<String, Entry>,
+    /// Every key of `entries`, expired ones included until they are removed
+    keys: RwLock<BTreeSet<String>>,
+}
+
+impl Table {
+    fn remove(&self, key: &str) -> Option<Entry> {
+        self.remove_if(key, |_| true)
+    }
+
+    fn remove_if(&self, key: &str, f: impl FnOnce(&Entry) -> bool) -> Option<Entry> {
+        let removed = self.entries.remove_if(key, |_, e| f(e)).map(|(_, e)| e);
+        if removed.is_some() {
+            self.sync_key(key);
+        }
+        removed
+    }
+
+    /// Add `key` to the index or take it out, whichever matches the entries now.
+    /// Called after a write that may have added or removed the key, and never
+    /// while holding a shard of `entries`.
+    fn sync_key(&self, key: &str) {
+        let mut keys = self.keys.write().unwrap();
+        if !self.entries.contains_key(key) {
+            keys.remove(key);
+        } else if !keys.contains(key) {
+            keys.insert(key.to_owned());
+        }
+    }
+}
+
+impl Clone for Table {
+    fn clone(&self) -> Self {
+        let keys = self.keys.read().unwrap().clone();
+        Self {
+            entries: self.entries.clone(),
+            keys: RwLock::new(keys),
+        }
+    }
+}
+
+/// A table, a key in it and its eviction score
+type Sample = (String, String, Option<(bool, u64, u64)>);
+
+/// Bytes counted as used ahead of a write, given back once it's done or failed
+struct Reservation<'a> {
+    used: &'a AtomicUsize,
+    size: usize,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.used.fetch_sub(self.size, Ordering::Relaxed);
+    }
+}
+
+impl Clone for MemTable {
+    fn clone(&self) -> Self {
+        Self {
+            tables: self.tables.clone(),
+            options: self.options.clone(),
+            used: AtomicUsize::new(self.used_memory()),
+            clock: AtomicU64::new(self.clock.load(Ordering::Relaxed)),
+            volatile: AtomicUsize::new(self.volatile.load(Ordering::Relaxed)),
+            eviction_cursor: Mutex::new(None),
+            renaming: RwLock::new(()),
+        }
+    }
 }
 
 impl MemTable {
@@ -13,13 +232,407 @@ impl MemTable {
         Self::default()
     }
 
+    /// Create a MemTable with a memory limit and an eviction policy
+    pub fn with_options(options: MemTableOptions) -> Self {
+        Self {
+            options,
+            ..Default::default()
+        }
+    }
+
+    /// Approximate number of bytes used by all keys and values
+    pub fn used_memory(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Names of all tables
+    pub(crate) fn tables(&self) -> Vec<String> {
+        self.tables.iter().map(|t| t.key().clone()).collect()
+    }
+
+    /// A live entry with its expiry metadata
+    pub(crate) fn live_entry(&self, table: &str, key: &str) -> Option<Entry> {
+        let now = now_ms();
+        self.tables
+            .get(table)?
+            .entries
+            .get(key)
+            .filter(|e| !e.is_expired(now))
+            .map(|e| e.clone())
+    }
+
+    /// Whether a table exists
+    pub(crate) fn has_table(&self, table: &str) -> bool {
+        self.tables.contains_key(table)
+    }
+
+    /// All live entries of a table, with their expiry metadata
+    pub(crate) fn entries(&self, table: &str) -> Vec<(String, Entry)> {
+        let now = now_ms();
+        match self.tables.get(table) {
+            Some(table) => table
+                .entries
+                .iter()
+                .filter(|e| !e.value().is_expired(now))
+                .map(|e| (e.key().clone(), e.value().clone()))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Set a key with an absolute expiry time, returning the old live value
+    pub(crate) fn set_at(
+        &self,
+        table_name: &str,
+        key: String,
+        value: Value,
+        expire_at: Option<u64>,
+    ) -> Result<Option<Value>, KvError> {
+        let now = now_ms();
+        let entry = Entry::new(&key, value, expire_at, self.tick());
+        loop {
+            let old_size = self
+                .tables
+                .get(table_name)
+                .and_then(|t| t.entries.get(&key).map(|e| e.size))
+                .unwrap_or_default();
+            let reserved = self.reserve(table_name, &key, entry.size.saturating_sub(old_size))?;
+
+            let table = self.get_or_create_table(table_name)?;
+            let old = match table.entries.entry(key.clone()) {
+                MapEntry::Occupied(mut e) => {
+                    if !self.admit(&entry, Some(e.get()), reserved) {
+                        continue;
+                    }
+                    Some(e.insert(entry))
+                }
+                MapEntry::Vacant(e) => {
+                    if !self.admit(&entry, None, reserved) {
+                        continue;
+                    }
+                    e.insert(entry);
+                    None
+                }
+            };
+            if old.is_none() {
+                table.sync_key(&key);
+            }
+            return Ok(old.filter(|old| !old.is_expired(now)).map(|old| old.value));
+        }
+    }
+
+    /// Change the absolute expiry time of a live key, returning false if it doesn't exist
+    pub(crate) fn expire_at(&self, table: &str, key: &str, expire_at: Option<u64>) -> bool {
+        let now = now_ms();
+        let table = match self.tables.get(table) {
+            Some(table) => table,
+            None => return false,
+        };
+        let result = match table.entries.get_mut(key) {
+            Some(mut entry) if !entry.is_expired(now) => {
+                match (entry.expire_at.is_some(), expire_at.is_some()) {
+                    (false, true) => self.volatile.fetch_add(1, Ordering::Relaxed),
+                    (true, false) => self.volatile.fetch_sub(1, Ordering::Relaxed),
+                    _ => 0,
+                };
+                entry.expire_at = expire_at;
+                true
+            }
+            _ => false,
+        };
+        result
+    }
+
+    /// Remove every expired key, returning how many were evicted
+    ///
+    /// Expired keys are collected shard by shard and removed one at a time, so no
+    /// shard stays locked for longer than a single scan or removal.
+    pub fn sweep_expired(&self) -> usize {
+        let mut evicted = 0;
+        for name in self.tables() {
+            let table = match self.tables.get(&name) {
+                Some(table) => table,
+                None => continue,
+            };
+            let now = now_ms();
+            let expired: Vec<_> = table
+                .entries
+                .iter()
+                .filter(|e| e.value().is_expired(now))
+                .map(|e| e.key().clone())
+                .collect();
+            for key in expired {
+                let removed = table.remove_if(&key, |e| e.is_expired(now));
+                if self.release(removed).is_some() {
+                    evicted += 1;
+                }
+            }
+        }
+        evicted
+    }
+
+    /// Spawn a thread that sweeps expired keys every `interval`.
+    /// The thread exits once the MemTable is dropped.
+    pub fn spawn_sweeper(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let store: Weak<Self> = Arc::downgrade(self);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            match store.upgrade() {
+                Some(store) => {
+                    let evicted = store.sweep_expired();
+                    if evicted > 0 {
+                        debug!("Swept {} expired keys", evicted);
+                    }
+                }
+                None => break,
+            }
+        })
+    }
+
+    /// Count `size` more bytes as used, evicting keys if the policy allows it to
+    /// make room. The bytes are given back if the reservation is dropped before
+    /// the entry they are for is admitted.
+    fn reserve(&self, table: &str, key: &str, size: usize) -> Result<Reservation<'_>, KvError> {
+        let max = self.options.max_memory;
+        let reserved = |size| Reservation {
+            used: &self.used,
+            size,
+        };
+        let try_reserve = || {
+            self.used
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                    (used + size <= max).then_some(used + size)
+                })
+                .is_ok()
+        };
+        if max == 0 || size == 0 {
+            return Ok(reserved(0));
+        }
+        if try_reserve() {
+            return Ok(reserved(size));
+        }
+        let oom = || KvError::OutOfMemory(table.into(), key.into());
+        let policy = self.options.policy;
+        if size > max
+            || policy == EvictionPolicy::NoEviction
+            || (policy == EvictionPolicy::VolatileTtl && self.volatile.load(Ordering::Relaxed) == 0)
+        {
+            return Err(oom());
+        }
+
+        // Evict the best of a few keys at a time, and give up once every key was
+        // looked at without finding one that can go
+        let mut cursor = self.eviction_cursor.lock().unwrap();
+        let mut tables = self.tables();
+        tables.sort_unstable();
+        let keys: usize = tables
+            .iter()
+            .filter_map(|name| self.tables.get(name))
+            .map(|t| t.keys.read().unwrap().len())
+            .sum();
+        let mut examined = 0;
+        while !try_reserve() {
+            if examined > keys {
+                return Err(oom());
+            }
+            let samples = self.sample(&mut cursor, &tables);
+            if samples.is_empty() {
+                return Err(oom());
+            }
+            examined += samples.len();
+            let victim = samples
+                .into_iter()
+                .filter_map(|(table, key, score)| Some((score?, table, key)))
+                .min();
+            if let Some((_, table_name, victim)) = victim {
+                if let Some(table) = self.tables.get(&table_name) {
+                    if self.release(table.remove(&victim)).is_some() {
+                        debug!("Evicted {}/{} to free memory", table_name, victim);
+                        examined = 0;
+                    }
+                }
+            }
+        }
+        Ok(reserved(size))
+    }
+
+    /// The next `EVICTION_SAMPLES` keys after `cursor`, going through `tables` in
+    /// order and wrapping around, with their eviction scores
+    fn sample(&self, cursor: &mut Option<(String, String)>, tables: &[String]) -> Vec<Sample> {
+        if tables.is_empty() {
+            return Vec::new();
+        }
+        let (mut i, mut after) = match cursor.take() {
+            Some((table, key)) => match tables.binary_search(&table) {
+                Ok(i) => (i, Some(key)),
+                Err(i) => (i % tables.len(), None),
+            },
+            None => (0, None),
+        };
+        let start = (i, after.clone());
+        let (policy, now) = (self.options.policy, now_ms());
+        let mut samples = Vec::with_capacity(EVICTION_SAMPLES);
+        // Back at the first table, only the keys before the start are left
+        for visit in 0..=tables.len() {
+            let upper = match (visit == tables.len(), &start.1) {
+                (false, _) => Bound::Unbounded,
+                (true, Some(key)) => Bound::Included(key.clone()),
+                (true, None) => break,
+            };
+            if let Some(table) = self.tables.get(&tables[i]) {
+                let lower = after.take().map_or(Bound::Unbounded, Bound::Excluded);
+                let keys = table.keys.read().unwrap();
+                for key in keys.range((lower, upper)) {
+                    let score = table
+                        .entries
+                        .get(key)
+                        .and_then(|e| e.eviction_score(policy, now));
+                    samples.push((tables[i].clone(), key.clone(), score));
+                    if samples.len() == EVICTION_SAMPLES {
+                        *cursor = Some((tables[i].clone(), key.clone()));
+                        return samples;
+                    }
+                }
+            }
+            i = (i + 1) % tables.len();
+            after = None;
+        }
+        samples
+    }
+
+    /// Account for an entry taking the place of `old` in a table, with the bytes reserved
+    /// for it, in a single update so other writers never see the two sizes counted at once
+    ///
+    /// The reservation is made before `old` is locked, and another writer may have
+    /// shrunk or removed it since. If the entry then no longer fits, nothing is
+    /// counted and false is returned, for the write to make room again.
+    fn admit(&self, entry: &Entry, old: Option<&Entry>, mut reserved: Reservation) -> bool {
+        let max = self.options.max_memory;
+        let freed = reserved.size + old.map_or(0, |e| e.size);
+        if entry.size >= freed {
+            let grown = entry.size - freed;
+            let fits = self
+                .used
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                    (max == 0 || used + grown <= max).then_some(used + grown)
+                })
+                .is_ok();
+            if !fits {
+                return false;
+            }
+        } else {
+            self.used.fetch_sub(freed - entry.size, Ordering::Relaxed);
+        }
+        reserved.size = 0;
+        if entry.expire_at.is_some() {
+            self.volatile.fetch_add(1, Ordering::Relaxed);
+        }
+        if old.is_some_and(|e| e.expire_at.is_some()) {
+            self.volatile.fetch_sub(1, Ordering::Relaxed);
+        }
+        true
+    }
+
+    /// Account for an entry removed from a table
+    fn release(&self, entry: Option<Entry>) -> Option<Entry> {
+        if let Some(e) = &entry {
+            self.used.fetch_sub(e.size, Ordering::Relaxed);
+            if e.expire_at.is_some() {
+                self.volatile.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+        entry
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Replace a numeric value with `f(current)` while holding the entry, keeping its ttl
+    fn update_number<T: Copy + Into<Value>>(
+        &self,
+        table_name: &str,
+        key: &str,
+        f: impl Fn(Option<&Value>) -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        let now = now_ms();
+        // A missing table holds no value, so a failing update doesn't create it
+        if !self.tables.contains_key(table_name) {
+            f(None)?;
+        }
+        // Evicting needs the shard locks, so make room for the largest number up front
+        let size = key.len() + MAX_NUMBER_LEN + ENTRY_OVERHEAD;
+        loop {
+            let old_size = self
+                .tables
+                .get(table_name)
+                .and_then(|t| t.entries.get(key).map(|e| e.size))
+                .unwrap_or_default();
+            let reserved = self.reserve(table_name, key, size.saturating_sub(old_size))?;
+
+            let table = self.get_or_create_table(table_name)?;
+            let (result, old) = match table.entries.entry(key.into()) {
+                MapEntry::Occupied(mut e) if !e.get().is_expired(now) => {
+                    let result = f(Some(&e.get().value))?;
+                    let entry = Entry::new(key, result.into(), e.get().expire_at, self.tick());
+                    if !self.admit(&entry, Some(e.get()), reserved) {
+                        continue;
+                    }
+                    (result, Some(e.insert(entry)))
+                }
+                e => {
+                    let result = f(None)?;
+                    let entry = Entry::new(key, result.into(), None, self.tick());
+                    let old = match e {
+                        MapEntry::Occupied(mut e) => {
+                            if !self.admit(&entry, Some(e.get()), reserved) {
+                                continue;
+                            }
+                            Some(e.insert(entry))
+                        }
+                        MapEntry::Vacant(e) => {
+                            if !self.admit(&entry, None, reserved) {
+                                continue;
+                            }
+                            e.insert(entry);
+                            None
+                        }
+                    };
+                    (result, old)
+                }
+            };
+            if old.is_none() {
+                table.sync_key(key);
+            }
+            return Ok(result);
+        }
+    }
+
+    /// Release the memory accounted to all entries of a removed table,
+    /// and return how many of them were live
+    fn release_table(&self, table: Table) -> usize {
+        let now = now_ms();
+        let mut live = 0;
+        for (_, entry) in table.entries {
+            if !entry.is_expired(now) {
+                live += 1;
+            }
+            self.release(Some(entry));
+        }
+        live
+    }
+
     /// 如果名为 name 的 hash table 不存在，则创建，否则返回
-    fn get_or_create_table(&self, name: &str) -> Ref<String, DashMap<String, Value>> {
+    ///
+    /// Only writes create tables, and in strict mode they have to exist already.
+    fn get_or_create_table(&self, name: &str) -> Result<Ref<'_, String, Table>, KvError> {
         match self.tables.get(name) {
-            Some(table) => table,
+            Some(table) => Ok(table),
+            None if self.options.strict => Err(KvError::TableNotFound(name.into())),
             None => {
+                let _creating = self.renaming.read().unwrap();
                 let entry = self.tables.entry(name.into()).or_default();
-                entry.downgrade()
+                Ok(entry.downgrade())
             }
         }
     }
@@ -27,36 +640,569 @@ impl MemTable {
 
 impl Storage for MemTable {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.get(key).map(|v| v.value().clone()))
+        let now = now_ms();
+        let table = match self.tables.get(table) {
+            Some(table) => table,
+            None => return Ok(None),
+        };
+        let value = match table.entries.get(key) {
+            Some(entry) if !entry.is_expired(now) => {
+                entry.touch(self.tick());
+                return Ok(Some(entry.value.clone()));
+            }
+            Some(_) => None,
+            None => return Ok(None),
+        };
+        // Expire lazily on read
+        let removed = table.remove_if(key, |e| e.is_expired(now));
+        self.release(removed);
+        Ok(value)
     }
 
     fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.insert(key, value))
+        self.set_at(table, key, value, None)
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.contains_key(key))
+        Ok(self.get(table, key)?.is_some())
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.remove(key).map(|(_k, v)| v))
+        let table = match self.tables.get(table) {
+            Some(table) => table,
+            None => return Ok(None),
+        };
+        Ok(self
+            .release(table.remove(key))
+            .filter(|e| !e.is_expired(now_ms()))
+            .map(|e| e.value))
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table
-            .iter()
-            .map(|v| Kvpair::new(v.key(), v.value().clone()))
-            .collect())
+        Ok(self.entries(table).into_iter().map(|e| e.into()).collect())
+    }
+
+    fn scan(&self, table: &str, range: &ScanRange) -> Result<Vec<Kvpair>, KvError> {
+        let (lower, upper) = match range.bounds() {
+            Some(bounds) => bounds,
+            None => return Ok(Vec::new()),
+        };
+        let limit = if range.limit > 0 {
+            range.limit
+        } else {
+            usize::MAX
+        };
+        let table = match self.tables.get(table) {
+            Some(table) => table,
+            None => return Ok(Vec::new()),
+        };
+
+        let now = now_ms();
+        let live = |key: &String| {
+            let entry = table.entries.get(key).filter(|e| !e.is_expired(now))?;
+            Some(Kvpair::new(key, entry.value.clone()))
+        };
+        let keys = table.keys.read().unwrap();
+        let iter = keys.range((lower, upper));
+        Ok(if range.reverse {
+            iter.rev().filter_map(live).take(limit).collect()
+        } else {
+            iter.filter_map(live).take(limit).collect()
+        })
+    }
+
+    fn set_ex(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        self.set_at(table, key, value, Some(deadline(ttl)))
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        Ok(self.expire_at(table, key, Some(deadline(ttl))))
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        let now = now_ms();
+        let expire_at = self.tables.get(table).and_then(|t| {
+            t.entries
+                .get(key)
+                .filter(|e| !e.is_expired(now))
+                .map(|e| e.expire_at)
+        });
+        match expire_at {
+            Some(t) => Ok(t.map(|t| Duration::from_millis(t - now))),
+            None => Err(KvError::NotFound(table.into(), key.into())),
+        }
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let now = now_ms();
+        let table = match self.tables.get(table) {
+            Some(table) => table,
+            None => return Ok(false),
+        };
+        let result = match table.entries.get_mut(key) {
+            Some(mut entry) if !entry.is_expired(now) => {
+                let persisted = entry.expire_at.take().is_some();
+                if persisted {
+                    self.volatile.fetch_sub(1, Ordering::Relaxed);
+                }
+                persisted
+            }
+            _ => false,
+        };
+        Ok(result)
+    }
+
+    fn compare_and_set(
+        &self,
+        table_name: &str,
+        key: String,
+        expected: Option<&Value>,
+        value: Value,
+    ) -> Result<bool, KvError> {
+        let now = now_ms();
+        let mut entry = Entry::new(&key, value, None, self.tick());
+        // Evicting needs the shard locks, so make room before taking the entry,
+        // but only once the swap looks like it will happen
+        loop {
+            let (matches, old_size) = match self.tables.get(table_name) {
+                Some(table) => match table.entries.get(&key) {
+                    Some(e) => {
+                        let current = Some(&e.value).filter(|_| !e.is_expired(now));
+                        (current == expected, e.size)
+                    }
+                    None => (expected.is_none(), 0),
+                },
+                None => (expected.is_none(), 0),
+            };
+            if !matches {
+                return Ok(false);
+            }
+            let reserved = self.reserve(table_name, &key, entry.size.saturating_sub(old_size))?;
+
+            let table = match expected {
+                Some(_) => match self.tables.get(table_name) {
+                    Some(table) => table,
+                    None => return Ok(false),
+                },
+                None => self.get_or_create_table(table_name)?,
+            };
+            let swapped = match table.entries.entry(key.clone()) {
+                MapEntry::Occupied(mut e) => {
+                    let current = Some(&e.get().value).filter(|_| !e.get().is_expired(now));
+                    if current == expected {
+                        // Swapping the value keeps the ttl of a live key
+                        entry.expire_at = current.and(e.get().expire_at);
+                        if !self.admit(&entry, Some(e.get()), reserved) {
+                            continue;
+                        }
+                        e.insert(entry);
+                        true
+                    } else {
+                        false
+                    }
+                }
+                MapEntry::Vacant(e) if expected.is_none() => {
+                    if !self.admit(&entry, None, reserved) {
+                        continue;
+                    }
+                    e.insert(entry);
+                    true
+                }
+                MapEntry::Vacant(_) => false,
+            };
+            if swapped && expected.is_none() {
+                table.sync_key(&key);
+            }
+            return Ok(swapped);
+        }
+    }
+
+    fn del_if_eq(&self, table: &str, key: &str, expected: &Value) -> Result<bool, KvError> {
+        let now = now_ms();
+        let table = match self.tables.get(table) {
+            Some(table) => table,
+            None => return Ok(false),
+        };
+        let removed = table.remove_if(key, |e| !e.is_expired(now) && &e.value == expected);
+        Ok(self.release(removed).is_some())
+    }
+
+    fn incr_by(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.update_number(table, key, |v| add_integer(v, delta))
+    }
+
+    fn incr_by_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        self.update_number(table, key, |v| add_float(v, delta))
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        let _creating = self.renaming.read().unwrap();
+        match self.tables.entry(table.into()) {
+            MapEntry::Occupied(_) => Ok(false),
+            MapEntry::Vacant(e) => {
+                e.insert(Table::default());
+                Ok(true)
+            }
+        }
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        match self.tables.remove(table) {
+            Some((_, t)) => {
+                self.release_table(t);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let mut tables = self.tables();
+        tables.sort_unstable();
+        Ok(tables)
+    }
+
+    fn rename_table(&self, table: &str, new_name: &str) -> Result<(), KvError> {
+        // No write can create either table until the move is done
+        let _renaming = self.renaming.write().unwrap();
+        if self.tables.contains_key(new_name) {
+            return Err(KvError::TableExists(new_name.into()));
+        }
+        let (_, t) = self
+            .tables
+            .remove(table)
+            .ok_or_else(|| KvError::TableNotFound(table.into()))?;
+        self.tables.insert(new_name.into(), t);
+        Ok(())
     }
 
-    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
-        let table = self.get_or_create_table(table).clone();
-        let iter = StorageIter::new(table.into_iter());
-        Ok(Box::new(iter))
+    fn truncate_table(&self, table: &str) -> Result<usize, KvError> {
+        let mut t = self
+            .tables
+            .get_mut(table)
+            .ok_or_else(|| KvError::TableNotFound(table.into()))?;
+        Ok(self.release_table(std::mem::take(t.value_mut())))
+    }
+}
+
+/// Absolute expiry time of a key living for `ttl` from now
+pub(crate) fn deadline(ttl: Duration) -> u64 {
+    now_ms().saturating_add(ttl.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounded(policy: EvictionPolicy, entries: usize) -> MemTable {
+        // Every entry below takes the same amount of memory
+        let size = Entry::new("k0", 0.into(), None, 0).size;
+        MemTable::with_options(MemTableOptions {
+            max_memory: size * entries,
+            policy,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn expired_keys_should_be_invisible() {
+        let store = MemTable::new();
+        store
+            .set_at("t1", "k1".into(), "v1".into(), Some(now_ms() - 1))
+            .unwrap();
+        store.set("t1", "k2".into(), "v2".into()).unwrap();
+
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.contains("t1", "k1"), Ok(false));
+        assert_eq!(store.get_all("t1").unwrap().len(), 1);
+        assert_eq!(store.get_iter("t1").unwrap().count(), 1);
+        assert!(matches!(
+            store.ttl("t1", "k1"),
+            Err(KvError::NotFound(_, _))
+        ));
+        // An expired key has no previous value
+        assert_eq!(store.set("t1", "k1".into(), "v3".into()), Ok(None));
+    }
+
+    #[test]
+    fn ttl_and_persist_should_work() {
+        let store = MemTable::new();
+        store
+            .set_ex("t1", "k1".into(), "v1".into(), Duration::from_secs(60))
+            .unwrap();
+        let ttl = store.ttl("t1", "k1").unwrap().unwrap();
+        assert!(ttl > Duration::from_secs(59) && ttl <= Duration::from_secs(60));
+
+        assert_eq!(store.persist("t1", "k1"), Ok(true));
+        assert_eq!(store.persist("t1", "k1"), Ok(false));
+        assert_eq!(store.ttl("t1", "k1"), Ok(None));
+
+        assert_eq!(store.expire("t1", "k1", Duration::from_secs(10)), Ok(true));
+        assert_eq!(store.expire("t1", "k2", Duration::from_secs(10)), Ok(false));
+        assert!(store.ttl("t1", "k1").unwrap().is_some());
+    }
+
+    #[test]
+    fn strict_mode_should_reject_writes_to_unknown_tables() {
+        let store = MemTable::with_options(MemTableOptions {
+            strict: true,
+            ..Default::default()
+        });
+        let res = store.set("t1", "k1".into(), "v1".into());
+        assert_eq!(res, Err(KvError::TableNotFound("t1".into())));
+
+        store.create_table("t1").unwrap();
+        assert_eq!(store.set("t1", "k1".into(), "v1".into()), Ok(None));
+    }
+
+    #[test]
+    fn only_one_set_nx_should_win() {
+        let store = Arc::new(MemTable::new());
+        let handles: Vec<_> = (0..8)
+            .map(|i: i64| {
+                let store = store.clone();
+                thread::spawn(move || store.set_nx("locks", "leader".into(), i.into()).unwrap())
+            })
+            .collect();
+        let winners = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .filter(|won| *won)
+            .count();
+        assert_eq!(winners, 1);
+    }
+
+    #[test]
+    fn conditional_writes_should_treat_expired_keys_as_absent() {
+        let store = MemTable::new();
+        store
+            .set_at("t1", "k1".into(), "v1".into(), Some(now_ms() - 1))
+            .unwrap();
+        assert_eq!(store.del_if_eq("t1", "k1", &"v1".into()), Ok(false));
+        assert_eq!(store.set_nx("t1", "k1".into(), "v2".into()), Ok(true));
+        assert_eq!(store.ttl("t1", "k1"), Ok(None));
+    }
+
+    #[test]
+    fn concurrent_incr_by_should_not_lose_updates() {
+        let store = Arc::new(MemTable::new());
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        store.incr_by("stats", "views", 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(store.get("stats", "views"), Ok(Some(800.into())));
+    }
+
+    #[test]
+    fn incr_by_should_keep_ttl() {
+        let store = MemTable::new();
+        store
+            .set_ex("stats", "views".into(), 1.into(), Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(store.incr_by("stats", "views", 1), Ok(2));
+        assert!(store.ttl("stats", "views").unwrap().is_some());
+    }
+
+    #[test]
+    fn dropping_tables_should_release_memory() {
+        let store = MemTable::new();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t2", "k1".into(), "v1".into()).unwrap();
+        let used = store.used_memory();
+
+        store.truncate_table("t1").unwrap();
+        assert_eq!(store.used_memory(), used / 2);
+        store.drop_table("t2").unwrap();
+        assert_eq!(store.used_memory(), 0);
+    }
+
+    #[test]
+    fn sweeper_should_evict_expired_keys() {
+        let store = Arc::new(MemTable::new());
+        store
+            .set_at("t1", "k1".into(), "v1".into(), Some(now_ms() - 1))
+            .unwrap();
+        store
+            .set_ex("t1", "k2".into(), "v2".into(), Duration::from_millis(20))
+            .unwrap();
+        store.set("t1", "k3".into(), "v3".into()).unwrap();
+        assert_eq!(store.sweep_expired(), 1);
+
+        store.spawn_sweeper(Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(100));
+        let table = store.tables.get("t1").unwrap();
+        assert_eq!(table.entries.len(), 1);
+        assert!(table.entries.contains_key("k3"));
+    }
+
+    #[test]
+    fn key_index_should_follow_concurrent_writes() {
+        let store = Arc::new(MemTable::new());
+        let writers: Vec<_> = (0..4)
+            .map(|n| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for i in 0..500i64 {
+                        let key = format!("k{}", i % 50);
+                        if (i + n) % 3 == 0 {
+                            store.del("t1", &key).unwrap();
+                        } else {
+                            store.set("t1", key, i.into()).unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        writers.into_iter().for_each(|w| w.join().unwrap());
+
+        let table = store.tables.get("t1").unwrap();
+        let mut entries: Vec<_> = table.entries.iter().map(|e| e.key().clone()).collect();
+        entries.sort_unstable();
+        let keys: Vec<_> = table.keys.read().unwrap().iter().cloned().collect();
+        assert_eq!(keys, entries);
+    }
+
+    #[test]
+    fn used_memory_should_be_tracked() {
+        let store = MemTable::new();
+        store.set("t1", "k1".into(), "v1".into()).unwrap();
+        store.set("t2", "k1".into(), "v1".into()).unwrap();
+        let two = store.used_memory();
+        store.set("t1", "k1".into(), "v2".into()).unwrap();
+        assert_eq!(store.used_memory(), two);
+        store.del("t1", "k1").unwrap();
+        store.del("t2", "k1").unwrap();
+        assert_eq!(store.used_memory(), 0);
+    }
+
+    #[test]
+    fn noeviction_should_reject_writes() {
+        let store = bounded(EvictionPolicy::NoEviction, 2);
+        store.set("t1", "k1".into(), 1.into()).unwrap();
+        store.set("t1", "k2".into(), 2.into()).unwrap();
+        assert_eq!(
+            store.set("t1", "k3".into(), 3.into()),
+            Err(KvError::OutOfMemory("t1".into(), "k3".into()))
+        );
+        // Overwriting with a value of the same size needs no extra memory
+        assert_eq!(store.set("t1", "k1".into(), 4.into()), Ok(Some(1.into())));
+    }
+
+    #[test]
+    fn lru_should_evict_least_recently_used() {
+        let store = bounded(EvictionPolicy::AllKeysLru, 2);
+        store.set("t1", "k1".into(), 1.into()).unwrap();
+        store.set("t1", "k2".into(), 2.into()).unwrap();
+        store.get("t1", "k1").unwrap();
+        store.set("t1", "k3".into(), 3.into()).unwrap();
+
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        assert_eq!(store.get("t1", "k1"), Ok(Some(1.into())));
+        assert_eq!(store.get("t1", "k3"), Ok(Some(3.into())));
+    }
+
+    #[test]
+    fn lfu_should_evict_least_frequently_used() {
+        let store = bounded(EvictionPolicy::AllKeysLfu, 2);
+        store.set("t1", "k1".into(), 1.into()).unwrap();
+        store.set("t1", "k2".into(), 2.into()).unwrap();
+        store.get("t1", "k1").unwrap();
+        store.get("t1", "k1").unwrap();
+        store.get("t1", "k2").unwrap();
+        store.set("t1", "k3".into(), 3.into()).unwrap();
+
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        assert_eq!(store.get("t1", "k1"), Ok(Some(1.into())));
+    }
+
+    #[test]
+    fn volatile_ttl_should_only_evict_keys_with_ttl() {
+        let store = bounded(EvictionPolicy::VolatileTtl, 2);
+        let ttl = Duration::from_secs(60);
+        store.set("t1", "k1".into(), 1.into()).unwrap();
+        store.set_ex("t1", "k2".into(), 2.into(), ttl).unwrap();
+        store.set("t1", "k3".into(), 3.into()).unwrap();
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+
+        // Only keys without a ttl are left
+        assert_eq!(
+            store.set("t1", "k4".into(), 4.into()),
+            Err(KvError::OutOfMemory("t1".into(), "k4".into()))
+        );
+    }
+
+    #[test]
+    fn compare_and_set_should_keep_the_ttl() {
+        let store = MemTable::new();
+        let ttl = Duration::from_secs(60);
+        store.set_ex("t1", "k1".into(), 1.into(), ttl).unwrap();
+        let swapped = store.compare_and_set("t1", "k1".into(), Some(&1.into()), 2.into());
+        assert_eq!(swapped, Ok(true));
+        assert_eq!(store.get("t1", "k1"), Ok(Some(2.into())));
+        assert!(store.ttl("t1", "k1").unwrap().is_some());
+    }
+
+    #[test]
+    fn failed_compare_and_set_should_not_evict() {
+        let store = bounded(EvictionPolicy::AllKeysLru, 2);
+        store.set("t1", "k1".into(), 1.into()).unwrap();
+        store.set("t1", "k2".into(), 2.into()).unwrap();
+        let swapped = store.compare_and_set("t1", "k3".into(), Some(&3.into()), 4.into());
+        assert_eq!(swapped, Ok(false));
+        assert_eq!(store.get("t1", "k1"), Ok(Some(1.into())));
+        assert_eq!(store.get("t1", "k2"), Ok(Some(2.into())));
+    }
+
+    #[test]
+    fn expired_keys_should_be_evicted_first() {
+        let store = bounded(EvictionPolicy::AllKeysLru, 2);
+        store.set("t1", "k1".into(), 1.into()).unwrap();
+        store
+            .set_at("t2", "k2".into(), 2.into(), Some(now_ms() - 1))
+            .unwrap();
+        store.set("t1", "k3".into(), 3.into()).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some(1.into())));
+        assert!(!store.tables.get("t2").unwrap().entries.contains_key("k2"));
+    }
+
+    #[test]
+    fn concurrent_writers_should_stay_within_the_limit() {
+        let store = Arc::new(bounded(EvictionPolicy::AllKeysLru, 10));
+        let max = store.options.max_memory;
+        let writers: Vec<_> = (0..4)
+            .map(|n| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for i in 0..200i64 {
+                        let table = format!("t{}", i % 3);
+                        store.set(&table, format!("k{}", n), i.into()).unwrap();
+                        assert!(store.used_memory() <= max);
+                    }
+                })
+            })
+            .collect();
+        writers.into_iter().for_each(|w| w.join().unwrap());
+
+        let entries: usize = store
+            .tables
+            .iter()
+            .map(|t| t.entries.iter().map(|e| e.size).sum::<usize>())
+            .sum();
+        assert_eq!(store.used_memory(), entries);
     }
 }
@@ -1,7 +1,23 @@
+mod bitcask;
 mod memory;
-pub use memory::MemTable;
+mod ordered;
+mod record;
+mod wal;
+pub use bitcask::{Bitcask, BitcaskOptions};
+pub use memory::{EvictionPolicy, MemTable, MemTableOptions};
+pub use ordered::BTreeTable;
+pub use wal::{WalOptions, WalTable};
 
-use crate::{KvError, Kvpair, Value};
+use crate::{value, KvError, Kvpair, Value};
+use std::{
+    iter,
+    ops::Bound,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Pairs read from a table at a time by `Storage::get_iter`
+const ITER_PAGE_LEN: usize = 256;
 
 /// 对存储的对象，我们不关心数据存在哪儿，但需要定义外界如何和存储打交道
 pub trait Storage {
@@ -16,7 +32,320 @@ pub trait Storage {
     /// 遍历 HashTable，返回所有 kv pair（这个接口不好）
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError>;
     /// 遍历 HashTable，返回 kv pair 的 Iterator
-    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError>;
+    ///
+    /// The pairs are read with `scan` one page at a time, in key order, so the
+    /// table is never copied as a whole. A page that fails to load after the
+    /// first one is yielded as an error, which ends the iteration.
+    fn get_iter(
+        &self,
+        table: &str,
+    ) -> Result<Box<dyn Iterator<Item = Result<Kvpair, KvError>> + '_>, KvError> {
+        let mut range = ScanRange {
+            limit: ITER_PAGE_LEN,
+            ..Default::default()
+        };
+        let first = self.scan(table, &range)?;
+        let table = table.to_owned();
+        let mut done = first.len() < ITER_PAGE_LEN;
+        range.start = first.last().map(|pair| after(&pair.key));
+        let rest = iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let page = self.scan(&table, &range);
+            done = page
+                .as_ref()
+                .map_or(true, |page| page.len() < ITER_PAGE_LEN);
+            if let Ok(page) = &page {
+                range.start = page.last().map(|pair| after(&pair.key));
+            }
+            Some(page)
+        });
+        let rest = rest.flat_map(|page| {
+            let (pairs, err) = match page {
+                Ok(pairs) => (pairs, None),
+                Err(e) => (Vec::new(), Some(Err(e))),
+            };
+            pairs.into_iter().map(Ok).chain(err)
+        });
+        Ok(Box::new(first.into_iter().map(Ok).chain(rest)))
+    }
+    /// 按 key 的字典序遍历 HashTable 中落在 range 里的 kv pair
+    fn scan(&self, table: &str, range: &ScanRange) -> Result<Vec<Kvpair>, KvError>;
+    /// 设置一个 key 的 value，并在 ttl 之后过期，返回旧的 value
+    fn set_ex(
+        &self,
+        _table: &str,
+        _key: String,
+        _value: Value,
+        _ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        Err(KvError::Unsupported("HSETEX"))
+    }
+    /// 为一个已存在的 key 设置过期时间，key 不存在时返回 false
+    fn expire(&self, _table: &str, _key: &str, _ttl: Duration) -> Result<bool, KvError> {
+        Err(KvError::Unsupported("HEXPIRE"))
+    }
+    /// 获取 key 剩余的存活时间，key 没有过期时间时返回 None
+    fn ttl(&self, _table: &str, _key: &str) -> Result<Option<Duration>, KvError> {
+        Err(KvError::Unsupported("HTTL"))
+    }
+    /// 移除 key 的过期时间，key 之前有过期时间时返回 true
+    fn persist(&self, _table: &str, _key: &str) -> Result<bool, KvError> {
+        Err(KvError::Unsupported("HPERSIST"))
+    }
+    /// 只有 key 当前的 value 等于 expected 时才设置新的 value，expected 为 None 时要求 key 不存在。
+    /// 条件不满足时返回 false
+    fn compare_and_set(
+        &self,
+        table: &str,
+        key: String,
+        expected: Option<&Value>,
+        value: Value,
+    ) -> Result<bool, KvError>;
+    /// 只有 key 不存在时才设置 value，条件不满足时返回 false
+    fn set_nx(&self, table: &str, key: String, value: Value) -> Result<bool, KvError> {
+        self.compare_and_set(table, key, None, value)
+    }
+    /// 只有 key 当前的 value 等于 expected 时才删除它，条件不满足时返回 false
+    fn del_if_eq(&self, table: &str, key: &str, expected: &Value) -> Result<bool, KvError>;
+    /// 把 key 的整数 value 加上 delta 并返回新的值，key 不存在时从 0 开始
+    fn incr_by(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError>;
+    /// 把 key 的浮点数 value 加上 delta 并返回新的值，key 不存在时从 0 开始
+    fn incr_by_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError>;
+    /// 创建一个 HashTable，已存在时返回 false
+    fn create_table(&self, table: &str) -> Result<bool, KvError>;
+    /// 删除一个 HashTable 及其中所有的 key，不存在时返回 false
+    fn drop_table(&self, table: &str) -> Result<bool, KvError>;
+    /// 按字典序列出所有 HashTable 的名字
+    fn list_tables(&self) -> Result<Vec<String>, KvError>;
+    /// 重命名一个 HashTable，new_name 不能已经存在
+    fn rename_table(&self, table: &str, new_name: &str) -> Result<(), KvError>;
+    /// 清空一个 HashTable，返回删除的 key 的数目
+    fn truncate_table(&self, table: &str) -> Result<usize, KvError>;
+    /// 执行 f，其中的写操作作为一个整体持久化：崩溃之后要么全部恢复，要么全部丢失。
+    /// 没有日志的存储直接执行 f
+    fn batch<T>(&self, f: impl FnOnce() -> T) -> Result<T, KvError> {
+        Ok(f())
+    }
+}
+
+/// Share a storage, e.g. with a background sweeper, while the service owns it
+impl<T: Storage> Storage for Arc<T> {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        (**self).get(table, key)
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+        (**self).set(table, key, value)
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        (**self).contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        (**self).del(table, key)
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        (**self).get_all(table)
+    }
+
+    fn get_iter(
+        &self,
+        table: &str,
+    ) -> Result<Box<dyn Iterator<Item = Result<Kvpair, KvError>> + '_>, KvError> {
+        (**self).get_iter(table)
+    }
+
+    fn scan(&self, table: &str, range: &ScanRange) -> Result<Vec<Kvpair>, KvError> {
+        (**self).scan(table, range)
+    }
+
+    fn set_ex(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        (**self).set_ex(table, key, value, ttl)
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        (**self).expire(table, key, ttl)
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        (**self).ttl(table, key)
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        (**self).persist(table, key)
+    }
+
+    fn compare_and_set(
+        &self,
+        table: &str,
+        key: String,
+        expected: Option<&Value>,
+        value: Value,
+    ) -> Result<bool, KvError> {
+        (**self).compare_and_set(table, key, expected, value)
+    }
+
+    fn set_nx(&self, table: &str, key: String, value: Value) -> Result<bool, KvError> {
+        (**self).set_nx(table, key, value)
+    }
+
+    fn del_if_eq(&self, table: &str, key: &str, expected: &Value) -> Result<bool, KvError> {
+        (**self).del_if_eq(table, key, expected)
+    }
+
+    fn incr_by(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        (**self).incr_by(table, key, delta)
+    }
+
+    fn incr_by_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        (**self).incr_by_float(table, key, delta)
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        (**self).create_table(table)
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        (**self).drop_table(table)
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        (**self).list_tables()
+    }
+
+    fn rename_table(&self, table: &str, new_name: &str) -> Result<(), KvError> {
+        (**self).rename_table(table, new_name)
+    }
+
+    fn truncate_table(&self, table: &str) -> Result<usize, KvError> {
+        (**self).truncate_table(table)
+    }
+
+    fn batch<R>(&self, f: impl FnOnce() -> R) -> Result<R, KvError> {
+        (**self).batch(f)
+    }
+}
+
+/// Current unix timestamp in milliseconds
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Add `delta` to an integer value, a missing value counts as 0
+pub(crate) fn add_integer(current: Option<&Value>, delta: i64) -> Result<i64, KvError> {
+    let n = match current {
+        None => 0,
+        Some(Value {
+            value: Some(value::Value::Integer(n)),
+        }) => *n,
+        Some(v) => return Err(KvError::ConvertError(v.clone(), "integer")),
+    };
+    n.checked_add(delta)
+        .ok_or_else(|| KvError::ConvertError(n.into(), "integer: the result overflows"))
+}
+
+/// Add `delta` to a float value, a missing value counts as 0 and integers are widened
+pub(crate) fn add_float(current: Option<&Value>, delta: f64) -> Result<f64, KvError> {
+    let n = match current {
+        None => 0.0,
+        Some(Value {
+            value: Some(value::Value::Float(n)),
+        }) => *n,
+        Some(Value {
+            value: Some(value::Value::Integer(n)),
+        }) => *n as f64,
+        Some(v) => return Err(KvError::ConvertError(v.clone(), "float")),
+    };
+    Some(n + delta)
+        .filter(|n| n.is_finite())
+        .ok_or_else(|| KvError::ConvertError(n.into(), "float: the result is not finite"))
+}
+
+/// A lexicographic key range used by `Storage::scan`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScanRange {
+    /// Inclusive lower bound, unbounded if None
+    pub start: Option<String>,
+    /// Exclusive upper bound, unbounded if None
+    pub end: Option<String>,
+    /// Only keep the keys starting with this prefix
+    pub prefix: Option<String>,
+    /// Maximum number of pairs to return, 0 means no limit
+    pub limit: usize,
+    /// Iterate from the largest key downwards
+    pub reverse: bool,
+}
+
+impl ScanRange {
+    /// Check whether `key` falls in the range, regardless of the limit
+    pub fn contains(&self, key: &str) -> bool {
+        self.start.as_deref().is_none_or(|start| key >= start)
+            && self.end.as_deref().is_none_or(|end| key < end)
+            && self
+                .prefix
+                .as_deref()
+                .is_none_or(|prefix| key.starts_with(prefix))
+    }
+
+    /// The bounds of the keys in the range, with the prefix narrowing both ends,
+    /// or None if no key can be in it
+    pub(crate) fn bounds(&self) -> Option<(Bound<String>, Bound<String>)> {
+        let start = match (&self.start, &self.prefix) {
+            (Some(start), Some(prefix)) => Some(start.max(prefix)),
+            (start, prefix) => start.as_ref().or(prefix.as_ref()),
+        };
+        // Keys sharing the prefix are all below the prefix with its last char bumped
+        let prefix_end = self.prefix.as_deref().and_then(prefix_end);
+        let end = match (&self.end, prefix_end) {
+            (Some(end), Some(prefix_end)) => Some(prefix_end.min(end.clone())),
+            (end, prefix_end) => end.clone().or(prefix_end),
+        };
+        if let (Some(start), Some(end)) = (start, &end) {
+            if start >= end {
+                return None;
+            }
+        }
+        Some((
+            start.map_or(Bound::Unbounded, |s| Bound::Included(s.clone())),
+            end.map_or(Bound::Unbounded, Bound::Excluded),
+        ))
+    }
+}
+
+/// The smallest string greater than `key`: no key is between `key` and `key`
+/// followed by '\0'
+pub(crate) fn after(key: &str) -> String {
+    format!("{}\0", key)
+}
+
+/// The smallest string greater than every string starting with `prefix`, None
+/// if they are unbounded
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut end = prefix.to_owned();
+    while let Some(c) = end.pop() {
+        let next = match c {
+            '\u{d7ff}' => Some('\u{e000}'),
+            c => char::from_u32(c as u32 + 1),
+        };
+        if let Some(next) = next {
+            end.push(next);
+            return Some(end);
+        }
+    }
+    None
 }
 
 /// Self-defined iterator for hashmap
@@ -46,6 +375,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::tempdir;
 
     #[test]
     fn memtable_basic_interface_should_work() {
@@ -65,6 +395,199 @@ mod tests {
         test_get_iter(store);
     }
 
+    #[test]
+    fn waltable_basic_interface_should_work() {
+        let dir = tempdir().unwrap();
+        let store = WalTable::open(dir.path()).unwrap();
+        test_basi_interface(store);
+    }
+
+    #[test]
+    fn waltable_get_all_should_work() {
+        let dir = tempdir().unwrap();
+        let store = WalTable::open(dir.path()).unwrap();
+        test_get_all(store);
+    }
+
+    #[test]
+    fn waltable_get_iter_should_work() {
+        let dir = tempdir().unwrap();
+        let store = WalTable::open(dir.path()).unwrap();
+        test_get_iter(store);
+    }
+
+    #[test]
+    fn bitcask_basic_interface_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::open(dir.path()).unwrap();
+        test_basi_interface(store);
+    }
+
+    #[test]
+    fn bitcask_get_all_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::open(dir.path()).unwrap();
+        test_get_all(store);
+    }
+
+    #[test]
+    fn bitcask_get_iter_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::open(dir.path()).unwrap();
+        test_get_iter(store);
+    }
+
+    #[test]
+    fn btreetable_basic_interface_should_work() {
+        let store = BTreeTable::new();
+        test_basi_interface(store);
+    }
+
+    #[test]
+    fn btreetable_get_all_should_work() {
+        let store = BTreeTable::new();
+        test_get_all(store);
+    }
+
+    #[test]
+    fn btreetable_get_iter_should_work() {
+        let store = BTreeTable::new();
+        test_get_iter(store);
+    }
+
+    #[test]
+    fn memtable_scan_should_work() {
+        let store = MemTable::new();
+        test_scan(store);
+    }
+
+    #[test]
+    fn btreetable_scan_should_work() {
+        let store = BTreeTable::new();
+        test_scan(store);
+    }
+
+    #[test]
+    fn waltable_scan_should_work() {
+        let dir = tempdir().unwrap();
+        let store = WalTable::open(dir.path()).unwrap();
+        test_scan(store);
+    }
+
+    #[test]
+    fn bitcask_scan_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::open(dir.path()).unwrap();
+        test_scan(store);
+    }
+
+    #[test]
+    fn prefix_end_should_bound_the_prefix() {
+        assert_eq!(prefix_end("user:"), Some("user;".into()));
+        assert_eq!(prefix_end("a\u{10ffff}"), Some("b".into()));
+        assert_eq!(prefix_end("\u{d7ff}"), Some("\u{e000}".into()));
+        assert_eq!(prefix_end(""), None);
+    }
+
+    #[test]
+    fn memtable_ttl_should_work() {
+        let store = MemTable::new();
+        test_ttl(store);
+    }
+
+    #[test]
+    fn waltable_ttl_should_work() {
+        let dir = tempdir().unwrap();
+        let store = WalTable::open(dir.path()).unwrap();
+        test_ttl(store);
+    }
+
+    #[test]
+    fn memtable_tables_should_work() {
+        let store = MemTable::new();
+        test_tables(store);
+    }
+
+    #[test]
+    fn waltable_tables_should_work() {
+        let dir = tempdir().unwrap();
+        let store = WalTable::open(dir.path()).unwrap();
+        test_tables(store);
+    }
+
+    #[test]
+    fn bitcask_tables_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::open(dir.path()).unwrap();
+        test_tables(store);
+    }
+
+    #[test]
+    fn btreetable_tables_should_work() {
+        let store = BTreeTable::new();
+        test_tables(store);
+    }
+
+    #[test]
+    fn memtable_conditional_writes_should_work() {
+        let store = MemTable::new();
+        test_conditional_writes(store);
+    }
+
+    #[test]
+    fn waltable_conditional_writes_should_work() {
+        let dir = tempdir().unwrap();
+        let store = WalTable::open(dir.path()).unwrap();
+        test_conditional_writes(store);
+    }
+
+    #[test]
+    fn bitcask_conditional_writes_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::open(dir.path()).unwrap();
+        test_conditional_writes(store);
+    }
+
+    #[test]
+    fn btreetable_conditional_writes_should_work() {
+        let store = BTreeTable::new();
+        test_conditional_writes(store);
+    }
+
+    #[test]
+    fn memtable_numbers_should_work() {
+        let store = MemTable::new();
+        test_numbers(store);
+    }
+
+    #[test]
+    fn waltable_numbers_should_work() {
+        let dir = tempdir().unwrap();
+        let store = WalTable::open(dir.path()).unwrap();
+        test_numbers(store);
+    }
+
+    #[test]
+    fn bitcask_numbers_should_work() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::open(dir.path()).unwrap();
+        test_numbers(store);
+    }
+
+    #[test]
+    fn btreetable_numbers_should_work() {
+        let store = BTreeTable::new();
+        test_numbers(store);
+    }
+
+    #[test]
+    fn bitcask_should_not_support_ttl() {
+        let dir = tempdir().unwrap();
+        let store = Bitcask::open(dir.path()).unwrap();
+        let res = store.expire("t1", "k1", Duration::from_secs(1));
+        assert_eq!(res, Err(KvError::Unsupported("HEXPIRE")));
+    }
+
     fn test_basi_interface(store: impl Storage) {
         // 第一次 set 会创建 table，插入 key 并返回 None (之前没值)
         let v = store.set("t1", "hello".to_owned(), "world".into());
@@ -112,7 +635,11 @@ mod tests {
     fn test_get_iter(store: impl Storage) {
         store.set("t2", "k1".into(), "v1".into()).unwrap();
         store.set("t2", "k2".into(), "v2".into()).unwrap();
-        let mut data: Vec<_> = store.get_iter("t2").unwrap().collect();
+        let mut data: Vec<_> = store
+            .get_iter("t2")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
         data.sort_by(|a, b| a.partial_cmp(b).unwrap());
         assert_eq!(
             data,
@@ -120,6 +647,183 @@ mod tests {
                 Kvpair::new("k1", "v1".into()),
                 Kvpair::new("k2", "v2".into())
             ]
-        )
+        );
+
+        // 跨越多页的遍历按 key 的顺序返回所有 kv pair
+        let n = ITER_PAGE_LEN * 2 + 1;
+        let keys: Vec<_> = (0..n).map(|i| format!("k{:04}", i)).collect();
+        store
+            .batch(|| {
+                for key in &keys {
+                    store.set("t3", key.clone(), key.as_str().into()).unwrap();
+                }
+            })
+            .unwrap();
+        let iterated: Vec<_> = store
+            .get_iter("t3")
+            .unwrap()
+            .map(|p| p.unwrap().key)
+            .collect();
+        assert_eq!(iterated, keys);
+    }
+
+    fn test_scan(store: impl Storage) {
+        for key in ["user:3", "user:1", "admin", "user:2", "zed"] {
+            store.set("t1", key.into(), key.into()).unwrap();
+        }
+        let keys = |range: ScanRange| -> Vec<String> {
+            let pairs = store.scan("t1", &range).unwrap();
+            pairs.into_iter().map(|p| p.key).collect()
+        };
+
+        assert_eq!(
+            keys(ScanRange::default()),
+            vec!["admin", "user:1", "user:2", "user:3", "zed"]
+        );
+        let range = ScanRange {
+            start: Some("b".into()),
+            end: Some("user:3".into()),
+            ..Default::default()
+        };
+        assert_eq!(keys(range), vec!["user:1", "user:2"]);
+        let range = ScanRange {
+            prefix: Some("user:".into()),
+            limit: 2,
+            reverse: true,
+            ..Default::default()
+        };
+        assert_eq!(keys(range), vec!["user:3", "user:2"]);
+        let range = ScanRange {
+            start: Some("user:2".into()),
+            limit: 2,
+            ..Default::default()
+        };
+        assert_eq!(keys(range), vec!["user:2", "user:3"]);
+        assert!(store.scan("t2", &ScanRange::default()).unwrap().is_empty());
+    }
+
+    fn test_ttl(store: impl Storage) {
+        let ttl = Duration::from_secs(60);
+        let v = store.set_ex("t1", "k1".into(), "v1".into(), ttl);
+        assert_eq!(v, Ok(None));
+        assert!(store.ttl("t1", "k1").unwrap().unwrap() <= ttl);
+        assert_eq!(store.persist("t1", "k1"), Ok(true));
+        assert_eq!(store.ttl("t1", "k1"), Ok(None));
+
+        let v = store.set_ex("t1", "k2".into(), "v2".into(), Duration::from_millis(1));
+        assert_eq!(v, Ok(None));
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+        assert_eq!(store.contains("t1", "k2"), Ok(false));
+        assert_eq!(store.expire("t1", "k2", ttl), Ok(false));
+        assert!(store.ttl("t1", "k2").is_err());
+
+        let mut data = store.get_all("t1").unwrap();
+        data.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(data, vec![Kvpair::new("k1", "v1".into())]);
+    }
+
+    fn test_tables(store: impl Storage) {
+        // 读操作不应该创建 table
+        store.get("t1", "k1").unwrap();
+        store.contains("t1", "k1").unwrap();
+        store.del("t1", "k1").unwrap();
+        store.get_all("t1").unwrap();
+        assert_eq!(store.list_tables(), Ok(vec![]));
+
+        assert_eq!(store.create_table("t1"), Ok(true));
+        assert_eq!(store.create_table("t1"), Ok(false));
+        store.set("t2", "k1".into(), "v1".into()).unwrap();
+        store.set("t2", "k2".into(), "v2".into()).unwrap();
+        assert_eq!(store.list_tables(), Ok(vec!["t1".into(), "t2".into()]));
+
+        assert_eq!(
+            store.rename_table("t2", "t1"),
+            Err(KvError::TableExists("t1".into()))
+        );
+        assert_eq!(
+            store.rename_table("t3", "t4"),
+            Err(KvError::TableNotFound("t3".into()))
+        );
+        assert_eq!(store.rename_table("t2", "t3"), Ok(()));
+        assert_eq!(store.get("t2", "k1"), Ok(None));
+        assert_eq!(store.get("t3", "k1"), Ok(Some("v1".into())));
+
+        assert_eq!(store.truncate_table("t3"), Ok(2));
+        assert_eq!(store.get_all("t3"), Ok(vec![]));
+        assert_eq!(
+            store.truncate_table("t2"),
+            Err(KvError::TableNotFound("t2".into()))
+        );
+
+        assert_eq!(store.drop_table("t1"), Ok(true));
+        assert_eq!(store.drop_table("t1"), Ok(false));
+        assert_eq!(store.list_tables(), Ok(vec!["t3".into()]));
+    }
+
+    fn test_conditional_writes(store: impl Storage) {
+        let v1: Value = "v1".into();
+        let v2: Value = "v2".into();
+        // 条件不满足时不应该创建 table
+        assert_eq!(
+            store.compare_and_set("t1", "k1".into(), Some(&v1), v2.clone()),
+            Ok(false)
+        );
+        assert_eq!(store.del_if_eq("t1", "k1", &v1), Ok(false));
+        assert_eq!(store.list_tables(), Ok(vec![]));
+
+        assert_eq!(store.set_nx("t1", "k1".into(), v1.clone()), Ok(true));
+        assert_eq!(store.set_nx("t1", "k1".into(), v2.clone()), Ok(false));
+        assert_eq!(store.get("t1", "k1"), Ok(Some(v1.clone())));
+
+        assert_eq!(
+            store.compare_and_set("t1", "k1".into(), Some(&v2), v2.clone()),
+            Ok(false)
+        );
+        assert_eq!(
+            store.compare_and_set("t1", "k1".into(), None, v2.clone()),
+            Ok(false)
+        );
+        assert_eq!(
+            store.compare_and_set("t1", "k1".into(), Some(&v1), v2.clone()),
+            Ok(true)
+        );
+        assert_eq!(store.get("t1", "k1"), Ok(Some(v2.clone())));
+
+        assert_eq!(store.del_if_eq("t1", "k1", &v1), Ok(false));
+        assert_eq!(store.del_if_eq("t1", "k1", &v2), Ok(true));
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+    }
+
+    fn test_numbers(store: impl Storage) {
+        assert_eq!(store.incr_by("t1", "n", 5), Ok(5));
+        assert_eq!(store.incr_by("t1", "n", -7), Ok(-2));
+        assert_eq!(store.get("t1", "n"), Ok(Some((-2).into())));
+        // 整数可以按浮点数累加
+        assert_eq!(store.incr_by_float("t1", "n", 0.5), Ok(-1.5));
+        assert_eq!(
+            store.incr_by("t1", "n", 1),
+            Err(KvError::ConvertError((-1.5).into(), "integer"))
+        );
+
+        store.set("t1", "max".into(), i64::MAX.into()).unwrap();
+        assert_eq!(
+            store.incr_by("t1", "max", 1),
+            Err(KvError::ConvertError(
+                i64::MAX.into(),
+                "integer: the result overflows"
+            ))
+        );
+        assert_eq!(store.get("t1", "max"), Ok(Some(i64::MAX.into())));
+
+        store.set("t1", "s".into(), "hello".into()).unwrap();
+        assert_eq!(
+            store.incr_by_float("t1", "s", 1.0),
+            Err(KvError::ConvertError("hello".into(), "float"))
+        );
+
+        // 失败的累加不会创建 table
+        assert!(store.incr_by_float("t2", "n", f64::INFINITY).is_err());
+        assert_eq!(store.list_tables(), Ok(vec!["t1".into()]));
     }
 }
@@ -0,0 +1,209 @@
+use super::{add_float, add_integer};
+use crate::{KvError, Kvpair, ScanRange, Storage, Value};
+use dashmap::{
+    mapref::{entry::Entry, one::Ref},
+    DashMap,
+};
+use std::{
+    collections::BTreeMap,
+    sync::{RwLock, RwLockReadGuard},
+};
+
+type Table = RwLock<BTreeMap<String, Value>>;
+
+/// 使用 BTreeMap 构建的有序存储，key 按字典序排列，支持范围和前缀遍历
+#[derive(Debug, Default)]
+pub struct BTreeTable {
+    tables: DashMap<String, Table>,
+    // Creating a table shares it, renaming holds it so no table appears meanwhile
+    renaming: RwLock<()>,
+}
+
+impl BTreeTable {
+    /// 创建一个缺省的 BTreeTable
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `f` with a read lock on the table, or return None if it doesn't exist
+    fn read<T>(
+        &self,
+        name: &str,
+        f: impl FnOnce(RwLockReadGuard<BTreeMap<String, Value>>) -> T,
+    ) -> Option<T> {
+        self.tables.get(name).map(|table| f(table.read().unwrap()))
+    }
+
+    /// 如果名为 name 的 table 不存在，则创建，否则返回
+    fn get_or_create_table(&self, name: &str) -> Ref<'_, String, Table> {
+        if let Some(table) = self.tables.get(name) {
+            return table;
+        }
+        let _creating = self.renaming.read().unwrap();
+        self.tables.entry(name.into()).or_default().downgrade()
+    }
+
+    /// Replace a numeric value with `f(current)` under the table's write lock
+    fn update_number<T: Copy + Into<Value>>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl Fn(Option<&Value>) -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        loop {
+            if let Some(t) = self.tables.get(table) {
+                let mut t = t.write().unwrap();
+                let result = f(t.get(key))?;
+                t.insert(key.into(), result.into());
+                return Ok(result);
+            }
+            // A missing table holds no value, so a failing update doesn't create it
+            f(None)?;
+            self.get_or_create_table(table);
+        }
+    }
+}
+
+impl Storage for BTreeTable {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        Ok(self.read(table, |t| t.get(key).cloned()).flatten())
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+        let table = self.get_or_create_table(table);
+        let mut table = table.write().unwrap();
+        Ok(table.insert(key, value))
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        Ok(self
+            .read(table, |t| t.contains_key(key))
+            .unwrap_or_default())
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        Ok(self
+            .tables
+            .get(table)
+            .and_then(|t| t.write().unwrap().remove(key)))
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        Ok(self
+            .read(table, |t| {
+                t.iter().map(|(k, v)| Kvpair::new(k, v.clone())).collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn scan(&self, table: &str, range: &ScanRange) -> Result<Vec<Kvpair>, KvError> {
+        let (lower, upper) = match range.bounds() {
+            Some(bounds) => bounds,
+            None => return Ok(Vec::new()),
+        };
+        let limit = if range.limit > 0 {
+            range.limit
+        } else {
+            usize::MAX
+        };
+
+        Ok(self
+            .read(table, |t| {
+                let iter = t.range((lower, upper));
+                let to_pair = |(k, v): (&String, &Value)| Kvpair::new(k, v.clone());
+                if range.reverse {
+                    iter.rev().take(limit).map(to_pair).collect()
+                } else {
+                    iter.take(limit).map(to_pair).collect()
+                }
+            })
+            .unwrap_or_default())
+    }
+
+    fn compare_and_set(
+        &self,
+        table: &str,
+        key: String,
+        expected: Option<&Value>,
+        value: Value,
+    ) -> Result<bool, KvError> {
+        let table = match expected {
+            Some(_) => match self.tables.get(table) {
+                Some(table) => table,
+                None => return Ok(false),
+            },
+            None => self.get_or_create_table(table),
+        };
+        let mut table = table.write().unwrap();
+        if table.get(&key) != expected {
+            return Ok(false);
+        }
+        table.insert(key, value);
+        Ok(true)
+    }
+
+    fn del_if_eq(&self, table: &str, key: &str, expected: &Value) -> Result<bool, KvError> {
+        let table = match self.tables.get(table) {
+            Some(table) => table,
+            None => return Ok(false),
+        };
+        let mut table = table.write().unwrap();
+        if table.get(key) != Some(expected) {
+            return Ok(false);
+        }
+        table.remove(key);
+        Ok(true)
+    }
+
+    fn incr_by(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.update_number(table, key, |v| add_integer(v, delta))
+    }
+
+    fn incr_by_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        self.update_number(table, key, |v| add_float(v, delta))
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        let _creating = self.renaming.read().unwrap();
+        match self.tables.entry(table.into()) {
+            Entry::Occupied(_) => Ok(false),
+            Entry::Vacant(e) => {
+                e.insert(Table::default());
+                Ok(true)
+            }
+        }
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        Ok(self.tables.remove(table).is_some())
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        let mut tables: Vec<_> = self.tables.iter().map(|t| t.key().clone()).collect();
+        tables.sort_unstable();
+        Ok(tables)
+    }
+
+    fn rename_table(&self, table: &str, new_name: &str) -> Result<(), KvError> {
+        // No write can create either table until the move is done
+        let _renaming = self.renaming.write().unwrap();
+        if self.tables.contains_key(new_name) {
+            return Err(KvError::TableExists(new_name.into()));
+        }
+        let (_, t) = self
+            .tables
+            .remove(table)
+            .ok_or_else(|| KvError::TableNotFound(table.into()))?;
+        self.tables.insert(new_name.into(), t);
+        Ok(())
+    }
+
+    fn truncate_table(&self, table: &str) -> Result<usize, KvError> {
+        let t = self
+            .tables
+            .get(table)
+            .ok_or_else(|| KvError::TableNotFound(table.into()))?;
+        let removed = std::mem::take(&mut *t.write().unwrap());
+        Ok(removed.len())
+    }
+}
@@ -0,0 +1,80 @@
+use bytes::{Buf, BufMut, BytesMut};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// Every record starts with a 4-byte payload length and a 4-byte CRC32 of the payload
+pub(crate) const RECORD_HEADER_LEN: usize = 8;
+
+/// Write `payload` framed as a record, returning the number of bytes written
+pub(crate) fn write_record(w: &mut impl Write, payload: &[u8]) -> io::Result<u64> {
+    let mut header = [0u8; RECORD_HEADER_LEN];
+    header[..4].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    header[4..].copy_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    w.write_all(&header)?;
+    w.write_all(payload)?;
+    Ok((RECORD_HEADER_LEN + payload.len()) as u64)
+}
+
+/// Read the payload of the next record.
+/// Returns None at the end of file, or if the record is torn or fails its CRC check.
+pub(crate) fn read_record(r: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0u8; RECORD_HEADER_LEN];
+    if !read_full(r, &mut header)? {
+        return Ok(None);
+    }
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+    // The length may come from a torn header, so only what's there is allocated
+    let mut payload = Vec::new();
+    r.take(len as u64).read_to_end(&mut payload)?;
+    if payload.len() < len || crc32fast::hash(&payload) != crc {
+        return Ok(None);
+    }
+    Ok(Some(payload))
+}
+
+/// Whether the record at `offset` runs to the end of a file of `file_len` bytes,
+/// as the one a crash tore while writing it does
+pub(crate) fn is_last_record(
+    f: &mut (impl Read + Seek),
+    offset: u64,
+    file_len: u64,
+) -> io::Result<bool> {
+    f.seek(SeekFrom::Start(offset))?;
+    let mut len = [0u8; 4];
+    if !read_full(f, &mut len)? {
+        return Ok(true);
+    }
+    let end = offset + RECORD_HEADER_LEN as u64 + u32::from_le_bytes(len) as u64;
+    Ok(end >= file_len)
+}
+
+/// Fill `buf` completely, returning false if the file ends first
+fn read_full(r: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    let mut read = 0;
+    while read < buf.len() {
+        match r.read(&mut buf[read..])? {
+            0 => return Ok(false),
+            n => read += n,
+        }
+    }
+    Ok(true)
+}
+
+pub(crate) fn put_str(buf: &mut BytesMut, s: &str) {
+    buf.put_u32_le(s.len() as u32);
+    buf.put_slice(s.as_bytes());
+}
+
+pub(crate) fn get_str(buf: &mut &[u8]) -> Option<String> {
+    if buf.remaining() < 4 {
+        return None;
+    }
+    let len = buf.get_u32_le() as usize;
+    if buf.remaining() < len {
+        return None;
+    }
+    let s = String::from_utf8(buf[..len].to_vec()).ok()?;
+    buf.advance(len);
+    Some(s)
+}
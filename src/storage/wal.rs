@@ -0,0 +1,1040 @@
+use super::{
+    add_float, add_integer,
+    memory::deadline,
+    record::{get_str, is_last_record, put_str, read_record, write_record, RECORD_HEADER_LEN},
+};
+use crate::{KvError, Kvpair, MemTable, ScanRange, Storage, Value};
+use bytes::{Buf, BufMut, BytesMut};
+use prost::Message;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Write},
+    mem,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, MutexGuard, Weak},
+    thread::{self, JoinHandle, ThreadId},
+    time::Duration,
+};
+use tracing::{debug, warn};
+
+const LOG_FILE: &str = "wal.log";
+const SNAPSHOT_FILE: &str = "snapshot";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+
+const OP_SET: u8 = 1;
+const OP_DEL: u8 = 2;
+const OP_SET_AT: u8 = 3;
+const OP_EXPIRE_AT: u8 = 4;
+const OP_CREATE_TABLE: u8 = 5;
+const OP_DROP_TABLE: u8 = 6;
+const OP_RENAME_TABLE: u8 = 7;
+const OP_TRUNCATE_TABLE: u8 = 8;
+const OP_BATCH: u8 = 9;
+
+/// Options of a `WalTable`
+#[derive(Clone, Debug)]
+pub struct WalOptions {
+    /// Call `fsync` after every appended record
+    pub sync: bool,
+    /// Take a snapshot and truncate the log after this many records, 0 disables it
+    pub snapshot_every: usize,
+    /// Reject writes to tables that were not created with `create_table`
+    pub strict: bool,
+}
+
+impl Default for WalOptions {
+    fn default() -> Self {
+        Self {
+            sync: true,
+            snapshot_every: 10_000,
+            strict: false,
+        }
+    }
+}
+
+/// A durable storage: every write is appended to a write-ahead log before it is
+/// applied to an in-memory `MemTable`. The log is periodically compacted into a
+/// snapshot, and both are replayed on startup.
+#[derive(Debug)]
+pub struct WalTable {
+    mem: MemTable,
+    dir: PathBuf,
+    options: WalOptions,
+    log: Mutex<LogWriter>,
+    // Signalled when a batch is written, for the writers of other threads to go on
+    batch_done: Condvar,
+}
+
+#[derive(Debug)]
+struct LogWriter {
+    file: BufWriter<File>,
+    records: usize,
+    batch: Option<Batch>,
+    // Why the log stopped taking records: what's in the file after a failed
+    // write is unknown, and memory may hold changes that never reached it
+    failed: Option<String>,
+}
+
+/// The records of a running batch, written as one once its outermost `batch` call returns
+#[derive(Debug)]
+struct Batch {
+    owner: ThreadId,
+    depth: usize,
+    records: Vec<Record>,
+}
+
+/// A single mutation recorded in the log or the snapshot
+#[derive(Debug, PartialEq)]
+enum Record {
+    Set(String, String, Value),
+    Del(String, String),
+    /// Set with an absolute expiry time in unix milliseconds
+    SetAt(String, String, Value, u64),
+    /// Change the absolute expiry time of a key, None removes it
+    ExpireAt(String, String, Option<u64>),
+    CreateTable(String),
+    DropTable(String),
+    /// Rename a table, the second field is the new name
+    RenameTable(String, String),
+    TruncateTable(String),
+    /// Records replayed all together or not at all
+    Batch(Vec<Record>),
+}
+
+impl WalTable {
+    /// Open (or create) a WalTable in `dir` with default options
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, KvError> {
+        Self::with_options(dir, WalOptions::default())
+    }
+
+    /// Open (or create) a WalTable in `dir`, recovering the snapshot and the log
+    pub fn with_options(dir: impl AsRef<Path>, options: WalOptions) -> Result<Self, KvError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        // Strict mode is enforced before logging, so replaying never rejects a record
+        let mem = MemTable::new();
+        let snapshot = dir.join(SNAPSHOT_FILE);
+        if snapshot.exists() {
+            // Snapshots are renamed into place once complete, so they are never torn
+            let (records, _) = read_records(&snapshot, false)?;
+            debug!("Loaded {} records from snapshot", records.len());
+            for record in records {
+                record.apply(&mem);
+            }
+        }
+
+        let log_path = dir.join(LOG_FILE);
+        let mut records = 0;
+        if log_path.exists() {
+            let (entries, valid_len) = read_records(&log_path, true)?;
+            records = entries.len();
+            debug!("Replayed {} records from the log", records);
+            for record in entries {
+                record.apply(&mem);
+            }
+
+            // Drop a torn record at the tail so new records are not appended after garbage
+            let file = OpenOptions::new().write(true).open(&log_path)?;
+            if file.metadata()?.len() > valid_len {
+                warn!("Truncating torn log tail at offset {}", valid_len);
+                file.set_len(valid_len)?;
+                file.sync_all()?;
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+
+        Ok(Self {
+            mem,
+            dir,
+            options,
+            log: Mutex::new(LogWriter {
+                file: BufWriter::new(file),
+                records,
+                batch: None,
+                failed: None,
+            }),
+            batch_done: Condvar::new(),
+        })
+    }
+
+    /// Write all tables into a new snapshot and truncate the log
+    pub fn snapshot(&self) -> Result<(), KvError> {
+        let mut log = self.lock();
+        if log.batch.is_some() {
+            return Err(KvError::Internal(
+                "cannot snapshot in the middle of a batch".into(),
+            ));
+        }
+        log.check()?;
+        self.snapshot_locked(&mut log)
+    }
+
+    /// Lock the log, once the batch of another thread, if any, is written
+    fn lock(&self) -> MutexGuard<'_, LogWriter> {
+        let log = self.log.lock().unwrap();
+        let current = thread::current().id();
+        self.batch_done
+            .wait_while(log, |log| {
+                log.batch.as_ref().is_some_and(|b| b.owner != current)
+            })
+            .unwrap()
+    }
+
+    fn snapshot_locked(&self, log: &mut LogWriter) -> Result<(), KvError> {
+        let tmp = self.dir.join(SNAPSHOT_TMP_FILE);
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        for table in self.mem.tables() {
+            // Empty tables must survive the snapshot too
+            Record::CreateTable(table.clone()).write_to(&mut file)?;
+            for (key, entry) in self.mem.entries(&table) {
+                let record = match entry.expire_at {
+                    Some(t) => Record::SetAt(table.clone(), key, entry.value, t),
+                    None => Record::Set(table.clone(), key, entry.value),
+                };
+                record.write_to(&mut file)?;
+            }
+        }
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        fs::rename(&tmp, self.dir.join(SNAPSHOT_FILE))?;
+        File::open(&self.dir)?.sync_all()?;
+
+        // Everything in the log is now covered by the snapshot
+        log.file.flush()?;
+        log.file.get_ref().set_len(0)?;
+        log.file.get_ref().sync_all()?;
+        log.records = 0;
+        debug!("Snapshot written to {:?}", self.dir);
+
+        Ok(())
+    }
+
+    /// Spawn a thread that sweeps expired keys from memory every `interval`.
+    /// Nothing is logged: replaying drops the keys that expired meanwhile.
+    /// The thread exits once the WalTable is dropped.
+    pub fn spawn_sweeper(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let store: Weak<Self> = Arc::downgrade(self);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            match store.upgrade() {
+                Some(store) => {
+                    let evicted = store.mem.sweep_expired();
+                    if evicted > 0 {
+                        debug!("Swept {} expired keys", evicted);
+                    }
+                }
+                None => break,
+            }
+        })
+    }
+
+    fn check_table(&self, table: &str) -> Result<(), KvError> {
+        if self.options.strict && !self.mem.has_table(table) {
+            return Err(KvError::TableNotFound(table.into()));
+        }
+        Ok(())
+    }
+
+    /// Replace a numeric value with `f(current)` under the log lock, keeping its ttl
+    fn update_number<T: Copy + Into<Value>>(
+        &self,
+        table: &str,
+        key: &str,
+        f: impl FnOnce(Option<&Value>) -> Result<T, KvError>,
+    ) -> Result<T, KvError> {
+        let mut log = self.lock();
+        let entry = self.mem.live_entry(table, key);
+        let result = f(entry.as_ref().map(|e| &e.value))?;
+        self.check_table(table)?;
+
+        let expire_at = entry.and_then(|e| e.expire_at);
+        let record = match expire_at {
+            Some(t) => Record::SetAt(table.into(), key.into(), result.into(), t),
+            None => Record::Set(table.into(), key.into(), result.into()),
+        };
+        self.append_locked(&mut log, record, |mem| {
+            mem.set_at(table, key.into(), result.into(), expire_at)
+        })??;
+        Ok(result)
+    }
+
+    /// Append `record` to the log, then apply the same change to the memtable with `f`
+    fn append<T>(&self, record: Record, f: impl FnOnce(&MemTable) -> T) -> Result<T, KvError> {
+        let mut log = self.lock();
+        self.append_locked(&mut log, record, f)
+    }
+
+    /// Like `append`, rejecting writes to unknown tables in strict mode. The check
+    /// holds the log lock, so a DROP TABLE can't slip in before the record.
+    fn append_to<T>(
+        &self,
+        table: &str,
+        record: Record,
+        f: impl FnOnce(&MemTable) -> T,
+    ) -> Result<T, KvError> {
+        let mut log = self.lock();
+        self.check_table(table)?;
+        self.append_locked(&mut log, record, f)
+    }
+
+    /// Like `append`, for callers that hold the log lock to check a condition first
+    fn append_locked<T>(
+        &self,
+        log: &mut LogWriter,
+        record: Record,
+        f: impl FnOnce(&MemTable) -> T,
+    ) -> Result<T, KvError> {
+        // Only the thread running the batch gets the lock, and its records wait for the end
+        if let Some(batch) = &mut log.batch {
+            batch.records.push(record);
+            return Ok(f(&self.mem));
+        }
+        self.write_locked(log, record)?;
+        let res = f(&self.mem);
+        self.compact_locked(log);
+        Ok(res)
+    }
+
+    /// Write `record` to the log. A failed write is fatal: every later write and
+    /// snapshot fails too, until the table is reopened from what's on disk.
+    fn write_locked(&self, log: &mut LogWriter, record: Record) -> Result<(), KvError> {
+        log.check()?;
+        let mut write = || -> Result<(), KvError> {
+            record.write_to(&mut log.file)?;
+            log.file.flush()?;
+            if self.options.sync {
+                log.file.get_ref().sync_data()?;
+            }
+            Ok(())
+        };
+        if let Err(e) = write() {
+            warn!(
+                "Failed to write the log, rejecting every write from now on: {}",
+                e
+            );
+            log.failed = Some(e.to_string());
+            return Err(e);
+        }
+        log.records += 1;
+        Ok(())
+    }
+
+    /// Take a snapshot once enough records are logged, after they are applied
+    fn compact_locked(&self, log: &mut LogWriter) {
+        if self.options.snapshot_every > 0 && log.records >= self.options.snapshot_every {
+            // The record is already durable in the log, so a failed snapshot is not fatal
+            if let Err(e) = self.snapshot_locked(log) {
+                warn!("Failed to write snapshot: {:?}", e);
+            }
+        }
+    }
+}
+
+impl Storage for WalTable {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.mem.get(table, key)
+    }
+
+    fn set(&self, table: &str, key: String, value: Value) -> Result<Option<Value>, KvError> {
+        let record = Record::Set(table.into(), key.clone(), value.clone());
+        self.append_to(table, record, |mem| mem.set_at(table, key, value, None))?
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        self.mem.contains(table, key)
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        self.append(Record::Del(table.into(), key.into()), |mem| {
+            mem.del(table, key)
+        })?
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        self.mem.get_all(table)
+    }
+
+    fn scan(&self, table: &str, range: &ScanRange) -> Result<Vec<Kvpair>, KvError> {
+        self.mem.scan(table, range)
+    }
+
+    fn set_ex(
+        &self,
+        table: &str,
+        key: String,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<Option<Value>, KvError> {
+        // Log the absolute time so replaying doesn't extend the ttl
+        let expire_at = deadline(ttl);
+        let record = Record::SetAt(table.into(), key.clone(), value.clone(), expire_at);
+        self.append_to(table, record, |mem| {
+            mem.set_at(table, key, value, Some(expire_at))
+        })?
+    }
+
+    fn expire(&self, table: &str, key: &str, ttl: Duration) -> Result<bool, KvError> {
+        let expire_at = Some(deadline(ttl));
+        let record = Record::ExpireAt(table.into(), key.into(), expire_at);
+        self.append(record, |mem| mem.expire_at(table, key, expire_at))
+    }
+
+    fn ttl(&self, table: &str, key: &str) -> Result<Option<Duration>, KvError> {
+        self.mem.ttl(table, key)
+    }
+
+    fn persist(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        let record = Record::ExpireAt(table.into(), key.into(), None);
+        self.append(record, |mem| mem.persist(table, key))?
+    }
+
+    // Every write goes through the log lock, so holding it makes the check and the write atomic
+    fn compare_and_set(
+        &self,
+        table: &str,
+        key: String,
+        expected: Option<&Value>,
+        value: Value,
+    ) -> Result<bool, KvError> {
+        let mut log = self.lock();
+        let current = self.mem.live_entry(table, &key);
+        if current.as_ref().map(|e| &e.value) != expected {
+            return Ok(false);
+        }
+        self.check_table(table)?;
+        // Swapping the value keeps the ttl of the key
+        let expire_at = current.and_then(|e| e.expire_at);
+        let record = match expire_at {
+            Some(t) => Record::SetAt(table.into(), key.clone(), value.clone(), t),
+            None => Record::Set(table.into(), key.clone(), value.clone()),
+        };
+        self.append_locked(&mut log, record, |mem| {
+            mem.set_at(table, key, value, expire_at)
+        })??;
+        Ok(true)
+    }
+
+    fn del_if_eq(&self, table: &str, key: &str, expected: &Value) -> Result<bool, KvError> {
+        let mut log = self.lock();
+        if self.mem.get(table, key)?.as_ref() != Some(expected) {
+            return Ok(false);
+        }
+        let record = Record::Del(table.into(), key.into());
+        self.append_locked(&mut log, record, |mem| mem.del(table, key))??;
+        Ok(true)
+    }
+
+    fn incr_by(&self, table: &str, key: &str, delta: i64) -> Result<i64, KvError> {
+        self.update_number(table, key, |v| add_integer(v, delta))
+    }
+
+    fn incr_by_float(&self, table: &str, key: &str, delta: f64) -> Result<f64, KvError> {
+        self.update_number(table, key, |v| add_float(v, delta))
+    }
+
+    fn create_table(&self, table: &str) -> Result<bool, KvError> {
+        self.append(Record::CreateTable(table.into()), |mem| {
+            mem.create_table(table)
+        })?
+    }
+
+    fn drop_table(&self, table: &str) -> Result<bool, KvError> {
+        self.append(Record::DropTable(table.into()), |mem| mem.drop_table(table))?
+    }
+
+    fn list_tables(&self) -> Result<Vec<String>, KvError> {
+        self.mem.list_tables()
+    }
+
+    // A rejected rename or truncate is still logged, but replaying it fails the same way
+    fn rename_table(&self, table: &str, new_name: &str) -> Result<(), KvError> {
+        let record = Record::RenameTable(table.into(), new_name.into());
+        self.append(record, |mem| mem.rename_table(table, new_name))?
+    }
+
+    fn truncate_table(&self, table: &str) -> Result<usize, KvError> {
+        self.append(Record::TruncateTable(table.into()), |mem| {
+            mem.truncate_table(table)
+        })?
+    }
+
+    // The writes of other threads wait for the batch, so the log replays them in
+    // the order they were applied. Memory already holds the changes when the batch
+    // is written, so failing to write it stops the log from taking any more.
+    fn batch<T>(&self, f: impl FnOnce() -> T) -> Result<T, KvError> {
+        {
+            let mut log = self.lock();
+            let batch = log.batch.get_or_insert_with(|| Batch {
+                owner: thread::current().id(),
+                depth: 0,
+                records: Vec::new(),
+            });
+            batch.depth += 1;
+        }
+        // The batch ends even if f panics, or the other threads would wait forever
+        let res = panic::catch_unwind(AssertUnwindSafe(f));
+
+        let mut log = self.log.lock().unwrap();
+        let batch = log.batch.as_mut().expect("a batch is running");
+        batch.depth -= 1;
+        if batch.depth > 0 {
+            return Ok(res.unwrap_or_else(|e| panic::resume_unwind(e)));
+        }
+        let mut records = mem::take(&mut batch.records);
+        log.batch = None;
+        let written = match records.len() {
+            0 => Ok(()),
+            1 => self.write_locked(&mut log, records.remove(0)),
+            _ => self.write_locked(&mut log, Record::Batch(records)),
+        };
+        if written.is_ok() {
+            self.compact_locked(&mut log);
+        }
+        drop(log);
+        self.batch_done.notify_all();
+        let res = res.unwrap_or_else(|e| panic::resume_unwind(e));
+        written.map(|_| res)
+    }
+}
+
+impl LogWriter {
+    fn check(&self) -> Result<(), KvError> {
+        match &self.failed {
+            Some(e) => Err(KvError::IoError(format!("the log failed earlier: {}", e))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Record {
+    fn apply(self, mem: &MemTable) {
+        match self {
+            Record::Set(table, key, value) => {
+                mem.set_at(&table, key, value, None).ok();
+            }
+            Record::Del(table, key) => {
+                mem.del(&table, &key).ok();
+            }
+            Record::SetAt(table, key, value, t) => {
+                mem.set_at(&table, key, value, Some(t)).ok();
+            }
+            Record::ExpireAt(table, key, t) => {
+                mem.expire_at(&table, &key, t);
+            }
+            Record::CreateTable(table) => {
+                mem.create_table(&table).ok();
+            }
+            Record::DropTable(table) => {
+                mem.drop_table(&table).ok();
+            }
+            Record::RenameTable(table, new_name) => {
+                mem.rename_table(&table, &new_name).ok();
+            }
+            Record::TruncateTable(table) => {
+                mem.truncate_table(&table).ok();
+            }
+            Record::Batch(records) => {
+                for record in records {
+                    record.apply(mem);
+                }
+            }
+        }
+    }
+
+    fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut payload = BytesMut::new();
+        self.encode(&mut payload)?;
+        write_record(w, &payload).map(|_| ())
+    }
+
+    fn encode(&self, payload: &mut BytesMut) -> io::Result<()> {
+        match self {
+            Record::Set(table, key, value) => {
+                payload.put_u8(OP_SET);
+                put_str(payload, table);
+                put_str(payload, key);
+                value.encode(payload).map_err(io::Error::other)?;
+            }
+            Record::Del(table, key) => {
+                payload.put_u8(OP_DEL);
+                put_str(payload, table);
+                put_str(payload, key);
+            }
+            Record::SetAt(table, key, value, t) => {
+                payload.put_u8(OP_SET_AT);
+                put_str(payload, table);
+                put_str(payload, key);
+                payload.put_u64_le(*t);
+                value.encode(payload).map_err(io::Error::other)?;
+            }
+            Record::ExpireAt(table, key, t) => {
+                payload.put_u8(OP_EXPIRE_AT);
+                put_str(payload, table);
+                put_str(payload, key);
+                // 0 stands for no expiry
+                payload.put_u64_le(t.unwrap_or_default());
+            }
+            Record::CreateTable(table) => {
+                payload.put_u8(OP_CREATE_TABLE);
+                put_str(payload, table);
+                put_str(payload, "");
+            }
+            Record::DropTable(table) => {
+                payload.put_u8(OP_DROP_TABLE);
+                put_str(payload, table);
+                put_str(payload, "");
+            }
+            Record::RenameTable(table, new_name) => {
+                payload.put_u8(OP_RENAME_TABLE);
+                put_str(payload, table);
+                put_str(payload, new_name);
+            }
+            Record::TruncateTable(table) => {
+                payload.put_u8(OP_TRUNCATE_TABLE);
+                put_str(payload, table);
+                put_str(payload, "");
+            }
+            Record::Batch(records) => {
+                payload.put_u8(OP_BATCH);
+                put_str(payload, "");
+                put_str(payload, "");
+                payload.put_u32_le(records.len() as u32);
+                for record in records {
+                    let mut nested = BytesMut::new();
+                    record.encode(&mut nested)?;
+                    payload.put_u32_le(nested.len() as u32);
+                    payload.put_slice(&nested);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn decode(mut payload: &[u8]) -> Option<Self> {
+        if !payload.has_remaining() {
+            return None;
+        }
+        let op = payload.get_u8();
+        let table = get_str(&mut payload)?;
+        let key = get_str(&mut payload)?;
+        match op {
+            OP_SET => Some(Record::Set(table, key, Value::decode(payload).ok()?)),
+            OP_DEL => Some(Record::Del(table, key)),
+            OP_SET_AT if payload.remaining() >= 8 => {
+                let t = payload.get_u64_le();
+                Some(Record::SetAt(table, key, Value::decode(payload).ok()?, t))
+            }
+            OP_EXPIRE_AT if payload.remaining() >= 8 => {
+                let t = Some(payload.get_u64_le()).filter(|t| *t > 0);
+                Some(Record::ExpireAt(table, key, t))
+            }
+            OP_CREATE_TABLE => Some(Record::CreateTable(table)),
+            OP_DROP_TABLE => Some(Record::DropTable(table)),
+            OP_RENAME_TABLE => Some(Record::RenameTable(table, key)),
+            OP_TRUNCATE_TABLE => Some(Record::TruncateTable(table)),
+            OP_BATCH if payload.remaining() >= 4 => {
+                let count = payload.get_u32_le();
+                let mut records = Vec::new();
+                for _ in 0..count {
+                    if payload.remaining() < 4 {
+                        return None;
+                    }
+                    let len = payload.get_u32_le() as usize;
+                    if payload.remaining() < len {
+                        return None;
+                    }
+                    records.push(Record::decode(&payload[..len])?);
+                    payload.advance(len);
+                }
+                Some(Record::Batch(records))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Read records until the end of file. A bad record is only tolerated as a torn
+/// tail, if `torn_tail` allows one, and any other fails with a corruption error.
+/// Returns the records and the length of the valid prefix of the file.
+fn read_records(path: &Path, torn_tail: bool) -> Result<(Vec<Record>, u64), KvError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut records = Vec::new();
+    let mut valid_len = 0;
+
+    while let Some(payload) = read_record(&mut reader)? {
+        match Record::decode(&payload) {
+            Some(record) => records.push(record),
+            None => break,
+        }
+        valid_len += (RECORD_HEADER_LEN + payload.len()) as u64;
+    }
+
+    let file_len = fs::metadata(path)?.len();
+    if valid_len < file_len {
+        if !torn_tail || !is_last_record(reader.get_mut(), valid_len, file_len)? {
+            return Err(KvError::IoError(format!(
+                "corrupted record at offset {} of {:?}",
+                valid_len, path
+            )));
+        }
+        warn!("Found a torn record at offset {} of {:?}", valid_len, path);
+    }
+
+    Ok((records, valid_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dispatch, CommandRequest};
+    use tempfile::tempdir;
+
+    #[test]
+    fn data_should_survive_reopen() {
+        let dir = tempdir().unwrap();
+        {
+            let store = WalTable::open(dir.path()).unwrap();
+            store.set("t1", "k1".into(), "v1".into()).unwrap();
+            store.set("t1", "k2".into(), 2.into()).unwrap();
+            store.del("t1", "k1").unwrap();
+        }
+
+        let store = WalTable::open(dir.path()).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.get("t1", "k2"), Ok(Some(2.into())));
+    }
+
+    #[test]
+    fn tables_should_survive_reopen_and_snapshot() {
+        let dir = tempdir().unwrap();
+        {
+            let store = WalTable::open(dir.path()).unwrap();
+            store.create_table("empty").unwrap();
+            store.set("t1", "k1".into(), "v1".into()).unwrap();
+            store.set("t2", "k1".into(), "v1".into()).unwrap();
+            store.rename_table("t1", "t3").unwrap();
+            store.truncate_table("t2").unwrap();
+            store.drop_table("missing").unwrap();
+            // A rejected rename is logged but must fail the same way on replay
+            store.rename_table("t2", "t3").unwrap_err();
+        }
+
+        let tables = vec!["empty".to_owned(), "t2".into(), "t3".into()];
+        let store = WalTable::open(dir.path()).unwrap();
+        assert_eq!(store.list_tables(), Ok(tables.clone()));
+        assert_eq!(store.get("t3", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get_all("t2"), Ok(vec![]));
+
+        store.snapshot().unwrap();
+        drop(store);
+        let store = WalTable::open(dir.path()).unwrap();
+        assert_eq!(store.list_tables(), Ok(tables));
+    }
+
+    #[test]
+    fn strict_mode_should_reject_writes_before_logging() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let store = WalTable::with_options(dir.path(), options).unwrap();
+        let res = store.set("t1", "k1".into(), "v1".into());
+        assert_eq!(res, Err(KvError::TableNotFound("t1".into())));
+        let (records, _) = read_records(&dir.path().join(LOG_FILE), true).unwrap();
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn snapshot_should_compact_the_log() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions {
+            sync: false,
+            snapshot_every: 3,
+            ..Default::default()
+        };
+        {
+            let store = WalTable::with_options(dir.path(), options.clone()).unwrap();
+            for i in 0..7 {
+                store.set("t1", format!("k{}", i), i.into()).unwrap();
+            }
+        }
+
+        // 7 records with a snapshot every 3 leaves one record in the log
+        let (records, _) = read_records(&dir.path().join(LOG_FILE), true).unwrap();
+        assert_eq!(records.len(), 1);
+
+        let store = WalTable::with_options(dir.path(), options).unwrap();
+        assert_eq!(store.get_all("t1").unwrap().len(), 7);
+        assert_eq!(store.get("t1", "k6"), Ok(Some(6.into())));
+    }
+
+    #[test]
+    fn expiry_should_survive_reopen() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions {
+            sync: false,
+            snapshot_every: 2,
+            ..Default::default()
+        };
+        {
+            let store = WalTable::with_options(dir.path(), options.clone()).unwrap();
+            let ttl = Duration::from_secs(60);
+            store.set_ex("t1", "k1".into(), "v1".into(), ttl).unwrap();
+            store.set("t1", "k2".into(), "v2".into()).unwrap();
+            store.expire("t1", "k2", ttl).unwrap();
+            store
+                .set_ex("t1", "k3".into(), "v3".into(), Duration::from_millis(1))
+                .unwrap();
+        }
+
+        std::thread::sleep(Duration::from_millis(5));
+        let store = WalTable::with_options(dir.path(), options).unwrap();
+        assert!(store.ttl("t1", "k1").unwrap().is_some());
+        assert!(store.ttl("t1", "k2").unwrap().is_some());
+        assert_eq!(store.get("t1", "k3"), Ok(None));
+    }
+
+    #[test]
+    fn compare_and_set_should_keep_the_ttl() {
+        let dir = tempdir().unwrap();
+        let options = WalOptions {
+            sync: false,
+            ..Default::default()
+        };
+        {
+            let store = WalTable::with_options(dir.path(), options.clone()).unwrap();
+            let ttl = Duration::from_secs(60);
+            store.set_ex("t1", "k1".into(), "v1".into(), ttl).unwrap();
+            let expected = Value::from("v1");
+            let swapped = store.compare_and_set("t1", "k1".into(), Some(&expected), "v2".into());
+            assert_eq!(swapped, Ok(true));
+            assert!(store.ttl("t1", "k1").unwrap().is_some());
+        }
+
+        let store = WalTable::with_options(dir.path(), options).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v2".into())));
+        assert!(store.ttl("t1", "k1").unwrap().is_some());
+    }
+
+    #[test]
+    fn torn_record_should_be_discarded() {
+        let dir = tempdir().unwrap();
+        {
+            let store = WalTable::open(dir.path()).unwrap();
+            store.set("t1", "k1".into(), "v1".into()).unwrap();
+            store.set("t1", "k2".into(), "v2".into()).unwrap();
+        }
+
+        // Simulate a crash in the middle of writing the third record
+        let log_path = dir.path().join(LOG_FILE);
+        let mut buf = Vec::new();
+        Record::Set("t1".into(), "k3".into(), "v3".into())
+            .write_to(&mut buf)
+            .unwrap();
+        let mut file = OpenOptions::new().append(true).open(&log_path).unwrap();
+        file.write_all(&buf[..buf.len() - 2]).unwrap();
+        drop(file);
+
+        let store = WalTable::open(dir.path()).unwrap();
+        assert_eq!(store.get("t1", "k2"), Ok(Some("v2".into())));
+        assert_eq!(store.get("t1", "k3"), Ok(None));
+
+        // New records go right after the last valid one
+        store.set("t1", "k4".into(), "v4".into()).unwrap();
+        drop(store);
+        let store = WalTable::open(dir.path()).unwrap();
+        assert_eq!(store.get("t1", "k4"), Ok(Some("v4".into())));
+        assert_eq!(store.get_all("t1").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn torn_batch_should_be_discarded_as_a_whole() {
+        let dir = tempdir().unwrap();
+        {
+            let store = WalTable::open(dir.path()).unwrap();
+            store.set("t1", "k0".into(), "v0".into()).unwrap();
+            store
+                .batch(|| {
+                    store.set("t1", "k1".into(), "v1".into()).unwrap();
+                    store.set("t1", "k2".into(), "v2".into()).unwrap();
+                })
+                .unwrap();
+            assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        }
+        let log_path = dir.path().join(LOG_FILE);
+        let (records, _) = read_records(&log_path, true).unwrap();
+        assert_eq!(records.len(), 2);
+
+        // Simulate a crash in the middle of writing the batch
+        let file = OpenOptions::new().write(true).open(&log_path).unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - 2).unwrap();
+        drop(file);
+
+        let store = WalTable::open(dir.path()).unwrap();
+        assert_eq!(store.get("t1", "k0"), Ok(Some("v0".into())));
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn failed_batch_write_should_stop_the_log() {
+        let dir = tempdir().unwrap();
+        let store = WalTable::open(dir.path()).unwrap();
+        store.set("t1", "k0".into(), "v0".into()).unwrap();
+
+        // Every write to /dev/full fails with ENOSPC
+        let full = OpenOptions::new().write(true).open("/dev/full").unwrap();
+        store.log.lock().unwrap().file = BufWriter::new(full);
+        let res = store.batch(|| store.set("t1", "k1".into(), "v1".into()).unwrap());
+        assert!(res.is_err());
+
+        // Memory holds k1 but the log doesn't, so nothing else may be logged
+        let file = OpenOptions::new()
+            .append(true)
+            .open(dir.path().join(LOG_FILE));
+        store.log.lock().unwrap().file = BufWriter::new(file.unwrap());
+        assert!(store.set("t1", "k2".into(), "v2".into()).is_err());
+        assert!(store.snapshot().is_err());
+        drop(store);
+
+        let store = WalTable::open(dir.path()).unwrap();
+        assert_eq!(store.get("t1", "k0"), Ok(Some("v0".into())));
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+    }
+
+    #[test]
+    fn sweeper_should_reclaim_expired_keys() {
+        let dir = tempdir().unwrap();
+        let store = Arc::new(WalTable::open(dir.path()).unwrap());
+        store
+            .set_ex("t1", "k1".into(), "v1".into(), Duration::from_millis(20))
+            .unwrap();
+        assert!(store.mem.used_memory() > 0);
+
+        store.spawn_sweeper(Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(store.mem.used_memory(), 0);
+    }
+
+    #[test]
+    fn transaction_should_be_logged_as_one_record() {
+        let dir = tempdir().unwrap();
+        {
+            let store = WalTable::open(dir.path()).unwrap();
+            let cmd = CommandRequest::new_transaction(
+                vec![],
+                vec![
+                    CommandRequest::new_hset("t1", "k1", "v1".into()),
+                    CommandRequest::new_hset("t1", "k2", "v2".into()),
+                ],
+            );
+            assert_eq!(dispatch(cmd, &store).status, 200);
+        }
+        let log_path = dir.path().join(LOG_FILE);
+        let (records, _) = read_records(&log_path, true).unwrap();
+        assert_eq!(records.len(), 1);
+
+        // A crash while writing the transaction loses all of it
+        let file = OpenOptions::new().write(true).open(&log_path).unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - 2).unwrap();
+        drop(file);
+
+        let store = WalTable::open(dir.path()).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(None));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+    }
+
+    #[test]
+    fn garbage_record_length_should_be_a_torn_tail() {
+        let dir = tempdir().unwrap();
+        {
+            let store = WalTable::open(dir.path()).unwrap();
+            store.set("t1", "k1".into(), "v1".into()).unwrap();
+        }
+
+        // A header claiming a payload of almost 4 GiB
+        let log_path = dir.path().join(LOG_FILE);
+        let mut file = OpenOptions::new().append(true).open(&log_path).unwrap();
+        file.write_all(&[0xf0, 0xff, 0xff, 0xff, 0, 0, 0, 0, 1, 2])
+            .unwrap();
+        drop(file);
+
+        let store = WalTable::open(dir.path()).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get_all("t1").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn corrupted_record_should_stop_replay() {
+        let dir = tempdir().unwrap();
+        {
+            let store = WalTable::open(dir.path()).unwrap();
+            store.set("t1", "k1".into(), "v1".into()).unwrap();
+            store.set("t1", "k2".into(), "v2".into()).unwrap();
+        }
+
+        // Flip the last byte of the log so the CRC of the second record mismatches
+        let log_path = dir.path().join(LOG_FILE);
+        let mut data = fs::read(&log_path).unwrap();
+        *data.last_mut().unwrap() ^= 0xff;
+        fs::write(&log_path, data).unwrap();
+
+        let store = WalTable::open(dir.path()).unwrap();
+        assert_eq!(store.get("t1", "k1"), Ok(Some("v1".into())));
+        assert_eq!(store.get("t1", "k2"), Ok(None));
+    }
+
+    #[test]
+    fn corrupted_record_in_the_middle_should_fail_the_open() {
+        let dir = tempdir().unwrap();
+        {
+            let store = WalTable::open(dir.path()).unwrap();
+            store.set("t1", "k1".into(), "v1".into()).unwrap();
+            store.set("t1", "k2".into(), "v2".into()).unwrap();
+        }
+
+        // Flip the last byte of the first record, the second one is intact
+        let log_path = dir.path().join(LOG_FILE);
+        let mut first = Vec::new();
+        Record::Set("t1".into(), "k1".into(), "v1".into())
+            .write_to(&mut first)
+            .unwrap();
+        let mut data = fs::read(&log_path).unwrap();
+        data[first.len() - 1] ^= 0xff;
+        fs::write(&log_path, &data).unwrap();
+
+        assert!(matches!(
+            WalTable::open(dir.path()),
+            Err(KvError::IoError(_))
+        ));
+        // Nothing was truncated
+        assert_eq!(fs::read(&log_path).unwrap(), data);
+    }
+
+    #[test]
+    fn corrupted_snapshot_should_fail_the_open() {
+        let dir = tempdir().unwrap();
+        {
+            let store = WalTable::open(dir.path()).unwrap();
+            store.set("t1", "k1".into(), "v1".into()).unwrap();
+            store.snapshot().unwrap();
+        }
+
+        let snapshot = dir.path().join(SNAPSHOT_FILE);
+        let mut data = fs::read(&snapshot).unwrap();
+        *data.last_mut().unwrap() ^= 0xff;
+        fs::write(&snapshot, data).unwrap();
+
+        assert!(WalTable::open(dir.path()).is_err());
+    }
+}